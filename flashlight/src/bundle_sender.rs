@@ -3,6 +3,7 @@ use std::{num::NonZeroUsize, time::Duration};
 use lru::LruCache;
 use sdk::slot_subscriber::SlotSubscriber;
 use solana_sdk::{
+    native_token::LAMPORTS_PER_SOL,
     pubkey::Pubkey,
     signature::{Keypair, Signature},
     transaction::VersionedTransaction,
@@ -13,12 +14,12 @@ use crate::types::JitoStrategy;
 struct TipStream {
     time: String,
     ts: u16,
-    landed_tips_25th_percentile: u16,     // in SOL
-    landed_tips_50th_percentile: u16,     // in SOL
-    landed_tips_75th_percentile: u16,     // in SOL
-    landed_tips_95th_percentile: u16,     // in SOL
-    landed_tips_99th_percentile: u16,     // in SOL
-    ema_landed_tips_50th_percentile: u16, // in SOL
+    landed_tips_25th_percentile: f64,     // in SOL
+    landed_tips_50th_percentile: f64,     // in SOL
+    landed_tips_75th_percentile: f64,     // in SOL
+    landed_tips_95th_percentile: f64,     // in SOL
+    landed_tips_99th_percentile: f64,     // in SOL
+    ema_landed_tips_50th_percentile: f64, // in SOL
 }
 
 enum DropReason {
@@ -144,6 +145,25 @@ impl BundleSender {
         }
     }
 
+    /// Tip (lamports) to pay for the next bundle: at least the 25th percentile of recently landed
+    /// tips, ramping up towards `max_bundle_tip` as bundles keep failing, never above `max_bundle_tip`
+    pub(crate) fn calculate_current_tip_amount(&self) -> u64 {
+        let fail_rate = self.fail_bundle_count as f64 / self.max_fail_bundle_count as f64;
+        let ramped_tip = (fail_rate.powi(self.tip_multiplier as i32) * self.max_bundle_tip as f64)
+            .min(self.max_bundle_tip as f64) as u64;
+        let landed_tip = match &self.last_tip_stream {
+            Some(tip_stream) => {
+                (tip_stream.landed_tips_25th_percentile * LAMPORTS_PER_SOL as f64).round() as u64
+            }
+            None => 0,
+        };
+
+        landed_tip
+            .max(self.min_bundle_tip as u64)
+            .max(ramped_tip)
+            .min(self.max_bundle_tip as u64)
+    }
+
     /// Alternatively, don't create the bundle now, but batch them and send them together with 1
     /// tip.
     pub(crate) async fn send_transaction(
@@ -0,0 +1,580 @@
+use std::{fmt, str::FromStr};
+
+use clap::Subcommand;
+use drift::state::user::{MarketType, OrderStatus, User};
+use sdk::{
+    constants::{MarketExt, BASE_PRECISION, PRICE_PRECISION, QUOTE_PRECISION},
+    drift_client::DriftClient,
+    math::{
+        leverage::get_leverage,
+        liquidation::{
+            calculate_collateral, calculate_liquidation_price_and_unrealized_pnl,
+            calculate_margin_requirements, CollateralInfo, LiquidationAndPnlInfo, MarginCategory,
+            MarginRequirementInfo,
+        },
+    },
+    AccountProvider, Wallet,
+};
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+
+#[derive(Subcommand)]
+pub enum AccountCommands {
+    /// Print positions, open orders and margin state of a user
+    Show {
+        /// user account pubkey or `<authority>:<sub_account_id>`
+        account: String,
+
+        /// print as JSON instead of tables
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+pub async fn run<T: AccountProvider>(
+    drift_client: &DriftClient<T>,
+    command: AccountCommands,
+) -> Result<(), String> {
+    match command {
+        AccountCommands::Show { account, json } => {
+            let user_pubkey = parse_user_account(&account)?;
+            let summary = AccountSummary::load(drift_client, &user_pubkey).await?;
+            if json {
+                println!("{}", summary.to_json()?);
+            } else {
+                println!("{summary}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Resolve `<pubkey>` or `<authority>:<sub_account_id>` into a drift user account
+pub fn parse_user_account(input: &str) -> Result<Pubkey, String> {
+    match input.split_once(':') {
+        Some((authority, sub_account_id)) => {
+            let authority = Pubkey::from_str(authority).map_err(|e| e.to_string())?;
+            let sub_account_id = sub_account_id
+                .parse::<u16>()
+                .map_err(|e| format!("invalid sub account id {sub_account_id}: {e}"))?;
+            Ok(Wallet::derive_user_account(
+                &authority,
+                sub_account_id,
+                &drift::ID,
+            ))
+        }
+        None => Pubkey::from_str(input).map_err(|e| e.to_string()),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PerpPositionSummary {
+    pub market_index: u16,
+    pub symbol: String,
+    pub base_asset_amount: f64,
+    pub entry_price: f64,
+    pub unrealized_pnl: f64,
+    /// `None` if the position can't be liquidated by price moves in this market
+    pub liquidation_price: Option<f64>,
+    pub open_orders: u8,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SpotPositionSummary {
+    pub market_index: u16,
+    pub symbol: String,
+    /// negative for borrows
+    pub token_amount: f64,
+    pub open_orders: u8,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrderSummary {
+    pub order_id: u32,
+    pub user_order_id: u8,
+    pub symbol: String,
+    pub direction: String,
+    pub order_type: String,
+    pub base_asset_amount: f64,
+    pub base_asset_amount_filled: f64,
+    pub price: f64,
+    pub trigger_price: f64,
+    pub oracle_price_offset: f64,
+    pub reduce_only: bool,
+    pub post_only: bool,
+}
+
+/// Positions, orders and margin state of a drift user account, in human units
+#[derive(Debug, Serialize)]
+pub struct AccountSummary {
+    pub pubkey: String,
+    pub authority: String,
+    pub sub_account_id: u16,
+    pub name: String,
+    pub total_collateral_initial: f64,
+    pub free_collateral_initial: f64,
+    pub total_collateral_maintenance: f64,
+    pub free_collateral_maintenance: f64,
+    pub margin_requirement_initial: f64,
+    pub margin_requirement_maintenance: f64,
+    pub leverage: f64,
+    pub perp_positions: Vec<PerpPositionSummary>,
+    pub spot_positions: Vec<SpotPositionSummary>,
+    pub orders: Vec<OrderSummary>,
+}
+
+impl AccountSummary {
+    pub async fn load<T: AccountProvider>(
+        drift_client: &DriftClient<T>,
+        user_pubkey: &Pubkey,
+    ) -> Result<Self, String> {
+        let user = drift_client
+            .get_user_account(user_pubkey)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Self::from_user(drift_client, user_pubkey, &user)
+    }
+
+    pub fn from_user<T: AccountProvider>(
+        drift_client: &DriftClient<T>,
+        user_pubkey: &Pubkey,
+        user: &User,
+    ) -> Result<Self, String> {
+        let margin = UserMarginInfo::load(drift_client, user)?;
+
+        let mut spot_positions = Vec::new();
+        for position in user.spot_positions.iter().filter(|p| !p.is_available()) {
+            let spot_market = drift_client
+                .get_spot_market_account(position.market_index)
+                .ok_or_else(|| format!("spot market {} not found", position.market_index))?;
+            let token_amount = position
+                .get_signed_token_amount(&spot_market)
+                .map_err(|e| e.to_string())?;
+
+            spot_positions.push(SpotPositionSummary {
+                market_index: position.market_index,
+                symbol: spot_market.symbol().to_string(),
+                token_amount: token_amount as f64 / 10_f64.powi(spot_market.decimals as i32),
+                open_orders: position.open_orders,
+            });
+        }
+
+        Ok(Self::new(
+            user_pubkey,
+            user,
+            &margin,
+            spot_positions,
+            |market_type, market_index| market_symbol(drift_client, market_type, market_index),
+        ))
+    }
+
+    /// Summary of `user` given its computed `margin`, `symbol` names markets
+    fn new(
+        user_pubkey: &Pubkey,
+        user: &User,
+        margin: &UserMarginInfo,
+        spot_positions: Vec<SpotPositionSummary>,
+        symbol: impl Fn(MarketType, u16) -> String,
+    ) -> Self {
+        let mut perp_positions = Vec::new();
+        for position in user.perp_positions.iter().filter(|p| p.is_open_position()) {
+            let info = margin
+                .perp_positions
+                .iter()
+                .find(|(market_index, _)| *market_index == position.market_index)
+                .map(|(_, info)| info);
+            let base_asset_amount = position.base_asset_amount as f64 / BASE_PRECISION as f64;
+            let entry_price = if base_asset_amount == 0.0 {
+                0.0
+            } else {
+                -(position.quote_entry_amount as f64 / QUOTE_PRECISION as f64) / base_asset_amount
+            };
+
+            perp_positions.push(PerpPositionSummary {
+                market_index: position.market_index,
+                symbol: symbol(MarketType::Perp, position.market_index),
+                base_asset_amount,
+                entry_price,
+                unrealized_pnl: info.map_or(0.0, |info| {
+                    info.unrealized_pnl as f64 / QUOTE_PRECISION as f64
+                }),
+                liquidation_price: info
+                    .filter(|info| info.liquidation_price >= 0)
+                    .map(|info| info.liquidation_price as f64 / PRICE_PRECISION as f64),
+                open_orders: position.open_orders,
+            });
+        }
+
+        let orders = user
+            .orders
+            .iter()
+            .filter(|o| o.status == OrderStatus::Open)
+            .map(|order| OrderSummary {
+                order_id: order.order_id,
+                user_order_id: order.user_order_id,
+                symbol: symbol(order.market_type, order.market_index),
+                direction: format!("{:?}", order.direction),
+                order_type: format!("{:?}", order.order_type),
+                base_asset_amount: order.base_asset_amount as f64 / BASE_PRECISION as f64,
+                base_asset_amount_filled: order.base_asset_amount_filled as f64
+                    / BASE_PRECISION as f64,
+                price: order.price as f64 / PRICE_PRECISION as f64,
+                trigger_price: order.trigger_price as f64 / PRICE_PRECISION as f64,
+                oracle_price_offset: order.oracle_price_offset as f64 / PRICE_PRECISION as f64,
+                reduce_only: order.reduce_only,
+                post_only: order.post_only,
+            })
+            .collect();
+
+        Self {
+            pubkey: user_pubkey.to_string(),
+            authority: user.authority.to_string(),
+            sub_account_id: user.sub_account_id,
+            name: String::from_utf8_lossy(&user.name).trim().to_string(),
+            total_collateral_initial: margin.initial.total as f64 / QUOTE_PRECISION as f64,
+            free_collateral_initial: margin.initial.free as f64 / QUOTE_PRECISION as f64,
+            total_collateral_maintenance: margin.maintenance.total as f64 / QUOTE_PRECISION as f64,
+            free_collateral_maintenance: margin.maintenance.free as f64 / QUOTE_PRECISION as f64,
+            margin_requirement_initial: margin.requirements.initial as f64 / QUOTE_PRECISION as f64,
+            margin_requirement_maintenance: margin.requirements.maintenance as f64
+                / QUOTE_PRECISION as f64,
+            leverage: margin.leverage as f64 / PRICE_PRECISION as f64,
+            perp_positions,
+            spot_positions,
+            orders,
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| e.to_string())
+    }
+}
+
+/// Margin state of a user as computed by the sdk, in native precision
+struct UserMarginInfo {
+    initial: CollateralInfo,
+    maintenance: CollateralInfo,
+    requirements: MarginRequirementInfo,
+    leverage: u128,
+    /// liquidation price and unrealized pnl of each open perp position, by market index
+    perp_positions: Vec<(u16, LiquidationAndPnlInfo)>,
+}
+
+impl UserMarginInfo {
+    fn load<T: AccountProvider>(
+        drift_client: &DriftClient<T>,
+        user: &User,
+    ) -> Result<Self, String> {
+        let mut perp_positions = Vec::new();
+        for position in user.perp_positions.iter().filter(|p| p.is_open_position()) {
+            let info = calculate_liquidation_price_and_unrealized_pnl(
+                drift_client,
+                user,
+                position.market_index,
+            )
+            .map_err(|e| e.to_string())?;
+            perp_positions.push((position.market_index, info));
+        }
+
+        Ok(Self {
+            initial: calculate_collateral(drift_client, user, MarginCategory::Initial)
+                .map_err(|e| e.to_string())?,
+            maintenance: calculate_collateral(drift_client, user, MarginCategory::Maintenance)
+                .map_err(|e| e.to_string())?,
+            requirements: calculate_margin_requirements(drift_client, user)
+                .map_err(|e| e.to_string())?,
+            leverage: get_leverage(drift_client, user).map_err(|e| e.to_string())?,
+            perp_positions,
+        })
+    }
+}
+
+impl fmt::Display for AccountSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Account:      {} ({})", self.pubkey, self.name)?;
+        writeln!(
+            f,
+            "Authority:    {} (sub account {})",
+            self.authority, self.sub_account_id
+        )?;
+        writeln!(
+            f,
+            "Collateral:   total {:.2} / free {:.2} (initial), total {:.2} / free {:.2} (maintenance)",
+            self.total_collateral_initial,
+            self.free_collateral_initial,
+            self.total_collateral_maintenance,
+            self.free_collateral_maintenance
+        )?;
+        writeln!(
+            f,
+            "Margin req.:  {:.2} (initial), {:.2} (maintenance)",
+            self.margin_requirement_initial, self.margin_requirement_maintenance
+        )?;
+        writeln!(f, "Leverage:     {:.2}x", self.leverage)?;
+
+        writeln!(f, "\nPerp positions")?;
+        writeln!(
+            f,
+            "{:<12} {:>16} {:>14} {:>14} {:>14} {:>6}",
+            "market", "base", "entry", "upnl", "liq. price", "orders"
+        )?;
+        for p in &self.perp_positions {
+            let liquidation_price = match p.liquidation_price {
+                Some(price) => format!("{price:.4}"),
+                None => "-".to_string(),
+            };
+            writeln!(
+                f,
+                "{:<12} {:>16.4} {:>14.4} {:>14.2} {:>14} {:>6}",
+                p.symbol,
+                p.base_asset_amount,
+                p.entry_price,
+                p.unrealized_pnl,
+                liquidation_price,
+                p.open_orders
+            )?;
+        }
+
+        writeln!(f, "\nSpot positions")?;
+        writeln!(f, "{:<12} {:>20} {:>6}", "market", "amount", "orders")?;
+        for p in &self.spot_positions {
+            writeln!(
+                f,
+                "{:<12} {:>20.6} {:>6}",
+                p.symbol, p.token_amount, p.open_orders
+            )?;
+        }
+
+        writeln!(f, "\nOpen orders")?;
+        writeln!(
+            f,
+            "{:>8} {:>4} {:<12} {:<6} {:<12} {:>14} {:>14} {:>12} {:>12} {:>12} {:<5} {:<5}",
+            "id",
+            "uid",
+            "market",
+            "side",
+            "type",
+            "size",
+            "filled",
+            "price",
+            "trigger",
+            "oracle off.",
+            "ro",
+            "po"
+        )?;
+        for o in &self.orders {
+            writeln!(
+                f,
+                "{:>8} {:>4} {:<12} {:<6} {:<12} {:>14.4} {:>14.4} {:>12.4} {:>12.4} {:>12.4} {:<5} {:<5}",
+                o.order_id,
+                o.user_order_id,
+                o.symbol,
+                o.direction,
+                o.order_type,
+                o.base_asset_amount,
+                o.base_asset_amount_filled,
+                o.price,
+                o.trigger_price,
+                o.oracle_price_offset,
+                o.reduce_only,
+                o.post_only
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn perp_symbol<T: AccountProvider>(drift_client: &DriftClient<T>, market_index: u16) -> String {
+    match drift_client.get_perp_market_account(market_index) {
+        Some(market) => market.symbol().to_string(),
+        None => format!("perp-{market_index}"),
+    }
+}
+
+fn market_symbol<T: AccountProvider>(
+    drift_client: &DriftClient<T>,
+    market_type: MarketType,
+    market_index: u16,
+) -> String {
+    match market_type {
+        MarketType::Perp => perp_symbol(drift_client, market_index),
+        MarketType::Spot => match drift_client.get_spot_market_account(market_index) {
+            Some(market) => market.symbol().to_string(),
+            None => format!("spot-{market_index}"),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use drift::{
+        controller::position::PositionDirection,
+        state::user::{Order, OrderType, PerpPosition},
+    };
+
+    use super::*;
+
+    fn fixture_user() -> User {
+        let mut user = User {
+            authority: Pubkey::new_unique(),
+            sub_account_id: 2,
+            ..Default::default()
+        };
+        user.name[..4].copy_from_slice(b"test");
+        user.name[4..].fill(b' ');
+        // 2 long entered at 100
+        user.perp_positions[0] = PerpPosition {
+            market_index: 1,
+            base_asset_amount: 2 * BASE_PRECISION as i64,
+            quote_entry_amount: -200 * QUOTE_PRECISION as i64,
+            quote_asset_amount: -200 * QUOTE_PRECISION as i64,
+            open_orders: 1,
+            ..Default::default()
+        };
+        // 1 short entered at 50, not liquidatable by price moves in its market
+        user.perp_positions[1] = PerpPosition {
+            market_index: 2,
+            base_asset_amount: -(BASE_PRECISION as i64),
+            quote_entry_amount: 50 * QUOTE_PRECISION as i64,
+            quote_asset_amount: 50 * QUOTE_PRECISION as i64,
+            ..Default::default()
+        };
+        user.orders[3] = Order {
+            status: OrderStatus::Open,
+            order_id: 7,
+            market_type: MarketType::Perp,
+            market_index: 1,
+            order_type: OrderType::Limit,
+            direction: PositionDirection::Short,
+            base_asset_amount: BASE_PRECISION,
+            price: 120 * PRICE_PRECISION as u64,
+            reduce_only: true,
+            ..Default::default()
+        };
+
+        user
+    }
+
+    fn fixture_margin() -> UserMarginInfo {
+        UserMarginInfo {
+            initial: CollateralInfo {
+                total: 1_000 * QUOTE_PRECISION as i128,
+                free: 750 * QUOTE_PRECISION as i128,
+            },
+            maintenance: CollateralInfo {
+                total: 1_100 * QUOTE_PRECISION as i128,
+                free: 975 * QUOTE_PRECISION as i128,
+            },
+            requirements: MarginRequirementInfo {
+                initial: 250 * QUOTE_PRECISION as u128,
+                maintenance: 125 * QUOTE_PRECISION as u128,
+            },
+            leverage: 3 * PRICE_PRECISION / 10,
+            perp_positions: vec![
+                (
+                    1,
+                    LiquidationAndPnlInfo {
+                        liquidation_price: 45 * PRICE_PRECISION as i64 / 2,
+                        unrealized_pnl: 20 * QUOTE_PRECISION as i128,
+                    },
+                ),
+                (
+                    2,
+                    LiquidationAndPnlInfo {
+                        liquidation_price: -1,
+                        unrealized_pnl: -5 * QUOTE_PRECISION as i128,
+                    },
+                ),
+            ],
+        }
+    }
+
+    fn summary() -> AccountSummary {
+        AccountSummary::new(
+            &Pubkey::new_unique(),
+            &fixture_user(),
+            &fixture_margin(),
+            vec![],
+            |market_type, market_index| format!("{market_type:?}-{market_index}"),
+        )
+    }
+
+    #[test]
+    fn summary_converts_margin_to_human_units() {
+        let summary = summary();
+
+        assert_eq!(summary.sub_account_id, 2);
+        assert_eq!(summary.name, "test");
+        assert_eq!(summary.total_collateral_initial, 1_000.0);
+        assert_eq!(summary.free_collateral_initial, 750.0);
+        assert_eq!(summary.total_collateral_maintenance, 1_100.0);
+        assert_eq!(summary.free_collateral_maintenance, 975.0);
+        assert_eq!(summary.margin_requirement_initial, 250.0);
+        assert_eq!(summary.margin_requirement_maintenance, 125.0);
+        assert_eq!(summary.leverage, 0.3);
+    }
+
+    #[test]
+    fn summary_has_perp_entry_and_liquidation_prices() {
+        let summary = summary();
+        assert_eq!(summary.perp_positions.len(), 2);
+
+        let long = &summary.perp_positions[0];
+        assert_eq!(long.symbol, "Perp-1");
+        assert_eq!(long.base_asset_amount, 2.0);
+        assert_eq!(long.entry_price, 100.0);
+        assert_eq!(long.unrealized_pnl, 20.0);
+        assert_eq!(long.liquidation_price, Some(22.5));
+        assert_eq!(long.open_orders, 1);
+
+        let short = &summary.perp_positions[1];
+        assert_eq!(short.base_asset_amount, -1.0);
+        assert_eq!(short.entry_price, 50.0);
+        assert_eq!(short.unrealized_pnl, -5.0);
+        assert_eq!(short.liquidation_price, None);
+    }
+
+    #[test]
+    fn summary_lists_only_open_orders() {
+        let summary = summary();
+        assert_eq!(summary.orders.len(), 1);
+
+        let order = &summary.orders[0];
+        assert_eq!(order.order_id, 7);
+        assert_eq!(order.symbol, "Perp-1");
+        assert_eq!(order.direction, "Short");
+        assert_eq!(order.base_asset_amount, 1.0);
+        assert_eq!(order.price, 120.0);
+        assert!(order.reduce_only);
+
+        let json = summary.to_json().unwrap();
+        assert!(json.contains("\"liquidation_price\": 22.5"));
+        assert!(json.contains("\"liquidation_price\": null"));
+    }
+
+    #[test]
+    fn parses_user_account_pubkey() {
+        let pubkey = Pubkey::new_unique();
+        assert_eq!(parse_user_account(&pubkey.to_string()), Ok(pubkey));
+        assert!(parse_user_account("not a pubkey").is_err());
+    }
+
+    #[test]
+    fn parses_authority_and_sub_account() {
+        let authority = Pubkey::new_unique();
+        assert_eq!(
+            parse_user_account(&format!("{authority}:3")),
+            Ok(Wallet::derive_user_account(&authority, 3, &drift::ID))
+        );
+        assert_ne!(
+            parse_user_account(&format!("{authority}:3")),
+            parse_user_account(&format!("{authority}:0"))
+        );
+        assert!(parse_user_account(&format!("{authority}:-1")).is_err());
+        assert!(parse_user_account(&format!("{authority}:")).is_err());
+        assert!(parse_user_account("bad:0").is_err());
+    }
+}
@@ -0,0 +1,583 @@
+use std::borrow::Cow;
+
+use clap::{Args, Subcommand, ValueEnum};
+use drift::{
+    controller::position::PositionDirection,
+    state::{
+        order_params::{ModifyOrderParams, PostOnlyParam},
+        user::{MarketType, Order, OrderTriggerCondition, User},
+    },
+};
+use sdk::{
+    drift_client::DriftClient,
+    order_builder::{AuctionParams, ModifyOrder, NewOrder, OrderMarket},
+    types::MarketId,
+    AccountProvider, TransactionBuilder,
+};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::rpc_config::{
+    RpcSimulateTransactionAccountsConfig, RpcSimulateTransactionConfig,
+};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+
+use crate::cli::account::AccountSummary;
+
+#[derive(Subcommand)]
+pub enum OrderCommands {
+    /// Place a new order
+    Place(PlaceOrderArgs),
+    /// Modify an open order
+    Modify(ModifyOrderArgs),
+    /// Cancel open orders by id or by market
+    Cancel(CancelOrderArgs),
+    /// Cancel all open orders, optionally only in one market
+    CancelAll(CancelAllOrdersArgs),
+}
+
+#[derive(Args)]
+pub struct OrderTxArgs {
+    /// sub-account of the wallet to trade from
+    #[arg(long, default_value_t = 0)]
+    pub sub_account_id: u16,
+
+    /// simulate the tx, print compute units and margin impact without sending
+    #[arg(long)]
+    pub simulate: bool,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+pub enum Side {
+    Long,
+    Short,
+}
+
+impl From<Side> for PositionDirection {
+    fn from(side: Side) -> Self {
+        match side {
+            Side::Long => PositionDirection::Long,
+            Side::Short => PositionDirection::Short,
+        }
+    }
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+pub enum OrderKind {
+    Market,
+    Limit,
+    TriggerMarket,
+    TriggerLimit,
+    Oracle,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+pub enum TriggerCondition {
+    Above,
+    Below,
+}
+
+impl From<TriggerCondition> for OrderTriggerCondition {
+    fn from(condition: TriggerCondition) -> Self {
+        match condition {
+            TriggerCondition::Above => OrderTriggerCondition::Above,
+            TriggerCondition::Below => OrderTriggerCondition::Below,
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct PlaceOrderArgs {
+    /// market symbol, e.g. SOL-PERP or SOL
+    pub market: String,
+
+    #[arg(value_enum)]
+    pub side: Side,
+
+    /// order size in base units
+    pub size: f64,
+
+    #[arg(long, value_enum, default_value_t = OrderKind::Limit)]
+    pub order_type: OrderKind,
+
+    /// limit price, worst fill price of market and trigger market orders
+    #[arg(long)]
+    pub price: Option<f64>,
+
+    /// trigger price of trigger orders
+    #[arg(long)]
+    pub trigger_price: Option<f64>,
+
+    #[arg(long, value_enum)]
+    pub trigger_condition: Option<TriggerCondition>,
+
+    /// price offset from the oracle, makes a floating limit order or limits an oracle order
+    #[arg(long, allow_hyphen_values = true, conflicts_with = "price")]
+    pub oracle_offset: Option<f64>,
+
+    #[arg(long)]
+    pub post_only: bool,
+
+    #[arg(long)]
+    pub reduce_only: bool,
+
+    #[arg(long, default_value_t = 0)]
+    pub user_order_id: u8,
+
+    /// auction duration in slots, prices are oracle offsets for oracle orders
+    #[arg(long, requires_all = ["auction_start_price", "auction_end_price"])]
+    pub auction_duration: Option<u8>,
+
+    #[arg(long, allow_hyphen_values = true)]
+    pub auction_start_price: Option<f64>,
+
+    #[arg(long, allow_hyphen_values = true)]
+    pub auction_end_price: Option<f64>,
+
+    #[command(flatten)]
+    pub tx: OrderTxArgs,
+}
+
+#[derive(Args)]
+pub struct ModifyOrderArgs {
+    /// program assigned id of the order
+    #[arg(
+        long,
+        conflicts_with = "user_order_id",
+        required_unless_present = "user_order_id"
+    )]
+    pub order_id: Option<u32>,
+
+    /// user assigned id of the order
+    #[arg(long)]
+    pub user_order_id: Option<u8>,
+
+    /// new order size in base units
+    #[arg(long)]
+    pub size: Option<f64>,
+
+    #[arg(long)]
+    pub price: Option<f64>,
+
+    #[arg(long)]
+    pub trigger_price: Option<f64>,
+
+    #[arg(long, allow_hyphen_values = true, conflicts_with = "price")]
+    pub oracle_offset: Option<f64>,
+
+    #[arg(long)]
+    pub post_only: Option<bool>,
+
+    #[arg(long)]
+    pub reduce_only: Option<bool>,
+
+    #[command(flatten)]
+    pub tx: OrderTxArgs,
+}
+
+#[derive(Args)]
+pub struct CancelOrderArgs {
+    /// program assigned ids of the orders
+    #[arg(long, num_args = 1..)]
+    pub order_id: Vec<u32>,
+
+    /// user assigned ids of the orders
+    #[arg(long, num_args = 1..)]
+    pub user_order_id: Vec<u8>,
+
+    /// cancel all orders in this market
+    #[arg(long)]
+    pub market: Option<String>,
+
+    /// only cancel orders of this side, requires `--market`
+    #[arg(long, value_enum, requires = "market")]
+    pub side: Option<Side>,
+
+    #[command(flatten)]
+    pub tx: OrderTxArgs,
+}
+
+#[derive(Args)]
+pub struct CancelAllOrdersArgs {
+    /// only cancel orders in this market
+    #[arg(long)]
+    pub market: Option<String>,
+
+    #[command(flatten)]
+    pub tx: OrderTxArgs,
+}
+
+pub async fn run<T: AccountProvider>(
+    drift_client: &DriftClient<T>,
+    command: OrderCommands,
+) -> Result<(), String> {
+    match command {
+        OrderCommands::Place(args) => {
+            let market = lookup_market(drift_client, &args.market)?;
+            let direction = args.side.into();
+            let trigger = || -> Result<(f64, OrderTriggerCondition), &'static str> {
+                match (args.trigger_price, args.trigger_condition) {
+                    (Some(trigger_price), Some(condition)) => Ok((trigger_price, condition.into())),
+                    _ => Err("trigger orders need --trigger-price and --trigger-condition"),
+                }
+            };
+
+            if args.oracle_offset.is_some()
+                && !matches!(args.order_type, OrderKind::Limit | OrderKind::Oracle)
+            {
+                return Err("--oracle-offset is only supported for limit and oracle orders".into());
+            }
+
+            let mut order = match args.order_type {
+                OrderKind::Market => NewOrder::market(market, direction, args.size),
+                // clap rejects --price with --oracle-offset
+                OrderKind::Limit => match (args.price, args.oracle_offset) {
+                    (Some(price), _) => NewOrder::limit(market, direction, args.size, price),
+                    (None, Some(offset)) => {
+                        NewOrder::oracle_limit(market, direction, args.size, offset)
+                    }
+                    (None, None) => {
+                        return Err("limit orders need --price or --oracle-offset".into())
+                    }
+                },
+                OrderKind::Oracle => {
+                    if args.price.is_some() {
+                        return Err(
+                            "oracle orders are priced by their auction, --price is not supported"
+                                .into(),
+                        );
+                    }
+                    let order = NewOrder::oracle(market, direction, args.size);
+                    match args.oracle_offset {
+                        Some(offset) => order.oracle_price_offset(offset),
+                        None => order,
+                    }
+                }
+                OrderKind::TriggerMarket => {
+                    let (trigger_price, condition) = trigger()?;
+                    NewOrder::trigger_market(market, direction, args.size, trigger_price, condition)
+                }
+                OrderKind::TriggerLimit => {
+                    let (trigger_price, condition) = trigger()?;
+                    let price = args.price.ok_or("trigger limit orders need --price")?;
+                    NewOrder::trigger_limit(
+                        market,
+                        direction,
+                        args.size,
+                        trigger_price,
+                        condition,
+                        price,
+                    )
+                }
+            };
+            if let (Some(price), OrderKind::Market | OrderKind::TriggerMarket) =
+                (args.price, args.order_type)
+            {
+                order = order.price(price);
+            }
+            if let Some(duration) = args.auction_duration {
+                order = order.auction(AuctionParams {
+                    duration,
+                    start_price: args
+                        .auction_start_price
+                        .ok_or("auctions need --auction-start-price")?,
+                    end_price: args
+                        .auction_end_price
+                        .ok_or("auctions need --auction-end-price")?,
+                });
+            }
+            if args.post_only {
+                order = order.post_only(PostOnlyParam::MustPostOnly);
+            }
+            let order = order
+                .reduce_only(args.reduce_only)
+                .user_order_id(args.user_order_id);
+
+            let params = drift_client.build_order(order).map_err(|e| e.to_string())?;
+
+            send(drift_client, &args.tx, |tx| tx.place_orders(vec![params])).await
+        }
+        OrderCommands::Modify(args) => {
+            let sub_account = drift_client.wallet().sub_account(args.tx.sub_account_id);
+            let order = match (args.order_id, args.user_order_id) {
+                (Some(order_id), _) => drift_client.get_order_by_id(&sub_account, order_id).await,
+                (None, Some(user_order_id)) => {
+                    drift_client
+                        .get_order_by_user_id(&sub_account, user_order_id)
+                        .await
+                }
+                (None, None) => return Err("--order-id or --user-order-id is required".into()),
+            }
+            .map_err(|e| e.to_string())?
+            .ok_or("order not found")?;
+
+            let mut modify = ModifyOrder::default();
+            if let Some(size) = args.size {
+                modify = modify.size(size);
+            }
+            if let Some(price) = args.price {
+                modify = modify.price(price);
+            }
+            if let Some(trigger_price) = args.trigger_price {
+                modify = modify.trigger_price(trigger_price);
+            }
+            if let Some(offset) = args.oracle_offset {
+                modify = modify.oracle_price_offset(offset);
+            }
+            if let Some(post_only) = args.post_only {
+                modify = modify.post_only(if post_only {
+                    PostOnlyParam::MustPostOnly
+                } else {
+                    PostOnlyParam::None
+                });
+            }
+            if let Some(reduce_only) = args.reduce_only {
+                modify = modify.reduce_only(reduce_only);
+            }
+
+            let market = MarketId::from((order.market_index, order.market_type));
+            let params = match market.kind() {
+                MarketType::Perp => build_modify(
+                    modify,
+                    &order,
+                    drift_client.get_perp_market_account(market.index()),
+                    market,
+                ),
+                MarketType::Spot => build_modify(
+                    modify,
+                    &order,
+                    drift_client.get_spot_market_account(market.index()),
+                    market,
+                ),
+            }?;
+
+            send(drift_client, &args.tx, |tx| {
+                tx.modify_orders(&[(order.order_id, params)])
+            })
+            .await
+        }
+        OrderCommands::Cancel(args) => {
+            let market = args
+                .market
+                .as_deref()
+                .map(|symbol| lookup_market(drift_client, symbol))
+                .transpose()?;
+            if args.order_id.is_empty() && args.user_order_id.is_empty() && market.is_none() {
+                return Err("--order-id, --user-order-id or --market is required".into());
+            }
+
+            send(drift_client, &args.tx, |mut tx| {
+                if !args.order_id.is_empty() {
+                    tx = tx.cancel_orders_by_id(args.order_id.clone());
+                }
+                if !args.user_order_id.is_empty() {
+                    tx = tx.cancel_orders_by_user_id(args.user_order_id.clone());
+                }
+                if let Some(market) = market {
+                    tx = tx
+                        .cancel_orders((market.index(), market.kind()), args.side.map(Into::into));
+                }
+                tx
+            })
+            .await
+        }
+        OrderCommands::CancelAll(args) => {
+            let market = args
+                .market
+                .as_deref()
+                .map(|symbol| lookup_market(drift_client, symbol))
+                .transpose()?;
+
+            send(drift_client, &args.tx, |tx| match market {
+                Some(market) => tx.cancel_orders((market.index(), market.kind()), None),
+                None => tx.cancel_all_orders(),
+            })
+            .await
+        }
+    }
+}
+
+/// Build the tx for the sub-account and either send or simulate it
+async fn send<T: AccountProvider>(
+    drift_client: &DriftClient<T>,
+    args: &OrderTxArgs,
+    build: impl FnOnce(TransactionBuilder) -> TransactionBuilder,
+) -> Result<(), String> {
+    let sub_account = drift_client.wallet().sub_account(args.sub_account_id);
+    let user = drift_client
+        .get_user_account(&sub_account)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let tx = build(TransactionBuilder::new(
+        drift_client.program_data(),
+        sub_account,
+        Cow::Borrowed(&user),
+        false,
+    ))
+    .build();
+
+    if args.simulate {
+        return simulate(drift_client, &sub_account, &user, tx).await;
+    }
+
+    let sig = drift_client
+        .sign_and_send(tx, false)
+        .await
+        .map_err(|e| e.to_string())?;
+    println!("sent: {sig}");
+
+    Ok(())
+}
+
+/// Simulate `tx` and print its compute units and the margin state of the sub-account after it
+async fn simulate<T: AccountProvider>(
+    drift_client: &DriftClient<T>,
+    sub_account: &Pubkey,
+    user: &User,
+    tx: solana_sdk::message::VersionedMessage,
+) -> Result<(), String> {
+    let recent_blockhash = drift_client
+        .get_latest_blockhash()
+        .await
+        .map_err(|e| e.to_string())?;
+    let tx = drift_client
+        .wallet()
+        .sign_tx(tx, recent_blockhash, false)
+        .map_err(|e| e.to_string())?;
+
+    let result = drift_client
+        .inner()
+        .simulate_transaction_with_config(
+            &tx,
+            RpcSimulateTransactionConfig {
+                sig_verify: false,
+                replace_recent_blockhash: true,
+                commitment: Some(CommitmentConfig::confirmed()),
+                accounts: Some(RpcSimulateTransactionAccountsConfig {
+                    encoding: Some(UiAccountEncoding::Base64),
+                    addresses: vec![sub_account.to_string()],
+                }),
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(|e| format!("failed to simulate: {e}"))?
+        .value;
+
+    println!(
+        "compute units: {}",
+        result
+            .units_consumed
+            .map_or("unknown".to_string(), |cu| cu.to_string())
+    );
+    if let Some(err) = result.err {
+        for log in result.logs.unwrap_or_default() {
+            println!("  {log}");
+        }
+        return Err(format!("simulation failed: {err}"));
+    }
+
+    let user_after = result
+        .accounts
+        .and_then(|accounts| accounts.into_iter().next().flatten())
+        .and_then(|account| account.decode::<solana_sdk::account::Account>())
+        .and_then(|account| sdk::utils::deserialize_account::<User>(&mut account.data.as_slice()))
+        .ok_or("simulation didn't return the user account")?;
+
+    let before = AccountSummary::from_user(drift_client, sub_account, user)?;
+    let after = AccountSummary::from_user(drift_client, sub_account, &user_after)?;
+    println!("{:<28} {:>16} {:>16}", "", "before", "after");
+    for (label, before, after) in [
+        (
+            "free collateral (initial)",
+            before.free_collateral_initial,
+            after.free_collateral_initial,
+        ),
+        (
+            "margin req. (initial)",
+            before.margin_requirement_initial,
+            after.margin_requirement_initial,
+        ),
+        (
+            "margin req. (maintenance)",
+            before.margin_requirement_maintenance,
+            after.margin_requirement_maintenance,
+        ),
+        ("leverage", before.leverage, after.leverage),
+    ] {
+        println!("{label:<28} {before:>16.4} {after:>16.4}");
+    }
+
+    Ok(())
+}
+
+fn lookup_market<T: AccountProvider>(
+    drift_client: &DriftClient<T>,
+    symbol: &str,
+) -> Result<MarketId, String> {
+    drift_client
+        .market_lookup(symbol)
+        .ok_or_else(|| format!("unknown market: {symbol}"))
+}
+
+/// Round `modify` to the order's market, fails if the market isn't loaded
+fn build_modify(
+    modify: ModifyOrder,
+    order: &Order,
+    market_account: Option<impl OrderMarket>,
+    market: MarketId,
+) -> Result<ModifyOrderParams, String> {
+    let market_account = market_account.ok_or_else(|| format!("market {market:?} not found"))?;
+    modify
+        .build(order, &market_account)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(subcommand)]
+        command: OrderCommands,
+    }
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(std::iter::once("order").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn price_conflicts_with_oracle_offset() {
+        assert!(parse(&["place", "SOL-PERP", "long", "1", "--price", "100"]).is_ok());
+        assert!(parse(&["place", "SOL-PERP", "long", "1", "--oracle-offset", "-0.5"]).is_ok());
+
+        let err = parse(&[
+            "place",
+            "SOL-PERP",
+            "long",
+            "1",
+            "--price",
+            "100",
+            "--oracle-offset",
+            "-0.5",
+        ])
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+
+        let err = parse(&[
+            "modify",
+            "--order-id",
+            "1",
+            "--price",
+            "100",
+            "--oracle-offset",
+            "0.5",
+        ])
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+    }
+}
@@ -1,7 +1,7 @@
 use sdk::types::Context as DriftEnv;
 use solana_sdk::pubkey::Pubkey;
 
-use crate::types::JitoStrategy;
+use crate::types::{JitoStrategy, MakerSelectionStrategy};
 
 #[derive(Debug)]
 enum TxSenderType {
@@ -27,6 +27,15 @@ pub struct FillerConfig {
 
     pub filler_polling_interval: Option<u16>,
 
+    /// how long to collect fill triggers (new taker orders, slots, oracle updates) before filling, default: 50
+    pub fill_trigger_debounce_ms: Option<u64>,
+
+    /// max time (ms) a single market's fill pass may take before it is abandoned, default: 5000
+    pub market_fill_timeout_ms: Option<u64>,
+
+    /// no new txs are sent for a market while this many of its txs are unconfirmed, default: 4
+    pub max_in_flight_txs_per_market: Option<u64>,
+
     pub revert_on_failure: Option<bool>,
 
     pub simulate_tx_for_cu_estimate: Option<bool>,
@@ -36,6 +45,15 @@ pub struct FillerConfig {
     pub rebalance_settled_pnl_threshold: Option<f64>,
 
     pub min_gas_balance_to_fill: Option<f64>,
+
+    /// skip fills whose expected reward minus fees and tips is below this (lamports), default: 0
+    pub min_fill_expected_value_lamports: Option<i64>,
+
+    /// default: liquidity weighted
+    pub maker_selection_strategy: Option<MakerSelectionStrategy>,
+
+    /// seeds the maker selection rng, random if unset
+    pub maker_selection_seed: Option<u64>,
 }
 
 #[derive(Debug, Default)]
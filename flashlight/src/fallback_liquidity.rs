@@ -0,0 +1,145 @@
+//! Spot L2 books with Phoenix and OpenBook v2 liquidity merged in as fallback levels
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use drift::state::{spot_market::SpotFulfillmentConfigStatus, user::MarketType};
+use sdk::{
+    dlob::{
+        dlob_subscriber::DLOBSubscriber,
+        openbook::OpenbookV2L2Generator,
+        order_book_levels::{L2OrderBook, L2OrderBookGenerator},
+        phoenix::PhoenixL2Generator,
+    },
+    drift_client::DriftClient,
+    AccountProvider,
+};
+use solana_sdk::pubkey::Pubkey;
+
+/// External market a drift spot market can be filled against
+#[derive(Debug, Clone, Copy)]
+pub enum FallbackMarket {
+    Phoenix(Pubkey),
+    OpenbookV2(Pubkey),
+}
+
+fn unix_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Loads the fulfillment config of spot market `market_index` for `fallback_market` and the
+/// external market's book as an L2 generator
+///
+/// Fails if the config is for another spot market or disabled.
+pub async fn load_fallback_l2_generator<T: AccountProvider>(
+    drift_client: &DriftClient<T>,
+    market_index: u16,
+    fallback_market: FallbackMarket,
+) -> Result<Box<dyn L2OrderBookGenerator>, String> {
+    let rpc_client = &drift_client.backend.rpc_client;
+    let commitment = rpc_client.commitment();
+
+    match fallback_market {
+        FallbackMarket::Phoenix(phoenix_market) => {
+            let config = drift_client
+                .get_phoenix_fulfillment_config(&phoenix_market)
+                .await
+                .map_err(|e| format!("phoenix fulfillment config of {phoenix_market}: {e}"))?;
+            if config.market_index != market_index
+                || config.status != SpotFulfillmentConfigStatus::Enabled
+            {
+                return Err(format!(
+                    "phoenix market {phoenix_market} is not enabled for spot market {market_index}"
+                ));
+            }
+
+            let response = rpc_client
+                .get_account_with_commitment(&config.phoenix_market, commitment)
+                .await
+                .map_err(|e| e.to_string())?;
+            let market = response
+                .value
+                .ok_or_else(|| format!("phoenix market {phoenix_market} not found"))?;
+            let generator =
+                PhoenixL2Generator::new(&market.data, response.context.slot, unix_timestamp())
+                    .map_err(|e| e.to_string())?;
+
+            Ok(Box::new(generator))
+        }
+        FallbackMarket::OpenbookV2(openbook_v2_market) => {
+            let config = drift_client
+                .get_openbook_v2_fulfillment_config(&openbook_v2_market)
+                .await
+                .map_err(|e| {
+                    format!("openbook v2 fulfillment config of {openbook_v2_market}: {e}")
+                })?;
+            if config.market_index != market_index
+                || config.status != SpotFulfillmentConfigStatus::Enabled
+            {
+                return Err(format!(
+                    "openbook v2 market {openbook_v2_market} is not enabled for spot market {market_index}"
+                ));
+            }
+
+            // market and book sides from the same slot
+            let accounts = rpc_client
+                .get_multiple_accounts_with_commitment(
+                    &[
+                        config.openbook_v2_market,
+                        config.openbook_v2_bids,
+                        config.openbook_v2_asks,
+                    ],
+                    commitment,
+                )
+                .await
+                .map_err(|e| e.to_string())?
+                .value;
+            let [market, bids, asks] = [0, 1, 2].map(|i| accounts.get(i).cloned().flatten());
+            match (market, bids, asks) {
+                (Some(market), Some(bids), Some(asks)) => {
+                    let generator = OpenbookV2L2Generator::new(
+                        &market.data,
+                        &bids.data,
+                        &asks.data,
+                        unix_timestamp(),
+                    )
+                    .map_err(|e| e.to_string())?;
+                    Ok(Box::new(generator))
+                }
+                _ => Err(format!(
+                    "openbook v2 market {openbook_v2_market} or its book sides not found"
+                )),
+            }
+        }
+    }
+}
+
+/// L2 book of spot market `market_index` with the resting orders of `fallback_markets` merged in
+pub async fn get_spot_l2_with_fallback<T: AccountProvider>(
+    dlob_subscriber: &mut DLOBSubscriber<T>,
+    drift_client: &DriftClient<T>,
+    market_index: u16,
+    fallback_markets: &[FallbackMarket],
+    depth: usize,
+) -> Result<L2OrderBook, String> {
+    let mut fallback_l2_generators = Vec::with_capacity(fallback_markets.len());
+    for fallback_market in fallback_markets {
+        fallback_l2_generators
+            .push(load_fallback_l2_generator(drift_client, market_index, *fallback_market).await?);
+    }
+
+    dlob_subscriber
+        .get_l2(
+            None,
+            Some(market_index),
+            Some(MarketType::Spot),
+            depth,
+            false,
+            None,
+            fallback_l2_generators,
+        )
+        .await
+        .map_err(|e| e.to_string())
+}
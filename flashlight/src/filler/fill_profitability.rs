@@ -0,0 +1,150 @@
+use std::cmp::Ordering;
+
+use sdk::dlob::dlob::NodeToFill;
+
+/// landing probability used for markets without landed/dropped history
+pub(crate) const DEFAULT_LANDING_PROBABILITY: f64 = 0.5;
+/// base fee paid per signature
+pub(crate) const LAMPORTS_PER_SIGNATURE: u64 = 5_000;
+pub(crate) const MICRO_LAMPORTS_PER_LAMPORT: u64 = 1_000_000;
+
+/// Expected reward and cost of sending a single fill
+#[derive(Debug, Clone)]
+pub(crate) struct FillEstimate {
+    pub(crate) node_to_fill: NodeToFill,
+    /// filler reward if the fill lands
+    pub(crate) reward_lamports: u64,
+    /// priority fee, signature fee and jito tip attributed to this fill
+    pub(crate) cost_lamports: u64,
+    /// chance the tx carrying this fill lands, from the market's recent landing rate
+    pub(crate) landing_probability: f64,
+}
+
+impl FillEstimate {
+    /// Reward minus cost, weighted by the chance of the tx landing (nothing is paid for dropped txs)
+    pub(crate) fn expected_value_lamports(&self) -> i64 {
+        let profit = self.reward_lamports as i64 - self.cost_lamports as i64;
+        (profit as f64 * self.landing_probability) as i64
+    }
+}
+
+/// Lamports spent on compute and tips by one fill using `compute_units`
+///
+/// `tx_share` is the fraction of the tx the fill occupies, the signature fee and tip are split by it
+pub(crate) fn calculate_fill_cost_lamports(
+    compute_units: u64,
+    compute_unit_price_micro_lamports: u64,
+    tip_lamports: u64,
+    tx_share: f64,
+) -> u64 {
+    let priority_fee = (compute_units as u128 * compute_unit_price_micro_lamports as u128)
+        .div_ceil(MICRO_LAMPORTS_PER_LAMPORT as u128) as u64;
+    let shared_cost = ((LAMPORTS_PER_SIGNATURE + tip_lamports) as f64 * tx_share).ceil() as u64;
+
+    priority_fee + shared_cost
+}
+
+/// Orders fills by expected value, best first, and splits off those below `min_expected_value_lamports`
+///
+/// Returns (fills to send, skipped fills)
+pub(crate) fn rank_fills_by_expected_value(
+    mut estimates: Vec<FillEstimate>,
+    min_expected_value_lamports: i64,
+) -> (Vec<FillEstimate>, Vec<FillEstimate>) {
+    estimates.sort_by(|a, b| {
+        b.expected_value_lamports()
+            .cmp(&a.expected_value_lamports())
+            .then_with(|| {
+                b.landing_probability
+                    .partial_cmp(&a.landing_probability)
+                    .unwrap_or(Ordering::Equal)
+            })
+    });
+
+    estimates
+        .into_iter()
+        .partition(|estimate| estimate.expected_value_lamports() >= min_expected_value_lamports)
+}
+
+#[cfg(test)]
+mod tests {
+    use drift::state::user::Order;
+    use sdk::dlob::dlob_node::{Node, NodeType};
+    use solana_sdk::pubkey::Pubkey;
+
+    use super::*;
+
+    fn estimate(
+        reward_lamports: u64,
+        cost_lamports: u64,
+        landing_probability: f64,
+    ) -> FillEstimate {
+        let node = Node::new(
+            NodeType::TakingLimit,
+            Order::default(),
+            Pubkey::new_unique(),
+        );
+        FillEstimate {
+            node_to_fill: NodeToFill::new(node, vec![]),
+            reward_lamports,
+            cost_lamports,
+            landing_probability,
+        }
+    }
+
+    #[test]
+    fn fill_cost_adds_priority_fee_and_shared_costs() {
+        // 200k CUs at 1_000 micro lamports, half of the signature fee and tip
+        assert_eq!(
+            calculate_fill_cost_lamports(200_000, 1_000, 10_000, 0.5),
+            7_700
+        );
+        // a whole tx without priority fee or tip pays the signature fee
+        assert_eq!(calculate_fill_cost_lamports(200_000, 0, 0, 1.0), 5_000);
+    }
+
+    #[test]
+    fn fill_cost_rounds_up() {
+        assert_eq!(calculate_fill_cost_lamports(1, 1, 0, 1.0 / 3.0), 1 + 1_667);
+    }
+
+    #[test]
+    fn expected_value_is_weighted_by_landing_probability() {
+        assert_eq!(
+            estimate(10_000, 2_000, 0.5).expected_value_lamports(),
+            4_000
+        );
+        assert_eq!(estimate(1_000, 2_000, 0.5).expected_value_lamports(), -500);
+    }
+
+    #[test]
+    fn ranks_by_expected_value_then_landing_probability() {
+        let estimates = vec![
+            estimate(10_000, 2_000, 0.5),
+            estimate(1_000, 2_000, 1.0),
+            estimate(10_000, 2_000, 1.0),
+            estimate(7_000, 2_000, 0.8),
+        ];
+
+        let (send, skip) = rank_fills_by_expected_value(estimates, 0);
+
+        let send_rewards: Vec<u64> = send.iter().map(|e| e.reward_lamports).collect();
+        assert_eq!(send_rewards, vec![10_000, 7_000, 10_000]);
+        assert_eq!(send[0].landing_probability, 1.0);
+        assert_eq!(send[2].landing_probability, 0.5);
+        assert_eq!(skip.len(), 1);
+        assert_eq!(skip[0].reward_lamports, 1_000);
+    }
+
+    #[test]
+    fn min_expected_value_is_inclusive() {
+        let (send, skip) = rank_fills_by_expected_value(
+            vec![estimate(6_000, 2_000, 1.0), estimate(5_999, 2_000, 1.0)],
+            4_000,
+        );
+
+        assert_eq!(send.len(), 1);
+        assert_eq!(send[0].reward_lamports, 6_000);
+        assert_eq!(skip.len(), 1);
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::{HashMap, HashSet};
+
+use drift::state::user::{MarketType, Order, OrderStatus};
+use sdk::{math::auction::is_auction_complete, slot_subscriber::SlotSubscriber, usermap::UserMap};
+use solana_sdk::pubkey::Pubkey;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Events that can make perp orders fillable
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum FillTrigger {
+    /// a user update carried a taker order whose auction is still running
+    TakerOrder { user_account: Pubkey, order: Order },
+    /// a new slot, moving auction prices and possibly oracle prices
+    Slot(u64),
+}
+
+/// Forwards user map and slot updates that can make orders fillable to `trigger_tx`
+pub(crate) fn subscribe_fill_triggers(
+    user_map: &UserMap,
+    slot_subscriber: &SlotSubscriber,
+    trigger_tx: UnboundedSender<FillTrigger>,
+) {
+    let current_slot = slot_subscriber.clone();
+    let taker_tx = trigger_tx.clone();
+    user_map.on_update(move |pubkey, user| {
+        let slot = current_slot.current_slot();
+        for order in user.orders.iter() {
+            if order.status != OrderStatus::Open
+                || order.market_type != MarketType::Perp
+                || is_auction_complete(order, slot)
+            {
+                continue;
+            }
+
+            let _ = taker_tx.send(FillTrigger::TakerOrder {
+                user_account: pubkey,
+                order: *order,
+            });
+        }
+    });
+
+    slot_subscriber.on_slot(move |slot| {
+        let _ = trigger_tx.send(FillTrigger::Slot(slot));
+    });
+}
+
+/// Oracle, vAMM and best resting limit prices of a perp market
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct MarketPrices {
+    pub market_index: u16,
+    pub oracle_price: i64,
+    pub vamm_bid: u64,
+    pub vamm_ask: u64,
+    pub best_bid: Option<u64>,
+    pub best_ask: Option<u64>,
+}
+
+impl MarketPrices {
+    /// Whether the best resting bid or ask limit crosses the oracle or the vAMM
+    fn has_crossing_limit(&self) -> bool {
+        let bid_crosses = self
+            .best_bid
+            .is_some_and(|bid| bid >= self.vamm_ask || bid as i128 >= self.oracle_price as i128);
+        let ask_crosses = self
+            .best_ask
+            .is_some_and(|ask| ask <= self.vamm_bid || ask as i128 <= self.oracle_price as i128);
+
+        bid_crosses || ask_crosses
+    }
+}
+
+/// Perp markets worth a fill pass, collected from triggers between passes
+#[derive(Debug, Default)]
+pub(crate) struct PendingFills {
+    markets: HashSet<u16>,
+    /// taker orders (and their user accounts) that the last built DLOB may not contain
+    taker_orders: Vec<(Pubkey, Order)>,
+    /// last auction end slot per market with running taker auctions
+    active_auctions: HashMap<u16, u64>,
+    /// oracle price per market at the last slot
+    oracle_prices: HashMap<u16, i64>,
+}
+
+impl PendingFills {
+    pub(crate) fn add_taker_order(&mut self, user_account: Pubkey, order: Order) {
+        self.markets.insert(order.market_index);
+        self.taker_orders.push((user_account, order));
+        let auction_end_slot = order.slot + order.auction_duration as u64;
+        let end_slot = self.active_auctions.entry(order.market_index).or_insert(0);
+        *end_slot = (*end_slot).max(auction_end_slot);
+    }
+
+    /// Adds markets with a running auction, whose price moves every slot
+    pub(crate) fn on_slot(&mut self, slot: u64) {
+        self.active_auctions
+            .retain(|_, auction_end_slot| *auction_end_slot >= slot);
+        self.markets.extend(self.active_auctions.keys());
+    }
+
+    /// Records the oracle price of a market, returns true if it changed since the last update
+    pub(crate) fn update_oracle_price(&mut self, market_index: u16, price: i64) -> bool {
+        match self.oracle_prices.insert(market_index, price) {
+            Some(last_price) => last_price != price,
+            None => false,
+        }
+    }
+
+    /// Adds the market if a resting limit order crosses its oracle or vAMM price, call after its
+    /// oracle price moved
+    pub(crate) fn on_oracle_move(&mut self, prices: &MarketPrices) {
+        if prices.has_crossing_limit() {
+            self.markets.insert(prices.market_index);
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.markets.is_empty()
+    }
+
+    /// Markets to fill and the taker orders to add to the DLOB first, resets both
+    pub(crate) fn take(&mut self) -> (HashSet<u16>, Vec<(Pubkey, Order)>) {
+        let taker_orders = std::mem::take(&mut self.taker_orders);
+        (std::mem::take(&mut self.markets), taker_orders)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prices(best_bid: Option<u64>, best_ask: Option<u64>) -> MarketPrices {
+        MarketPrices {
+            market_index: 1,
+            oracle_price: 100,
+            vamm_bid: 99,
+            vamm_ask: 101,
+            best_bid,
+            best_ask,
+        }
+    }
+
+    fn taker_order(order_id: u32, slot: u64, auction_duration: u8) -> Order {
+        Order {
+            order_id,
+            market_index: 1,
+            slot,
+            auction_duration,
+            ..Order::default()
+        }
+    }
+
+    #[test]
+    fn taker_auctions_are_filled_until_they_end() {
+        let taker = Pubkey::new_unique();
+        let mut pending = PendingFills::default();
+        pending.add_taker_order(taker, taker_order(1, 10, 10));
+        pending.add_taker_order(taker, taker_order(2, 10, 5));
+        let (markets, taker_orders) = pending.take();
+        assert_eq!(markets, HashSet::from([1]));
+        assert_eq!(
+            taker_orders
+                .iter()
+                .map(|(user_account, order)| (*user_account, order.order_id))
+                .collect::<Vec<_>>(),
+            vec![(taker, 1), (taker, 2)]
+        );
+
+        pending.on_slot(20);
+        let (markets, taker_orders) = pending.take();
+        assert_eq!(markets, HashSet::from([1]));
+        assert!(taker_orders.is_empty());
+
+        pending.on_slot(21);
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn only_oracle_changes_count_as_moves() {
+        let mut pending = PendingFills::default();
+        assert!(!pending.update_oracle_price(1, 100));
+        assert!(!pending.update_oracle_price(1, 100));
+        assert!(pending.update_oracle_price(1, 101));
+        assert!(!pending.update_oracle_price(2, 101));
+    }
+
+    #[test]
+    fn oracle_move_marks_only_crossing_markets() {
+        let mut pending = PendingFills::default();
+
+        pending.on_oracle_move(&prices(None, None));
+        pending.on_oracle_move(&prices(Some(98), Some(102)));
+        assert!(pending.is_empty());
+
+        // bid through the oracle, below the vAMM ask
+        pending.on_oracle_move(&prices(Some(100), None));
+        assert_eq!(pending.take().0, HashSet::from([1]));
+
+        // ask through the vAMM bid
+        pending.on_oracle_move(&prices(None, Some(99)));
+        assert_eq!(pending.take().0, HashSet::from([1]));
+
+        // a bid below the oracle can still cross the vAMM ask
+        let mut wide = prices(Some(100), None);
+        wide.oracle_price = 105;
+        pending.on_oracle_move(&wide);
+        assert!(pending.is_empty());
+        wide.best_bid = Some(101);
+        pending.on_oracle_move(&wide);
+        assert_eq!(pending.take().0, HashSet::from([1]));
+    }
+}
@@ -2,20 +2,28 @@ use std::{
     collections::{HashMap, HashSet},
     num::NonZeroUsize,
     str::FromStr,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc, Mutex, RwLock,
+    },
     time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use drift::state::{
-    oracle::OracleSource,
-    perp_market::PerpMarket,
-    user::{MarketType, OrderType, User},
+use drift::{
+    math::constants::{BASE_PRECISION_I128, PRICE_PRECISION, QUOTE_PRECISION},
+    state::{
+        oracle::OracleSource,
+        perp_market::PerpMarket,
+        user::{MarketType, Order, OrderType, User},
+    },
 };
+use futures_util::Future;
 use log::info;
 use lru::LruCache;
 use rand::{seq::SliceRandom, thread_rng};
 use sdk::{
     accounts::BulkAccountLoader,
+    async_utils::join_all_with_timeout,
     blockhash_subscriber::BlockhashSubscriber,
     clock::clock_subscriber::ClockSubscriber,
     dlob::{
@@ -27,13 +35,20 @@ use sdk::{
     drift_client::DriftClient,
     jupiter::JupiterClient,
     math::{
+        fees::{calculate_fill_fees, determine_user_fee_tier},
         market::{calculate_ask_price, calculate_bid_price},
         oracle::is_oracle_valid,
         order::{is_fillable_by_vamm, is_order_expired},
     },
     priority_fee::priority_fee_subscriber::PriorityFeeSubscriber,
     slot_subscriber::SlotSubscriber,
-    types::{MakerInfo, ReferrerInfo},
+    tx::{
+        landing_rate_fee_controller::{LandingRateFeeController, LandingRateFeeControllerConfig},
+        tx_confirmer::{ConfirmedTx, TxConfirmer, TxConfirmerConfig, TxOutcome},
+        tx_packer::{TxPacker, TxPackerConfig},
+    },
+    types::{MakerInfo, ReferrerInfo, UserStatsAccount},
+    user_stats::UserStats,
     usermap::{user_stats_map::UserStatsMap, UserMap},
     AccountProvider,
 };
@@ -42,37 +57,50 @@ use solana_sdk::{
     address_lookup_table_account::AddressLookupTableAccount,
     commitment_config::{CommitmentConfig, CommitmentLevel},
     compute_budget::ComputeBudgetInstruction,
-    instruction::{AccountMeta, Instruction},
+    instruction::Instruction,
     native_token::LAMPORTS_PER_SOL,
     pubkey::Pubkey,
     signature::Signature,
     transaction::VersionedTransaction,
 };
-use solana_transaction_status::{option_serializer::OptionSerializer, UiTransactionEncoding};
+use tokio::sync::mpsc::UnboundedReceiver;
 
 use crate::{
     bundle_sender::BundleSender,
     config::{FillerConfig, GlobalConfig},
-    maker_selection::select_makers,
-    metrics::RuntimeSpec,
+    maker_selection::{new_maker_selector, MakerSelectionContext, MakerSelector},
+    metrics::{FillProfitabilityMetrics, Metrics, RuntimeSpec},
     types::JitoStrategy,
     util::{
         get_fill_signature_from_user_account_and_orader_id, get_node_to_fill_signature,
-        get_node_to_trigger_signature, get_transaction_account_metas, simulate_and_get_tx_with_cus,
-        valid_minimum_gas_amount, valid_rebalance_settled_pnl_threshold,
-        SimulateAndGetTxWithCUsParams, SimulateAndGetTxWithCUsResponse,
+        get_node_to_trigger_signature, get_priority_fee_lamports, get_transaction_account_metas,
+        simulate_and_get_tx_with_cus, valid_minimum_gas_amount,
+        valid_rebalance_settled_pnl_threshold, SimulateAndGetTxWithCUsParams,
+        SimulateAndGetTxWithCUsResponse,
     },
 };
 
-use self::pending_tx_sigs_to_confirm::{PendingTxSigsToconfirm, TxType};
+use self::{
+    fill_profitability::{
+        calculate_fill_cost_lamports, rank_fills_by_expected_value, FillEstimate,
+        DEFAULT_LANDING_PROBABILITY,
+    },
+    fill_triggers::{subscribe_fill_triggers, FillTrigger, MarketPrices, PendingFills},
+    pending_tx_sigs_to_confirm::{PendingTxSigsToconfirm, TxType},
+};
 
+mod fill_profitability;
+mod fill_triggers;
 mod pending_tx_sigs_to_confirm;
 
-const MAX_TX_PACK_SIZE: usize = 1230; //1232;
+const MAX_TX_PACK_SIZE: usize = 1230; // leave room below 1232 for logs truncated near the end of the tx
 const CU_PER_FILL: usize = 260_000; // CU cost for a successful fill
 const BURST_CU_PER_FILL: usize = 350_000; // CU cost for a successful fill
 const MAX_CU_PER_TX: usize = 1_400_000; // seems like this is all budget program gives us...on devnet
 const DEFAULT_INTERVAL_MS: u16 = 6000;
+const DEFAULT_FILL_TRIGGER_DEBOUNCE_MS: u64 = 50; // collect bursts of fill triggers for this long before filling
+const DEFAULT_MARKET_FILL_TIMEOUT_MS: u64 = 5_000; // give up on a market's fill pass after this long so it can't hold up the next pass
+const DEFAULT_MAX_IN_FLIGHT_TXS_PER_MARKET: u64 = 4; // stop sending txs for a market once this many are unconfirmed
 const FILL_ORDER_THROTTLE_BACKOFF: u64 = 1000; // the time to wait before trying to fill a throttled (error filling) node again
 const THROTTLED_NODE_SIZE_TO_PRUNE: usize = 10; // Size of throttled nodes to get to before pruning the map
 const TRIGGER_ORDER_COOLDOWN_MS: u64 = 1000; // the time to wait before trying to a node in the triggering map again
@@ -81,12 +109,29 @@ const MAX_ACCOUNTS_PER_TX: usize = 64; // solana limit, track https://github.com
 
 const SIM_CU_ESTIMATE_MULTIPLIER: f64 = 1.15;
 const SLOTS_UNTIL_JITO_LEADER_TO_SEND: u64 = 4;
-const TX_CONFIRMATION_BATCH_SIZE: usize = 100;
-const TX_TIMEOUT_THRESHOLD_MS: u128 = 60_000; // tx considered stale after this time and give up confirming
-const CONFIRM_TX_RATE_LIMIT_BACKOFF_MS: u64 = 5_000; // wait this long until trying to confirm tx again if rate limited
+
+const SOL_SPOT_MARKET_INDEX: u16 = 1;
+const MS_PER_SLOT: u64 = 400;
 
 const EXPIRE_ORDER_BUFFER_SEC: i64 = 60; // add extra time before trying to expire orders (want to avoid 6252 error due to clock drift)
 
+/// A user's stats values, copied out so the user stats map is not held while they are used
+struct UserStatsInfo {
+    user_stats_account_pubkey: Pubkey,
+    account: Option<UserStatsAccount>,
+    referrer_info: Option<ReferrerInfo>,
+}
+
+impl UserStatsInfo {
+    fn new<T: AccountProvider>(user_stats: &UserStats<T>) -> Self {
+        Self {
+            user_stats_account_pubkey: user_stats.user_stats_account_pubkey,
+            account: user_stats.get_account().ok().flatten(),
+            referrer_info: user_stats.get_referrer_info().ok().flatten(),
+        }
+    }
+}
+
 pub struct FillerBot<'a, T>
 where
     T: AccountProvider,
@@ -99,9 +144,13 @@ where
     bulk_account_loader: Option<BulkAccountLoader>,
     // user_stats_map_subscription_config: &'a UserSubscriptionConfig<U>,
     drift_client: Arc<DriftClient<T>>,
-    /// Connection to use specifically for confirming transactions
-    tx_confirmation_connection: Arc<RpcClient>,
     polling_interval_ms: u16,
+    /// how long fill triggers are collected before an event driven fill pass
+    fill_trigger_debounce: Duration,
+    /// max time a single market's fill pass may take before it is abandoned
+    market_fill_timeout: Duration,
+    /// no new txs are sent for a market while this many of its txs are unconfirmed
+    max_in_flight_txs_per_market: u64,
     revert_on_failure: Option<bool>,
     simulate_tx_for_cu_estimate: Option<bool>,
     lookup_table_account: Option<AddressLookupTableAccount>,
@@ -112,7 +161,8 @@ where
     dlob_subscriber: Option<DLOBSubscriber<T>>,
 
     user_map: Option<UserMap>,
-    user_stats_map: Option<UserStatsMap<T>>,
+    /// only locked to read or insert stats, new stats are subscribed without holding it
+    user_stats_map: RwLock<Option<UserStatsMap<T>>>,
 
     // periodic_task_mutex = new Mutex();
 
@@ -120,31 +170,36 @@ where
     watchdog_timer_last_pat_time: Instant,
 
     interval_ids: Vec<Instant>,
-    throttled_nodes: HashMap<String, Instant>,
-    filling_nodes: HashMap<String, Instant>,
-    triggering_nodes: HashMap<String, Instant>,
+    // shared by the concurrent per-market fill passes
+    throttled_nodes: Mutex<HashMap<String, Instant>>,
+    filling_nodes: Mutex<HashMap<String, Instant>>,
+    triggering_nodes: Mutex<HashMap<String, Instant>>,
 
     use_burst_cu_limit: bool,
     fill_tx_since_burst_cu: u16,
-    fill_tx_id: u16,
+    fill_tx_id: AtomicU16,
     last_settle_pnl: Instant,
 
     priority_fee_subscriber: PriorityFeeSubscriber<T>,
+    /// scales the priority fee per market based on how many of our txs land
+    landing_rate_fee_controller: Mutex<LandingRateFeeController>,
     blockhash_subscriber: BlockhashSubscriber,
-    /// stores txSigs that need to been confirmed in a slower loop, and the time they were confirmed
-    pending_tx_sigs_toconfirm: LruCache<Signature, PendingTxSigsToconfirm>,
-    expired_nodes_set: LruCache<String, bool>,
-    confirm_loop_running: bool,
-    confirm_loop_rate_limit_ts: Instant,
+    /// confirms sent txs, their outcomes arrive on `tx_outcomes`
+    tx_confirmer: TxConfirmer<PendingTxSigsToconfirm>,
+    /// taken by the fill loop that handles the outcomes
+    tx_outcomes: Option<UnboundedReceiver<ConfirmedTx<PendingTxSigsToconfirm>>>,
+    expired_nodes_set: Mutex<LruCache<String, bool>>,
 
     jupiter_client: Option<JupiterClient<'a>>,
+    maker_selector: Mutex<Box<dyn MakerSelector>>,
 
     // metrics
     // metrics_initialized: bool,
     // metrics_port: Option<u16>,
-    // metrics: Option<Metrics>,
+    metrics: Metrics,
     // boot_time_ms: Option<u16>,
     runtime_spec: RuntimeSpec,
+    fill_profitability_metrics: FillProfitabilityMetrics,
     // runtime_specs_gauge: Option<GaugeValue>,
     // try_fill_duration_histogram: Option<HistogramValue>,
     // est_tx_cu_histogram: Option<HistogramValue>,
@@ -168,6 +223,8 @@ where
     rebalance_filler: bool,
     min_gas_balance_to_fill: f64,
     rebalance_settled_pnl_threshold: f64,
+    /// fills with a lower expected value (lamports) are not sent
+    min_fill_expected_value_lamports: i64,
 }
 
 impl<'a, T> FillerBot<'a, T>
@@ -271,9 +328,28 @@ where
             Pubkey::from_str("8UJgxaiQx5nTrdDgph5FiahMmzduuLTLf5WmsPegYA6W").unwrap(),
         ]);
 
-        let pubsub_client = PubsubClient::new(websocket_url)
-            .await
-            .expect("init pubsub client");
+        let landing_rate_fee_controller =
+            LandingRateFeeController::new(LandingRateFeeControllerConfig {
+                max_priority_fee_micro_lamports: global_config
+                    .max_priority_fee_micro_lamports
+                    .map(|fee| fee as u64),
+                ..Default::default()
+            });
+
+        let pubsub_client = Arc::new(
+            PubsubClient::new(websocket_url)
+                .await
+                .expect("init pubsub client"),
+        );
+
+        let (tx_confirmer, tx_outcomes) = TxConfirmer::new(
+            tx_confirmation_connection,
+            Some(pubsub_client.clone()),
+            blockhash_subscriber.clone(),
+            TxConfirmerConfig::default(),
+        );
+
+        let metrics = Metrics::default();
 
         Self {
             global_config,
@@ -282,8 +358,7 @@ where
             dry_run: filler_config.base_config.dry_run,
             slot_subscriber,
             drift_client,
-            tx_confirmation_connection,
-            clock_subscriber: ClockSubscriber::new(Arc::new(pubsub_client), None),
+            clock_subscriber: ClockSubscriber::new(pubsub_client, None),
             // tx_confirmation_connection,
             bulk_account_loader,
             // user_stats_map_subscription_config: &user_stats_map_subscription_config,
@@ -291,6 +366,19 @@ where
             polling_interval_ms: filler_config
                 .filler_polling_interval
                 .unwrap_or(DEFAULT_INTERVAL_MS),
+            fill_trigger_debounce: Duration::from_millis(
+                filler_config
+                    .fill_trigger_debounce_ms
+                    .unwrap_or(DEFAULT_FILL_TRIGGER_DEBOUNCE_MS),
+            ),
+            market_fill_timeout: Duration::from_millis(
+                filler_config
+                    .market_fill_timeout_ms
+                    .unwrap_or(DEFAULT_MARKET_FILL_TIMEOUT_MS),
+            ),
+            max_in_flight_txs_per_market: filler_config
+                .max_in_flight_txs_per_market
+                .unwrap_or(DEFAULT_MAX_IN_FLIGHT_TXS_PER_MARKET),
             user_map: Some(user_map),
             revert_on_failure: Some(filler_config.revert_on_failure.unwrap_or(true)),
             simulate_tx_for_cu_estimate: Some(
@@ -298,26 +386,35 @@ where
             ),
             bundle_sender,
             jupiter_client,
+            maker_selector: Mutex::new(new_maker_selector(
+                filler_config.maker_selection_strategy.unwrap_or_default(),
+                filler_config.maker_selection_seed,
+            )),
             rebalance_filler: filler_config.rebalance_filler.unwrap_or(false),
             min_gas_balance_to_fill,
             rebalance_settled_pnl_threshold,
+            min_fill_expected_value_lamports: filler_config
+                .min_fill_expected_value_lamports
+                .unwrap_or(0),
+            fill_profitability_metrics: FillProfitabilityMetrics::register(&metrics),
+            metrics,
             priority_fee_subscriber,
+            landing_rate_fee_controller: Mutex::new(landing_rate_fee_controller),
             blockhash_subscriber,
-            pending_tx_sigs_toconfirm: LruCache::new(NonZeroUsize::new(10_000).unwrap()),
-            expired_nodes_set: LruCache::new(NonZeroUsize::new(100).unwrap()),
-            confirm_loop_running: false,
-            confirm_loop_rate_limit_ts: Instant::now() - Duration::from_secs(5_000),
+            tx_confirmer,
+            tx_outcomes: Some(tx_outcomes),
+            expired_nodes_set: Mutex::new(LruCache::new(NonZeroUsize::new(100).unwrap())),
             dlob_subscriber: None,
-            fill_tx_id: 0,
+            fill_tx_id: AtomicU16::new(0),
             fill_tx_since_burst_cu: 0,
-            filling_nodes: HashMap::new(),
+            filling_nodes: Mutex::new(HashMap::new()),
             has_enough_sol_to_fill: false,
             interval_ids: vec![],
             last_settle_pnl: Instant::now() - Duration::from_secs(60_000),
             lookup_table_account: None,
-            throttled_nodes: HashMap::new(),
-            triggering_nodes: HashMap::new(),
-            user_stats_map: None,
+            throttled_nodes: Mutex::new(HashMap::new()),
+            triggering_nodes: Mutex::new(HashMap::new()),
+            user_stats_map: RwLock::new(None),
             use_burst_cu_limit: false,
             watchdog_timer_last_pat_time: Instant::now(),
         }
@@ -364,13 +461,21 @@ where
             start_init_user_stats_map.elapsed().as_millis()
         );
 
-        self.user_stats_map = Some(user_stats_map);
+        *self.user_stats_map.get_mut().unwrap() = Some(user_stats_map);
 
         self.clock_subscriber
             .subscribe()
             .await
             .expect("subscribe clock");
 
+        // the tx confirmer expires txs by the block height it tracks
+        self.blockhash_subscriber
+            .subscribe()
+            .await
+            .expect("subscribe blockhash");
+        self.blockhash_subscriber.track_slots(&self.slot_subscriber);
+        self.tx_confirmer.subscribe();
+
         self.lookup_table_account = Some(self.drift_client.fetch_market_lookup_table_account());
     }
 
@@ -407,7 +512,7 @@ where
     pub async fn start_interval_loop(&mut self) {
         self.try_fill().await;
         self.settle_pnls().await;
-        self.confirm_pending_tx_sigs().await;
+        self.handle_tx_outcomes().await;
 
         log::info!(
             "{} Bot started! (websocket: {})",
@@ -416,90 +521,221 @@ where
         );
     }
 
-    fn record_jito_bundle_stats() {
-        todo!()
-    }
-
-    async fn confirm_pending_tx_sigs(&mut self) {
-        let next_time_can_run =
-            self.confirm_loop_rate_limit_ts + Duration::from_secs(CONFIRM_TX_RATE_LIMIT_BACKOFF_MS);
-        let now = Instant::now();
-        if now < next_time_can_run {
-            log::warn!(
-                "Skipping confirm loop due to rate limit, next run in {} ms",
-                (next_time_can_run - now).as_millis()
-            );
-            return;
-        }
-        if self.confirm_loop_running {
-            return;
+    /// Fills in reaction to new taker orders, slots moving auction prices and oracle updates, with a
+    /// full pass every `polling_interval_ms` as fallback
+    pub async fn start_event_loop(&mut self) {
+        let (trigger_tx, mut trigger_rx) = tokio::sync::mpsc::unbounded_channel();
+        if let Some(user_map) = &self.user_map {
+            subscribe_fill_triggers(user_map, &self.slot_subscriber, trigger_tx);
         }
-        self.confirm_loop_running = true;
+
+        let mut tx_outcomes = match self.tx_outcomes.take() {
+            Some(tx_outcomes) => tx_outcomes,
+            None => {
+                log::error!(
+                    "{}: tx outcomes are already handled by another loop",
+                    self.name
+                );
+                return;
+            }
+        };
+
+        let mut pending_fills = PendingFills::default();
+        let mut interval =
+            tokio::time::interval(Duration::from_millis(self.polling_interval_ms as u64));
 
         log::info!(
-            "Confirming tx sigs: {}",
-            self.pending_tx_sigs_toconfirm.len()
+            "{} Bot started! (event driven, debounce: {} ms)",
+            self.name,
+            self.fill_trigger_debounce.as_millis()
         );
-        let start = Instant::now();
-        let pending_tx_sigs_toconfirm = self.pending_tx_sigs_toconfirm.clone();
-        let tx_entries: Vec<(&Signature, &PendingTxSigsToconfirm)> =
-            pending_tx_sigs_toconfirm.iter().collect();
-        for i in (0..tx_entries.len()).step_by(TX_CONFIRMATION_BATCH_SIZE) {
-            let tx_sigs_batch = &tx_entries[i..i + TX_CONFIRMATION_BATCH_SIZE];
-            let sigs: Vec<Signature> = tx_sigs_batch
-                .into_iter()
-                .map(|(sig, _pending_tx)| **sig)
-                .collect();
 
-            let fetches = sigs.iter().map(|sig| {
-                self.tx_confirmation_connection
-                    .get_transaction(sig, UiTransactionEncoding::Json)
-            });
-            let txs = futures_util::future::join_all(fetches).await;
-
-            for j in 0..txs.len() {
-                let tx_resp = &txs[j];
-                let tx_confirmation_info = tx_sigs_batch[j];
-                let tx_sig = tx_confirmation_info.0;
-                let tx_age = tx_confirmation_info.1.ts - Instant::now();
-                let node_filled = &tx_confirmation_info.1.node_filled;
-                let tx_type = &tx_confirmation_info.1.tx_type;
-                let fill_tx_id = tx_confirmation_info.1.fill_tx_id;
-
-                match tx_resp {
-                    Ok(tx) => {
-                        log::info!("Tx landed (fill_tx_id: {fill_tx_id}) (tx_type: {tx_type:?}): {tx_sig}, tx age: {} s", tx_age.as_secs());
-                        self.pending_tx_sigs_toconfirm.pop(tx_sig);
-
-                        if matches!(tx_type, TxType::Fill) {
-                            if let Some(meta) = &tx.transaction.meta {
-                                if let OptionSerializer::Some(msgs) = &meta.log_messages {
-                                    let _result =
-                                        self.handle_transaction_logs(&node_filled, msgs).await;
-                                }
+        loop {
+            tokio::select! {
+                trigger = trigger_rx.recv() => {
+                    match trigger {
+                        Some(trigger) => self.handle_fill_trigger(&mut pending_fills, trigger).await,
+                        None => {
+                            log::warn!("{}: fill trigger stream ended", self.name);
+                            break;
+                        }
+                    }
+
+                    // collect the rest of the burst before filling
+                    let debounce = tokio::time::sleep(self.fill_trigger_debounce);
+                    tokio::pin!(debounce);
+                    loop {
+                        tokio::select! {
+                            Some(trigger) = trigger_rx.recv() => {
+                                self.handle_fill_trigger(&mut pending_fills, trigger).await;
                             }
+                            _ = &mut debounce => break,
                         }
+                    }
 
-                        log::info!(
-                            "Confirming tx sigs took: {} ms",
-                            start.elapsed().as_millis()
+                    if !pending_fills.is_empty() {
+                        let (market_indexes, taker_orders) = pending_fills.take();
+                        log::debug!(
+                            "{}: event driven fill for markets {market_indexes:?} (new taker orders: {})",
+                            self.name,
+                            taker_orders.len()
                         );
+                        self.try_fill_markets(Some(&market_indexes), &taker_orders)
+                            .await;
                     }
-                    Err(e) => {
-                        if e.to_string().contains("429") {
-                            log::info!("Confirming tx loop rate limited: {}", e.to_string());
-                            self.confirm_loop_rate_limit_ts = Instant::now();
-                        }
+                }
+                Some(confirmed) = tx_outcomes.recv() => {
+                    self.handle_tx_outcome(confirmed).await;
+                }
+                _ = interval.tick() => {
+                    self.try_fill().await;
+                    self.settle_pnls().await;
+                }
+            }
+        }
+    }
 
-                        log::info!("Tx not found, (fill_tx_id: {fill_tx_id}) (tx_type: {tx_type:?}: {tx_sig}, tx age: {} s", tx_age.as_secs());
-                        if tx_age.as_millis() > TX_TIMEOUT_THRESHOLD_MS {
-                            self.pending_tx_sigs_toconfirm.pop(tx_sig);
-                        }
+    async fn handle_fill_trigger(&self, pending_fills: &mut PendingFills, trigger: FillTrigger) {
+        match trigger {
+            FillTrigger::TakerOrder {
+                user_account,
+                order,
+            } => pending_fills.add_taker_order(user_account, order),
+            FillTrigger::Slot(slot) => {
+                pending_fills.on_slot(slot);
+
+                let mut moved = Vec::new();
+                for market in self.drift_client.get_perp_market_accounts() {
+                    let oracle = match self
+                        .drift_client
+                        .get_oracle_price_data_and_slot_for_perp_market(market.market_index)
+                    {
+                        Some(oracle) => oracle,
+                        None => continue,
+                    };
+                    if pending_fills.update_oracle_price(market.market_index, oracle.data.price) {
+                        moved.push((market, oracle.data));
                     }
                 }
+                if moved.is_empty() {
+                    return;
+                }
+
+                // one DLOB copy for all markets whose oracle moved this slot
+                let mut dlob = self.get_dlob().await;
+                for (market, oracle_price_data) in moved {
+                    let (vamm_bid, vamm_ask) = match (
+                        calculate_bid_price(&market, &oracle_price_data),
+                        calculate_ask_price(&market, &oracle_price_data),
+                    ) {
+                        (Ok(bid), Ok(ask)) => (bid, ask),
+                        _ => continue,
+                    };
+                    let (best_bid, best_ask) = match &mut dlob {
+                        Some(dlob) => dlob.get_best_resting_limit_prices(
+                            market.market_index,
+                            &MarketType::Perp,
+                            slot,
+                            &oracle_price_data,
+                        ),
+                        None => (None, None),
+                    };
+
+                    pending_fills.on_oracle_move(&MarketPrices {
+                        market_index: market.market_index,
+                        oracle_price: oracle_price_data.price,
+                        vamm_bid,
+                        vamm_ask,
+                        best_bid,
+                        best_ask,
+                    });
+                }
+            }
+        }
+    }
+
+    fn record_jito_bundle_stats() {
+        todo!()
+    }
+
+    /// Handles the tx outcomes delivered since the last call
+    async fn handle_tx_outcomes(&mut self) {
+        let mut outcomes = Vec::new();
+        if let Some(tx_outcomes) = &mut self.tx_outcomes {
+            while let Ok(confirmed) = tx_outcomes.try_recv() {
+                outcomes.push(confirmed);
+            }
+        }
+
+        for confirmed in outcomes {
+            self.handle_tx_outcome(confirmed).await;
+        }
+    }
+
+    /// Updates the landing rate and maker stats with the outcome of a sent tx, and handles its logs
+    async fn handle_tx_outcome(&self, confirmed: ConfirmedTx<PendingTxSigsToconfirm>) {
+        let tx_sig = confirmed.signature;
+        let pending_tx = confirmed.metadata;
+        let tx_age = pending_tx.ts.elapsed();
+        let fill_tx_id = pending_tx.fill_tx_id;
+        let tx_type = &pending_tx.tx_type;
+
+        let (executed, logs) = match confirmed.outcome {
+            TxOutcome::Landed { slot, logs } => {
+                log::info!("Tx landed (fill_tx_id: {fill_tx_id}) (tx_type: {tx_type:?}): {tx_sig}, slot: {slot}, tx age: {} s", tx_age.as_secs());
+                (true, logs)
+            }
+            TxOutcome::Failed { slot, error, logs } => {
+                log::warn!("Tx failed (fill_tx_id: {fill_tx_id}) (tx_type: {tx_type:?}): {tx_sig}, slot: {slot}, error: {error}, tx age: {} s", tx_age.as_secs());
+                (false, logs)
+            }
+            TxOutcome::Dropped => {
+                log::info!("Tx dropped (fill_tx_id: {fill_tx_id}) (tx_type: {tx_type:?}): {tx_sig}, tx age: {} s", tx_age.as_secs());
+                self.landing_rate_fee_controller
+                    .lock()
+                    .unwrap()
+                    .record_dropped(&pending_tx.writable_markets);
+                if matches!(tx_type, TxType::Fill) {
+                    self.record_maker_fill_results(&pending_tx.makers, false);
+                }
+                return;
+            }
+            TxOutcome::Evicted => {
+                log::warn!("Tx evicted before its outcome was known (fill_tx_id: {fill_tx_id}) (tx_type: {tx_type:?}): {tx_sig}, tx age: {} s", tx_age.as_secs());
+                // its outcome is unknown, it only stops counting as in flight
+                self.landing_rate_fee_controller
+                    .lock()
+                    .unwrap()
+                    .record_untracked(&pending_tx.writable_markets);
+                return;
+            }
+        };
+
+        // failed txs were included as well, their priority fee was paid
+        self.landing_rate_fee_controller
+            .lock()
+            .unwrap()
+            .record_landed(
+                &pending_tx.writable_markets,
+                pending_tx.priority_fee_lamports,
+                pending_tx.expected_reward_lamports,
+            );
+
+        if matches!(tx_type, TxType::Fill) {
+            self.record_maker_fill_results(&pending_tx.makers, executed);
+            if let Some(logs) = logs {
+                let _result = self
+                    .handle_transaction_logs(&pending_tx.node_filled, &logs)
+                    .await;
             }
+        }
+    }
 
-            self.confirm_loop_running = false;
+    /// Reports to the maker selector whether a fill tx including `makers` landed
+    fn record_maker_fill_results(&self, makers: &[Pubkey], landed: bool) {
+        let mut maker_selector = self.maker_selector.lock().unwrap();
+        for maker in makers {
+            maker_selector.record_fill_result(maker, landed);
         }
     }
 
@@ -551,11 +787,12 @@ where
         );
     }
 
-    /// Return `nodes_to_fill`, `nodes_to_trigger`
-    async fn get_perp_nodes_for_market(
+    /// Return `nodes_to_fill`, `nodes_to_trigger`, orders expiring before `expire_ts` are fillable
+    fn get_perp_nodes_for_market(
         &self,
         market: PerpMarket,
         dlob: &mut DLOB,
+        expire_ts: i64,
     ) -> Option<(Vec<NodeToFill>, Vec<Node>)> {
         let market_index = market.market_index;
 
@@ -581,7 +818,7 @@ where
                     v_bid,
                     v_ask,
                     fill_slot,
-                    self.clock_subscriber.get_unix_ts().await - EXPIRE_ORDER_BUFFER_SEC,
+                    expire_ts,
                     MarketType::Perp,
                     &oracle.data,
                     &state,
@@ -603,10 +840,16 @@ where
     }
 
     /// Check if the node is still throttled, if not, clears it from the throttled_nodes map
-    fn is_throttled_node_still_throttled(&mut self, throttle_key: String) -> bool {
-        if let Some(last_fill_attempt) = self.throttled_nodes.get(&throttle_key.to_string()) {
+    fn is_throttled_node_still_throttled(&self, throttle_key: String) -> bool {
+        let last_fill_attempt = self
+            .throttled_nodes
+            .lock()
+            .unwrap()
+            .get(&throttle_key)
+            .copied();
+        if let Some(last_fill_attempt) = last_fill_attempt {
             let duration = Duration::new(FILL_ORDER_THROTTLE_BACKOFF, 0);
-            if *last_fill_attempt + duration > Instant::now() {
+            if last_fill_attempt + duration > Instant::now() {
                 return true;
             } else {
                 self.clear_throttled_node(throttle_key);
@@ -617,13 +860,17 @@ where
         false
     }
 
-    fn is_dlob_node_throttled(&mut self, dlob_node: &Node) -> bool {
+    fn is_node_throttled(&self, throttle_key: &str) -> bool {
+        self.throttled_nodes
+            .lock()
+            .unwrap()
+            .contains_key(throttle_key)
+    }
+
+    fn is_dlob_node_throttled(&self, dlob_node: &Node) -> bool {
         // first check if the user_account itself is throttled
         let user_account_pubkey = dlob_node.get_user_account();
-        if self
-            .throttled_nodes
-            .contains_key(&user_account_pubkey.to_string())
-        {
+        if self.is_node_throttled(&user_account_pubkey.to_string()) {
             if self.is_throttled_node_still_throttled(user_account_pubkey.to_string()) {
                 return true;
             } else {
@@ -636,7 +883,7 @@ where
             user_account_pubkey,
             dlob_node.get_order().order_id,
         );
-        if self.throttled_nodes.contains_key(&order_sig) {
+        if self.is_node_throttled(&order_sig) {
             if self.is_throttled_node_still_throttled(order_sig) {
                 return true;
             } else {
@@ -647,21 +894,21 @@ where
         false
     }
 
-    fn clear_throttled_node(&mut self, sig: String) {
-        self.throttled_nodes.remove(&sig);
+    fn clear_throttled_node(&self, sig: String) {
+        self.throttled_nodes.lock().unwrap().remove(&sig);
     }
 
-    fn prune_throttled_node(&mut self) {
-        if self.throttled_nodes.len() > THROTTLED_NODE_SIZE_TO_PRUNE {
+    fn prune_throttled_node(&self) {
+        let mut throttled_nodes = self.throttled_nodes.lock().unwrap();
+        if throttled_nodes.len() > THROTTLED_NODE_SIZE_TO_PRUNE {
             let now = Instant::now();
             let duration_threshold = Duration::new(2_u64 * FILL_ORDER_THROTTLE_BACKOFF, 0);
 
-            self.throttled_nodes
-                .retain(|_, v| *v + duration_threshold <= now)
+            throttled_nodes.retain(|_, v| *v + duration_threshold <= now)
         }
     }
 
-    async fn filter_fillable_nodes(&mut self, node_to_fill: &NodeToFill) -> bool {
+    async fn filter_fillable_nodes(&self, node_to_fill: &NodeToFill) -> bool {
         let node = node_to_fill.get_node();
 
         if node.is_vamm_node() {
@@ -683,19 +930,27 @@ where
 
         let now = Instant::now();
         let node_to_fill_signature = get_node_to_fill_signature(node_to_fill);
-        if self.filling_nodes.contains_key(&node_to_fill_signature) {
-            if let Some(time_started_to_fill_node) = self.filling_nodes.get(&node_to_fill_signature)
-            {
-                let duration = Duration::new(FILL_ORDER_THROTTLE_BACKOFF, 0);
-                if *time_started_to_fill_node + duration > now {
-                    // still cooling down on this node, filter it out
-                    return false;
-                }
+        let time_started_to_fill_node = self
+            .filling_nodes
+            .lock()
+            .unwrap()
+            .get(&node_to_fill_signature)
+            .copied();
+        if let Some(time_started_to_fill_node) = time_started_to_fill_node {
+            let duration = Duration::new(FILL_ORDER_THROTTLE_BACKOFF, 0);
+            if time_started_to_fill_node + duration > now {
+                // still cooling down on this node, filter it out
+                return false;
             }
         }
 
         // expired orders that we previously tried to fill
-        if self.expired_nodes_set.contains(&node_to_fill_signature) {
+        if self
+            .expired_nodes_set
+            .lock()
+            .unwrap()
+            .contains(&node_to_fill_signature)
+        {
             return false;
         }
 
@@ -797,7 +1052,9 @@ where
 
         let now = Instant::now();
         let node_to_fill_sig = get_node_to_trigger_signature(node_to_trigger);
-        if let Some(time_started_to_trigger_node) = self.triggering_nodes.get(&node_to_fill_sig) {
+        if let Some(time_started_to_trigger_node) =
+            self.triggering_nodes.lock().unwrap().get(&node_to_fill_sig)
+        {
             let duration = Duration::new(TRIGGER_ORDER_COOLDOWN_MS, 0);
             if *time_started_to_trigger_node + duration > now {
                 return false;
@@ -810,7 +1067,7 @@ where
     /// Return `maker_info`, `taker_user_pubkey`, `taker_user`, `taker_user_slot`, `referrer_info`,
     /// `market_type`
     async fn get_node_fill_info(
-        &mut self,
+        &self,
         node_to_fill: &NodeToFill,
     ) -> Option<(
         Vec<(u64, MakerInfo)>,
@@ -840,8 +1097,20 @@ where
 
             if maker_nodes_map.len() > MAX_MAKERS_PER_FILL {
                 log::info!("selecting from {} makers", maker_nodes_map.len());
-                maker_nodes_map = select_makers(&mut maker_nodes_map);
-                // log::info!("selected: {}", maker_nodes_map.keys)
+                let market_index = node_to_fill.get_node().get_order().market_index;
+                let context = MakerSelectionContext {
+                    oracle_price_data: self
+                        .drift_client
+                        .get_oracle_price_data_and_slot_for_perp_market(market_index)
+                        .map(|oracle| oracle.data)
+                        .unwrap_or_default(),
+                    slot: self.get_max_slot(),
+                };
+                maker_nodes_map = self.maker_selector.lock().unwrap().select_makers(
+                    maker_nodes_map,
+                    MAX_MAKERS_PER_FILL,
+                    &context,
+                );
             }
 
             for (maker_account, maker_nodes) in maker_nodes_map {
@@ -850,26 +1119,19 @@ where
                 if let Some((maker_user_account, slot)) =
                     self.get_user_account_and_slot_from_map(maker_account).await
                 {
-                    let maker_authority = maker_user_account.authority;
-
-                    if let Some(ref mut user_stats_map) = self.user_stats_map {
-                        let user_stats = user_stats_map
-                            .must_get(&maker_authority)
-                            .await
-                            .expect("must get userstats");
-                        if let Some(user_stats) = user_stats {
-                            let maker_user_stats = user_stats.user_stats_account_pubkey;
-
-                            maker_infos.push((
-                                slot,
-                                MakerInfo::new(
-                                    maker_account,
-                                    maker_user_stats,
-                                    maker_user_account,
-                                    Some(*maker_node.get_order()),
-                                ),
-                            ));
-                        }
+                    if let Some(user_stats) = self
+                        .get_user_stats_info(&maker_user_account.authority)
+                        .await
+                    {
+                        maker_infos.push((
+                            slot,
+                            MakerInfo::new(
+                                maker_account,
+                                user_stats.user_stats_account_pubkey,
+                                maker_user_account,
+                                Some(*maker_node.get_order()),
+                            ),
+                        ));
                     }
                 }
             }
@@ -880,69 +1142,91 @@ where
             .get_user_account_and_slot_from_map(taker_user_pubkey)
             .await
         {
-            if let Some(ref mut user_stats_map) = self.user_stats_map {
-                let user_stats = user_stats_map
-                    .must_get(&taker_user_account.authority)
-                    .await
-                    .expect("must get userstats");
-                if let Some(user_stats) = user_stats {
-                    let referrer_info = user_stats.get_referrer_info().expect("get referrer info");
-
-                    return Some((
-                        maker_infos,
-                        taker_user_pubkey,
-                        taker_user_account,
-                        taker_user_account_slot,
-                        referrer_info,
-                        node_to_fill.get_node().get_order().market_type,
-                    ));
-                }
+            if let Some(user_stats) = self
+                .get_user_stats_info(&taker_user_account.authority)
+                .await
+            {
+                return Some((
+                    maker_infos,
+                    taker_user_pubkey,
+                    taker_user_account,
+                    taker_user_account_slot,
+                    user_stats.referrer_info,
+                    node_to_fill.get_node().get_order().market_type,
+                ));
             }
         }
 
         None
     }
 
-    // Returns the number of bytes occupied by this array if it were serialized in compact-u16-format.
-    // NOTE: assumes each element of the array is 1 byte (not sure if this holds?)
-    //
-    // https://docs.solana.com/developing/programming-model/transactions#compact-u16-format
-    //
-    // https://stackoverflow.com/a/69951832
-    //  hex     |  compact-u16
-    //  --------+------------
-    //  0x0000  |  [0x00]
-    //  0x0001  |  [0x01]
-    //  0x007f  |  [0x7f]
-    //  0x0080  |  [0x80 0x01]
-    //  0x3fff  |  [0xff 0x7f]
-    //  0x4000  |  [0x80 0x80 0x01]
-    //  0xc000  |  [0x80 0x80 0x03]
-    //  0xffff  |  [0xff 0xff 0x03])
-    //
-    fn calc_compact_u16_encoded_size<A>(&self, array: &[A], elem_size: Option<usize>) -> usize {
-        let elem_size = elem_size.unwrap_or(1);
-
-        if array.len() > 0x3fff {
-            3 + array.len() * elem_size
-        } else if array.len() > 0x7f {
-            2 + array.len() * elem_size
-        } else {
-            let array_len = array.len();
-            let product = array_len * elem_size;
-            let safe_product = if product == 0 { 1 } else { product };
-            1 + safe_product
+    /// Stats of `authority` if they are already in the user stats map
+    fn get_cached_user_stats_info(&self, authority: &Pubkey) -> Option<UserStatsInfo> {
+        let user_stats_map = self.user_stats_map.read().unwrap();
+        user_stats_map
+            .as_ref()?
+            .get(authority)
+            .map(UserStatsInfo::new)
+    }
+
+    /// Stats of `authority`, subscribing to them first if they are not in the user stats map.
+    ///
+    /// The map is not held during the subscription, so concurrent market passes are not blocked on it
+    async fn get_user_stats_info(&self, authority: &Pubkey) -> Option<UserStatsInfo> {
+        let new_user_stat = {
+            let user_stats_map = self.user_stats_map.read().unwrap();
+            let user_stats_map = user_stats_map.as_ref()?;
+            if let Some(user_stats) = user_stats_map.get(authority) {
+                return Some(UserStatsInfo::new(user_stats));
+            }
+            user_stats_map.new_user_stat(*authority)
+        };
+
+        let mut user_stat = match new_user_stat {
+            Ok(user_stat) => user_stat,
+            Err(err) => {
+                log::error!(
+                    "{}: failed to create user stats for {authority}: {err:?}",
+                    self.name
+                );
+                return None;
+            }
+        };
+        if let Err(err) = user_stat.subscribe(None).await {
+            log::error!(
+                "{}: failed to subscribe user stats for {authority}: {err:?}",
+                self.name
+            );
+            return None;
+        }
+        let user_stats_info = UserStatsInfo::new(&user_stat);
+
+        // another market pass may have added the same stats meanwhile
+        let duplicate = {
+            let mut user_stats_map = self.user_stats_map.write().unwrap();
+            match user_stats_map.as_mut() {
+                Some(user_stats_map) if !user_stats_map.has(authority) => {
+                    user_stats_map.insert(*authority, user_stat);
+                    None
+                }
+                _ => Some(user_stat),
+            }
+        };
+        if let Some(mut duplicate) = duplicate {
+            duplicate.unsubscribe().await;
         }
+
+        Some(user_stats_info)
     }
 
     async fn build_tx_with_maker_infos(
-        &mut self,
+        &self,
         makers: &[MakerInfo],
         param_ixs: &[Instruction],
         node_to_fill: &NodeToFill,
         taker_user: &User,
         referrer_info: &Option<ReferrerInfo>,
-    ) -> SimulateAndGetTxWithCUsResponse {
+    ) -> (SimulateAndGetTxWithCUsResponse, u64) {
         let user_account_pubkey = self.drift_client.wallet().authority();
         let mut builder = self
             .drift_client
@@ -957,7 +1241,10 @@ where
             );
 
         let sig = get_node_to_fill_signature(node_to_fill);
-        self.filling_nodes.insert(sig, Instant::now());
+        self.filling_nodes
+            .lock()
+            .unwrap()
+            .insert(sig, Instant::now());
 
         if self.revert_on_failure.is_some() {
             builder = builder.revert_fill(*user_account_pubkey);
@@ -977,13 +1264,7 @@ where
             ixs.push(builder_ix);
         }
 
-        let recent_blockhash = self
-            .drift_client
-            .backend
-            .rpc_client
-            .get_latest_blockhash()
-            .await
-            .expect("get recent blockhash");
+        let blockhash = self.blockhash_subscriber.blockhash_with_expiry().await;
 
         let mut params = SimulateAndGetTxWithCUsParams {
             connection: self.drift_client.backend.rpc_client.clone(),
@@ -996,7 +1277,7 @@ where
             ixs: ixs.into(),
             cu_limit_multiplier: Some(SIM_CU_ESTIMATE_MULTIPLIER),
             do_simulation: Some(true),
-            recent_blockhash: Some(recent_blockhash),
+            recent_blockhash: Some(blockhash.blockhash),
             dump_tx: None,
         };
 
@@ -1004,23 +1285,7 @@ where
             .await
             .expect("simulate");
 
-        sim_res
-    }
-
-    // Instruction are made of 3 parts:
-    // - index of accounts where program_id resides (1 byte)
-    // - affected accounts (compact-u16-format byte array)
-    // - raw instruction data (compact-u16-format byte array)
-    fn calc_ix_encoded_size(&self, ixs: &[Instruction]) -> usize {
-        let mut sum = 0;
-
-        for ix in ixs {
-            sum += 1
-                + self.calc_compact_u16_encoded_size(&[ix.accounts.len()], Some(1))
-                + self.calc_compact_u16_encoded_size(&[ix.data.len()], Some(1));
-        }
-
-        sum
+        (sim_res, blockhash.last_valid_block_height)
     }
 
     /// Iterates through a tx's logs and handles it appropriately (e.g. throttling users, updating metrics, etc.)
@@ -1030,24 +1295,301 @@ where
         todo!()
     }
 
-    /// Queues up the tx_sig to be confirmed in a slower loop, and have tx logs handled
-    fn register_tx_sig_to_confirm(
-        &mut self,
+    /// Hands the tx_sig to the tx confirmer, its outcome updates landing stats and has tx logs handled
+    async fn register_tx_sig_to_confirm(
+        &self,
         tx_sig: Signature,
+        last_valid_block_height: u64,
         now: Instant,
         node_filled: &[NodeToFill],
+        makers: &[Pubkey],
         fill_tx_id: u16,
         tx_type: TxType,
+        writable_markets: &[Pubkey],
+        priority_fee_lamports: u64,
     ) {
-        self.pending_tx_sigs_toconfirm.put(
+        let num_rewarded = match tx_type {
+            TxType::Fill => node_filled.len(),
+            TxType::Trigger => 1,
+            TxType::SettlePnl => 0,
+        };
+        let expected_reward_lamports = self.estimate_reward_lamports(&tx_type, num_rewarded);
+
+        self.landing_rate_fee_controller
+            .lock()
+            .unwrap()
+            .record_sent(writable_markets);
+        self.tx_confirmer.track(
             tx_sig,
-            PendingTxSigsToconfirm::new(now, node_filled, fill_tx_id, tx_type),
+            last_valid_block_height,
+            PendingTxSigsToconfirm::new(
+                now,
+                node_filled,
+                makers,
+                fill_tx_id,
+                tx_type,
+                writable_markets,
+                priority_fee_lamports,
+                expected_reward_lamports,
+            ),
         );
     }
 
-    fn remove_filling_nodes(&mut self, nodes: &[NodeToFill]) {
+    /// Return the perp market accounts written to when filling or triggering orders in `market_indexes`
+    fn get_writable_perp_markets(&self, market_indexes: &[u16]) -> Vec<Pubkey> {
+        let mut markets = Vec::new();
+        for market_index in market_indexes {
+            if let Some(market) = self.drift_client.get_perp_market_account(*market_index) {
+                if !markets.contains(&market.pubkey) {
+                    markets.push(market.pubkey);
+                }
+            }
+        }
+
+        markets
+    }
+
+    /// Compute unit price from the priority fee subscriber, scaled by the landing rate of `writable_markets`
+    fn get_compute_unit_price(&self, writable_markets: &[Pubkey]) -> u64 {
+        self.landing_rate_fee_controller
+            .lock()
+            .unwrap()
+            .get_compute_unit_price(
+                writable_markets,
+                self.priority_fee_subscriber.get_custom_strategy_result() as u64,
+            )
+    }
+
+    /// Number of sent txs writing to perp market `market_index` that are not yet confirmed or dropped
+    fn get_in_flight_txs(&self, market_index: u16) -> u64 {
+        let landing_rate_fee_controller = self.landing_rate_fee_controller.lock().unwrap();
+        self.get_writable_perp_markets(&[market_index])
+            .iter()
+            .filter_map(|market| landing_rate_fee_controller.get_market_stats(market))
+            .map(|stats| stats.in_flight())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Whether perp market `market_index` has as many unconfirmed txs as allowed
+    fn is_market_at_in_flight_limit(&self, market_index: u16) -> bool {
+        self.get_in_flight_txs(market_index) >= self.max_in_flight_txs_per_market
+    }
+
+    /// Conservative estimate (in lamports) of the keeper reward earned by a landed tx.
+    /// Fills use the time based filler reward lower bound, triggers the flat filler fee.
+    fn estimate_reward_lamports(&self, tx_type: &TxType, num_rewarded: usize) -> u64 {
+        let state_account = self.drift_client.get_state_account();
+        let reward_per_tx_quote = {
+            let state = state_account.read().unwrap();
+            let fee_structure = &state.perp_fee_structure;
+            match tx_type {
+                TxType::Fill => {
+                    fee_structure
+                        .filler_reward_structure
+                        .time_based_reward_lower_bound
+                }
+                TxType::Trigger => fee_structure.flat_filler_fee as u128,
+                TxType::SettlePnl => 0,
+            }
+        };
+        self.quote_to_lamports(reward_per_tx_quote * num_rewarded as u128)
+            .unwrap_or(0)
+    }
+
+    /// Converts a quote amount (QUOTE_PRECISION) to lamports at the SOL oracle price, None without a price
+    fn quote_to_lamports(&self, quote_amount: u128) -> Option<u64> {
+        match self
+            .drift_client
+            .get_oracle_price_data_and_slot_for_spot_market(SOL_SPOT_MARKET_INDEX)
+        {
+            Some(oracle) if oracle.data.price > 0 => Some(
+                (quote_amount * LAMPORTS_PER_SOL as u128 * PRICE_PRECISION
+                    / (oracle.data.price as u128 * QUOTE_PRECISION)) as u64,
+            ),
+            _ => None,
+        }
+    }
+
+    /// Metrics of the expected value check done before sending fills
+    pub fn fill_profitability_metrics(&self) -> FillProfitabilityMetrics {
+        self.fill_profitability_metrics.clone()
+    }
+
+    /// Registry of the bot's counters and gauges
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Estimates what filling `node_to_fill` earns and costs.
+    ///
+    /// The reward is the filler's share of the taker fee for the order's remaining size at the oracle
+    /// price, using the taker's fee tier. The cost is the priority fee of the fill's CU budget plus its
+    /// share of the signature fee and jito tip of the tx it is packed into.
+    /// None if there is no SOL price to convert the reward to lamports.
+    async fn estimate_fill(
+        &self,
+        node_to_fill: &NodeToFill,
+        build_for_bundle: bool,
+    ) -> Option<FillEstimate> {
+        let order = node_to_fill.get_node().get_order();
+        let market = self
+            .drift_client
+            .get_perp_market_account(order.market_index);
+        let oracle_price = self
+            .drift_client
+            .get_oracle_price_data_and_slot_for_perp_market(order.market_index)
+            .map(|oracle| oracle.data.price)
+            .unwrap_or(0);
+
+        let remaining_base = order
+            .base_asset_amount
+            .saturating_sub(order.base_asset_amount_filled);
+        let quote_asset_amount =
+            (remaining_base as i128 * oracle_price as i128 / BASE_PRECISION_I128).unsigned_abs();
+
+        let mut taker_stats = None;
+        let mut has_referrer = false;
+        if let Some((taker, _)) = self
+            .get_user_account_and_slot_from_map(node_to_fill.get_node().get_user_account())
+            .await
+        {
+            // only stats already in the map, ranking should not wait on new subscriptions
+            if let Some(user_stats) = self.get_cached_user_stats_info(&taker.authority) {
+                taker_stats = user_stats.account;
+                has_referrer = user_stats.referrer_info.is_some();
+            }
+        }
+
+        let seconds_since_order =
+            self.get_max_slot().saturating_sub(order.slot) * MS_PER_SLOT / 1_000;
+        let fill_fees = {
+            let state_account = self.drift_client.get_state_account();
+            let state = state_account.read().unwrap();
+            let fee_structure = &state.perp_fee_structure;
+            let taker_tier =
+                determine_user_fee_tier(taker_stats.as_ref(), fee_structure, MarketType::Perp);
+            calculate_fill_fees(
+                quote_asset_amount.min(u64::MAX as u128) as u64,
+                fee_structure,
+                &taker_tier,
+                None,
+                market
+                    .as_ref()
+                    .map(|market| market.fee_adjustment)
+                    .unwrap_or(0),
+                has_referrer,
+                seconds_since_order as i64,
+            )
+        };
+
+        let compute_units = if self.use_burst_cu_limit {
+            BURST_CU_PER_FILL
+        } else {
+            CU_PER_FILL
+        } as u64;
+        // multi maker fills are sent in their own tx
+        let tx_share = if node_to_fill.get_maker_nodes().is_empty() {
+            compute_units as f64 / MAX_CU_PER_TX as f64
+        } else {
+            1.0
+        };
+        let writable_markets = self.get_writable_perp_markets(&[order.market_index]);
+        let (compute_unit_price, tip_lamports) = match (build_for_bundle, &self.bundle_sender) {
+            (true, Some(bundle_sender)) => (0, bundle_sender.calculate_current_tip_amount()),
+            _ => (self.get_compute_unit_price(&writable_markets), 0),
+        };
+
+        let landing_probability = {
+            let landing_rate_fee_controller = self.landing_rate_fee_controller.lock().unwrap();
+            writable_markets
+                .first()
+                .and_then(|market| landing_rate_fee_controller.get_market_stats(market))
+                .and_then(|stats| stats.landing_rate())
+                .unwrap_or(DEFAULT_LANDING_PROBABILITY)
+        };
+
+        Some(FillEstimate {
+            node_to_fill: node_to_fill.clone(),
+            reward_lamports: self.quote_to_lamports(fill_fees.filler_reward as u128)?,
+            cost_lamports: calculate_fill_cost_lamports(
+                compute_units,
+                compute_unit_price,
+                tip_lamports,
+                tx_share,
+            ),
+            landing_probability,
+        })
+    }
+
+    /// Orders `nodes_to_fill` by expected value and drops those below `min_fill_expected_value_lamports`.
+    /// Without a SOL price rewards can't be valued, so all nodes are kept in their original order.
+    async fn rank_fillable_nodes(
+        &self,
+        nodes_to_fill: &[NodeToFill],
+        build_for_bundle: bool,
+    ) -> Vec<NodeToFill> {
+        let mut estimates = Vec::with_capacity(nodes_to_fill.len());
+        for node_to_fill in nodes_to_fill {
+            match self.estimate_fill(node_to_fill, build_for_bundle).await {
+                Some(estimate) => estimates.push(estimate),
+                None => {
+                    log::warn!(
+                        "{}: no SOL oracle price (spot market {SOL_SPOT_MARKET_INDEX}), sending {} fills without an expected value check",
+                        self.name,
+                        nodes_to_fill.len()
+                    );
+                    self.fill_profitability_metrics
+                        .unchecked_fill_passes
+                        .inc_by(1);
+                    return nodes_to_fill.to_vec();
+                }
+            }
+        }
+
+        let (to_fill, skipped) =
+            rank_fills_by_expected_value(estimates, self.min_fill_expected_value_lamports);
+
+        for estimate in &skipped {
+            log::info!(
+                "{}: skipping fill {}: expected value {} lamports (reward: {}, cost: {}, landing probability: {:.2})",
+                self.name,
+                get_node_to_fill_signature(&estimate.node_to_fill),
+                estimate.expected_value_lamports(),
+                estimate.reward_lamports,
+                estimate.cost_lamports,
+                estimate.landing_probability,
+            );
+        }
+
+        let metrics = &self.fill_profitability_metrics;
+        metrics
+            .evaluated_fills
+            .inc_by((to_fill.len() + skipped.len()) as u64);
+        metrics.skipped_fills.inc_by(skipped.len() as u64);
+        metrics.skipped_expected_value_lamports.add(
+            skipped
+                .iter()
+                .map(|estimate| estimate.expected_value_lamports())
+                .sum::<i64>(),
+        );
+        metrics.sent_expected_value_lamports.add(
+            to_fill
+                .iter()
+                .map(|estimate| estimate.expected_value_lamports())
+                .sum::<i64>(),
+        );
+
+        to_fill
+            .into_iter()
+            .map(|estimate| estimate.node_to_fill)
+            .collect()
+    }
+
+    fn remove_filling_nodes(&self, nodes: &[NodeToFill]) {
+        let mut filling_nodes = self.filling_nodes.lock().unwrap();
         for node in nodes {
-            self.filling_nodes.remove(&get_node_to_fill_signature(node));
+            filling_nodes.remove(&get_node_to_fill_signature(node));
         }
     }
 
@@ -1079,10 +1621,12 @@ where
     }
 
     async fn send_fill_tx_and_parse_logs(
-        &mut self,
+        &self,
         fill_tx_id: u16,
         nodes_sent: &[NodeToFill],
+        makers: &[Pubkey],
         tx: VersionedTransaction,
+        last_valid_block_height: u64,
         build_for_bundle: bool,
     ) {
         if let Some(look_up_table_account) = &self.lookup_table_account {
@@ -1091,13 +1635,40 @@ where
 
             let tx_start = Instant::now();
             let tx_sig = tx.signatures[0];
+            let priority_fee_lamports = get_priority_fee_lamports(&tx.message);
+            let market_indexes: Vec<u16> = nodes_sent
+                .iter()
+                .map(|node| node.get_node().get_order().market_index)
+                .collect();
+            let writable_markets = self.get_writable_perp_markets(&market_indexes);
+
+            // track before sending: the market pass may time out while the send is awaited and
+            // an untracked tx would never leave the in-flight count
+            self.register_tx_sig_to_confirm(
+                tx_sig,
+                last_valid_block_height,
+                Instant::now(),
+                nodes_sent,
+                makers,
+                fill_tx_id,
+                TxType::Fill,
+                &writable_markets,
+                priority_fee_lamports,
+            )
+            .await;
 
             if build_for_bundle {
                 self.send_tx_through_jito(&tx, &format!("{fill_tx_id}"), Some(tx_sig))
                     .await;
                 self.remove_filling_nodes(nodes_sent);
             } else if self.can_send_outside_jito() {
-                match self.drift_client.sign_and_send(tx.message, false).await {
+                match self
+                    .drift_client
+                    .backend
+                    .rpc_client
+                    .send_transaction(&tx)
+                    .await
+                {
                     Ok(resp) => {
                         log::info!(
                             "sent tx: {resp}, took: {}ms (fill_tx_id: {fill_tx_id}",
@@ -1109,27 +1680,21 @@ where
                     }
                 }
             }
-
-            self.register_tx_sig_to_confirm(
-                tx_sig,
-                Instant::now(),
-                nodes_sent,
-                fill_tx_id,
-                TxType::Fill,
-            );
         }
     }
 
     async fn fill_multi_maker_perp_nodes(
-        &mut self,
+        &self,
         fill_tx_id: u16,
         node_to_fill: &NodeToFill,
         build_for_bundle: bool,
     ) -> Result<bool, String> {
         let mut ixs = vec![ComputeBudgetInstruction::set_compute_unit_limit(1_400_000)];
         if !build_for_bundle {
+            let writable_markets =
+                self.get_writable_perp_markets(&[node_to_fill.get_node().get_order().market_index]);
             ixs.push(ComputeBudgetInstruction::set_compute_unit_price(
-                self.priority_fee_subscriber.get_custom_strategy_result() as u64,
+                self.get_compute_unit_price(&writable_markets),
             ));
         }
 
@@ -1152,7 +1717,7 @@ where
                 .map(|(_slot, maker_info)| maker_info)
                 .collect();
 
-            let mut sim_res = self
+            let (mut sim_res, mut last_valid_block_height) = self
                 .build_tx_with_maker_infos(
                     &maker_infos_to_use,
                     &ixs,
@@ -1166,7 +1731,7 @@ where
             while tx_accounts > MAX_ACCOUNTS_PER_TX && maker_infos_to_use.len() > 0 {
                 log::info!("(fill_tx_id: {fill_tx_id} attempt {attempt}) Too many accounts, remove 1 and try again (had {} maker and {tx_accounts} accounts)", maker_infos_to_use.len());
                 maker_infos_to_use = maker_infos_to_use[0..maker_infos_to_use.len() - 1].to_vec();
-                sim_res = self
+                (sim_res, last_valid_block_height) = self
                     .build_tx_with_maker_infos(
                         &maker_infos_to_use,
                         &ixs,
@@ -1196,10 +1761,14 @@ where
                         log::info!("dry run, not sending tx (fill_tx_id: {fill_tx_id})");
                     } else {
                         if self.has_enough_sol_to_fill {
+                            let makers: Vec<Pubkey> =
+                                maker_infos_to_use.iter().map(|info| info.maker).collect();
                             self.send_fill_tx_and_parse_logs(
                                 fill_tx_id,
                                 &[node_to_fill.clone()],
+                                &makers,
                                 sim_res.tx,
+                                last_valid_block_height,
                                 build_for_bundle,
                             )
                             .await;
@@ -1216,12 +1785,11 @@ where
 
     /// It's difficult to estimate CU cost of multi maker ix, so we'll just send it in its own transaction
     async fn try_fill_multi_maker_perp_nodes(
-        &mut self,
+        &self,
         node_to_fill: &NodeToFill,
         build_for_bundle: bool,
     ) {
-        let fill_tx_id = self.fill_tx_id;
-        self.fill_tx_id += 1;
+        let fill_tx_id = self.fill_tx_id.fetch_add(1, Ordering::Relaxed);
 
         let mut node_with_market_set = node_to_fill.clone();
         while !self
@@ -1248,7 +1816,7 @@ where
     }
 
     async fn try_bulk_fill_perp_nodes(
-        &mut self,
+        &self,
         nodes_to_fill: &[NodeToFill],
         build_for_bundle: bool,
     ) -> usize {
@@ -1262,8 +1830,8 @@ where
             // }
             market_node_map
                 .entry(market_index)
-                .and_modify(|nodes: &mut Vec<NodeToFill>| nodes.push(node_to_fill.clone()))
-                .or_insert(Vec::new());
+                .or_insert_with(Vec::new)
+                .push(node_to_fill.clone());
         }
 
         for nodes_to_fill_for_market in market_node_map.values() {
@@ -1278,65 +1846,63 @@ where
     }
 
     async fn try_bulk_fill_perp_nodes_for_market(
-        &mut self,
+        &self,
         nodes_to_fill: &[NodeToFill],
         build_for_bundle: bool,
     ) -> Result<usize, String> {
         let drift_client = self.drift_client.clone();
         let user_account_pubkey = drift_client.wallet().authority();
-        let mut builder = drift_client
-            .init_tx(&user_account_pubkey, false)
-            .expect("build tx");
         let mut ixs = vec![ComputeBudgetInstruction::set_compute_unit_limit(1_400_000)];
         if !build_for_bundle {
+            let market_indexes: Vec<u16> = nodes_to_fill
+                .iter()
+                .map(|node| node.get_node().get_order().market_index)
+                .collect();
+            let writable_markets = self.get_writable_perp_markets(&market_indexes);
             ixs.push(ComputeBudgetInstruction::set_compute_unit_price(
-                self.priority_fee_subscriber.get_custom_strategy_result() as u64,
+                self.get_compute_unit_price(&writable_markets),
             ));
         }
 
-        //
-        // At all times, the running Tx size is:
-        // - signatures (compact-u16 array, 64 bytes per elem)
-        // - message header (3 bytes)
-        // - affected accounts (compact-u16 array, 32 bytes per elem)
-        // - previous block hash (32 bytes)
-        // - message instructions (
-        //		- progamIdIdx (1 byte)
-        //		- accountsIdx (compact-u16, 1 byte per elem)
-        //   	- instruction data (compact-u16, 1 byte per elem)
-        //
-        let mut running_tx_size = 0;
+        // the revert ix goes last but is sized in from the start
+        let revert_ixs = if let Some(true) = self.revert_on_failure {
+            drift_client
+                .init_tx(&user_account_pubkey, false)
+                .expect("build tx")
+                .revert_fill(*user_account_pubkey)
+                .instructions()
+                .to_vec()
+        } else {
+            vec![]
+        };
+        let mut base_ixs = ixs.clone();
+        base_ixs.extend(revert_ixs.iter().cloned());
+        let packer = TxPacker::new(
+            *user_account_pubkey,
+            self.lookup_table_account.clone().into_iter().collect(),
+            TxPackerConfig {
+                max_tx_size: Some(MAX_TX_PACK_SIZE),
+                max_accounts: Some(MAX_ACCOUNTS_PER_TX),
+                max_compute_units: Some(MAX_CU_PER_TX as u32),
+            },
+        )
+        .base_instructions(base_ixs, 0);
+        let mut tx_sizer = packer.sizer();
         let mut running_cu_used = 0;
 
-        let mut unique_accounts = HashSet::new();
-        unique_accounts.insert(*self.drift_client.wallet().authority());
-
-        let compute_budget_ix = &ixs[0];
-        for key in compute_budget_ix.accounts.iter() {
-            unique_accounts.insert(key.pubkey);
-        }
-        unique_accounts.insert(compute_budget_ix.program_id);
-
-        // initialize the barebones transactions
-        // signatures
-        running_tx_size += self.calc_compact_u16_encoded_size(&[1], Some(64));
-        // msssage header
-        running_tx_size += 3;
-        // accounts
-        running_tx_size += self.calc_compact_u16_encoded_size(&[unique_accounts.len()], Some(32));
-        // blockhash
-        running_tx_size += 32;
-        running_tx_size += self.calc_ix_encoded_size(&[compute_budget_ix.clone()]);
-
         let mut nodes_sent: Vec<_> = Vec::new();
+        let mut makers_sent: Vec<Pubkey> = Vec::new();
         let mut idx_used = 0;
-        let starting_ixs_size = ixs.len();
-        let fill_tx_id = self.fill_tx_id;
-        self.fill_tx_id += 1;
+        let fill_tx_id = self.fill_tx_id.fetch_add(1, Ordering::Relaxed);
 
         for node_to_fill in nodes_to_fill.iter() {
             // do multi maker fills in a separate tx since they're larger
             if !node_to_fill.get_maker_nodes().is_empty() {
+                let market_index = node_to_fill.get_node().get_order().market_index;
+                if self.is_market_at_in_flight_limit(market_index) {
+                    log::info!("market {market_index} reached the in flight tx limit, skipping multi maker fill");
+                    continue;
+                }
                 self.try_fill_multi_maker_perp_nodes(node_to_fill, build_for_bundle)
                     .await;
                 nodes_sent.push(node_to_fill);
@@ -1362,13 +1928,16 @@ where
 
                 let maker_info: Vec<MakerInfo> =
                     maker_infos.into_iter().map(|(_, info)| info).collect();
-                builder = builder.fill_perp_order(
-                    *user_account_pubkey,
-                    &taker_user,
-                    node_to_fill.get_node().get_order(),
-                    &maker_info,
-                    &referrer_info,
-                );
+                let builder = drift_client
+                    .init_tx(&user_account_pubkey, false)
+                    .expect("build tx")
+                    .fill_perp_order(
+                        *user_account_pubkey,
+                        &taker_user,
+                        node_to_fill.get_node().get_order(),
+                        &maker_info,
+                        &referrer_info,
+                    );
 
                 let instructions = builder.instructions();
                 if instructions.is_empty() {
@@ -1376,31 +1945,6 @@ where
                     break;
                 }
 
-                let sig = get_node_to_fill_signature(node_to_fill);
-                self.filling_nodes.insert(sig, Instant::now());
-
-                // first estimate new tx size with this additional ix and new accounts
-                let mut ix_keys = Vec::new();
-                for ix in ixs.clone() {
-                    ix_keys.extend(ix.accounts);
-                }
-                let mut new_accounts: Vec<AccountMeta> = Vec::new();
-                for ix_key in ix_keys {
-                    if !unique_accounts.contains(&ix_key.pubkey) {
-                        new_accounts.push(ix_key);
-                    }
-                }
-                let new_ix_cost = self.calc_ix_encoded_size(instructions);
-                let additional_accounts_cost = if new_accounts.is_empty() {
-                    0
-                } else {
-                    self.calc_compact_u16_encoded_size(&new_accounts, Some(32)) - 1
-                };
-
-                // We have to use MAX_TX_PACK_SIZE because it appears we cannnot send tx with a
-                // size of exactly 1232 bytes.
-                // Also, some logs may get truncated near the end of the tx, so we need to leave
-                // some room for that.
                 let cu_to_user_per_fill = if self.use_burst_cu_limit {
                     BURST_CU_PER_FILL
                 } else {
@@ -1408,27 +1952,31 @@ where
                 };
 
                 // ensure at least 1 attempted fill
-                if (running_tx_size + new_ix_cost + additional_accounts_cost >= MAX_TX_PACK_SIZE
-                    || running_cu_used + cu_to_user_per_fill >= MAX_CU_PER_TX)
-                    && ixs.len() > starting_ixs_size + 1
-                {
-                    log::info!("Fully packed fill tx (ixs: {}): est. tx size {}, max: {MAX_TX_PACK_SIZE}, est. CU used: expected {}, max {MAX_CU_PER_TX}, (fill_tx_id: {fill_tx_id}", ixs.len(), running_tx_size + new_ix_cost + additional_accounts_cost, running_cu_used + cu_to_user_per_fill);
+                let mut next_tx_sizer = tx_sizer.clone();
+                next_tx_sizer.add_instructions(instructions);
+                let next_cu_used = running_cu_used + cu_to_user_per_fill;
+                if !packer.fits(&next_tx_sizer, next_cu_used as u32) && idx_used > 0 {
+                    log::info!("Fully packed fill tx (ixs: {}): est. tx size {}, max: {MAX_TX_PACK_SIZE}, accounts: {}, max: {MAX_ACCOUNTS_PER_TX}, est. CU used: expected {next_cu_used}, max {MAX_CU_PER_TX}, (fill_tx_id: {fill_tx_id}", ixs.len(), next_tx_sizer.transaction_size(), next_tx_sizer.num_accounts());
                     break;
                 }
 
+                let sig = get_node_to_fill_signature(node_to_fill);
+                self.filling_nodes
+                    .lock()
+                    .unwrap()
+                    .insert(sig, Instant::now());
+
                 // add to tx
-                // log::info!("");
-                let instructions: Vec<Instruction> =
-                    instructions.into_iter().map(|ix| ix.clone()).collect();
-                ixs.extend(instructions);
-                running_tx_size += new_ix_cost + additional_accounts_cost;
-                running_cu_used += cu_to_user_per_fill;
-
-                for new_account in new_accounts {
-                    unique_accounts.insert(new_account.pubkey);
-                }
+                ixs.extend(instructions.iter().cloned());
+                tx_sizer = next_tx_sizer;
+                running_cu_used = next_cu_used;
                 idx_used += 1;
                 nodes_sent.push(node_to_fill);
+                for info in &maker_info {
+                    if !makers_sent.contains(&info.maker) {
+                        makers_sent.push(info.maker);
+                    }
+                }
             }
         }
 
@@ -1440,17 +1988,9 @@ where
             return Ok(0);
         }
 
-        if let Some(true) = self.revert_on_failure {
-            builder.revert_fill(*user_account_pubkey);
-        }
+        ixs.extend(revert_ixs);
 
-        let recent_blockhash = self
-            .drift_client
-            .backend
-            .rpc_client
-            .get_latest_blockhash()
-            .await
-            .expect("get recent blockhash");
+        let blockhash = self.blockhash_subscriber.blockhash_with_expiry().await;
 
         let mut params = SimulateAndGetTxWithCUsParams {
             connection: self.drift_client.backend.rpc_client.clone(),
@@ -1463,7 +2003,7 @@ where
             ixs: ixs.into(),
             cu_limit_multiplier: Some(SIM_CU_ESTIMATE_MULTIPLIER),
             do_simulation: Some(true),
-            recent_blockhash: Some(recent_blockhash),
+            recent_blockhash: Some(blockhash.blockhash),
             dump_tx: None,
         };
 
@@ -1489,7 +2029,9 @@ where
                     self.send_fill_tx_and_parse_logs(
                         fill_tx_id,
                         &nodes_sent,
+                        &makers_sent,
                         sim_res.tx,
+                        blockhash.last_valid_block_height,
                         build_for_bundle,
                     )
                     .await;
@@ -1503,7 +2045,7 @@ where
     }
 
     async fn filter_perp_nodes_for_market(
-        &mut self,
+        &self,
         fillable_nodes: &[NodeToFill],
         triggerable_nodes: &[Node],
     ) -> (Vec<NodeToFill>, Vec<Node>) {
@@ -1537,7 +2079,7 @@ where
     }
 
     async fn execute_fillable_perp_nodes_for_market(
-        &mut self,
+        &self,
         fillable_nodes: &[NodeToFill],
         build_for_bundle: bool,
     ) {
@@ -1546,7 +2088,7 @@ where
     }
 
     async fn execute_triggerable_perp_nodes_for_market(
-        &mut self,
+        &self,
         triggerable_nodes: &[Node],
         build_for_bundle: bool,
     ) {
@@ -1556,6 +2098,13 @@ where
             let user_account = node_to_trigger.get_user_account();
             let user = self.get_user_account_and_slot_from_map(user_account).await;
             let order = node_to_trigger.get_order();
+            if self.is_market_at_in_flight_limit(order.market_index) {
+                log::info!(
+                    "market {} reached the in flight tx limit, skipping triggers",
+                    order.market_index
+                );
+                break;
+            }
             if let Some((user, slot)) = user {
                 log::info!(
                     "trying to trigger (account: {}, slot: {}) order {}",
@@ -1565,11 +2114,15 @@ where
                 );
 
                 let node_sig = get_node_to_trigger_signature(node_to_trigger);
-                self.triggering_nodes.insert(node_sig, Instant::now());
+                self.triggering_nodes
+                    .lock()
+                    .unwrap()
+                    .insert(node_sig, Instant::now());
 
+                let writable_markets = self.get_writable_perp_markets(&[order.market_index]);
                 let mut ixs = vec![ComputeBudgetInstruction::set_compute_unit_limit(1_400_000)];
                 ixs.push(ComputeBudgetInstruction::set_compute_unit_price(
-                    self.priority_fee_subscriber.get_custom_strategy_result() as u64,
+                    self.get_compute_unit_price(&writable_markets),
                 ));
 
                 let mut builder = drift_client
@@ -1583,12 +2136,7 @@ where
 
                 ixs.extend(builder.instructions().to_vec());
 
-                let recent_blockhash = drift_client
-                    .backend
-                    .rpc_client
-                    .get_latest_blockhash()
-                    .await
-                    .expect("get recent blockhash");
+                let blockhash = self.blockhash_subscriber.blockhash_with_expiry().await;
 
                 let mut params = SimulateAndGetTxWithCUsParams {
                     connection: drift_client.backend.rpc_client.clone(),
@@ -1601,7 +2149,7 @@ where
                     ixs: ixs.into(),
                     cu_limit_multiplier: Some(SIM_CU_ESTIMATE_MULTIPLIER),
                     do_simulation: Some(true),
-                    recent_blockhash: Some(recent_blockhash),
+                    recent_blockhash: Some(blockhash.blockhash),
                     dump_tx: None,
                 };
 
@@ -1622,11 +2170,16 @@ where
                             let tx_sig = sim_res.tx.signatures[0];
                             self.register_tx_sig_to_confirm(
                                 tx_sig,
+                                blockhash.last_valid_block_height,
                                 Instant::now(),
                                 &[],
+                                &[],
                                 u16::MAX,
                                 TxType::Trigger,
-                            );
+                                &writable_markets,
+                                get_priority_fee_lamports(&sim_res.tx.message),
+                            )
+                            .await;
 
                             if build_for_bundle {
                                 self.send_tx_through_jito(
@@ -1636,7 +2189,12 @@ where
                                 )
                                 .await;
                             } else {
-                                match drift_client.sign_and_send(sim_res.tx.message, false).await {
+                                match drift_client
+                                    .backend
+                                    .rpc_client
+                                    .send_transaction(&sim_res.tx)
+                                    .await
+                                {
                                     Ok(sig) => {
                                         log::info!("Signature: {sig}");
                                     }
@@ -1713,61 +2271,245 @@ where
         true
     }
 
-    async fn try_fill(&mut self) {
+    async fn try_fill(&self) {
+        self.try_fill_markets(None, &[]).await;
+    }
+
+    /// Fills and triggers nodes in `market_indexes`, all perp markets if `None`.
+    /// `taker_orders` placed since the subscriber's last periodic DLOB build are added to a copy of
+    /// it, so they can be filled without rebuilding the whole DLOB.
+    ///
+    /// Markets are processed concurrently, each bounded by `market_fill_timeout` so a market stuck
+    /// on a slow rpc call does not hold back fills on the others.
+    async fn try_fill_markets(
+        &self,
+        market_indexes: Option<&HashSet<u16>>,
+        taker_orders: &[(Pubkey, Order)],
+    ) {
         let start_time = Instant::now();
-        let mut ran = false;
 
         if !self.has_enough_sol_to_fill {
             log::info!("Not enough SOL to fill, skipping fill");
             return;
         }
 
-        let user = self.drift_client.get_user(None);
-
-        let mut dlob = self.get_dlob().await;
-        self.prune_throttled_node();
-
-        // 1) get all fillable nodes
-        let mut fillable_nodes = Vec::new();
-        let mut triggerable_nodes = Vec::new();
-        for market in self.drift_client.get_perp_market_accounts() {
-            if let Some(ref mut dlob) = dlob {
-                match self.get_perp_nodes_for_market(market, dlob).await {
-                    Some((nodes_to_fill, nodes_to_trigger)) => {
-                        fillable_nodes.extend(nodes_to_fill);
-                        triggerable_nodes.extend(nodes_to_trigger);
-                    }
-                    None => {
-                        log::warn!(
-                            "{}: :x: Failed to get fillable nodes for market {}",
-                            self.name,
-                            market.market_index
-                        );
-                        continue;
-                    }
+        let dlob = self.get_dlob().await.map(|dlob| {
+            let slot = self.get_max_slot();
+            for (user_account, order) in taker_orders {
+                // orders already in the DLOB would be inserted twice
+                if dlob.get_order(order.order_id, *user_account).is_none() {
+                    dlob.insert_order(order, *user_account, slot);
                 }
             }
+            dlob
+        });
+        let dlob = match dlob {
+            Some(dlob) => Mutex::new(dlob),
+            None => {
+                log::warn!("{}: no DLOB to fill from", self.name);
+                return;
+            }
+        };
+        self.prune_throttled_node();
+
+        let build_bundle = self.should_build_for_bundle();
+        let markets_to_fill: Vec<u16> = self
+            .drift_client
+            .get_perp_market_accounts()
+            .iter()
+            .map(|market| market.market_index)
+            .filter(|market_index| match market_indexes {
+                Some(market_indexes) => market_indexes.contains(market_index),
+                None => true,
+            })
+            .collect();
+
+        let expire_ts = self.clock_subscriber.get_unix_ts().await - EXPIRE_ORDER_BUFFER_SEC;
+        let timed_out = fill_markets_concurrently(
+            markets_to_fill,
+            &dlob,
+            self.market_fill_timeout,
+            |market_index, dlob| self.find_market_nodes(market_index, dlob, expire_ts),
+            |market_index, nodes| self.fill_market_nodes(market_index, nodes, build_bundle),
+        )
+        .await;
+        for market_index in timed_out {
+            log::warn!(
+                "{}: fill pass for market {market_index} timed out after {} ms",
+                self.name,
+                self.market_fill_timeout.as_millis()
+            );
         }
 
+        log::debug!(
+            "{}: try_fill took {} ms",
+            self.name,
+            start_time.elapsed().as_millis()
+        );
+    }
+
+    /// Fillable and triggerable nodes of a single perp market, `None` if it should not be filled now
+    fn find_market_nodes(
+        &self,
+        market_index: u16,
+        dlob: &mut DLOB,
+        expire_ts: i64,
+    ) -> Option<(Vec<NodeToFill>, Vec<Node>)> {
+        let in_flight_txs = self.get_in_flight_txs(market_index);
+        if in_flight_txs >= self.max_in_flight_txs_per_market {
+            log::info!(
+                "{}: market {market_index} has {in_flight_txs} txs in flight, skipping fill",
+                self.name
+            );
+            return None;
+        }
+
+        let market = self.drift_client.get_perp_market_account(market_index)?;
+        let nodes = self.get_perp_nodes_for_market(market, dlob, expire_ts);
+        if nodes.is_none() {
+            log::warn!(
+                "{}: :x: Failed to get fillable nodes for market {market_index}",
+                self.name
+            );
+        }
+
+        nodes
+    }
+
+    /// Filters, ranks and sends the fillable and triggerable nodes of a single perp market
+    async fn fill_market_nodes(
+        &self,
+        market_index: u16,
+        (fillable_nodes, triggerable_nodes): (Vec<NodeToFill>, Vec<Node>),
+        build_bundle: bool,
+    ) {
         // filler out nodes that we know can not be filled
         let (filtered_fillable_nodes, filtered_triggerable_nodes) = self
             .filter_perp_nodes_for_market(&fillable_nodes, &triggerable_nodes)
             .await;
         log::debug!(
-            "filtered fillable nodes from {} to {}, filtered triggerable nodes from {} to {}",
+            "market {market_index}: filtered fillable nodes from {} to {}, filtered triggerable nodes from {} to {}",
             fillable_nodes.len(),
             filtered_fillable_nodes.len(),
             triggerable_nodes.len(),
             filtered_triggerable_nodes.len()
         );
 
-        let build_bundle = self.should_build_for_bundle();
+        // most valuable fills first, unprofitable fills are not sent
+        let ranked_fillable_nodes = self
+            .rank_fillable_nodes(&filtered_fillable_nodes, build_bundle)
+            .await;
+        log::debug!(
+            "market {market_index}: ranked fillable nodes, sending {} of {}",
+            ranked_fillable_nodes.len(),
+            filtered_fillable_nodes.len()
+        );
 
-        self.execute_fillable_perp_nodes_for_market(&filtered_fillable_nodes, build_bundle)
+        self.execute_fillable_perp_nodes_for_market(&ranked_fillable_nodes, build_bundle)
             .await;
         self.execute_triggerable_perp_nodes_for_market(&filtered_triggerable_nodes, build_bundle)
             .await;
+    }
+}
+
+/// Runs a fill pass for every market concurrently against a shared `dlob`, each bounded by `timeout`.
+/// Returns the markets whose pass timed out.
+///
+/// The DLOB is only locked while `find_nodes` runs and never across an await, so a market whose
+/// `fill_nodes` stalls does not hold back the others.
+/// A timed out pass is dropped at its current await, so fill txs must be tracked before they are sent.
+async fn fill_markets_concurrently<N, F, Fut>(
+    markets: Vec<u16>,
+    dlob: &Mutex<DLOB>,
+    timeout: Duration,
+    find_nodes: impl Fn(u16, &mut DLOB) -> Option<N>,
+    fill_nodes: F,
+) -> Vec<u16>
+where
+    F: Fn(u16, N) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let find_nodes = &find_nodes;
+    let fill_nodes = &fill_nodes;
+    join_all_with_timeout(markets, timeout, |market_index| async move {
+        let nodes = {
+            let mut dlob = dlob.lock().unwrap();
+            find_nodes(market_index, &mut dlob)
+        };
+        if let Some(nodes) = nodes {
+            fill_nodes(market_index, nodes).await;
+        }
+    })
+    .await
+    .into_iter()
+    .filter_map(|result| result.err())
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::future::pending;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn stalled_market_does_not_hold_back_other_markets() {
+        let dlob = Mutex::new(DLOB::new());
+        let searched = Mutex::new(Vec::new());
+        let filled = Mutex::new(Vec::new());
+
+        let timed_out = fill_markets_concurrently(
+            vec![0, 1, 2],
+            &dlob,
+            Duration::from_millis(200),
+            |market_index, _dlob| {
+                searched.lock().unwrap().push(market_index);
+                Some(market_index)
+            },
+            |market_index, nodes| {
+                let filled = &filled;
+                async move {
+                    // market 1 hangs, e.g. on an rpc call, after taking its nodes
+                    if market_index == 1 {
+                        pending::<()>().await;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                    filled.lock().unwrap().push(nodes);
+                }
+            },
+        )
+        .await;
+
+        let mut searched = searched.into_inner().unwrap();
+        searched.sort();
+        let mut filled = filled.into_inner().unwrap();
+        filled.sort();
+
+        assert_eq!(searched, vec![0, 1, 2]);
+        assert_eq!(filled, vec![0, 2]);
+        assert_eq!(timed_out, vec![1]);
+        // the stalled pass released the DLOB
+        assert!(dlob.try_lock().is_ok());
+    }
+
+    #[tokio::test]
+    async fn markets_without_nodes_are_not_filled() {
+        let dlob = Mutex::new(DLOB::new());
+        let filled = Mutex::new(Vec::new());
+
+        let timed_out = fill_markets_concurrently(
+            vec![0, 1],
+            &dlob,
+            Duration::from_secs(1),
+            |market_index, _dlob| (market_index != 0).then_some(market_index),
+            |market_index, _nodes| {
+                let filled = &filled;
+                async move { filled.lock().unwrap().push(market_index) }
+            },
+        )
+        .await;
 
-        ran = true;
+        assert!(timed_out.is_empty());
+        assert_eq!(filled.into_inner().unwrap(), vec![1]);
     }
 }
@@ -1,6 +1,7 @@
 use std::time::Instant;
 
 use sdk::dlob::dlob::NodeToFill;
+use solana_sdk::pubkey::Pubkey;
 
 #[derive(Debug, Clone)]
 pub(crate) enum TxType {
@@ -13,17 +14,36 @@ pub(crate) enum TxType {
 pub(crate) struct PendingTxSigsToconfirm {
     pub(crate) ts: Instant,
     pub(crate) node_filled: Vec<NodeToFill>,
+    /// maker user accounts included in the tx
+    pub(crate) makers: Vec<Pubkey>,
     pub(crate) fill_tx_id: u16,
     pub(crate) tx_type: TxType,
+    /// market accounts the tx writes to, used to track landing rate per market
+    pub(crate) writable_markets: Vec<Pubkey>,
+    pub(crate) priority_fee_lamports: u64,
+    pub(crate) expected_reward_lamports: u64,
 }
 
 impl PendingTxSigsToconfirm {
-    pub fn new(ts: Instant, node_filled: &[NodeToFill], fill_tx_id: u16, tx_type: TxType) -> Self {
+    pub fn new(
+        ts: Instant,
+        node_filled: &[NodeToFill],
+        makers: &[Pubkey],
+        fill_tx_id: u16,
+        tx_type: TxType,
+        writable_markets: &[Pubkey],
+        priority_fee_lamports: u64,
+        expected_reward_lamports: u64,
+    ) -> Self {
         Self {
             ts,
             node_filled: node_filled.to_vec(),
+            makers: makers.to_vec(),
             fill_tx_id,
             tx_type,
+            writable_markets: writable_markets.to_vec(),
+            priority_fee_lamports,
+            expected_reward_lamports,
         }
     }
 }
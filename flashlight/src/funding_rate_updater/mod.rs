@@ -1,20 +1,21 @@
 use std::{
-    collections::HashMap,
+    collections::BTreeSet,
     time::{Instant, SystemTime, UNIX_EPOCH},
 };
 
 use drift::{
     math::helpers::on_the_hour_update,
-    state::{paused_operations::PerpOperation, perp_market::MarketStatus},
+    state::{
+        paused_operations::PerpOperation,
+        perp_market::{MarketStatus, PerpMarket},
+    },
 };
 use log::{error, info, warn};
 use sdk::{
     config::DriftEnv,
-    constants::perp_markets::read_perp_markets,
     drift_client::DriftClient,
     priority_fee::{
         drift_priority_fee_method::DriftMarketInfo,
-        helius_priority_fee_method::HeliusPriorityLevel,
         priority_fee_subscriber_map::PriorityFeeSubscriberMap,
         types::PriorityFeeSubscriberMapConfig,
     },
@@ -23,7 +24,7 @@ use sdk::{
 };
 use solana_sdk::{
     address_lookup_table_account::AddressLookupTableAccount,
-    compute_budget::ComputeBudgetInstruction, instruction::InstructionError, pubkey::Pubkey,
+    compute_budget::ComputeBudgetInstruction, instruction::InstructionError,
     transaction::TransactionError,
 };
 use tokio::{sync::oneshot, task::JoinHandle, time::interval, time::Duration};
@@ -47,6 +48,11 @@ const ERROR_CODES_CAN_RETRY: &[u32] = &[
 
 pub const CU_EST_MULTIPLIER: f64 = 1.4;
 
+/// Maximum number of `update_funding_rate` ixs packed into a single tx
+pub const MAX_FUNDING_UPDATES_PER_TX: usize = 5;
+
+const DEFAULT_PRIORITY_FEE_MICRO_LAMPORTS: u64 = 10_000;
+
 pub struct FundingRateUpdaterBot<T: AccountProvider> {
     name: String,
     dry_run: bool,
@@ -65,18 +71,13 @@ pub struct FundingRateUpdaterBot<T: AccountProvider> {
 
 impl<T: AccountProvider> FundingRateUpdaterBot<T> {
     pub fn new(drift_client: DriftClient<T>, config: BaseBotConfig) -> Self {
-        let perp_markets = read_perp_markets(DriftEnv::Devnet);
-        let drift_markets = perp_markets
-            .iter()
-            .map(|perp_market| DriftMarketInfo {
-                market_type: "perp".to_string(),
-                market_index: perp_market.market_index,
-            })
-            .collect();
+        let env = DriftEnv::from(drift_client.context());
+        let drift_markets = to_drift_markets(&Self::active_market_indexes(&drift_client));
         let priority_config = PriorityFeeSubscriberMapConfig {
             frequency_ms: Some(10_000),
             drift_markets: Some(drift_markets),
-            drift_priority_fee_endpoint: get_drift_priority_fee_endpoint(DriftEnv::Devnet),
+            drift_priority_fee_endpoint: get_drift_priority_fee_endpoint(env),
+            default_strategy: None,
         };
 
         Self {
@@ -143,6 +144,47 @@ impl<T: AccountProvider> FundingRateUpdaterBot<T> {
         Ok(())
     }
 
+    /// Indexes of perp markets currently in the client's market map that can still be funded
+    fn active_market_indexes(drift_client: &DriftClient<T>) -> BTreeSet<u16> {
+        drift_client
+            .get_perp_market_accounts()
+            .iter()
+            .filter(|perp_market| {
+                !matches!(
+                    perp_market.status,
+                    MarketStatus::Settlement | MarketStatus::Delisted
+                )
+            })
+            .map(|perp_market| perp_market.market_index)
+            .collect()
+    }
+
+    /// Sync the priority fee subscription with newly listed or delisted markets
+    fn refresh_markets(&mut self) {
+        let active = Self::active_market_indexes(&self.drift_client);
+        let known: BTreeSet<u16> = self
+            .priority_fee_subscriber_map
+            .get_drift_markets()
+            .unwrap_or_default()
+            .iter()
+            .map(|market| market.market_index)
+            .collect();
+
+        if active == known {
+            return;
+        }
+
+        for market_index in active.difference(&known) {
+            info!("{} Perp market {market_index} listed", self.name);
+        }
+        for market_index in known.difference(&active) {
+            info!("{} Perp market {market_index} delisted", self.name);
+        }
+
+        self.priority_fee_subscriber_map
+            .update_market_type_and_index(to_drift_markets(&active));
+    }
+
     pub async fn try_update_funding_rate(&mut self) -> Result<(), String> {
         if self.in_progress {
             info!(
@@ -155,15 +197,28 @@ impl<T: AccountProvider> FundingRateUpdaterBot<T> {
         let _start = Instant::now();
         self.in_progress = true;
 
-        let mut perp_market_and_oracle_data = HashMap::new();
+        self.refresh_markets();
+        let result = self.update_due_markets().await;
 
-        let perp_market_accounts = self.drift_client.get_perp_market_accounts();
-        for market_account in perp_market_accounts {
-            perp_market_and_oracle_data.insert(market_account.market_index, market_account);
-        }
+        self.in_progress = false;
+        result
+    }
 
-        for (_index, perp_market) in perp_market_and_oracle_data {
-            if perp_market.status == MarketStatus::Initialized {
+    async fn update_due_markets(&self) -> Result<(), String> {
+        let mut perp_markets = self.drift_client.get_perp_market_accounts();
+        perp_markets.sort_by_key(|perp_market| perp_market.market_index);
+
+        let current_ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs() as i64;
+
+        let mut due_markets = Vec::new();
+        for perp_market in perp_markets {
+            if matches!(
+                perp_market.status,
+                MarketStatus::Initialized | MarketStatus::Settlement | MarketStatus::Delisted
+            ) {
                 info!(
                     "{} Skipping perp market {} because market status = {:?}",
                     self.name, perp_market.market_index, perp_market.status
@@ -177,7 +232,9 @@ impl<T: AccountProvider> FundingRateUpdaterBot<T> {
                     String::from_utf8(perp_market.name.to_vec()).map_err(|e| e.to_string())?;
                 warn!(
                     "{} Update funding paused for market: {} {}, skipping",
-                    self.name, perp_market.market_index, market_str
+                    self.name,
+                    perp_market.market_index,
+                    market_str.trim()
                 );
                 continue;
             }
@@ -190,17 +247,20 @@ impl<T: AccountProvider> FundingRateUpdaterBot<T> {
                 continue;
             }
 
-            let current_ts = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("Time went backwards")
-                .as_secs() as i64;
-
-            let time_remaining_til_update = on_the_hour_update(
+            let time_remaining_til_update = match on_the_hour_update(
                 current_ts,
                 perp_market.amm.last_funding_rate_ts,
                 perp_market.amm.funding_period,
-            )
-            .expect("");
+            ) {
+                Ok(time_remaining) => time_remaining,
+                Err(e) => {
+                    warn!(
+                        "{} Perp market {}: failed to compute time until funding update: {e}, skipping",
+                        self.name, perp_market.market_index
+                    );
+                    continue;
+                }
+            };
 
             info!(
                 "{} Perp market {}: time_remaining_til_update={}",
@@ -215,69 +275,100 @@ impl<T: AccountProvider> FundingRateUpdaterBot<T> {
                     perp_market.amm.funding_period,
                     perp_market.amm.last_funding_rate_ts + perp_market.amm.funding_period
                 );
+                due_markets.push(perp_market);
+            }
+        }
+
+        for batch in due_markets.chunks(MAX_FUNDING_UPDATES_PER_TX) {
+            if self.send_or_log(batch).await || batch.len() == 1 {
+                continue;
+            }
 
-                self.send_with_retry(perp_market.market_index, &perp_market.amm.oracle)
-                    .await?;
+            // one failing market shouldn't hold back the rest of the batch
+            warn!(
+                "{} Batched funding update failed for markets {:?}, sending individually",
+                self.name,
+                market_indexes(batch)
+            );
+            for perp_market in batch {
+                self.send_or_log(std::slice::from_ref(perp_market)).await;
             }
         }
 
         Ok(())
     }
 
-    async fn send_with_retry(&self, market_index: u16, oracle: &Pubkey) -> Result<(), String> {
-        let pfs = self
-            .priority_fee_subscriber_map
-            .get_priority_fees("perp", market_index as u64);
-        let mut micro_lamports = 10_000;
-        if let Some(pfs) = pfs {
-            if let Some(lamport) = pfs.priority_fee_level.get(&HeliusPriorityLevel::Medium) {
-                micro_lamports = *lamport;
+    /// `send_with_retry`, logging errors so the remaining markets are still updated
+    async fn send_or_log(&self, perp_markets: &[PerpMarket]) -> bool {
+        match self.send_with_retry(perp_markets).await {
+            Ok(sent) => sent,
+            Err(e) => {
+                error!(
+                    "{} Funding update failed for markets {:?}: {e}",
+                    self.name,
+                    market_indexes(perp_markets)
+                );
+                false
             }
         }
+    }
+
+    /// Send a funding update for `perp_markets` in one tx, returns whether it landed in simulation and was sent
+    async fn send_with_retry(&self, perp_markets: &[PerpMarket]) -> Result<bool, String> {
+        let micro_lamports = perp_markets
+            .iter()
+            .filter_map(|perp_market| {
+                self.priority_fee_subscriber_map
+                    .get_priority_fee("perp", perp_market.market_index as u64)
+            })
+            .max()
+            .unwrap_or(DEFAULT_PRIORITY_FEE_MICRO_LAMPORTS);
+        let market_indexes = market_indexes(perp_markets);
 
         let max_retries = 30;
         for i in 0..max_retries {
             info!(
-                "{} Funding rate update on market {market_index}, attempt: {}/{max_retries}",
+                "{} Funding rate update on markets {market_indexes:?}, attempt: {}/{max_retries}",
                 self.name,
                 i + 1
             );
 
-            let result = self.send_txs(micro_lamports, market_index, oracle).await?;
-            // success
-            if result.0 {
-                break;
+            let (success, can_retry) = self.send_txs(micro_lamports, perp_markets).await?;
+            if success {
+                return Ok(true);
             }
-            // can retry
-            if result.1 {
-                info!("{} Retrying market {market_index} in 1s...", self.name);
-                continue;
-            } else {
+            if !can_retry {
                 break;
             }
+
+            info!("{} Retrying markets {market_indexes:?} in 1s...", self.name);
+            tokio::time::sleep(Duration::from_secs(1)).await;
         }
 
-        Ok(())
+        Ok(false)
     }
 
     async fn send_txs(
         &self,
         micro_lamports: u64,
-        market_index: u16,
-        oracle: &Pubkey,
+        perp_markets: &[PerpMarket],
     ) -> Result<(bool, bool), String> {
+        let market_indexes = market_indexes(perp_markets);
+
         let mut ixs = Vec::new();
         ixs.push(ComputeBudgetInstruction::set_compute_unit_limit(1_400_000));
         ixs.push(ComputeBudgetInstruction::set_compute_unit_price(
             micro_lamports,
         ));
 
-        let ix = self
-            .drift_client
-            .get_update_funding_rate_ix(market_index, oracle)
-            .await
-            .expect("failed to get instruction of update_funding_rate");
-        ixs.push(ix);
+        for perp_market in perp_markets {
+            let ix = self
+                .drift_client
+                .get_update_funding_rate_ix(perp_market.market_index, &perp_market.amm.oracle)
+                .await
+                .map_err(|e| e.to_string())?;
+            ixs.push(ix);
+        }
 
         let recent_blockhash = self
             .drift_client
@@ -285,7 +376,7 @@ impl<T: AccountProvider> FundingRateUpdaterBot<T> {
             .rpc_client
             .get_latest_blockhash()
             .await
-            .expect("get recent blockhash");
+            .map_err(|e| e.to_string())?;
         let lookup_table_account = if let Some(lookup) = &self.lookup_table_account {
             lookup.clone()
         } else {
@@ -306,26 +397,34 @@ impl<T: AccountProvider> FundingRateUpdaterBot<T> {
         .await?;
 
         info!(
-            "{} UpdateFundingRate estimated {} CUs for market: {market_index}",
+            "{} UpdateFundingRate estimated {} CUs for markets: {market_indexes:?}",
             self.name, sim_result.cu_estimate
         );
 
-        if let Some(sim_error) = sim_result.sim_error {
-            if let TransactionError::InstructionError(code, e) = sim_error {
-                if let InstructionError::Custom(custom_err) = e {
-                    if ERROR_CODES_TO_SUPPRESS.contains(&custom_err) {
-                        error!("{} Sim error (suppressed) on market: {market_index}, Error Code: {code} {e}", self.name);
-                    } else {
-                        error!("{} Sim error (not suppressed) on market: {market_index}, Error Code: {code} {e}", self.name);
-                    }
-                }
-
-                if let InstructionError::Custom(_custom_err) = e {
-                    return Ok((false, true));
+        if let Some(TransactionError::InstructionError(code, e)) = sim_result.sim_error {
+            if let InstructionError::Custom(custom_err) = e {
+                if ERROR_CODES_TO_SUPPRESS.contains(&custom_err) {
+                    error!("{} Sim error (suppressed) on markets: {market_indexes:?}, Error Code: {code} {e}", self.name);
                 } else {
-                    return Ok((false, false));
+                    error!("{} Sim error (not suppressed) on markets: {market_indexes:?}, Error Code: {code} {e}", self.name);
                 }
+
+                return Ok((false, ERROR_CODES_CAN_RETRY.contains(&custom_err)));
             }
+
+            error!(
+                "{} Sim error on markets: {market_indexes:?}, Error Code: {code} {e}",
+                self.name
+            );
+            return Ok((false, false));
+        }
+
+        if self.dry_run {
+            info!(
+                "{} dry run, not sending UpdateFundingRate for markets: {market_indexes:?}",
+                self.name
+            );
+            return Ok((true, false));
         }
 
         let send_tx_start = Instant::now();
@@ -336,7 +435,7 @@ impl<T: AccountProvider> FundingRateUpdaterBot<T> {
             .map_err(|e| e.to_string())?;
 
         info!(
-            "{} UpdateFundingRate for market: {market_index}, tx sent in {}: https://solana.fm/tx/{}",
+            "{} UpdateFundingRate for markets: {market_indexes:?}, tx sent in {}: https://solana.fm/tx/{}",
             self.name,
             send_tx_start.elapsed().as_millis(),
             tx_sig.to_string()
@@ -345,3 +444,20 @@ impl<T: AccountProvider> FundingRateUpdaterBot<T> {
         Ok((true, true))
     }
 }
+
+fn to_drift_markets(market_indexes: &BTreeSet<u16>) -> Vec<DriftMarketInfo> {
+    market_indexes
+        .iter()
+        .map(|market_index| DriftMarketInfo {
+            market_type: "perp".to_string(),
+            market_index: *market_index,
+        })
+        .collect()
+}
+
+fn market_indexes(perp_markets: &[PerpMarket]) -> Vec<u16> {
+    perp_markets
+        .iter()
+        .map(|perp_market| perp_market.market_index)
+        .collect()
+}
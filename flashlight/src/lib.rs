@@ -3,8 +3,10 @@
 // pub use types::*;
 
 pub mod bundle_sender;
+pub mod cli;
 pub mod config;
 pub mod error;
+pub mod fallback_liquidity;
 pub mod filler;
 pub mod funding_rate_updater;
 pub mod maker_selection;
@@ -3,6 +3,10 @@ use std::{env, sync::Arc, time::Duration};
 use clap::{Parser, Subcommand};
 use dotenv::dotenv;
 use flashlight::{
+    cli::{
+        account::{self, AccountCommands},
+        order::{self, OrderCommands},
+    },
     config::{BaseBotConfig, FillerConfig, GlobalConfig},
     filler::FillerBot,
     funding_rate_updater::FundingRateUpdaterBot,
@@ -34,7 +38,47 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Initialize user
-    InitUser {},
+    InitUser {
+        /// sub-account to initialize, must be the next unused id
+        #[arg(long, default_value_t = 0)]
+        sub_account_id: u16,
+
+        /// name of the sub-account
+        #[arg(long, default_value = "Main Account")]
+        name: String,
+    },
+
+    /// Deposit collateral into a sub-account
+    Deposit {
+        /// spot market of the deposited token
+        #[arg(long)]
+        market_index: u16,
+
+        /// token amount, e.g. 10.5
+        #[arg(long)]
+        amount: f64,
+
+        #[arg(long, default_value_t = 0)]
+        sub_account_id: u16,
+    },
+
+    /// Withdraw collateral from a sub-account
+    Withdraw {
+        /// spot market of the withdrawn token
+        #[arg(long)]
+        market_index: u16,
+
+        /// token amount, e.g. 10.5
+        #[arg(long)]
+        amount: f64,
+
+        #[arg(long, default_value_t = 0)]
+        sub_account_id: u16,
+
+        /// don't borrow if the withdraw exceeds the deposit
+        #[arg(long)]
+        reduce_only: bool,
+    },
 
     /// Just In Time Auction Bot
     Jit {},
@@ -47,6 +91,31 @@ enum Commands {
 
     /// Enable Triggering bot
     Trigger {},
+
+    /// Inspect drift user accounts
+    Account {
+        #[command(subcommand)]
+        command: AccountCommands,
+    },
+
+    /// Place, modify and cancel orders
+    Order {
+        #[command(subcommand)]
+        command: OrderCommands,
+    },
+}
+
+impl Commands {
+    /// Bots act on behalf of the wallet's default sub-account
+    fn needs_user(&self) -> bool {
+        matches!(
+            self,
+            Commands::Jit {}
+                | Commands::Filler {}
+                | Commands::FundingRateUpdater {}
+                | Commands::Trigger {}
+        )
+    }
 }
 
 #[tokio::main]
@@ -70,7 +139,9 @@ async fn main() {
         DriftClient::new(Context::DevNet, account_provider, &wallet)
             .await
             .expect("fail to construct drift client");
-    drift_client.add_user(0).await.expect("add user");
+    if cli.command.needs_user() {
+        drift_client.add_user(0).await.expect("add user");
+    }
     drift_client
         .subscribe()
         .await
@@ -90,13 +161,47 @@ async fn main() {
     info!("SOL balance: {}", lamports_balance / 10 * 9);
 
     match cli.command {
-        Commands::InitUser {} => {
-            // let keypair = read_keypair_file(&private_key).expect("read keypair");
-            // let secret_key_slices = keypair.secret().to_bytes();
-            // let key = bs58::encode(keypair.secret()).into_string();
-            // let key = String::from_utf8(secret_key_slices.to_vec()).unwrap();
-
-            // println!("{:?}", wallet.signer());
+        Commands::InitUser {
+            sub_account_id,
+            name,
+        } => match drift_client.initialize_user(sub_account_id, &name).await {
+            Ok(sig) => info!("Initialized sub account {sub_account_id}: {sig}"),
+            Err(e) => exit_with_error(e),
+        },
+        Commands::Deposit {
+            market_index,
+            amount,
+            sub_account_id,
+        } => {
+            let amount = match to_token_amount(&drift_client, market_index, amount) {
+                Ok(amount) => amount,
+                Err(e) => exit_with_error(e),
+            };
+            match drift_client
+                .deposit(amount, market_index, sub_account_id, None)
+                .await
+            {
+                Ok(sig) => info!("Deposited {amount} into spot market {market_index}: {sig}"),
+                Err(e) => exit_with_error(e),
+            }
+        }
+        Commands::Withdraw {
+            market_index,
+            amount,
+            sub_account_id,
+            reduce_only,
+        } => {
+            let amount = match to_token_amount(&drift_client, market_index, amount) {
+                Ok(amount) => amount,
+                Err(e) => exit_with_error(e),
+            };
+            match drift_client
+                .withdraw(amount, market_index, sub_account_id, Some(reduce_only))
+                .await
+            {
+                Ok(sig) => info!("Withdrew {amount} from spot market {market_index}: {sig}"),
+                Err(e) => exit_with_error(e),
+            }
         }
         Commands::Jit {} => {}
         Commands::Filler {} => {
@@ -134,7 +239,7 @@ async fn main() {
 
             bot.init().await;
 
-            bot.start_interval_loop().await;
+            bot.start_event_loop().await;
         }
         Commands::FundingRateUpdater {} => {
             let config = BaseBotConfig {
@@ -147,14 +252,24 @@ async fn main() {
             let mut bot: FundingRateUpdaterBot<RpcAccountProvider> =
                 FundingRateUpdaterBot::new(drift_client, config);
             if let Err(e) = bot.init().await {
-                println!("{e}");
+                exit_with_error(e);
             }
 
             if let Err(e) = bot
                 .start_interval_loop(Duration::from_secs(2).as_millis() as u64)
                 .await
             {
-                println!("{e}");
+                exit_with_error(e);
+            }
+        }
+        Commands::Account { command } => {
+            if let Err(e) = account::run(&drift_client, command).await {
+                exit_with_error(e);
+            }
+        }
+        Commands::Order { command } => {
+            if let Err(e) = order::run(&drift_client, command).await {
+                exit_with_error(e);
             }
         }
         Commands::Trigger {} => {
@@ -162,7 +277,7 @@ async fn main() {
                 bot_id: "trigger".to_string(),
                 dry_run: true,
                 metrics_port: Some(9465),
-                run_once: Some(true),
+                run_once: Some(false),
             };
 
             let mut user_map = UserMap::new(CommitmentConfig::confirmed(), &endpoint, true, None);
@@ -171,10 +286,40 @@ async fn main() {
             let mut bot: TriggerBot =
                 TriggerBot::new(Arc::new(drift_client), slot_subscriber, user_map, config);
             if let Err(e) = bot.init().await {
-                println!("{e}");
+                exit_with_error(e);
+            }
+
+            if let Err(e) = bot.start_interval_loop().await {
+                exit_with_error(e);
             }
 
-            bot.start_interval_loop().await;
+            tokio::signal::ctrl_c().await.expect("listen for ctrl-c");
+            if let Err(e) = bot.reset().await {
+                exit_with_error(e);
+            }
         }
     }
 }
+
+/// Report a failed CLI command on stderr and exit with a non-zero status
+fn exit_with_error(e: impl std::fmt::Display) -> ! {
+    eprintln!("error: {e}");
+    std::process::exit(1);
+}
+
+/// Convert a UI token amount into the spot market's token precision
+fn to_token_amount(
+    drift_client: &DriftClient<RpcAccountProvider>,
+    market_index: u16,
+    amount: f64,
+) -> Result<u64, String> {
+    let spot_market = drift_client
+        .get_spot_market_account(market_index)
+        .ok_or_else(|| format!("spot market {market_index} not found"))?;
+
+    if !amount.is_finite() || amount <= 0.0 {
+        return Err(format!("invalid amount: {amount}"));
+    }
+
+    Ok((amount * 10_f64.powi(spot_market.decimals as i32)).round() as u64)
+}
@@ -1,69 +1,234 @@
 use std::collections::HashMap;
 
-use sdk::{
-    dlob::dlob_node::{DLOBNode, Node},
-    math::util::div_ceil,
-};
+use drift::{controller::position::PositionDirection, state::oracle::OraclePriceData};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use sdk::dlob::dlob_node::{DLOBNode, Node};
 use solana_sdk::pubkey::Pubkey;
 
-use crate::filler::MAX_MAKERS_PER_FILL;
-
-const PROBABILITY_PRECISION: u64 = 1000;
-
-pub fn select_makers(
-    maker_node_map: &mut HashMap<Pubkey, Vec<Node>>,
-) -> HashMap<Pubkey, Vec<Node>> {
-    let mut selected_makers = HashMap::new();
-
-    while selected_makers.len() < MAX_MAKERS_PER_FILL && !maker_node_map.is_empty() {
-        let maker = select_maker(maker_node_map);
-        match maker {
-            Some(maker) => {
-                if let Some(maker_nodes) = maker_node_map.get(&maker) {
-                    selected_makers.insert(maker, maker_nodes.to_vec());
-                    maker_node_map.remove(&maker);
-                }
-            }
-            None => break,
+use crate::types::MakerSelectionStrategy;
+
+/// Market state the makers of a fill are selected against
+#[derive(Debug, Clone, Copy)]
+pub struct MakerSelectionContext {
+    pub oracle_price_data: OraclePriceData,
+    pub slot: u64,
+}
+
+/// Picks which makers are included in a multi maker fill
+pub trait MakerSelector: Send + Sync {
+    /// Selects up to `max_makers` makers out of `maker_node_map`, keyed by maker user account
+    fn select_makers(
+        &mut self,
+        maker_node_map: HashMap<Pubkey, Vec<Node>>,
+        max_makers: usize,
+        context: &MakerSelectionContext,
+    ) -> HashMap<Pubkey, Vec<Node>>;
+
+    /// Records whether a fill tx including `maker` landed
+    fn record_fill_result(&mut self, _maker: &Pubkey, _landed: bool) {}
+}
+
+/// Creates the selector for `strategy`, random choices are reproducible when `seed` is set
+pub fn new_maker_selector(
+    strategy: MakerSelectionStrategy,
+    seed: Option<u64>,
+) -> Box<dyn MakerSelector> {
+    match strategy {
+        MakerSelectionStrategy::LiquidityWeighted => Box::new(LiquidityWeightedSelector::new(seed)),
+        MakerSelectionStrategy::BestPrice => Box::new(BestPriceSelector),
+        MakerSelectionStrategy::FillSuccess => Box::new(FillSuccessSelector::default()),
+        MakerSelectionStrategy::RotateThrottled => Box::new(RotateThrottledSelector::default()),
+    }
+}
+
+/// Random makers, each picked with probability proportional to its remaining liquidity
+pub struct LiquidityWeightedSelector {
+    rng: StdRng,
+}
+
+impl LiquidityWeightedSelector {
+    pub fn new(seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        Self { rng }
+    }
+}
+
+impl MakerSelector for LiquidityWeightedSelector {
+    fn select_makers(
+        &mut self,
+        maker_node_map: HashMap<Pubkey, Vec<Node>>,
+        max_makers: usize,
+        _context: &MakerSelectionContext,
+    ) -> HashMap<Pubkey, Vec<Node>> {
+        let mut candidates = sorted_candidates(maker_node_map);
+        let mut selected_makers = HashMap::new();
+
+        while selected_makers.len() < max_makers && !candidates.is_empty() {
+            let total_liquidity: u128 = candidates
+                .iter()
+                .map(|(_, nodes)| get_maker_liquidity(nodes) as u128)
+                .sum();
+
+            let maker_index = if total_liquidity == 0 {
+                self.rng.gen_range(0..candidates.len())
+            } else {
+                let target = self.rng.gen_range(0..total_liquidity);
+                let mut cumulative_liquidity = 0;
+                candidates
+                    .iter()
+                    .position(|(_, nodes)| {
+                        cumulative_liquidity += get_maker_liquidity(nodes) as u128;
+                        target < cumulative_liquidity
+                    })
+                    .unwrap_or(candidates.len() - 1)
+            };
+
+            let (maker, nodes) = candidates.remove(maker_index);
+            selected_makers.insert(maker, nodes);
         }
+
+        selected_makers
+    }
+}
+
+/// Makers quoting the best price for the taker first, ties go to the larger maker
+pub struct BestPriceSelector;
+
+impl MakerSelector for BestPriceSelector {
+    fn select_makers(
+        &mut self,
+        maker_node_map: HashMap<Pubkey, Vec<Node>>,
+        max_makers: usize,
+        context: &MakerSelectionContext,
+    ) -> HashMap<Pubkey, Vec<Node>> {
+        let mut candidates: Vec<(i128, u64, Pubkey, Vec<Node>)> = sorted_candidates(maker_node_map)
+            .into_iter()
+            .map(|(maker, nodes)| {
+                let price_score = nodes
+                    .iter()
+                    .map(|node| {
+                        let price =
+                            node.get_price(&context.oracle_price_data, context.slot) as i128;
+                        // makers on the ask side are better the lower they quote
+                        match node.get_order().direction {
+                            PositionDirection::Short => -price,
+                            PositionDirection::Long => price,
+                        }
+                    })
+                    .max()
+                    .unwrap_or(i128::MIN);
+                (price_score, get_maker_liquidity(&nodes), maker, nodes)
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)).then(a.2.cmp(&b.2)));
+
+        candidates
+            .into_iter()
+            .take(max_makers)
+            .map(|(_, _, maker, nodes)| (maker, nodes))
+            .collect()
     }
+}
 
-    selected_makers
+/// Makers whose fills landed most often first, unknown makers start at 50%
+#[derive(Default)]
+pub struct FillSuccessSelector {
+    /// (landed, attempted) fills per maker
+    fill_results: HashMap<Pubkey, (u64, u64)>,
 }
 
-fn select_maker(maker_node_map: &HashMap<Pubkey, Vec<Node>>) -> Option<Pubkey> {
-    if maker_node_map.is_empty() {
-        return None;
+impl FillSuccessSelector {
+    /// Laplace smoothed share of landed fills
+    fn success_rate(&self, maker: &Pubkey) -> f64 {
+        let (landed, attempted) = self.fill_results.get(maker).copied().unwrap_or_default();
+        (landed + 1) as f64 / (attempted + 2) as f64
     }
+}
+
+impl MakerSelector for FillSuccessSelector {
+    fn select_makers(
+        &mut self,
+        maker_node_map: HashMap<Pubkey, Vec<Node>>,
+        max_makers: usize,
+        _context: &MakerSelectionContext,
+    ) -> HashMap<Pubkey, Vec<Node>> {
+        let mut candidates: Vec<(f64, u64, Pubkey, Vec<Node>)> = sorted_candidates(maker_node_map)
+            .into_iter()
+            .map(|(maker, nodes)| {
+                (
+                    self.success_rate(&maker),
+                    get_maker_liquidity(&nodes),
+                    maker,
+                    nodes,
+                )
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0).then(b.1.cmp(&a.1)).then(a.2.cmp(&b.2)));
 
-    let mut total_liquidity = 0;
-    for dlob_nodes in maker_node_map.values() {
-        total_liquidity += get_maker_liquidity(dlob_nodes);
+        candidates
+            .into_iter()
+            .take(max_makers)
+            .map(|(_, _, maker, nodes)| (maker, nodes))
+            .collect()
     }
 
-    let mut probabilities = Vec::new();
-    for dlob_nodes in maker_node_map.values() {
-        probabilities.push(get_probability(dlob_nodes, total_liquidity));
+    fn record_fill_result(&mut self, maker: &Pubkey, landed: bool) {
+        let (landed_count, attempted) = self.fill_results.entry(*maker).or_default();
+        *attempted += 1;
+        if landed {
+            *landed_count += 1;
+        }
     }
+}
 
-    let mut maker_index = 0;
-    let random: u64 = rand::random();
-    let mut sum = 0;
-    for i in 0..probabilities.len() {
-        sum += probabilities[i];
-        if random < sum {
-            maker_index = i;
-            break;
+/// Rotates through makers between fills so no maker is included in every attempt, makers whose
+/// last fill did not land go last until one lands again
+#[derive(Default)]
+pub struct RotateThrottledSelector {
+    rotation: usize,
+    /// consecutive fills without landing per maker
+    failures: HashMap<Pubkey, u32>,
+}
+
+impl MakerSelector for RotateThrottledSelector {
+    fn select_makers(
+        &mut self,
+        maker_node_map: HashMap<Pubkey, Vec<Node>>,
+        max_makers: usize,
+        _context: &MakerSelectionContext,
+    ) -> HashMap<Pubkey, Vec<Node>> {
+        let mut candidates = sorted_candidates(maker_node_map);
+        if candidates.is_empty() {
+            return HashMap::new();
         }
+
+        let offset = self.rotation % candidates.len();
+        self.rotation = self.rotation.wrapping_add(1);
+        candidates.rotate_left(offset);
+        // stable, keeps the rotated order among makers with equal failures
+        candidates.sort_by_key(|(maker, _)| self.failures.get(maker).copied().unwrap_or(0));
+
+        candidates.into_iter().take(max_makers).collect()
     }
 
-    let keys: Vec<&Pubkey> = maker_node_map.keys().collect();
-    keys.get(maker_index).copied().copied()
+    fn record_fill_result(&mut self, maker: &Pubkey, landed: bool) {
+        if landed {
+            self.failures.remove(maker);
+        } else {
+            *self.failures.entry(*maker).or_default() += 1;
+        }
+    }
 }
 
-fn get_probability(dlob_nodes: &[Node], total_liquidity: u64) -> u64 {
-    let maker_liquidity = get_maker_liquidity(dlob_nodes);
-    div_ceil(maker_liquidity * PROBABILITY_PRECISION, total_liquidity)
+/// Makers ordered by pubkey so selection does not depend on `HashMap` iteration order
+fn sorted_candidates(maker_node_map: HashMap<Pubkey, Vec<Node>>) -> Vec<(Pubkey, Vec<Node>)> {
+    let mut candidates: Vec<(Pubkey, Vec<Node>)> = maker_node_map.into_iter().collect();
+    candidates.sort_by(|a, b| a.0.cmp(&b.0));
+    candidates
 }
 
 fn get_maker_liquidity(dlob_nodes: &[Node]) -> u64 {
@@ -72,3 +237,141 @@ fn get_maker_liquidity(dlob_nodes: &[Node]) -> u64 {
         acc + order.base_asset_amount - order.base_asset_amount_filled
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use drift::state::user::{Order, OrderType};
+    use sdk::dlob::dlob_node::NodeType;
+
+    use super::*;
+
+    const CONTEXT: MakerSelectionContext = MakerSelectionContext {
+        oracle_price_data: OraclePriceData {
+            price: 100,
+            confidence: 0,
+            delay: 0,
+            has_sufficient_number_of_data_points: true,
+        },
+        slot: 0,
+    };
+
+    fn maker_nodes(maker: Pubkey, price: u64, size: u64) -> Vec<Node> {
+        let order = Order {
+            order_type: OrderType::Limit,
+            direction: PositionDirection::Short,
+            price,
+            base_asset_amount: size,
+            ..Default::default()
+        };
+        vec![Node::new(NodeType::RestingLimit, order, maker)]
+    }
+
+    /// makers 0..n with ask prices 100 + i and sizes `sizes[i]`
+    fn makers(sizes: &[u64]) -> (Vec<Pubkey>, HashMap<Pubkey, Vec<Node>>) {
+        let mut makers: Vec<Pubkey> = sizes.iter().map(|_| Pubkey::new_unique()).collect();
+        makers.sort();
+        let map = makers
+            .iter()
+            .zip(sizes)
+            .enumerate()
+            .map(|(i, (maker, size))| (*maker, maker_nodes(*maker, 100 + i as u64, *size)))
+            .collect();
+        (makers, map)
+    }
+
+    fn selected(selection: HashMap<Pubkey, Vec<Node>>) -> Vec<Pubkey> {
+        let mut selected: Vec<Pubkey> = selection.into_keys().collect();
+        selected.sort();
+        selected
+    }
+
+    #[test]
+    fn seeded_liquidity_weighted_selection_is_reproducible() {
+        let (_, map) = makers(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        let mut a = new_maker_selector(MakerSelectionStrategy::LiquidityWeighted, Some(42));
+        let mut b = new_maker_selector(MakerSelectionStrategy::LiquidityWeighted, Some(42));
+
+        for _ in 0..10 {
+            let from_a = selected(a.select_makers(map.clone(), 3, &CONTEXT));
+            let from_b = selected(b.select_makers(map.clone(), 3, &CONTEXT));
+            assert_eq!(from_a.len(), 3);
+            assert_eq!(from_a, from_b);
+        }
+    }
+
+    #[test]
+    fn liquidity_weighted_never_picks_empty_makers_while_others_have_liquidity() {
+        let (makers, map) = makers(&[0, 10, 0]);
+        let mut selector = LiquidityWeightedSelector::new(Some(7));
+
+        for _ in 0..20 {
+            assert_eq!(
+                selected(selector.select_makers(map.clone(), 1, &CONTEXT)),
+                vec![makers[1]]
+            );
+        }
+    }
+
+    #[test]
+    fn best_price_picks_lowest_asks_then_largest_maker() {
+        let (makers, mut map) = makers(&[1, 1, 1]);
+        // quotes the same price as maker 0 with more size
+        let big_maker = Pubkey::new_unique();
+        map.insert(big_maker, maker_nodes(big_maker, 100, 50));
+
+        assert_eq!(
+            selected(BestPriceSelector.select_makers(map.clone(), 1, &CONTEXT)),
+            vec![big_maker]
+        );
+
+        let mut expected = vec![makers[0], big_maker];
+        expected.sort();
+        assert_eq!(
+            selected(BestPriceSelector.select_makers(map, 2, &CONTEXT)),
+            expected
+        );
+    }
+
+    #[test]
+    fn fill_success_prefers_makers_whose_fills_landed() {
+        let (makers, map) = makers(&[1, 1, 1]);
+        let mut selector = FillSuccessSelector::default();
+        selector.record_fill_result(&makers[0], false);
+        selector.record_fill_result(&makers[0], false);
+        selector.record_fill_result(&makers[2], true);
+
+        assert_eq!(
+            selected(selector.select_makers(map.clone(), 1, &CONTEXT)),
+            vec![makers[2]]
+        );
+        // unknown makers rank above makers that keep failing
+        assert_eq!(
+            selected(selector.select_makers(map, 2, &CONTEXT)),
+            vec![makers[1], makers[2]]
+        );
+    }
+
+    #[test]
+    fn rotate_throttled_rotates_and_demotes_failing_makers() {
+        let (makers, map) = makers(&[1, 1, 1]);
+        let mut selector = RotateThrottledSelector::default();
+
+        let rounds: Vec<Vec<Pubkey>> = (0..3)
+            .map(|_| selected(selector.select_makers(map.clone(), 1, &CONTEXT)))
+            .collect();
+        assert_eq!(
+            rounds,
+            vec![vec![makers[0]], vec![makers[1]], vec![makers[2]]]
+        );
+
+        // maker 0 is next in rotation but its last fill did not land
+        selector.record_fill_result(&makers[0], false);
+        assert_eq!(
+            selected(selector.select_makers(map.clone(), 1, &CONTEXT)),
+            vec![makers[1]]
+        );
+
+        selector.record_fill_result(&makers[0], true);
+        assert_eq!(selected(selector.select_makers(map, 3, &CONTEXT)).len(), 3);
+    }
+}
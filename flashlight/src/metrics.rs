@@ -1,3 +1,8 @@
+use std::sync::{
+    atomic::{AtomicI64, AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
 /// RuntimeSpec is the attributes of the runtime environment, used to
 /// distinguish this metric set from others
 #[derive(Debug, Default)]
@@ -14,3 +19,185 @@ impl RuntimeSpec {
         todo!()
     }
 }
+
+/// Monotonic counter, clones share the same value
+#[derive(Debug, Default, Clone)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    pub fn inc_by(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Gauge that can go up and down, clones share the same value
+#[derive(Debug, Default, Clone)]
+pub struct Gauge(Arc<AtomicI64>);
+
+impl Gauge {
+    pub fn add(&self, value: i64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Metric {
+    Counter(Counter),
+    Gauge(Gauge),
+}
+
+#[derive(Debug)]
+struct RegisteredMetric {
+    name: String,
+    help: String,
+    metric: Metric,
+}
+
+/// Registry of a bot's counters and gauges, rendered in the prometheus text format
+#[derive(Debug, Default)]
+pub struct Metrics {
+    metrics: Mutex<Vec<RegisteredMetric>>,
+}
+
+impl Metrics {
+    /// Registers a counter under `name`, returns the existing one if `name` is already a counter
+    pub fn register_counter(&self, name: &str, help: &str) -> Counter {
+        let mut metrics = self.metrics.lock().unwrap();
+        if let Some(Metric::Counter(counter)) = metrics
+            .iter()
+            .find(|registered| registered.name == name)
+            .map(|registered| registered.metric.clone())
+        {
+            return counter;
+        }
+        let counter = Counter::default();
+        metrics.push(RegisteredMetric {
+            name: name.to_string(),
+            help: help.to_string(),
+            metric: Metric::Counter(counter.clone()),
+        });
+        counter
+    }
+
+    /// Registers a gauge under `name`, returns the existing one if `name` is already a gauge
+    pub fn register_gauge(&self, name: &str, help: &str) -> Gauge {
+        let mut metrics = self.metrics.lock().unwrap();
+        if let Some(Metric::Gauge(gauge)) = metrics
+            .iter()
+            .find(|registered| registered.name == name)
+            .map(|registered| registered.metric.clone())
+        {
+            return gauge;
+        }
+        let gauge = Gauge::default();
+        metrics.push(RegisteredMetric {
+            name: name.to_string(),
+            help: help.to_string(),
+            metric: Metric::Gauge(gauge.clone()),
+        });
+        gauge
+    }
+
+    /// All registered metrics in the prometheus text exposition format
+    pub fn render(&self) -> String {
+        let metrics = self.metrics.lock().unwrap();
+        let mut out = String::new();
+        for registered in metrics.iter() {
+            let (kind, value) = match &registered.metric {
+                Metric::Counter(counter) => ("counter", counter.get().to_string()),
+                Metric::Gauge(gauge) => ("gauge", gauge.get().to_string()),
+            };
+            out.push_str(&format!(
+                "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n",
+                name = registered.name,
+                help = registered.help,
+            ));
+        }
+        out
+    }
+}
+
+/// Metrics of the filler's expected value check on fillable nodes
+#[derive(Debug, Clone)]
+pub struct FillProfitabilityMetrics {
+    /// nodes an expected value was estimated for
+    pub evaluated_fills: Counter,
+    /// nodes not sent because their expected value was below the configured minimum
+    pub skipped_fills: Counter,
+    /// summed expected value (lamports) of the skipped nodes
+    pub skipped_expected_value_lamports: Gauge,
+    /// summed expected value (lamports) of the nodes passed on to be filled
+    pub sent_expected_value_lamports: Gauge,
+    /// fill passes sent without an expected value check because there was no SOL price
+    pub unchecked_fill_passes: Counter,
+}
+
+impl FillProfitabilityMetrics {
+    pub fn register(metrics: &Metrics) -> Self {
+        Self {
+            evaluated_fills: metrics.register_counter(
+                "filler_evaluated_fills",
+                "fillable nodes an expected value was estimated for",
+            ),
+            skipped_fills: metrics.register_counter(
+                "filler_skipped_fills",
+                "fillable nodes skipped for an expected value below the minimum",
+            ),
+            skipped_expected_value_lamports: metrics.register_gauge(
+                "filler_skipped_expected_value_lamports",
+                "summed expected value (lamports) of the skipped fillable nodes",
+            ),
+            sent_expected_value_lamports: metrics.register_gauge(
+                "filler_sent_expected_value_lamports",
+                "summed expected value (lamports) of the fillable nodes sent",
+            ),
+            unchecked_fill_passes: metrics.register_counter(
+                "filler_unchecked_fill_passes",
+                "fill passes sent without an expected value check for lack of a SOL price",
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registered_metrics_are_rendered() {
+        let metrics = Metrics::default();
+        let fill_metrics = FillProfitabilityMetrics::register(&metrics);
+        fill_metrics.evaluated_fills.inc_by(3);
+        fill_metrics.skipped_expected_value_lamports.add(-250);
+
+        let rendered = metrics.render();
+        assert!(
+            rendered.contains("# TYPE filler_evaluated_fills counter\nfiller_evaluated_fills 3\n")
+        );
+        assert!(rendered.contains(
+            "# TYPE filler_skipped_expected_value_lamports gauge\nfiller_skipped_expected_value_lamports -250\n"
+        ));
+    }
+
+    #[test]
+    fn registering_twice_shares_the_value() {
+        let metrics = Metrics::default();
+        metrics.register_counter("sent", "sent txs").inc_by(1);
+        metrics.register_counter("sent", "sent txs").inc_by(2);
+
+        assert_eq!(metrics.register_counter("sent", "sent txs").get(), 3);
+        assert_eq!(metrics.render().matches("# TYPE sent counter").count(), 1);
+    }
+}
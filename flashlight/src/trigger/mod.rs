@@ -1,47 +1,77 @@
 use std::{
     collections::HashMap,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
     time::{Duration, Instant},
 };
 
-use drift::state::{perp_market::PerpMarket, spot_market::SpotMarket, user::MarketType};
-use futures_util::{FutureExt, TryFutureExt};
+use drift::state::user::MarketType;
 use log::{error, info, warn};
 use sdk::{
+    config::DriftEnv,
     dlob::{
-        dlob_node::DLOBNode,
+        dlob::DLOB,
+        dlob_node::{DLOBNode, Node},
         dlob_subscriber::DLOBSubscriber,
         types::{DLOBSubscriptionConfig, DlobSource, SlotSource},
     },
     drift_client::DriftClient,
+    priority_fee::{
+        drift_priority_fee_method::DriftMarketInfo,
+        priority_fee_subscriber_map::PriorityFeeSubscriberMap,
+        types::PriorityFeeSubscriberMapConfig,
+    },
     slot_subscriber::SlotSubscriber,
     tx::priority_fee_calculator::PriorityFeeCalculator,
-    types::{BaseTxParams, ProcessingTxParams, TxParams},
+    types::SdkError,
     usermap::UserMap,
     RpcAccountProvider,
 };
-use tokio::{sync::oneshot, task::JoinHandle};
+use solana_client::client_error::ClientErrorKind;
+use solana_sdk::{
+    address_lookup_table_account::AddressLookupTableAccount,
+    compute_budget::ComputeBudgetInstruction,
+};
+use tokio::{sync::oneshot, task::JoinHandle, time::interval};
 
-use crate::{config::BaseBotConfig, util::get_node_to_trigger_signature};
+use crate::{
+    config::BaseBotConfig,
+    util::{
+        get_drift_priority_fee_endpoint, get_node_to_trigger_signature,
+        simulate_and_get_tx_with_cus, SimulateAndGetTxWithCUsParams,
+    },
+};
 
 // time to wait between triggering an order
 const TRIGGER_ORDER_COOLDOWN_MS: u64 = 10000;
+const MAX_TRIGGERS_PER_TX: usize = 4; // max number of trigger_order ixs packed into one tx
+const CU_PER_TRIGGER: u32 = 100_000;
+const DEFAULT_PRIORITY_FEE_MICRO_LAMPORTS: u64 = 10_000; // used until the market has a fee sample
+const SIM_CU_ESTIMATE_MULTIPLIER: f64 = 1.15;
 
 pub struct TriggerBot {
     name: String,
     dry_run: bool,
+    run_once: bool,
     default_interval_ms: u64,
 
     drift_client: Arc<DriftClient<RpcAccountProvider>>,
     slot_subscriber: SlotSubscriber,
     dlob_subscriber: Option<DLOBSubscriber<RpcAccountProvider>>,
-    triggering_nodes: HashMap<String, Instant>,
-    periodic_task_mutex: Arc<Mutex<()>>,
+    triggering_nodes: Arc<Mutex<HashMap<String, Instant>>>,
+    periodic_task_mutex: Arc<tokio::sync::Mutex<()>>,
     interval_tx: Option<oneshot::Sender<()>>,
     interval_handles: Option<JoinHandle<()>>,
     user_map: UserMap,
+    lookup_table_account: Option<AddressLookupTableAccount>,
 
-    priority_fee_calculator: PriorityFeeCalculator,
+    priority_fee_calculator: Arc<Mutex<PriorityFeeCalculator>>,
+    /// number of trigger txs whose send timed out, drives `priority_fee_calculator`
+    tx_timeout_count: Arc<AtomicU64>,
+    /// per market fee paid once txs start failing
+    priority_fee_subscriber_map: PriorityFeeSubscriberMap,
 }
 
 impl TriggerBot {
@@ -51,19 +81,34 @@ impl TriggerBot {
         user_map: UserMap,
         config: BaseBotConfig,
     ) -> Self {
+        let env = DriftEnv::from(drift_client.context());
+        let priority_config = PriorityFeeSubscriberMapConfig {
+            frequency_ms: Some(10_000),
+            drift_markets: Some(trigger_markets(&drift_client)),
+            drift_priority_fee_endpoint: get_drift_priority_fee_endpoint(env),
+            default_strategy: None,
+        };
+
         Self {
             name: config.bot_id,
             dry_run: config.dry_run,
+            run_once: config.run_once.unwrap_or(false),
             default_interval_ms: 1000,
             drift_client,
             slot_subscriber,
             dlob_subscriber: None,
-            triggering_nodes: HashMap::new(),
-            periodic_task_mutex: Arc::new(Mutex::new(())),
+            triggering_nodes: Arc::new(Mutex::new(HashMap::new())),
+            periodic_task_mutex: Arc::new(tokio::sync::Mutex::new(())),
             interval_tx: None,
             interval_handles: None,
             user_map,
-            priority_fee_calculator: PriorityFeeCalculator::new(Instant::now(), None),
+            lookup_table_account: None,
+            priority_fee_calculator: Arc::new(Mutex::new(PriorityFeeCalculator::new(
+                Instant::now(),
+                None,
+            ))),
+            tx_timeout_count: Arc::new(AtomicU64::new(0)),
+            priority_fee_subscriber_map: PriorityFeeSubscriberMap::new(priority_config),
         }
     }
 
@@ -80,14 +125,34 @@ impl TriggerBot {
             subscriber.subscribe().await.map_err(|e| e.to_string())?;
         }
 
+        // triggers fall back to the default fee until a load succeeds
+        if let Err(e) = self.priority_fee_subscriber_map.subscribe().await {
+            warn!("{} Failed to load priority fees: {e}", self.name);
+        }
+
+        self.lookup_table_account = Some(self.drift_client.fetch_market_lookup_table_account());
+
         Ok(())
     }
 
     pub async fn reset(&mut self) -> Result<(), String> {
+        if let Some(interval_tx) = self.interval_tx.take() {
+            interval_tx
+                .send(())
+                .map_err(|_| String::from("failed to send oneshot channel"))?;
+
+            self.interval_handles = None;
+        }
+
         if let Some(subscriber) = &mut self.dlob_subscriber {
             subscriber.unsubscribe().await;
         }
 
+        self.priority_fee_subscriber_map
+            .unsubscribe()
+            .await
+            .map_err(|e| e.to_string())?;
+
         self.user_map
             .unsubscribe()
             .await
@@ -96,251 +161,399 @@ impl TriggerBot {
         Ok(())
     }
 
-    pub async fn start_interval_loop(&mut self) {
-        self.try_trigger().await;
-    }
+    pub async fn start_interval_loop(&mut self) -> Result<(), String> {
+        let context = self.trigger_context()?;
 
-    async fn try_trigger(&mut self) {
-        let start = Instant::now();
-        let mut ran = false;
-
-        match self.periodic_task_mutex.clone().try_lock() {
-            Ok(_guard) => {
-                let perp_markets = self.drift_client.get_perp_market_accounts();
-                let spot_markets = self.drift_client.get_spot_market_accounts();
-                let user_map = self.user_map.clone();
-
-                let drift_client = &self.drift_client;
-                let triggering_nodes = Arc::new(Mutex::new(self.triggering_nodes.clone()));
-                let priority_fee_calculator =
-                    Arc::new(Mutex::new(self.priority_fee_calculator.clone()));
-
-                if let Some(subscriber) = &self.dlob_subscriber {
-                    let subscriber = Arc::new(subscriber.clone());
-                    // let trigger_perp_markets: Vec<_> = perp_markets
-                    //     .into_iter()
-                    //     .map(|market| {
-                    //         try_trigger_for_perp_market(
-                    //             drift_client.clone(),
-                    //             subscriber.clone(),
-                    //             triggering_nodes.clone(),
-                    //             user_map.clone(),
-                    //             market,
-                    //         )
-                    //     })
-                    //     .collect();
-
-                    let trigger_spot_markets: Vec<_> = spot_markets
-                        .into_iter()
-                        .map(|market| {
-                            try_trigger_trigger_fro_spot_market(
-                                drift_client.clone(),
-                                subscriber.clone(),
-                                triggering_nodes.clone(),
-                                user_map.clone(),
-                                priority_fee_calculator.clone(),
-                                market,
-                            )
-                        })
-                        .collect();
-
-                    // let all_futures = trigger_perp_markets
-                    //     .into_iter()
-                    //     .chain(trigger_spot_markets.into_iter())
-                    //     .collect::<Vec<_>>();
-
-                    // perp_market.iter().map(|m| )
-                    let results = futures_util::future::join_all(trigger_spot_markets).await;
-                    for result in results {
-                        match result {
-                            Ok(()) => log::info!("success triggering"),
-                            Err(e) => log::error!("Fail to trigger: {e}"),
-                        }
+        if self.run_once {
+            context.try_trigger().await;
+            return Ok(());
+        }
+
+        let (interval_tx, mut interval_rx) = oneshot::channel();
+        self.interval_tx = Some(interval_tx);
+
+        let mut interval = interval(Duration::from_millis(self.default_interval_ms));
+        self.interval_handles = Some(tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        context.try_trigger().await;
+                    }
+                    _ = &mut interval_rx => {
+                        break;
                     }
                 }
             }
-            Err(e) => {
-                error!("Mutex is already locked: {e}")
-            }
-        }
+        }));
+
+        info!("{} Bot started!", self.name);
+
+        Ok(())
+    }
+
+    fn trigger_context(&self) -> Result<TriggerContext, String> {
+        let dlob_subscriber = self
+            .dlob_subscriber
+            .clone()
+            .ok_or(format!("{} is not initialized", self.name))?;
+        let lookup_table_account = self
+            .lookup_table_account
+            .clone()
+            .ok_or(format!("{} is not initialized", self.name))?;
+
+        Ok(TriggerContext {
+            name: self.name.clone(),
+            dry_run: self.dry_run,
+            drift_client: self.drift_client.clone(),
+            dlob_subscriber,
+            user_map: self.user_map.clone(),
+            lookup_table_account,
+            triggering_nodes: self.triggering_nodes.clone(),
+            periodic_task_mutex: self.periodic_task_mutex.clone(),
+            priority_fee_calculator: self.priority_fee_calculator.clone(),
+            tx_timeout_count: self.tx_timeout_count.clone(),
+            priority_fee_subscriber_map: self.priority_fee_subscriber_map.clone(),
+        })
     }
 }
 
-async fn try_trigger_for_perp_market(
+/// State shared between `TriggerBot` and its interval loop
+#[derive(Clone)]
+struct TriggerContext {
+    name: String,
+    dry_run: bool,
     drift_client: Arc<DriftClient<RpcAccountProvider>>,
-    subscriber: Arc<DLOBSubscriber<RpcAccountProvider>>,
-    triggering_nodes: Arc<Mutex<HashMap<String, Instant>>>,
+    dlob_subscriber: DLOBSubscriber<RpcAccountProvider>,
     user_map: UserMap,
-    market: PerpMarket,
-) -> Result<(), String> {
-    let market_index = market.market_index;
-
-    let oracle_price_data =
-        drift_client.get_oracle_price_data_and_slot_for_perp_market(market_index);
-
-    // if let Some(subscriber) = dlob_subscriber {
-    let dlob = subscriber.get_dlob().await;
-    let state = drift_client.get_state_account();
-    let nodes_to_trigger = dlob.find_nodes_to_trigger(
-        market_index,
-        oracle_price_data.unwrap().data.price as u64,
-        MarketType::Perp,
-        state,
-    );
-
-    log::info!("Find nodes to trigger: {}", nodes_to_trigger.len());
-
-    for node_to_trigger in nodes_to_trigger {
-        let now = Instant::now();
-        let node_to_fill_signature = get_node_to_trigger_signature(&node_to_trigger);
-        if let Some(time_started_to_trigger_node) = triggering_nodes
-            .lock()
-            .unwrap()
-            .get(&node_to_fill_signature)
-        {
-            if now - *time_started_to_trigger_node
-                < Duration::from_millis(TRIGGER_ORDER_COOLDOWN_MS)
-            {
-                warn!("triggering node {node_to_fill_signature} too soon ({}ms since last trigger), skipping",(now - *time_started_to_trigger_node).as_millis());
-                continue;
+    lookup_table_account: AddressLookupTableAccount,
+    triggering_nodes: Arc<Mutex<HashMap<String, Instant>>>,
+    periodic_task_mutex: Arc<tokio::sync::Mutex<()>>,
+    priority_fee_calculator: Arc<Mutex<PriorityFeeCalculator>>,
+    tx_timeout_count: Arc<AtomicU64>,
+    priority_fee_subscriber_map: PriorityFeeSubscriberMap,
+}
+
+impl TriggerContext {
+    async fn try_trigger(&self) {
+        let start = Instant::now();
+
+        let _guard = match self.periodic_task_mutex.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => {
+                warn!(
+                    "{} previous trigger loop still running, skipping",
+                    self.name
+                );
+                return;
             }
-        }
+        };
 
-        // if node_to_trigger.
+        self.prune_triggering_nodes();
+
+        let dlob = self.dlob_subscriber.get_dlob().await;
+
+        let perp_markets = self
+            .drift_client
+            .get_perp_market_accounts()
+            .into_iter()
+            .map(|market| (MarketType::Perp, market.market_index));
+        let spot_markets = self
+            .drift_client
+            .get_spot_market_accounts()
+            .into_iter()
+            .map(|market| (MarketType::Spot, market.market_index));
+
+        let trigger_markets: Vec<_> = perp_markets
+            .chain(spot_markets)
+            .map(|(market_type, market_index)| {
+                self.try_trigger_for_market(&dlob, market_type, market_index)
+            })
+            .collect();
 
-        triggering_nodes
-            .lock()
-            .unwrap()
-            .insert(node_to_fill_signature, Instant::now());
+        let results = futures_util::future::join_all(trigger_markets).await;
+        for result in results {
+            if let Err(e) = result {
+                error!("{} Fail to trigger: {e}", self.name);
+            }
+        }
 
         info!(
-            "trying to trigger perp order on market {} (account {}) perp order {}",
-            node_to_trigger.get_order().market_index,
-            node_to_trigger.get_user_account(),
-            node_to_trigger.get_order().order_id
+            "{} try_trigger took {}ms",
+            self.name,
+            start.elapsed().as_millis()
         );
+    }
 
-        let user = user_map
-            .must_get(&node_to_trigger.get_user_account().to_string())
-            .await
-            .map_err(|e| e.to_string())?;
-
-        let sub_account = drift_client.wallet().authority();
-        let user_account = drift_client.get_user(Some(0)).ok_or("failed to get user")?;
-        let msg = drift_client
-            .init_tx(&sub_account, false)
-            .map_err(|e| e.to_string())?
-            .trigger_order_ix(
-                &node_to_trigger.get_user_account(),
-                &user,
-                node_to_trigger.get_order(),
-                Some(&user_account.pubkey),
-                vec![],
-            )
-            .build();
-
-        match drift_client.sign_and_send(msg, false).await {
-            Ok(sig) => {
-                info!(
-                    "Triggered perp user (account: {}) perp order: {}",
-                    node_to_trigger.get_user_account(),
-                    node_to_trigger.get_order().order_id
+    async fn try_trigger_for_market(
+        &self,
+        dlob: &DLOB,
+        market_type: MarketType,
+        market_index: u16,
+    ) -> Result<(), String> {
+        let oracle_price = match self.get_oracle_price(market_type, market_index) {
+            Some(price) => price,
+            None => {
+                warn!(
+                    "{} No oracle price for {market_type:?} market {market_index}, skipping",
+                    self.name
                 );
-                info!("Tx: {sig}");
+                return Ok(());
             }
-            Err(e) => {
-                // node_to_trigger.
+        };
 
-                error!("Failed to trigger: {e}");
+        let nodes_to_trigger = dlob.find_nodes_to_trigger(
+            market_index,
+            oracle_price,
+            market_type,
+            self.drift_client.get_state_account(),
+        );
+        let nodes_to_trigger = self.take_triggerable_nodes(nodes_to_trigger);
+        if nodes_to_trigger.is_empty() {
+            return Ok(());
+        }
+
+        info!(
+            "{} Found {} nodes to trigger on {market_type:?} market {market_index}",
+            self.name,
+            nodes_to_trigger.len()
+        );
+
+        for nodes in nodes_to_trigger.chunks(MAX_TRIGGERS_PER_TX) {
+            let sim_ok = self.trigger_nodes(market_type, market_index, nodes).await?;
+
+            // one bad order fails the whole batch, so fall back to triggering them one by one
+            if !sim_ok && nodes.len() > 1 {
+                for node in nodes {
+                    self.trigger_nodes(market_type, market_index, &[*node])
+                        .await?;
+                }
             }
         }
+
+        Ok(())
     }
-    //  }
 
-    Ok(())
-}
+    /// Returns false if the trigger tx failed simulation
+    async fn trigger_nodes(
+        &self,
+        market_type: MarketType,
+        market_index: u16,
+        nodes: &[Node],
+    ) -> Result<bool, String> {
+        let authority = self.drift_client.wallet().authority();
+        let filler = self
+            .drift_client
+            .get_user(None)
+            .ok_or("failed to get user")?
+            .pubkey;
+
+        let mut builder = self
+            .drift_client
+            .init_tx(authority, false)
+            .map_err(|e| e.to_string())?;
+        let mut num_triggers = 0;
+        for node in nodes {
+            let user = match self
+                .user_map
+                .must_get(&node.get_user_account().to_string())
+                .await
+            {
+                Ok(user) => user,
+                Err(e) => {
+                    warn!(
+                        "{} Failed to load user {} for order {}, skipping: {e}",
+                        self.name,
+                        node.get_user_account(),
+                        node.get_order().order_id
+                    );
+                    continue;
+                }
+            };
 
-async fn try_trigger_trigger_fro_spot_market(
-    drift_client: Arc<DriftClient<RpcAccountProvider>>,
-    subscriber: Arc<DLOBSubscriber<RpcAccountProvider>>,
-    triggering_nodes: Arc<Mutex<HashMap<String, Instant>>>,
-    user_map: UserMap,
-    priority_fee_calculator: Arc<Mutex<PriorityFeeCalculator>>,
-    market: SpotMarket,
-) -> Result<(), String> {
-    let market_index = market.market_index;
-
-    let oracle_price_data =
-        drift_client.get_oracle_price_data_and_slot_for_spot_market(market_index);
-
-    // if let Some(subscriber) = &subscriber {
-    let dlob = subscriber.get_dlob().await;
-    let state = drift_client.get_state_account();
-    let nodes_to_trigger = dlob.find_nodes_to_trigger(
-        market_index,
-        oracle_price_data.unwrap().data.price as u64,
-        MarketType::Spot,
-        state,
-    );
-
-    log::info!("Find nodes to trigger: {}", nodes_to_trigger.len());
-
-    for node_to_trigger in nodes_to_trigger {
-        info!(
-            "trying to trigger (account {}) spot order {}",
-            node_to_trigger.get_user_account(),
-            node_to_trigger.get_order().order_id
-        );
+            info!(
+                "{} trying to trigger (account {}) {market_type:?} order {}",
+                self.name,
+                node.get_user_account(),
+                node.get_order().order_id
+            );
+
+            builder = builder.trigger_order_ix(
+                &node.get_user_account(),
+                &user,
+                node.get_order(),
+                Some(&filler),
+                vec![],
+            );
+            num_triggers += 1;
+        }
+
+        if num_triggers == 0 {
+            return Ok(true);
+        }
+
+        let priority_fee = self
+            .priority_fee_subscriber_map
+            .get_priority_fee(market_type_str(market_type), market_index as u64)
+            .unwrap_or(DEFAULT_PRIORITY_FEE_MICRO_LAMPORTS);
+
+        let mut ixs = {
+            let mut priority_fee_calculator = self.priority_fee_calculator.lock().unwrap();
+            let use_priority_fee = priority_fee_calculator.update_priority_fee(
+                Instant::now(),
+                self.tx_timeout_count.load(Ordering::Relaxed),
+            );
+            let mut ixs =
+                priority_fee_calculator.generate_compute_budget_ixs(CU_PER_TRIGGER * num_triggers);
+            // the subscriber's fee is already a price per CU
+            if use_priority_fee {
+                ixs.push(ComputeBudgetInstruction::set_compute_unit_price(
+                    priority_fee,
+                ));
+            }
+            ixs
+        };
+        ixs.extend(builder.instructions().to_vec());
 
-        let user = user_map
-            .must_get(&node_to_trigger.get_user_account().to_string())
+        let recent_blockhash = self
+            .drift_client
+            .backend
+            .rpc_client
+            .get_latest_blockhash()
             .await
             .map_err(|e| e.to_string())?;
 
-        // TODO: modify tx_time_count
-        let mut priority_fee_calculator = priority_fee_calculator.lock().unwrap();
-        let user_priority_fee = priority_fee_calculator.update_priority_fee(Instant::now(), 0);
-
-        let tx_params: Option<TxParams> = if user_priority_fee {
-            let compute_units = 100_000;
-            let compute_unit_price =
-                priority_fee_calculator.calculate_compute_unit_price(compute_units, 1_000_000_000);
-            Some(TxParams {
-                base: BaseTxParams {
-                    compute_units: Some(compute_units),
-                    compute_units_price: Some(compute_unit_price),
-                },
-                processing: ProcessingTxParams::default(),
-            })
-        } else {
-            None
-        };
+        let sim_res = simulate_and_get_tx_with_cus(&mut SimulateAndGetTxWithCUsParams {
+            connection: self.drift_client.backend.rpc_client.clone(),
+            payer: self.drift_client.wallet.signer.clone(),
+            lookup_table_accounts: vec![self.lookup_table_account.clone()],
+            ixs: ixs.into(),
+            cu_limit_multiplier: Some(SIM_CU_ESTIMATE_MULTIPLIER),
+            do_simulation: Some(true),
+            recent_blockhash: Some(recent_blockhash),
+            dump_tx: None,
+        })
+        .await?;
+
+        if let Some(sim_error) = sim_res.sim_error {
+            error!(
+                "{} Sim error triggering {} orders on {market_type:?} market {market_index}: {sim_error}",
+                self.name,
+                num_triggers
+            );
+            return Ok(false);
+        }
 
-        match drift_client
-            .trigger_order(
-                &node_to_trigger.get_user_account(),
-                user,
-                node_to_trigger.get_order(),
-                tx_params,
-                None,
-            )
+        if self.dry_run {
+            info!(
+                "{} dry run, not triggering {} orders on {market_type:?} market {market_index}",
+                self.name, num_triggers
+            );
+            return Ok(true);
+        }
+
+        match self
+            .drift_client
+            .sign_and_send(sim_res.tx.message, false)
             .await
         {
             Ok(sig) => {
                 info!(
-                    "Triggered user (account: {}) spot order: {}",
-                    node_to_trigger.get_user_account(),
-                    node_to_trigger.get_order().order_id
+                    "{} Triggered {} orders on {market_type:?} market {market_index}, Tx: {sig}",
+                    self.name, num_triggers
                 );
-                info!("Tx: {sig}");
             }
             Err(e) => {
-                // node_to_trigger.
-
-                error!("{e}");
+                if is_timeout(&e) {
+                    self.tx_timeout_count.fetch_add(1, Ordering::Relaxed);
+                }
+                error!("{} Failed to trigger: {e}", self.name);
             }
         }
+
+        Ok(true)
+    }
+
+    fn get_oracle_price(&self, market_type: MarketType, market_index: u16) -> Option<u64> {
+        let oracle_map = &self.drift_client.backend.oracle_map;
+        let oracle = match market_type {
+            MarketType::Perp => oracle_map.current_perp_oracle(market_index),
+            MarketType::Spot => oracle_map.current_spot_oracle(market_index),
+        }?;
+
+        oracle_map
+            .get(&oracle)
+            .map(|oracle| oracle.data.price as u64)
+    }
+
+    /// Filters out nodes triggered less than `TRIGGER_ORDER_COOLDOWN_MS` ago and marks the rest as triggering
+    fn take_triggerable_nodes(&self, nodes: Vec<Node>) -> Vec<Node> {
+        let now = Instant::now();
+        let mut triggering_nodes = self.triggering_nodes.lock().unwrap();
+
+        nodes
+            .into_iter()
+            .filter(|node| {
+                let node_sig = get_node_to_trigger_signature(node);
+                if let Some(time_started_to_trigger_node) = triggering_nodes.get(&node_sig) {
+                    let elapsed = now - *time_started_to_trigger_node;
+                    if elapsed < Duration::from_millis(TRIGGER_ORDER_COOLDOWN_MS) {
+                        warn!(
+                            "triggering node {node_sig} too soon ({}ms since last trigger), skipping",
+                            elapsed.as_millis()
+                        );
+                        return false;
+                    }
+                }
+
+                triggering_nodes.insert(node_sig, now);
+                true
+            })
+            .collect()
+    }
+
+    fn prune_triggering_nodes(&self) {
+        self.triggering_nodes
+            .lock()
+            .unwrap()
+            .retain(|_, time_started| {
+                time_started.elapsed() < Duration::from_millis(TRIGGER_ORDER_COOLDOWN_MS)
+            });
+    }
+}
+
+/// Every perp and spot market, the bot may trigger orders on any of them
+fn trigger_markets(drift_client: &DriftClient<RpcAccountProvider>) -> Vec<DriftMarketInfo> {
+    let perp_markets = drift_client
+        .get_perp_market_accounts()
+        .into_iter()
+        .map(|market| (MarketType::Perp, market.market_index));
+    let spot_markets = drift_client
+        .get_spot_market_accounts()
+        .into_iter()
+        .map(|market| (MarketType::Spot, market.market_index));
+
+    perp_markets
+        .chain(spot_markets)
+        .map(|(market_type, market_index)| DriftMarketInfo {
+            market_type: market_type_str(market_type).to_string(),
+            market_index,
+        })
+        .collect()
+}
+
+fn market_type_str(market_type: MarketType) -> &'static str {
+    match market_type {
+        MarketType::Perp => "perp",
+        MarketType::Spot => "spot",
+    }
+}
+
+/// Whether the send rpc request timed out, other send errors don't call for a priority fee
+fn is_timeout(err: &SdkError) -> bool {
+    match err {
+        SdkError::Rpc(err) => {
+            matches!(err.kind(), ClientErrorKind::Reqwest(err) if err.is_timeout())
+        }
+        _ => false,
     }
-    // }
-    Ok(())
 }
@@ -18,3 +18,17 @@ pub enum JitoStrategy {
     NonJitoOnly,
     Hybrid,
 }
+
+/// How the filler picks makers when more than `MAX_MAKERS_PER_FILL` can fill an order
+#[derive(Debug, Clone, Copy, Default)]
+pub enum MakerSelectionStrategy {
+    /// random, weighted by remaining maker liquidity
+    #[default]
+    LiquidityWeighted,
+    /// best quoted price first
+    BestPrice,
+    /// highest share of landed fills first
+    FillSuccess,
+    /// rotate through makers, deprioritizing those whose fills did not land
+    RotateThrottled,
+}
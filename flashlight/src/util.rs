@@ -12,6 +12,7 @@ use sdk::{
         dlob::NodeToFill,
         dlob_node::{get_order_signature, DLOBNode, Node},
     },
+    tx::landing_rate_fee_controller::priority_fee_lamports,
 };
 use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::{
@@ -26,6 +27,8 @@ use solana_sdk::{
     transaction::{TransactionError, VersionedTransaction},
 };
 
+const DEFAULT_COMPUTE_UNIT_LIMIT: u32 = 200_000;
+
 pub fn get_node_to_fill_signature(node: &NodeToFill) -> String {
     let user_account = node.get_node().get_user_account();
     get_order_signature(node.get_node().get_order().order_id, user_account)
@@ -50,6 +53,31 @@ pub fn is_set_compute_units_ix(ix: &Instruction) -> bool {
     false
 }
 
+/// Return the priority fee in lamports requested by the compute budget ixs of `message`
+pub fn get_priority_fee_lamports(message: &VersionedMessage) -> u64 {
+    let account_keys = message.static_account_keys();
+    let mut compute_unit_limit = DEFAULT_COMPUTE_UNIT_LIMIT;
+    let mut compute_unit_price = 0_u64;
+
+    for ix in message.instructions() {
+        if account_keys.get(ix.program_id_index as usize) != Some(&ComputeBudgetProgramId) {
+            continue;
+        }
+
+        match ix.data.split_first() {
+            Some((2, data)) if data.len() >= 4 => {
+                compute_unit_limit = u32::from_le_bytes(data[..4].try_into().unwrap());
+            }
+            Some((3, data)) if data.len() >= 8 => {
+                compute_unit_price = u64::from_le_bytes(data[..8].try_into().unwrap());
+            }
+            _ => {}
+        }
+    }
+
+    priority_fee_lamports(compute_unit_price, compute_unit_limit)
+}
+
 fn get_versioned_transaction(
     payer: &Keypair,
     ixs: &[Instruction],
@@ -4,7 +4,8 @@ use std::time::Duration;
 
 use futures_util::{
     future::{ready, BoxFuture},
-    Future, FutureExt,
+    stream::FuturesUnordered,
+    Future, FutureExt, StreamExt,
 };
 use tokio::task::JoinHandle;
 
@@ -107,3 +108,86 @@ where
         }
     })
 }
+
+/// Runs `task` for every key concurrently, each bounded by `timeout`
+///
+/// Results are returned in completion order so a slow or stalled key does not hold back the others,
+/// keys whose task timed out are returned as `Err(key)`
+pub async fn join_all_with_timeout<K, R, F, Fut>(
+    keys: impl IntoIterator<Item = K>,
+    timeout: Duration,
+    task: F,
+) -> Vec<Result<(K, R), K>>
+where
+    K: Clone,
+    F: Fn(K) -> Fut,
+    Fut: Future<Output = R>,
+{
+    let mut tasks: FuturesUnordered<_> = keys
+        .into_iter()
+        .map(|key| {
+            let task = tokio::time::timeout(timeout, task(key.clone()));
+            async move {
+                match task.await {
+                    Ok(result) => Ok((key, result)),
+                    Err(_) => Err(key),
+                }
+            }
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(tasks.len());
+    while let Some(result) = tasks.next().await {
+        results.push(result);
+    }
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use futures_util::future::pending;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn stalled_key_does_not_block_others() {
+        let timeout = Duration::from_millis(200);
+        let start = Instant::now();
+
+        let results = join_all_with_timeout([0_u16, 1, 2], timeout, |key| async move {
+            if key == 1 {
+                pending::<()>().await;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            key * 10
+        })
+        .await;
+
+        assert_eq!(results.len(), 3);
+        let mut completed: Vec<(u16, u16)> = results[..2]
+            .iter()
+            .map(|result| result.clone().expect("completed"))
+            .collect();
+        completed.sort();
+        assert_eq!(completed, vec![(0, 0), (2, 20)]);
+        assert_eq!(results[2], Err(1));
+        assert!(start.elapsed() >= timeout);
+    }
+
+    #[tokio::test]
+    async fn results_are_in_completion_order() {
+        let results = join_all_with_timeout([30_u64, 0, 10], Duration::from_secs(5), |delay_ms| {
+            tokio::time::sleep(Duration::from_millis(delay_ms))
+        })
+        .await;
+
+        let keys: Vec<u64> = results
+            .into_iter()
+            .map(|result| result.expect("completed").0)
+            .collect();
+        assert_eq!(keys, vec![0, 10, 30]);
+    }
+}
@@ -1,21 +1,179 @@
-use std::{collections::VecDeque, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
-use solana_client::nonblocking::rpc_client::RpcClient;
-use solana_sdk::hash::Hash;
+use serde_json::json;
+use solana_client::{
+    nonblocking::rpc_client::RpcClient,
+    rpc_config::RpcContextConfig,
+    rpc_request::RpcRequest,
+    rpc_response::{Response, RpcBlockhash},
+};
+use solana_sdk::{clock::MAX_PROCESSING_AGE, hash::Hash};
 use tokio::{
-    sync::Mutex,
-    time::{self, Duration},
+    sync::{Mutex, Notify},
+    time::{self, Duration, Instant},
+};
+
+use crate::{
+    event_emitter::{Event, EventEmitter},
+    slot_subscriber::SlotSubscriber,
+    SdkError, SdkResult,
 };
 
-use crate::SdkResult;
+/// number of recent blockhashes kept
+const MAX_CACHED_BLOCKHASHES: usize = 20;
+/// blockhashes expiring within this many blocks are not handed out for signing
+const BLOCKHASH_EXPIRY_BUFFER: u64 = 10;
+/// the latest blockhash is refetched early once it has fewer than this many valid blocks left
+const BLOCKHASH_REFRESH_BUFFER: u64 = MAX_PROCESSING_AGE as u64 - 30;
+
+/// A blockhash and the last block height a tx signed with it can land at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BlockhashWithExpiry {
+    pub blockhash: Hash,
+    pub last_valid_block_height: u64,
+}
+
+/// Emitted when a blockhash handed out for signing expires, txs signed with it can no longer land
+#[derive(Debug, Clone)]
+pub struct BlockhashExpired {
+    pub blockhash: Hash,
+    pub last_valid_block_height: u64,
+    /// block height the expiry was observed at
+    pub block_height: u64,
+}
+
+impl Event for BlockhashExpired {
+    fn box_clone(&self) -> Box<dyn Event> {
+        Box::new((*self).clone())
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
 
 #[derive(Clone)]
 pub struct BlockhashState {
     latest_block_height: u64,
 
-    latest_blockhash: Hash,
+    latest_blockhash: BlockhashWithExpiry,
+
+    last_twenty_hashes: VecDeque<BlockhashWithExpiry>,
 
-    last_twenty_hashes: VecDeque<Hash>,
+    /// slot and block height of the last blockhash fetch, new slots advance the block height from it
+    anchor: Option<(u64, u64)>,
+
+    /// blockhashes handed out for signing that did not expire yet, with their last valid block height
+    signed_hashes: HashMap<Hash, u64>,
+}
+
+impl BlockhashState {
+    fn new() -> Self {
+        Self {
+            latest_block_height: 0,
+            latest_blockhash: BlockhashWithExpiry::default(),
+            last_twenty_hashes: VecDeque::with_capacity(MAX_CACHED_BLOCKHASHES),
+            anchor: None,
+            signed_hashes: HashMap::new(),
+        }
+    }
+
+    fn push_blockhash(&mut self, blockhash: BlockhashWithExpiry) {
+        // avoid caching duplicate blockhashes
+        if let Some(last_blockhash) = self.last_twenty_hashes.back() {
+            if blockhash.blockhash == last_blockhash.blockhash {
+                return;
+            }
+        }
+
+        self.last_twenty_hashes.push_back(blockhash);
+
+        if self.last_twenty_hashes.len() > MAX_CACHED_BLOCKHASHES {
+            self.last_twenty_hashes.pop_front();
+        }
+
+        self.latest_blockhash = blockhash;
+    }
+
+    /// Updates the block height, returns the signed blockhashes that expired at it
+    fn set_block_height(&mut self, block_height: u64) -> Vec<BlockhashWithExpiry> {
+        self.latest_block_height = self.latest_block_height.max(block_height);
+
+        let latest_block_height = self.latest_block_height;
+        let mut expired = Vec::new();
+        self.signed_hashes
+            .retain(|blockhash, last_valid_block_height| {
+                if *last_valid_block_height < latest_block_height {
+                    expired.push(BlockhashWithExpiry {
+                        blockhash: *blockhash,
+                        last_valid_block_height: *last_valid_block_height,
+                    });
+                    return false;
+                }
+                true
+            });
+        expired.sort_by_key(|blockhash| blockhash.last_valid_block_height);
+
+        expired
+    }
+
+    /// Records the block height observed at `slot` by a blockhash fetch, returns the signed
+    /// blockhashes that expired at it
+    fn set_anchor(&mut self, slot: u64, block_height: u64) -> Vec<BlockhashWithExpiry> {
+        self.anchor = Some((slot, block_height));
+        self.set_block_height(block_height)
+    }
+
+    /// Advances the block height by the slots since the last fetch, returns the signed blockhashes
+    /// that expired at it.
+    ///
+    /// Skipped slots produce no block, so this can run ahead of the real block height until the
+    /// next fetch re-anchors it.
+    fn on_slot(&mut self, slot: u64) -> Vec<BlockhashWithExpiry> {
+        match self.anchor {
+            Some((anchor_slot, anchor_block_height)) if slot > anchor_slot => {
+                self.set_block_height(anchor_block_height + (slot - anchor_slot))
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether the latest blockhash is missing or aged past `BLOCKHASH_REFRESH_BUFFER`
+    fn needs_refresh(&self) -> bool {
+        self.latest_blockhash.last_valid_block_height
+            < self.latest_block_height + BLOCKHASH_REFRESH_BUFFER
+    }
+
+    /// Oldest cached blockhash that stays valid for more than `BLOCKHASH_EXPIRY_BUFFER` blocks,
+    /// the latest blockhash if there is none
+    fn valid_blockhash(&self) -> BlockhashWithExpiry {
+        self.last_twenty_hashes
+            .iter()
+            .find(|blockhash| {
+                blockhash.last_valid_block_height
+                    >= self.latest_block_height + BLOCKHASH_EXPIRY_BUFFER
+            })
+            .copied()
+            .unwrap_or(self.latest_blockhash)
+    }
+
+    /// `valid_blockhash`, remembered so its expiry is reported
+    fn blockhash_for_signing(&mut self) -> BlockhashWithExpiry {
+        let blockhash = self.valid_blockhash();
+        if blockhash.blockhash != Hash::default() {
+            self.signed_hashes
+                .insert(blockhash.blockhash, blockhash.last_valid_block_height);
+        }
+
+        blockhash
+    }
 }
 
 #[derive(Clone)]
@@ -27,19 +185,28 @@ pub struct BlockhashSubscriber {
     refresh_frequency: u64,
 
     rpc_client: Arc<RpcClient>,
+
+    event_emitter: EventEmitter,
+
+    /// latest slot from `track_slots`, 0 if slots are not tracked
+    latest_slot: Arc<AtomicU64>,
+
+    /// wakes the refresh task on every new slot
+    slot_notify: Arc<Notify>,
 }
 
 impl BlockhashSubscriber {
+    pub const EXPIRED_EVENT_ID: &'static str = "blockhash_expired";
+
     pub fn new(refresh_frequency: u64, endpoint: String) -> Self {
         BlockhashSubscriber {
             is_subscribed: false,
-            state: Arc::new(Mutex::new(BlockhashState {
-                latest_block_height: 0,
-                latest_blockhash: Hash::default(),
-                last_twenty_hashes: VecDeque::with_capacity(20),
-            })),
+            state: Arc::new(Mutex::new(BlockhashState::new())),
             refresh_frequency,
             rpc_client: Arc::new(RpcClient::new(endpoint)),
+            event_emitter: EventEmitter::new(),
+            latest_slot: Arc::new(AtomicU64::new(0)),
+            slot_notify: Arc::new(Notify::new()),
         }
     }
 
@@ -55,99 +222,305 @@ impl BlockhashSubscriber {
 
     pub async fn get_latest_blockhash(&self) -> Hash {
         let state = self.state.lock().await;
-        state.latest_blockhash
+        state.latest_blockhash.blockhash
     }
 
     pub async fn subscribe(&mut self) -> SdkResult<()> {
         if self.is_subscribed {
             return Ok(());
         }
-        self.is_subscribed = true;
 
-        // update_blockhash(&self.rpc_client, &self.state).await?;
+        update_blockhash(
+            &self.rpc_client,
+            &self.state,
+            &self.event_emitter,
+            &self.latest_slot,
+        )
+        .await?;
+        self.is_subscribed = true;
 
         let state = self.state.clone();
         let rpc_client = self.rpc_client.clone();
+        let event_emitter = self.event_emitter.clone();
+        let latest_slot = self.latest_slot.clone();
+        let slot_notify = self.slot_notify.clone();
         let update_frequency = Duration::from_millis(self.refresh_frequency);
         tokio::spawn(async move {
+            let mut last_refresh = Instant::now();
             loop {
-                time::sleep(update_frequency).await;
-                match update_blockhash(&rpc_client, &state).await {
-                    Ok(()) => log::info!("success updating"),
+                tokio::select! {
+                    _ = slot_notify.notified() => {}
+                    _ = time::sleep_until(last_refresh + update_frequency) => {}
+                }
+
+                let is_near_expiry =
+                    advance_to_slot(&state, &event_emitter, latest_slot.load(Ordering::Relaxed))
+                        .await;
+                if !is_near_expiry && last_refresh.elapsed() < update_frequency {
+                    continue;
+                }
+
+                last_refresh = Instant::now();
+                match update_blockhash(&rpc_client, &state, &event_emitter, &latest_slot).await {
+                    Ok(()) => log::debug!("success updating"),
                     Err(e) => log::error!("cannot update: {e}"),
                 }
             }
         });
-        // let blockhash_subscriber = blockhash_subscriber.clone();
-        // let blockhash_subscriber_reader = blockhash_subscriber.read().await;
-        // let refresh_frequency = blockhash_subscriber_reader.refresh_frequency;
-        // drop(blockhash_subscriber_reader);
-
-        // tokio::spawn(async move {
-        //     loop {
-        //         let mut blockhash_subscriber_writer = blockhash_subscriber.write().await;
-        //         let blockhash = blockhash_subscriber_writer
-        //             .rpc_client
-        //             .get_latest_blockhash()
-        //             .await
-        //             .expect("blockhash");
-        //         blockhash_subscriber_writer
-        //             .last_twenty_hashes
-        //             .push(blockhash);
-        //         blockhash_subscriber_writer.latest_blockhash = blockhash;
-        //         if blockhash_subscriber_writer.last_twenty_hashes.len() > 20 {
-        //             blockhash_subscriber_writer.last_twenty_hashes.remove(0);
-        //         }
-        //         drop(blockhash_subscriber_writer);
-        //         tokio::time::sleep(tokio::time::Duration::from_secs(refresh_frequency)).await;
-        //     }
-        // });
 
         log::info!("Done subscribing state");
 
         Ok(())
     }
 
+    /// Advances the block height on every new slot from `slot_subscriber`, between the
+    /// `refresh_frequency` blockhash fetches
+    pub fn track_slots(&self, slot_subscriber: &SlotSubscriber) {
+        let latest_slot = self.latest_slot.clone();
+        let slot_notify = self.slot_notify.clone();
+        slot_subscriber.on_slot(move |slot| {
+            latest_slot.fetch_max(slot, Ordering::Relaxed);
+            slot_notify.notify_one();
+        });
+    }
+
+    /// Blockhash to sign a tx with and the last block height the tx can land at.
+    ///
+    /// A `BlockhashExpired` event is emitted once the returned blockhash expires.
+    pub async fn blockhash_with_expiry(&self) -> BlockhashWithExpiry {
+        let mut state = self.state.lock().await;
+        state.blockhash_for_signing()
+    }
+
     pub async fn get_valid_blockhash(&self) -> Hash {
-        let state = self.state.lock().await;
-        *state
-            .last_twenty_hashes
-            .front()
-            .unwrap_or(&state.latest_blockhash)
+        self.blockhash_with_expiry().await.blockhash
+    }
+
+    /// Call `handler` whenever a blockhash handed out for signing expires
+    pub fn on_blockhash_expired<F: 'static + Send + Fn(&BlockhashExpired)>(&self, handler: F) {
+        self.event_emitter
+            .subscribe(BlockhashSubscriber::EXPIRED_EVENT_ID, move |event| {
+                if let Some(expired) = event.as_any().downcast_ref::<BlockhashExpired>() {
+                    handler(expired);
+                }
+            });
+    }
+}
+
+/// Advances the block height to `slot` and reports the signed blockhashes that expired, returns
+/// whether the latest blockhash needs a refresh
+async fn advance_to_slot(
+    state: &Mutex<BlockhashState>,
+    event_emitter: &EventEmitter,
+    slot: u64,
+) -> bool {
+    let (expired, block_height, needs_refresh) = {
+        let mut state = state.lock().await;
+        let expired = state.on_slot(slot);
+        (expired, state.latest_block_height, state.needs_refresh())
+    };
+    emit_expired(event_emitter, expired, block_height);
+
+    needs_refresh
+}
+
+fn emit_expired(
+    event_emitter: &EventEmitter,
+    expired: Vec<BlockhashWithExpiry>,
+    block_height: u64,
+) {
+    for blockhash in expired {
+        event_emitter.emit(
+            BlockhashSubscriber::EXPIRED_EVENT_ID,
+            Box::new(BlockhashExpired {
+                blockhash: blockhash.blockhash,
+                last_valid_block_height: blockhash.last_valid_block_height,
+                block_height,
+            }),
+        );
     }
 }
 
+/// Fetches the latest blockhash, the block height is derived from its last valid block height
 async fn update_blockhash(
     rpc_client: &Arc<RpcClient>,
     state: &Arc<Mutex<BlockhashState>>,
+    event_emitter: &EventEmitter,
+    latest_slot: &AtomicU64,
 ) -> SdkResult<()> {
-    let blockhash = rpc_client.get_latest_blockhash().await?;
-    let block_height = rpc_client.get_block_height().await?;
+    let config = RpcContextConfig {
+        commitment: Some(rpc_client.commitment()),
+        min_context_slot: None,
+    };
+    let response = rpc_client
+        .send::<Response<RpcBlockhash>>(RpcRequest::GetLatestBlockhash, json!([config]))
+        .await?;
+    let blockhash = Hash::from_str(&response.value.blockhash)
+        .map_err(|e| SdkError::Generic(format!("invalid blockhash: {e}")))?;
+    let last_valid_block_height = response.value.last_valid_block_height;
 
-    let mut state = state.lock().await;
-    state.latest_block_height = block_height;
+    let (expired, block_height) = {
+        let mut state = state.lock().await;
+        state.push_blockhash(BlockhashWithExpiry {
+            blockhash,
+            last_valid_block_height,
+        });
+        let mut expired = state.set_anchor(
+            response.context.slot,
+            last_valid_block_height.saturating_sub(MAX_PROCESSING_AGE as u64),
+        );
+        expired.extend(state.on_slot(latest_slot.load(Ordering::Relaxed)));
+        (expired, state.latest_block_height)
+    };
+    emit_expired(event_emitter, expired, block_height);
 
-    // avoid caching duplicate blockhashes
-    if let Some(last_blockhash) = state.last_twenty_hashes.back() {
-        if blockhash == *last_blockhash {
-            return Ok(());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blockhash(last_valid_block_height: u64) -> BlockhashWithExpiry {
+        BlockhashWithExpiry {
+            blockhash: Hash::new_unique(),
+            last_valid_block_height,
         }
     }
 
-    state.last_twenty_hashes.push_back(blockhash);
+    #[test]
+    fn caches_last_twenty_distinct_blockhashes() {
+        let mut state = BlockhashState::new();
+        let first = blockhash(150);
+        state.push_blockhash(first);
+        state.push_blockhash(first);
+        assert_eq!(state.last_twenty_hashes.len(), 1);
 
-    if state.last_twenty_hashes.len() > 20 {
-        state.last_twenty_hashes.pop_front();
+        for i in 0..MAX_CACHED_BLOCKHASHES as u64 {
+            state.push_blockhash(blockhash(151 + i));
+        }
+        assert_eq!(state.last_twenty_hashes.len(), MAX_CACHED_BLOCKHASHES);
+        assert_ne!(state.last_twenty_hashes.front(), Some(&first));
+        assert_eq!(state.latest_blockhash.last_valid_block_height, 170);
     }
 
-    state.latest_blockhash = blockhash;
+    #[test]
+    fn valid_blockhash_skips_hashes_close_to_expiry() {
+        let mut state = BlockhashState::new();
+        let oldest = blockhash(150);
+        let middle = blockhash(160);
+        let latest = blockhash(170);
+        state.push_blockhash(oldest);
+        state.push_blockhash(middle);
+        state.push_blockhash(latest);
 
-    Ok(())
-}
+        assert_eq!(state.valid_blockhash(), oldest);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        state.set_block_height(145);
+        assert_eq!(state.valid_blockhash(), middle);
+
+        state.set_block_height(165);
+        assert_eq!(state.valid_blockhash(), latest);
+    }
+
+    #[test]
+    fn signed_blockhash_expires_once() {
+        let mut state = BlockhashState::new();
+        let first = blockhash(150);
+        state.push_blockhash(first);
+        assert_eq!(state.blockhash_for_signing(), first);
+
+        assert!(state.set_block_height(150).is_empty());
+        assert_eq!(state.set_block_height(151), vec![first]);
+        assert!(state.set_block_height(152).is_empty());
+    }
+
+    #[test]
+    fn unsigned_blockhashes_do_not_expire() {
+        let mut state = BlockhashState::new();
+        state.push_blockhash(blockhash(150));
+        assert!(state.set_block_height(200).is_empty());
+
+        // nothing fetched yet
+        let mut state = BlockhashState::new();
+        assert_eq!(
+            state.blockhash_for_signing(),
+            BlockhashWithExpiry::default()
+        );
+        assert!(state.set_block_height(1).is_empty());
+    }
+
+    #[tokio::test]
+    async fn expired_event_fires_once_past_last_valid_block_height() {
+        let subscriber = BlockhashSubscriber::new(1_000, "http://localhost:8899".to_string());
+        let (tx, rx) = std::sync::mpsc::channel();
+        subscriber.on_blockhash_expired(move |expired| {
+            let _ = tx.send(expired.clone());
+        });
+
+        let signed = blockhash(1_150);
+        {
+            let mut state = subscriber.state.lock().await;
+            state.push_blockhash(signed);
+            state.set_anchor(1_000, 1_000);
+        }
+        assert_eq!(subscriber.blockhash_with_expiry().await, signed);
+
+        let state = &subscriber.state;
+        let event_emitter = &subscriber.event_emitter;
+        advance_to_slot(state, event_emitter, 1_150).await;
+        assert!(rx
+            .recv_timeout(std::time::Duration::from_millis(100))
+            .is_err());
+
+        advance_to_slot(state, event_emitter, 1_151).await;
+        advance_to_slot(state, event_emitter, 1_160).await;
+        let expired = rx
+            .recv_timeout(std::time::Duration::from_secs(1))
+            .expect("expired event");
+        assert_eq!(expired.blockhash, signed.blockhash);
+        assert_eq!(expired.last_valid_block_height, 1_150);
+        assert_eq!(expired.block_height, 1_151);
+        assert!(rx
+            .recv_timeout(std::time::Duration::from_millis(100))
+            .is_err());
+    }
+
+    #[test]
+    fn slots_advance_block_height_from_last_fetch() {
+        let mut state = BlockhashState::new();
+        // no fetch yet, nothing to advance from
+        state.on_slot(1_000);
+        assert_eq!(state.latest_block_height, 0);
+
+        state.set_anchor(1_000, 900);
+        state.on_slot(999);
+        assert_eq!(state.latest_block_height, 900);
+        state.on_slot(1_005);
+        assert_eq!(state.latest_block_height, 905);
+
+        // a later fetch behind the estimate does not move the block height back
+        state.set_anchor(1_006, 904);
+        assert_eq!(state.latest_block_height, 905);
+        state.on_slot(1_010);
+        assert_eq!(state.latest_block_height, 908);
+    }
+
+    #[test]
+    fn needs_refresh_once_latest_blockhash_ages() {
+        let mut state = BlockhashState::new();
+        assert!(state.needs_refresh());
+
+        state.push_blockhash(blockhash(900 + MAX_PROCESSING_AGE as u64));
+        state.set_anchor(1_000, 900);
+        assert!(!state.needs_refresh());
+
+        let age = MAX_PROCESSING_AGE as u64 - BLOCKHASH_REFRESH_BUFFER;
+        state.on_slot(1_000 + age);
+        assert!(!state.needs_refresh());
+        state.on_slot(1_001 + age);
+        assert!(state.needs_refresh());
+    }
 
     #[tokio::test]
     async fn test_blockhash_subscribe() {
@@ -1,4 +1,15 @@
+use crate::types::Context;
+
 pub enum DriftEnv {
     Devnet,
     MainnetBeta,
 }
+
+impl From<Context> for DriftEnv {
+    fn from(context: Context) -> Self {
+        match context {
+            Context::DevNet => DriftEnv::Devnet,
+            Context::MainNet => DriftEnv::MainnetBeta,
+        }
+    }
+}
@@ -25,6 +25,13 @@ static STATE_ACCOUNT: OnceLock<Pubkey> = OnceLock::new();
 pub const TOKEN_PROGRAM_ID: Pubkey =
     solana_sdk::pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
 
+pub const ASSOCIATED_TOKEN_PROGRAM_ID: Pubkey =
+    solana_sdk::pubkey!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+
+/// mint of wrapped SOL (spot market 1)
+pub const WRAPPED_SOL_MINT: Pubkey =
+    solana_sdk::pubkey!("So11111111111111111111111111111111111111112");
+
 /// Return the market lookup table
 pub(crate) const fn market_lookup_table(context: Context) -> Pubkey {
     match context {
@@ -73,6 +80,36 @@ pub fn derive_spot_market_vault(market_index: u16) -> Pubkey {
     account
 }
 
+/// calculate the PDA of the drift fulfillment config for a Phoenix market
+pub fn derive_phoenix_fulfillment_config(phoenix_market: &Pubkey) -> Pubkey {
+    let (account, _seed) = Pubkey::find_program_address(
+        &[&b"phoenix_fulfillment_config"[..], phoenix_market.as_ref()],
+        &PROGRAM_ID,
+    );
+    account
+}
+
+/// calculate the PDA of the drift fulfillment config for an OpenBook v2 market
+pub fn derive_openbook_v2_fulfillment_config(openbook_v2_market: &Pubkey) -> Pubkey {
+    let (account, _seed) = Pubkey::find_program_address(
+        &[
+            &b"openbook_v2_fulfillment_config"[..],
+            openbook_v2_market.as_ref(),
+        ],
+        &PROGRAM_ID,
+    );
+    account
+}
+
+/// calculate the associated token account of `owner` for `mint`
+pub fn derive_associated_token_account(owner: &Pubkey, mint: &Pubkey) -> Pubkey {
+    let (account, _seed) = Pubkey::find_program_address(
+        &[owner.as_ref(), TOKEN_PROGRAM_ID.as_ref(), mint.as_ref()],
+        &ASSOCIATED_TOKEN_PROGRAM_ID,
+    );
+    account
+}
+
 /// calculate the PDA for the drift signer
 pub fn derive_drift_signer() -> Pubkey {
     let (account, _seed) = Pubkey::find_program_address(&[&b"drift_signer"[..]], &PROGRAM_ID);
@@ -0,0 +1,350 @@
+//! Market data statistics over L2 books
+//!
+//! Prices are PRICE_PRECISION, sizes are the market's base precision and quote amounts are
+//! QUOTE_PRECISION, matching `L2Level`.
+
+use crate::types::MarketId;
+
+use super::order_book_levels::{L2Level, L2OrderBook};
+
+const BPS: f64 = 10_000.0;
+
+/// Best prices of a book and how they relate
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TopOfBook {
+    pub best_bid: Option<u128>,
+    pub best_ask: Option<u128>,
+    /// mean of the best bid and ask
+    pub mid: Option<u128>,
+    /// `(ask - bid) / mid`, negative if the book is crossed
+    pub spread_bps: Option<f64>,
+    /// `(bid size - ask size) / (bid size + ask size)` at the best levels, in -1..=1
+    pub imbalance: Option<f64>,
+}
+
+/// Amount to take from one side of a book
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FillSize {
+    /// base asset amount
+    Base(u64),
+    /// quote asset amount
+    Quote(u64),
+}
+
+/// Result of walking one side of a book to fill a size
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FillEstimate {
+    pub base_filled: u128,
+    pub quote_filled: u128,
+    /// volume weighted average fill price
+    pub vwap: u128,
+    /// price of the last level taken from
+    pub worst_price: u128,
+    /// distance of the vwap from the best level
+    pub price_impact_bps: f64,
+    /// false if the book ran out before the size was filled
+    pub fully_filled: bool,
+}
+
+/// Liquidity resting within `bps` of the oracle price
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BookDepth {
+    pub bps: u32,
+    pub bid_base: u128,
+    pub bid_quote: u128,
+    pub ask_base: u128,
+    pub ask_quote: u128,
+}
+
+/// Snapshot statistics of one market's book
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarketAnalytics {
+    pub market: MarketId,
+    pub slot: u64,
+    pub oracle_price: i64,
+    pub top_of_book: TopOfBook,
+    /// imbalance over every level of the book
+    pub book_imbalance: Option<f64>,
+    /// one entry per requested bps band, in request order
+    pub depth: Vec<BookDepth>,
+}
+
+fn quote_amount(price: u128, base: u128, base_precision: u64) -> u128 {
+    price * base / base_precision as u128
+}
+
+fn imbalance(bid_size: u128, ask_size: u128) -> Option<f64> {
+    let total = bid_size + ask_size;
+    if total == 0 {
+        return None;
+    }
+
+    Some((bid_size as f64 - ask_size as f64) / total as f64)
+}
+
+fn side_size<'a>(levels: impl Iterator<Item = &'a L2Level>) -> u128 {
+    levels.map(|level| level.size.unsigned_abs()).sum()
+}
+
+pub fn top_of_book(book: &L2OrderBook) -> TopOfBook {
+    let best_bid = book.bids.first();
+    let best_ask = book.asks.first();
+
+    let mut top = TopOfBook {
+        best_bid: best_bid.map(|level| level.price),
+        best_ask: best_ask.map(|level| level.price),
+        ..Default::default()
+    };
+
+    if let (Some(bid), Some(ask)) = (best_bid, best_ask) {
+        let mid = (bid.price + ask.price) / 2;
+        top.mid = Some(mid);
+        if mid > 0 {
+            top.spread_bps = Some((ask.price as f64 - bid.price as f64) / mid as f64 * BPS);
+        }
+        top.imbalance = imbalance(bid.size.unsigned_abs(), ask.size.unsigned_abs());
+    }
+
+    top
+}
+
+/// Imbalance of the first `num_levels` levels of each side
+pub fn book_imbalance(book: &L2OrderBook, num_levels: usize) -> Option<f64> {
+    imbalance(
+        side_size(book.bids.iter().take(num_levels)),
+        side_size(book.asks.iter().take(num_levels)),
+    )
+}
+
+/// Walk `levels` best first (asks to buy, bids to sell) until `size` is filled
+///
+/// Returns `None` if there is no liquidity to fill from
+pub fn estimate_fill(
+    levels: &[L2Level],
+    size: FillSize,
+    base_precision: u64,
+) -> Option<FillEstimate> {
+    let mut base_filled = 0_u128;
+    let mut quote_filled = 0_u128;
+    let mut best_price = None;
+    let mut worst_price = 0;
+    let mut fully_filled = false;
+
+    for level in levels.iter().filter(|l| l.size != 0 && l.price != 0) {
+        let level_base = level.size.unsigned_abs();
+        let level_quote = quote_amount(level.price, level_base, base_precision);
+        best_price.get_or_insert(level.price);
+        worst_price = level.price;
+
+        let (base, quote) = match size {
+            FillSize::Base(target) => {
+                let remaining = target as u128 - base_filled;
+                if remaining <= level_base {
+                    fully_filled = true;
+                    (
+                        remaining,
+                        quote_amount(level.price, remaining, base_precision),
+                    )
+                } else {
+                    (level_base, level_quote)
+                }
+            }
+            FillSize::Quote(target) => {
+                let remaining = target as u128 - quote_filled;
+                if remaining <= level_quote {
+                    fully_filled = true;
+                    (remaining * base_precision as u128 / level.price, remaining)
+                } else {
+                    (level_base, level_quote)
+                }
+            }
+        };
+
+        base_filled += base;
+        quote_filled += quote;
+        if fully_filled {
+            break;
+        }
+    }
+
+    let best_price = best_price?;
+    if base_filled == 0 {
+        return None;
+    }
+
+    let vwap = quote_filled * base_precision as u128 / base_filled;
+    Some(FillEstimate {
+        base_filled,
+        quote_filled,
+        vwap,
+        worst_price,
+        price_impact_bps: (vwap as f64 - best_price as f64).abs() / best_price as f64 * BPS,
+        fully_filled,
+    })
+}
+
+/// Cumulative size of bids priced at or above, and asks at or below, `bps` from `oracle_price`
+pub fn depth_within_bps(
+    book: &L2OrderBook,
+    oracle_price: i64,
+    bps: u32,
+    base_precision: u64,
+) -> BookDepth {
+    let oracle_price = oracle_price.max(0) as u128;
+    let bid_bound = oracle_price * 10_000_u128.saturating_sub(bps as u128) / 10_000;
+    let ask_bound = oracle_price * (10_000 + bps as u128) / 10_000;
+
+    let mut depth = BookDepth {
+        bps,
+        ..Default::default()
+    };
+
+    for level in book.bids.iter().take_while(|l| l.price >= bid_bound) {
+        let base = level.size.unsigned_abs();
+        depth.bid_base += base;
+        depth.bid_quote += quote_amount(level.price, base, base_precision);
+    }
+    for level in book.asks.iter().take_while(|l| l.price <= ask_bound) {
+        let base = level.size.unsigned_abs();
+        depth.ask_base += base;
+        depth.ask_quote += quote_amount(level.price, base, base_precision);
+    }
+
+    depth
+}
+
+pub fn market_analytics(
+    market: MarketId,
+    book: &L2OrderBook,
+    oracle_price: i64,
+    base_precision: u64,
+    depth_bps: &[u32],
+) -> MarketAnalytics {
+    MarketAnalytics {
+        market,
+        slot: book.slot,
+        oracle_price,
+        top_of_book: top_of_book(book),
+        book_imbalance: book_imbalance(book, usize::MAX),
+        depth: depth_bps
+            .iter()
+            .map(|bps| depth_within_bps(book, oracle_price, *bps, base_precision))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::dlob::order_book_levels::LiquiditySource;
+
+    const PRICE: u128 = 1_000_000;
+    const BASE: u64 = 1_000_000_000;
+
+    fn level(price: u128, size: u64, source: LiquiditySource) -> L2Level {
+        let size = size as i128;
+        L2Level::new(price, size, HashMap::from([(source, size)]))
+    }
+
+    // bids 99 / 98 / 97, asks 101 / 102 / 104, mixed dlob and vamm liquidity
+    fn book() -> L2OrderBook {
+        L2OrderBook {
+            bids: vec![
+                level(99 * PRICE, 3 * BASE, LiquiditySource::Dlob),
+                level(98 * PRICE, 2 * BASE, LiquiditySource::Vamm),
+                level(97 * PRICE, 5 * BASE, LiquiditySource::Vamm),
+            ],
+            asks: vec![
+                level(101 * PRICE, BASE, LiquiditySource::Dlob),
+                level(102 * PRICE, 2 * BASE, LiquiditySource::Vamm),
+                level(104 * PRICE, 4 * BASE, LiquiditySource::Vamm),
+            ],
+            slot: 7,
+        }
+    }
+
+    #[test]
+    fn top_of_book_stats() {
+        let top = top_of_book(&book());
+
+        assert_eq!(top.best_bid, Some(99 * PRICE));
+        assert_eq!(top.best_ask, Some(101 * PRICE));
+        assert_eq!(top.mid, Some(100 * PRICE));
+        assert!((top.spread_bps.unwrap() - 200.0).abs() < 1e-9);
+        assert!((top.imbalance.unwrap() - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn one_sided_book_has_no_mid() {
+        let mut book = book();
+        book.asks.clear();
+        let top = top_of_book(&book);
+
+        assert_eq!(top.best_bid, Some(99 * PRICE));
+        assert_eq!(top.mid, None);
+        assert_eq!(top.spread_bps, None);
+        assert!((book_imbalance(&book, 2).unwrap() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fill_base_size_across_levels() {
+        let estimate = estimate_fill(&book().asks, FillSize::Base(2 * BASE), BASE).unwrap();
+
+        assert!(estimate.fully_filled);
+        assert_eq!(estimate.base_filled, 2 * BASE as u128);
+        assert_eq!(estimate.quote_filled, 203 * PRICE);
+        assert_eq!(estimate.vwap, 101_500_000);
+        assert_eq!(estimate.worst_price, 102 * PRICE);
+        assert!((estimate.price_impact_bps - 0.5 / 101.0 * 10_000.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn fill_quote_size_across_levels() {
+        let estimate =
+            estimate_fill(&book().bids, FillSize::Quote(396 * PRICE as u64), BASE).unwrap();
+
+        // 3 @ 99 = 297, then 99 quote of the 98 level
+        assert!(estimate.fully_filled);
+        assert_eq!(estimate.quote_filled, 396 * PRICE);
+        assert_eq!(
+            estimate.base_filled,
+            3 * BASE as u128 + 99 * BASE as u128 / 98
+        );
+        assert_eq!(estimate.worst_price, 98 * PRICE);
+        assert!(estimate.vwap < 99 * PRICE && estimate.vwap > 98 * PRICE);
+    }
+
+    #[test]
+    fn fill_larger_than_book() {
+        let estimate = estimate_fill(&book().asks, FillSize::Base(10 * BASE), BASE).unwrap();
+
+        assert!(!estimate.fully_filled);
+        assert_eq!(estimate.base_filled, 7 * BASE as u128);
+        assert_eq!(estimate.worst_price, 104 * PRICE);
+
+        assert_eq!(estimate_fill(&[], FillSize::Base(BASE), BASE), None);
+    }
+
+    #[test]
+    fn depth_around_oracle() {
+        let book = book();
+        let oracle = 100 * PRICE as i64;
+
+        let depth = depth_within_bps(&book, oracle, 200, BASE);
+        assert_eq!(depth.bid_base, 5 * BASE as u128);
+        assert_eq!(depth.bid_quote, (3 * 99 + 2 * 98) * PRICE);
+        assert_eq!(depth.ask_base, 3 * BASE as u128);
+        assert_eq!(depth.ask_quote, (101 + 2 * 102) * PRICE);
+
+        let depth = depth_within_bps(&book, oracle, 50, BASE);
+        assert_eq!((depth.bid_base, depth.ask_base), (0, 0));
+
+        let analytics = market_analytics(MarketId::perp(0), &book, oracle, BASE, &[50, 200]);
+        assert_eq!(analytics.slot, 7);
+        assert_eq!(analytics.depth.len(), 2);
+        assert_eq!(analytics.depth[1].bps, 200);
+        assert!((analytics.book_imbalance.unwrap() - 3.0 / 17.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,474 @@
+//! Incremental L3 and L2 updates of DLOB markets
+//!
+//! Each time the DLOB is rebuilt, `BookDiffTracker` compares every market with the state it
+//! last published and emits a `BookDiff` for markets that changed. Diffs of a market carry
+//! consecutive sequence numbers: a client starts from a `BookSnapshot`, applies the diffs that
+//! follow it with `BookSnapshot::apply` and fetches a new snapshot when it finds a gap.
+//!
+//! L2 levels here aggregate resting DLOB orders only, vAMM liquidity is not included.
+
+use std::collections::{BTreeMap, HashMap};
+
+use drift::state::user::MarketType;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::types::MarketId;
+
+use super::order_book_levels::{L3Level, L3OrderBook};
+
+/// (user account, order id)
+type OrderKey = (Pubkey, u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BookSide {
+    Bid,
+    Ask,
+}
+
+/// A resting order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct L3Order {
+    pub side: BookSide,
+    pub price: u64,
+    pub size: u64,
+    pub maker: Pubkey,
+    pub order_id: u32,
+}
+
+impl L3Order {
+    fn new(side: BookSide, level: &L3Level) -> Self {
+        Self {
+            side,
+            price: level.price,
+            size: level.size,
+            maker: level.maker,
+            order_id: level.order_id,
+        }
+    }
+
+    fn key(&self) -> OrderKey {
+        (self.maker, self.order_id)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum L3Event {
+    /// an order started resting, also emitted after `Removed` when an order's price moves
+    Added(L3Order),
+    /// an order was partially filled, `order.size` is the size left
+    Reduced { order: L3Order, reduced_by: u64 },
+    /// an order was filled, cancelled or expired
+    Removed(L3Order),
+    /// a trigger order's condition was met, it is `Added` once it rests
+    Triggered { maker: Pubkey, order_id: u32 },
+}
+
+/// New total size at a price, 0 if the level is gone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct L2Change {
+    pub side: BookSide,
+    pub price: u64,
+    pub size: u64,
+}
+
+/// Changes to one market between two DLOB builds
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookDiff {
+    pub market: MarketId,
+    pub slot: u64,
+    /// one more than the sequence of the market's previous diff
+    pub sequence: u64,
+    pub l3: Vec<L3Event>,
+    pub l2: Vec<L2Change>,
+}
+
+/// Result of applying a diff to a snapshot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffStatus {
+    Applied,
+    /// the snapshot already contains the diff, or it is for another market
+    Ignored,
+    /// diffs were missed, a new snapshot must be fetched
+    Gap,
+}
+
+/// Orders and L2 levels of one market as of `sequence`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BookSnapshot {
+    pub market: MarketId,
+    pub slot: u64,
+    pub sequence: u64,
+    orders: HashMap<OrderKey, L3Order>,
+    bids: BTreeMap<u64, u64>,
+    asks: BTreeMap<u64, u64>,
+}
+
+impl BookSnapshot {
+    fn new(market: MarketId) -> Self {
+        Self {
+            market,
+            ..Default::default()
+        }
+    }
+
+    pub fn orders(&self) -> impl Iterator<Item = &L3Order> {
+        self.orders.values()
+    }
+
+    /// (price, size) of bid levels, best first
+    pub fn l2_bids(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.bids.iter().rev().map(|(price, size)| (*price, *size))
+    }
+
+    /// (price, size) of ask levels, best first
+    pub fn l2_asks(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.asks.iter().map(|(price, size)| (*price, *size))
+    }
+
+    fn levels_mut(&mut self, side: BookSide) -> &mut BTreeMap<u64, u64> {
+        match side {
+            BookSide::Bid => &mut self.bids,
+            BookSide::Ask => &mut self.asks,
+        }
+    }
+
+    /// Apply the next diff of this market
+    pub fn apply(&mut self, diff: &BookDiff) -> DiffStatus {
+        if diff.market != self.market || diff.sequence <= self.sequence {
+            return DiffStatus::Ignored;
+        }
+        if diff.sequence != self.sequence + 1 {
+            return DiffStatus::Gap;
+        }
+
+        for event in &diff.l3 {
+            match event {
+                L3Event::Added(order) | L3Event::Reduced { order, .. } => {
+                    self.orders.insert(order.key(), *order);
+                }
+                L3Event::Removed(order) => {
+                    self.orders.remove(&order.key());
+                }
+                L3Event::Triggered { .. } => {}
+            }
+        }
+        for change in &diff.l2 {
+            let levels = self.levels_mut(change.side);
+            if change.size == 0 {
+                levels.remove(&change.price);
+            } else {
+                levels.insert(change.price, change.size);
+            }
+        }
+
+        self.slot = diff.slot;
+        self.sequence = diff.sequence;
+        DiffStatus::Applied
+    }
+}
+
+fn l2_changes(
+    side: BookSide,
+    old: &BTreeMap<u64, u64>,
+    new: &BTreeMap<u64, u64>,
+    changes: &mut Vec<L2Change>,
+) {
+    for (price, size) in new {
+        if old.get(price) != Some(size) {
+            changes.push(L2Change {
+                side,
+                price: *price,
+                size: *size,
+            });
+        }
+    }
+    for price in old.keys().filter(|price| !new.contains_key(price)) {
+        changes.push(L2Change {
+            side,
+            price: *price,
+            size: 0,
+        });
+    }
+}
+
+#[derive(Default)]
+struct MarketState {
+    book: BookSnapshot,
+    triggered: HashMap<OrderKey, bool>,
+}
+
+/// Last published state of every market, see the module docs
+#[derive(Default)]
+pub(crate) struct BookDiffTracker {
+    perp: HashMap<u16, MarketState>,
+    spot: HashMap<u16, MarketState>,
+}
+
+impl BookDiffTracker {
+    fn markets(&self, market_type: MarketType) -> &HashMap<u16, MarketState> {
+        match market_type {
+            MarketType::Perp => &self.perp,
+            MarketType::Spot => &self.spot,
+        }
+    }
+
+    pub(crate) fn snapshot(&self, market: MarketId) -> Option<BookSnapshot> {
+        self.markets(market.kind())
+            .get(&market.index())
+            .map(|state| state.book.clone())
+    }
+
+    pub(crate) fn tracked_markets(&self) -> Vec<MarketId> {
+        let perp = self.perp.keys().map(|index| MarketId::perp(*index));
+        let spot = self.spot.keys().map(|index| MarketId::spot(*index));
+
+        perp.chain(spot).collect()
+    }
+
+    /// Compare `market` with its new resting orders and the (user account, order id, triggered)
+    /// of its trigger orders, returns `None` if nothing changed
+    pub(crate) fn update(
+        &mut self,
+        market: MarketId,
+        l3: &L3OrderBook,
+        trigger_orders: &[(Pubkey, u32, bool)],
+    ) -> Option<BookDiff> {
+        let markets = match market.kind() {
+            MarketType::Perp => &mut self.perp,
+            MarketType::Spot => &mut self.spot,
+        };
+        let state = markets
+            .entry(market.index())
+            .or_insert_with(|| MarketState {
+                book: BookSnapshot::new(market),
+                ..Default::default()
+            });
+
+        let mut events = vec![];
+
+        let triggered: HashMap<OrderKey, bool> = trigger_orders
+            .iter()
+            .map(|(maker, order_id, triggered)| ((*maker, *order_id), *triggered))
+            .collect();
+        for (&(maker, order_id), is_triggered) in &triggered {
+            if *is_triggered && state.triggered.get(&(maker, order_id)) == Some(&false) {
+                events.push(L3Event::Triggered { maker, order_id });
+            }
+        }
+        state.triggered = triggered;
+
+        let mut orders = HashMap::new();
+        let mut bids = BTreeMap::new();
+        let mut asks = BTreeMap::new();
+        let sides = [(BookSide::Bid, &l3.bids), (BookSide::Ask, &l3.asks)];
+        for (side, levels) in sides {
+            for level in levels.iter() {
+                let order = L3Order::new(side, level);
+                orders.insert(order.key(), order);
+                let l2_levels = match side {
+                    BookSide::Bid => &mut bids,
+                    BookSide::Ask => &mut asks,
+                };
+                *l2_levels.entry(order.price).or_insert(0) += order.size;
+            }
+        }
+
+        for (key, order) in &orders {
+            match state.book.orders.get(key) {
+                None => events.push(L3Event::Added(*order)),
+                Some(old) if old == order => {}
+                Some(old)
+                    if old.side == order.side
+                        && old.price == order.price
+                        && order.size < old.size =>
+                {
+                    events.push(L3Event::Reduced {
+                        order: *order,
+                        reduced_by: old.size - order.size,
+                    });
+                }
+                Some(old) => {
+                    events.push(L3Event::Removed(*old));
+                    events.push(L3Event::Added(*order));
+                }
+            }
+        }
+        for (key, old) in &state.book.orders {
+            if !orders.contains_key(key) {
+                events.push(L3Event::Removed(*old));
+            }
+        }
+
+        let mut changes = vec![];
+        l2_changes(BookSide::Bid, &state.book.bids, &bids, &mut changes);
+        l2_changes(BookSide::Ask, &state.book.asks, &asks, &mut changes);
+
+        if events.is_empty() && changes.is_empty() {
+            // the snapshot keeps the slot of the last diff, which clients apply it on top of
+            return None;
+        }
+
+        let book = &mut state.book;
+        book.slot = l3.slot;
+        book.orders = orders;
+        book.bids = bids;
+        book.asks = asks;
+        book.sequence += 1;
+        Some(BookDiff {
+            market,
+            slot: l3.slot,
+            sequence: book.sequence,
+            l3: events,
+            l2: changes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(price: u64, size: u64, maker: Pubkey, order_id: u32) -> L3Level {
+        L3Level {
+            price,
+            size,
+            maker,
+            order_id,
+        }
+    }
+
+    fn l3(bids: Vec<L3Level>, asks: Vec<L3Level>, slot: u64) -> L3OrderBook {
+        L3OrderBook { asks, bids, slot }
+    }
+
+    #[test]
+    fn first_update_adds_every_order() {
+        let maker = Pubkey::new_unique();
+        let mut tracker = BookDiffTracker::default();
+        let market = MarketId::perp(0);
+
+        let diff = tracker
+            .update(
+                market,
+                &l3(
+                    vec![level(99, 2, maker, 1), level(99, 3, maker, 2)],
+                    vec![level(101, 1, maker, 3)],
+                    10,
+                ),
+                &[],
+            )
+            .unwrap();
+
+        assert_eq!(diff.sequence, 1);
+        assert_eq!(diff.slot, 10);
+        assert_eq!(diff.l3.len(), 3);
+        assert!(diff
+            .l3
+            .iter()
+            .all(|event| matches!(event, L3Event::Added(_))));
+        assert_eq!(
+            diff.l2,
+            vec![
+                L2Change {
+                    side: BookSide::Bid,
+                    price: 99,
+                    size: 5
+                },
+                L2Change {
+                    side: BookSide::Ask,
+                    price: 101,
+                    size: 1
+                },
+            ]
+        );
+
+        // unchanged book publishes nothing
+        let same = l3(
+            vec![level(99, 2, maker, 1), level(99, 3, maker, 2)],
+            vec![level(101, 1, maker, 3)],
+            11,
+        );
+        assert_eq!(tracker.update(market, &same, &[]), None);
+        assert_eq!(tracker.snapshot(market).unwrap().slot, 10);
+    }
+
+    #[test]
+    fn reduce_remove_and_trigger() {
+        let maker = Pubkey::new_unique();
+        let mut tracker = BookDiffTracker::default();
+        let market = MarketId::spot(1);
+
+        tracker.update(
+            market,
+            &l3(
+                vec![level(99, 5, maker, 1)],
+                vec![level(101, 1, maker, 2)],
+                10,
+            ),
+            &[(maker, 3, false)],
+        );
+        let diff = tracker
+            .update(
+                market,
+                &l3(vec![level(99, 2, maker, 1)], vec![], 11),
+                &[(maker, 3, true)],
+            )
+            .unwrap();
+
+        assert_eq!(diff.sequence, 2);
+        assert!(diff.l3.contains(&L3Event::Triggered { maker, order_id: 3 }));
+        assert!(diff.l3.contains(&L3Event::Reduced {
+            order: L3Order {
+                side: BookSide::Bid,
+                price: 99,
+                size: 2,
+                maker,
+                order_id: 1
+            },
+            reduced_by: 3
+        }));
+        assert!(diff
+            .l3
+            .iter()
+            .any(|event| matches!(event, L3Event::Removed(order) if order.order_id == 2)));
+        assert!(diff.l2.contains(&L2Change {
+            side: BookSide::Ask,
+            price: 101,
+            size: 0
+        }));
+    }
+
+    #[test]
+    fn snapshot_and_diffs_stay_in_sync() {
+        let maker = Pubkey::new_unique();
+        let mut tracker = BookDiffTracker::default();
+        let market = MarketId::perp(2);
+
+        tracker.update(market, &l3(vec![level(99, 5, maker, 1)], vec![], 10), &[]);
+        let mut snapshot = tracker.snapshot(market).unwrap();
+
+        let diff_2 = tracker
+            .update(
+                market,
+                &l3(
+                    vec![level(98, 5, maker, 1)],
+                    vec![level(101, 4, maker, 2)],
+                    11,
+                ),
+                &[],
+            )
+            .unwrap();
+        let diff_3 = tracker
+            .update(market, &l3(vec![], vec![level(101, 4, maker, 2)], 12), &[])
+            .unwrap();
+
+        assert_eq!(snapshot.apply(&diff_3), DiffStatus::Gap);
+        assert_eq!(snapshot.apply(&diff_2), DiffStatus::Applied);
+        assert_eq!(snapshot.apply(&diff_2), DiffStatus::Ignored);
+        assert_eq!(snapshot.apply(&diff_3), DiffStatus::Applied);
+
+        assert_eq!(snapshot, tracker.snapshot(market).unwrap());
+        assert_eq!(snapshot.l2_bids().count(), 0);
+        assert_eq!(snapshot.l2_asks().collect::<Vec<_>>(), vec![(101, 4)]);
+    }
+}
@@ -23,10 +23,11 @@ use crate::dlob::order_book_levels::{create_l2_levels, merge_l2_level_generators
 use crate::event_emitter::Event;
 use crate::math::auction::is_fallback_available_liquidity_source;
 use crate::math::exchange_status::fill_paused;
+use crate::math::fees::get_maker_rebate;
 use crate::math::order::{
     get_limit_price, is_order_expired, is_resting_limit_order, is_triggered, must_be_triggered,
 };
-use crate::types::SdkResult;
+use crate::types::{MarketId, SdkResult};
 use crate::usermap::UserMap;
 use crate::utils::market_type_to_string;
 
@@ -288,29 +289,13 @@ impl DLOB {
         state_account: &State,
         market_account: &MarketAccount,
     ) -> (u32, u32) {
-        let (mut marker_rebate_numerator, maker_rebate_denominator) =
-            if MarketType::Perp == market_type {
-                (
-                    state_account.perp_fee_structure.fee_tiers[0].maker_rebate_numerator,
-                    state_account.perp_fee_structure.fee_tiers[0].maker_rebate_denominator,
-                )
-            } else {
-                (
-                    state_account.spot_fee_structure.fee_tiers[0].maker_rebate_numerator,
-                    state_account.spot_fee_structure.fee_tiers[0].maker_rebate_denominator,
-                )
-            };
-
         let fee_adjustment = if let MarketAccount::PerpMarket(perp) = market_account {
-            perp.fee_adjustment | 0
+            perp.fee_adjustment
         } else {
             0
         };
-        if fee_adjustment != 0 {
-            marker_rebate_numerator += (maker_rebate_denominator * fee_adjustment as u32) / 100;
-        }
 
-        (marker_rebate_numerator, maker_rebate_denominator)
+        get_maker_rebate(state_account, market_type, fee_adjustment)
     }
 
     fn merge_nodes_to_fill(
@@ -873,6 +858,34 @@ impl DLOB {
         best_orders
     }
 
+    /// Prices of the best resting limit bid and ask of a market, `None` for an empty side
+    pub fn get_best_resting_limit_prices(
+        &mut self,
+        market_index: u16,
+        market_type: &MarketType,
+        slot: u64,
+        oracle_price_data: &OraclePriceData,
+    ) -> (Option<u64>, Option<u64>) {
+        let has_market = match market_type {
+            MarketType::Perp => self.exchange.perp.contains_key(&market_index),
+            MarketType::Spot => self.exchange.spot.contains_key(&market_index),
+        };
+        if !has_market {
+            return (None, None);
+        }
+
+        let best_bid = self
+            .get_resting_limit_bids(slot, market_type, market_index, oracle_price_data)
+            .first()
+            .map(|node| node.get_price(oracle_price_data, slot));
+        let best_ask = self
+            .get_resting_limit_asks(slot, market_type, market_index, oracle_price_data)
+            .first()
+            .map(|node| node.get_price(oracle_price_data, slot));
+
+        (best_bid, best_ask)
+    }
+
     fn get_resting_limit_asks(
         &mut self,
         slot: u64,
@@ -1191,6 +1204,50 @@ impl DLOB {
 
         L3OrderBook { asks, bids, slot }
     }
+
+    /// Markets that have had orders inserted
+    pub fn market_ids(&self) -> Vec<MarketId> {
+        let perp = self.exchange.perp.iter().map(|m| MarketId::perp(*m.key()));
+        let spot = self.exchange.spot.iter().map(|m| MarketId::spot(*m.key()));
+
+        perp.chain(spot).collect()
+    }
+
+    /// (user account, order id, triggered) of each trigger order in a market
+    pub fn get_trigger_orders(
+        &self,
+        market_index: u16,
+        market_type: &MarketType,
+    ) -> Vec<(Pubkey, u32, bool)> {
+        let market = match market_type {
+            MarketType::Perp => self.exchange.perp.get(&market_index),
+            MarketType::Spot => self.exchange.spot.get(&market_index),
+        };
+        let market = match market {
+            Some(market) => market,
+            None => return vec![],
+        };
+
+        [
+            &market.resting_limit_orders,
+            &market.floating_limit_orders,
+            &market.taking_limit_orders,
+            &market.market_orders,
+            &market.trigger_orders,
+        ]
+        .iter()
+        .flat_map(|order_list| order_list.bids.iter().chain(order_list.asks.iter()))
+        .filter(|node| must_be_triggered(node.node.get_order()))
+        .map(|node| {
+            let order = node.node.get_order();
+            (
+                node.node.get_user_account(),
+                order.order_id,
+                is_triggered(order),
+            )
+        })
+        .collect()
+    }
 }
 
 impl Default for DLOB {
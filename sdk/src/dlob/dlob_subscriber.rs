@@ -1,20 +1,28 @@
 use std::sync::Arc;
 
-use drift::state::user::MarketType;
+use drift::{
+    controller::position::PositionDirection,
+    state::user::{MarketType, OrderType},
+};
 use log::info;
 use tokio::{
-    sync::Mutex,
+    sync::{broadcast, Mutex},
     time::{self, Duration},
 };
 
 use crate::{
+    constants::PRICE_PRECISION,
     drift_client::DriftClient,
     event_emitter::EventEmitter,
-    types::{SdkError, SdkResult},
+    math::auction::{derive_market_order_auction, AuctionConfig},
+    order_builder::{AuctionParams, NewOrder, OrderMarket},
+    types::{MarketId, SdkError, SdkResult},
     AccountProvider,
 };
 
 use super::{
+    analytics::{estimate_fill, market_analytics, FillEstimate, FillSize, MarketAnalytics},
+    book_diff::{BookDiff, BookDiffTracker, BookSnapshot},
     dlob::DLOB,
     order_book_levels::{
         L2OrderBook, L2OrderBookGenerator, L3OrderBook, VammL2Generator,
@@ -23,8 +31,15 @@ use super::{
     types::{DLOBSubscriptionConfig, DlobSource, SlotSource},
 };
 
+/// L2 levels walked when deriving a market order auction
+const DEFAULT_AUCTION_L2_DEPTH: usize = 20;
+
+/// Book diffs buffered per receiver before it lags
+const BOOK_DIFF_CHANNEL_SIZE: usize = 1024;
+
 struct DLOBSubscriberInner {
     dlob: DLOB,
+    books: BookDiffTracker,
 }
 
 // https://github.com/drift-labs/protocol-v2/blob/master/sdk/src/dlob/DLOBSubscriber.ts
@@ -43,6 +58,8 @@ pub struct DLOBSubscriber<T: AccountProvider> {
     dlob: Arc<Mutex<DLOBSubscriberInner>>,
 
     event_emitter: EventEmitter,
+
+    book_diffs: broadcast::Sender<BookDiff>,
 }
 
 impl<T> DLOBSubscriber<T>
@@ -56,8 +73,12 @@ where
             slot_source: config.slot_source,
             update_frequency: config.update_frequency,
             interval_id: None,
-            dlob: Arc::new(Mutex::new(DLOBSubscriberInner { dlob: DLOB::new() })),
+            dlob: Arc::new(Mutex::new(DLOBSubscriberInner {
+                dlob: DLOB::new(),
+                books: BookDiffTracker::default(),
+            })),
             event_emitter: EventEmitter::new(),
+            book_diffs: broadcast::channel(BOOK_DIFF_CHANNEL_SIZE).0,
         }
     }
 
@@ -113,15 +134,76 @@ where
     async fn update_dlob(&self) -> SdkResult<()> {
         let slot = self.slot_source.get_slot();
         let mut dlob = self.dlob.lock().await;
-        let dlob_source = self.dlob_source.get_dlob(slot);
+        let mut dlob_source = self.dlob_source.get_dlob(slot);
 
         info!("DLOB: {} {}", dlob_source.size().0, dlob_source.size().1);
 
+        self.publish_book_diffs(&mut dlob.books, &mut dlob_source, slot);
         dlob.dlob = dlob_source;
 
         Ok(())
     }
 
+    /// Diff each market of `new_dlob`, and each market it no longer has, against `books` and
+    /// broadcast the changes
+    fn publish_book_diffs(&self, books: &mut BookDiffTracker, new_dlob: &mut DLOB, slot: u64) {
+        let live_markets = new_dlob.market_ids();
+        let mut markets = live_markets.clone();
+        for market in books.tracked_markets() {
+            if !markets.contains(&market) {
+                markets.push(market);
+            }
+        }
+
+        for market in markets {
+            let (l3, trigger_orders) = if live_markets.contains(&market) {
+                let oracle = if market.kind() == MarketType::Perp {
+                    self.drift_client
+                        .get_oracle_price_data_and_slot_for_perp_market(market.index())
+                } else {
+                    self.drift_client
+                        .get_oracle_price_data_and_slot_for_spot_market(market.index())
+                };
+                let oracle = match oracle {
+                    Some(oracle) => oracle,
+                    None => {
+                        log::warn!("no oracle for {market:?}, skipping book diff");
+                        continue;
+                    }
+                };
+                (
+                    new_dlob.get_l3(market.index(), &market.kind(), slot, &oracle.data),
+                    new_dlob.get_trigger_orders(market.index(), &market.kind()),
+                )
+            } else {
+                let empty = L3OrderBook {
+                    asks: vec![],
+                    bids: vec![],
+                    slot,
+                };
+                (empty, vec![])
+            };
+
+            if let Some(diff) = books.update(market, &l3, &trigger_orders) {
+                // no receivers is not an error
+                let _ = self.book_diffs.send(diff);
+            }
+        }
+    }
+
+    /// Per-market L3 and L2 changes of each DLOB update, see `dlob::book_diff`
+    ///
+    /// A receiver that lags or gets `DiffStatus::Gap` from `BookSnapshot::apply` should resync
+    /// from `get_book_snapshot`
+    pub fn subscribe_book_diffs(&self) -> broadcast::Receiver<BookDiff> {
+        self.book_diffs.subscribe()
+    }
+
+    /// Book of `market` as of its latest published diff
+    pub async fn get_book_snapshot(&self, market: MarketId) -> Option<BookSnapshot> {
+        self.dlob.lock().await.books.snapshot(market)
+    }
+
     pub async fn get_dlob(&self) -> DLOB {
         self.dlob.lock().await.dlob.clone()
     }
@@ -262,6 +344,139 @@ where
         ))
     }
 
+    /// Oracle price and base precision of `market`
+    fn oracle_price_and_base_precision(&self, market: MarketId) -> SdkResult<(i64, u64)> {
+        let (oracle, base_precision) = if market.kind() == MarketType::Perp {
+            let perp_market = self
+                .drift_client
+                .get_perp_market_account(market.index())
+                .ok_or_else(|| {
+                    SdkError::Generic(format!("perp market {} not found", market.index()))
+                })?;
+            (
+                self.drift_client
+                    .get_oracle_price_data_and_slot_for_perp_market(market.index()),
+                perp_market.base_precision(),
+            )
+        } else {
+            let spot_market = self
+                .drift_client
+                .get_spot_market_account(market.index())
+                .ok_or_else(|| {
+                    SdkError::Generic(format!("spot market {} not found", market.index()))
+                })?;
+            (
+                self.drift_client
+                    .get_oracle_price_data_and_slot_for_spot_market(market.index()),
+                spot_market.base_precision(),
+            )
+        };
+        let oracle_price = oracle.ok_or(SdkError::InvalidOracle)?.data.price;
+
+        Ok((oracle_price, base_precision))
+    }
+
+    /// Top of book, imbalance and depth within each of `depth_bps` of the oracle for `market`,
+    /// from its L2 book (including the vAMM for perps) up to `depth` levels
+    pub async fn get_market_analytics(
+        &mut self,
+        market: MarketId,
+        depth: usize,
+        depth_bps: &[u32],
+    ) -> SdkResult<MarketAnalytics> {
+        let (oracle_price, base_precision) = self.oracle_price_and_base_precision(market)?;
+        let l2 = self.get_market_l2(market, depth).await?;
+
+        Ok(market_analytics(
+            market,
+            &l2,
+            oracle_price,
+            base_precision,
+            depth_bps,
+        ))
+    }
+
+    /// VWAP and price impact of a `direction` taker order of `size` in `market`, walking up to
+    /// `depth` levels of its L2 book (including the vAMM for perps)
+    ///
+    /// Returns `None` if the side taken from is empty
+    pub async fn estimate_fill(
+        &mut self,
+        market: MarketId,
+        direction: PositionDirection,
+        size: FillSize,
+        depth: usize,
+    ) -> SdkResult<Option<FillEstimate>> {
+        let (_, base_precision) = self.oracle_price_and_base_precision(market)?;
+        let l2 = self.get_market_l2(market, depth).await?;
+        let levels = match direction {
+            PositionDirection::Long => &l2.asks,
+            PositionDirection::Short => &l2.bids,
+        };
+
+        Ok(estimate_fill(levels, size, base_precision))
+    }
+
+    async fn get_market_l2(&mut self, market: MarketId, depth: usize) -> SdkResult<L2OrderBook> {
+        self.get_l2(
+            None,
+            Some(market.index()),
+            Some(market.kind()),
+            depth,
+            market.kind() == MarketType::Perp,
+            None,
+            vec![],
+        )
+        .await
+    }
+
+    /// Set the auction and worst price of a `Market` or `Oracle` order from the current L2
+    /// book (including the vAMM for perps) and oracle price
+    ///
+    /// see `math::auction::derive_market_order_auction`
+    pub async fn with_market_order_auction(
+        &mut self,
+        order: NewOrder,
+        config: &AuctionConfig,
+    ) -> SdkResult<NewOrder> {
+        if !matches!(order.order_type(), OrderType::Market | OrderType::Oracle) {
+            return Err(SdkError::Generic(format!(
+                "can't derive an auction for {:?} orders",
+                order.order_type()
+            )));
+        }
+
+        let market = order.market_id();
+        let (oracle_price, base_precision) = self.oracle_price_and_base_precision(market)?;
+
+        let l2 = self.get_market_l2(market, DEFAULT_AUCTION_L2_DEPTH).await?;
+        let levels = match order.direction() {
+            PositionDirection::Long => &l2.asks,
+            PositionDirection::Short => &l2.bids,
+        };
+
+        let base_asset_amount = (order.size() * base_precision as f64).round() as u64;
+        let mut auction = derive_market_order_auction(
+            levels,
+            oracle_price,
+            order.direction(),
+            base_asset_amount,
+            config,
+        )?;
+        if order.order_type() == OrderType::Oracle {
+            auction = auction.to_oracle_offsets(oracle_price);
+        }
+
+        let to_price = |price: i64| price as f64 / PRICE_PRECISION as f64;
+        Ok(order
+            .price(to_price(auction.limit_price))
+            .auction(AuctionParams {
+                duration: auction.duration,
+                start_price: to_price(auction.start_price),
+                end_price: to_price(auction.end_price),
+            }))
+    }
+
     pub async fn unsubscribe(&mut self) {
         if self.interval_id.is_some() {
             self.interval_id = None
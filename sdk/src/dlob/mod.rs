@@ -1,8 +1,14 @@
+pub mod analytics;
+pub mod book_diff;
 pub mod dlob;
 pub mod dlob_builder;
 pub mod dlob_node;
 pub mod dlob_subscriber;
 pub mod market;
-pub(crate) mod order_book_levels;
+pub mod openbook;
+pub mod order_book_levels;
 pub mod order_list;
+pub mod phoenix;
+#[cfg(test)]
+mod test_fixtures;
 pub mod types;
@@ -0,0 +1,410 @@
+//! L2 levels of an OpenBook v2 market decoded from its raw market and book side account data
+//!
+//! Bids and asks live in separate `BookSide` accounts, each a crit-bit tree of 1024 88 byte nodes,
+//! see https://github.com/openbook-dex/openbook-v2/tree/master/programs/openbook-v2/src/state
+//!
+//! Only the fixed price tree is decoded, oracle pegged orders are not included.
+
+use solana_sdk::{hash::hash, pubkey::Pubkey};
+
+use crate::{
+    constants::PRICE_PRECISION,
+    types::{SdkError, SdkResult},
+    utils::read_bytes,
+};
+
+use super::order_book_levels::{
+    aggregate_orders, l2_levels_from_source, L2Level, L2OrderBookGenerator, LiquiditySource,
+};
+
+// `Market` fields, after the 8 byte discriminator
+const BASE_DECIMALS_OFFSET: usize = 9;
+const QUOTE_DECIMALS_OFFSET: usize = 10;
+const BIDS_OFFSET: usize = 200;
+const ASKS_OFFSET: usize = 232;
+const QUOTE_LOT_SIZE_OFFSET: usize = 448;
+const BASE_LOT_SIZE_OFFSET: usize = 456;
+
+// `BookSide` fields, after the 8 byte discriminator
+/// `roots[0]`, the fixed price tree: (node handle, leaf count)
+const FIXED_ROOT_OFFSET: usize = 8;
+const ORDER_TREE_TYPE_OFFSET: usize = 312;
+const NODES_OFFSET: usize = 840;
+const MAX_NODES: usize = 1024;
+const NODE_SIZE: usize = 88;
+/// 90952 bytes, discriminator included
+const BOOK_SIDE_SIZE: usize = NODES_OFFSET + MAX_NODES * NODE_SIZE;
+
+const INNER_NODE_TAG: u8 = 1;
+const LEAF_NODE_TAG: u8 = 2;
+const BIDS_TREE_TYPE: u8 = 0;
+const ASKS_TREE_TYPE: u8 = 1;
+
+// `InnerNode` and `LeafNode` fields
+const CHILDREN_OFFSET: usize = 24;
+const TIME_IN_FORCE_OFFSET: usize = 2;
+const KEY_OFFSET: usize = 8;
+const QUANTITY_OFFSET: usize = 56;
+const TIMESTAMP_OFFSET: usize = 64;
+
+fn read_u8(data: &[u8], offset: usize) -> SdkResult<u8> {
+    Ok(read_bytes::<1>(data, offset)?[0])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> SdkResult<u32> {
+    Ok(u32::from_le_bytes(read_bytes(data, offset)?))
+}
+
+fn read_i64(data: &[u8], offset: usize) -> SdkResult<i64> {
+    Ok(i64::from_le_bytes(read_bytes(data, offset)?))
+}
+
+/// Anchor account discriminator of `name`
+fn discriminator(name: &str) -> [u8; 8] {
+    let mut discriminator = [0; 8];
+    discriminator.copy_from_slice(&hash(format!("account:{name}").as_bytes()).to_bytes()[..8]);
+    discriminator
+}
+
+fn check_discriminator(data: &[u8], name: &str) -> SdkResult<()> {
+    if read_bytes::<8>(data, 0)? != discriminator(name) {
+        return Err(SdkError::UnsupportedAccountData);
+    }
+
+    Ok(())
+}
+
+/// (bids, asks) book side accounts of an OpenBook v2 market account
+pub fn get_book_sides(market_data: &[u8]) -> SdkResult<(Pubkey, Pubkey)> {
+    check_discriminator(market_data, "Market")?;
+
+    Ok((
+        Pubkey::new_from_array(read_bytes(market_data, BIDS_OFFSET)?),
+        Pubkey::new_from_array(read_bytes(market_data, ASKS_OFFSET)?),
+    ))
+}
+
+/// Live (price lots, base lots) orders of the fixed price tree of a `BookSide` account
+fn book_side_orders(data: &[u8], tree_type: u8, now: i64) -> SdkResult<Vec<(i64, i64)>> {
+    check_discriminator(data, "BookSide")?;
+    if data.len() < BOOK_SIDE_SIZE {
+        return Err(SdkError::Deserializing);
+    }
+    if read_u8(data, ORDER_TREE_TYPE_OFFSET)? != tree_type {
+        return Err(SdkError::UnsupportedAccountData);
+    }
+
+    let mut orders = vec![];
+    if read_u32(data, FIXED_ROOT_OFFSET + 4)? == 0 {
+        return Ok(orders);
+    }
+
+    let mut stack = vec![read_u32(data, FIXED_ROOT_OFFSET)?];
+    let mut visited = 0;
+    while let Some(handle) = stack.pop() {
+        if handle as usize >= MAX_NODES || visited == MAX_NODES {
+            return Err(SdkError::Deserializing);
+        }
+        visited += 1;
+
+        let node = NODES_OFFSET + handle as usize * NODE_SIZE;
+        match read_u8(data, node)? {
+            INNER_NODE_TAG => {
+                stack.push(read_u32(data, node + CHILDREN_OFFSET)?);
+                stack.push(read_u32(data, node + CHILDREN_OFFSET + 4)?);
+            }
+            LEAF_NODE_TAG => {
+                let time_in_force =
+                    u16::from_le_bytes(read_bytes(data, node + TIME_IN_FORCE_OFFSET)?);
+                let timestamp = read_i64(data, node + TIMESTAMP_OFFSET)?;
+                if time_in_force != 0 && now >= timestamp.saturating_add(time_in_force as i64) {
+                    continue;
+                }
+
+                let key = u128::from_le_bytes(read_bytes(data, node + KEY_OFFSET)?);
+                orders.push(((key >> 64) as i64, read_i64(data, node + QUANTITY_OFFSET)?));
+            }
+            _ => return Err(SdkError::Deserializing),
+        }
+    }
+
+    Ok(orders)
+}
+
+/// Resting OpenBook v2 liquidity as fallback L2 levels, tagged `LiquiditySource::Openbook`
+///
+/// Prices are PRICE_PRECISION and sizes are base token atoms (the drift spot market precision)
+pub struct OpenbookV2L2Generator {
+    bids: Vec<(u128, i128)>,
+    asks: Vec<(u128, i128)>,
+}
+
+impl OpenbookV2L2Generator {
+    /// Decode the book of an OpenBook v2 market from its market account and the bids and asks
+    /// accounts named by `get_book_sides`, skipping orders expired at `now`
+    pub fn new(
+        market_data: &[u8],
+        bids_data: &[u8],
+        asks_data: &[u8],
+        now: i64,
+    ) -> SdkResult<Self> {
+        check_discriminator(market_data, "Market")?;
+
+        let base_decimals = read_u8(market_data, BASE_DECIMALS_OFFSET)? as u32;
+        let quote_decimals = read_u8(market_data, QUOTE_DECIMALS_OFFSET)? as u32;
+        let quote_lot_size = read_i64(market_data, QUOTE_LOT_SIZE_OFFSET)?;
+        let base_lot_size = read_i64(market_data, BASE_LOT_SIZE_OFFSET)?;
+        if quote_lot_size <= 0 || base_lot_size <= 0 {
+            return Err(SdkError::Deserializing);
+        }
+
+        let pow10 = |decimals: u32| 10_u128.checked_pow(decimals).ok_or(SdkError::Deserializing);
+        // price lots are quote lots per base lot
+        let price_multiplier = (quote_lot_size as u128)
+            .checked_mul(pow10(base_decimals)?)
+            .and_then(|multiplier| multiplier.checked_mul(PRICE_PRECISION))
+            .ok_or(SdkError::Deserializing)?;
+        let price_divisor = (base_lot_size as u128)
+            .checked_mul(pow10(quote_decimals)?)
+            .ok_or(SdkError::Deserializing)?;
+        let to_levels = |orders: Vec<(i64, i64)>| {
+            orders
+                .into_iter()
+                .filter(|(price_lots, quantity)| *price_lots > 0 && *quantity > 0)
+                .map(|(price_lots, quantity)| {
+                    let price = (price_lots as u128)
+                        .checked_mul(price_multiplier)
+                        .ok_or(SdkError::Deserializing)?
+                        / price_divisor;
+                    Ok((price, quantity as i128 * base_lot_size as i128))
+                })
+                .collect::<SdkResult<Vec<_>>>()
+        };
+
+        let bids = to_levels(book_side_orders(bids_data, BIDS_TREE_TYPE, now)?)?;
+        let asks = to_levels(book_side_orders(asks_data, ASKS_TREE_TYPE, now)?)?;
+
+        Ok(Self {
+            bids: aggregate_orders(bids, true),
+            asks: aggregate_orders(asks, false),
+        })
+    }
+}
+
+impl L2OrderBookGenerator for OpenbookV2L2Generator {
+    fn get_l2_asks(&mut self) -> Box<dyn Iterator<Item = L2Level>> {
+        l2_levels_from_source(self.asks.clone(), LiquiditySource::Openbook)
+    }
+
+    fn get_l2_bids(&mut self) -> Box<dyn Iterator<Item = L2Level>> {
+        l2_levels_from_source(self.bids.clone(), LiquiditySource::Openbook)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dlob::test_fixtures::{load_account, load_expected_top_of_book, ExpectedLevel};
+
+    const MARKET_SIZE: usize = 848;
+
+    fn write(data: &mut [u8], offset: usize, bytes: &[u8]) {
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// SOL/USDC style market: 9 decimal base, 6 decimal quote, 0.001 SOL base lots and
+    /// 1 atom quote lots, so 1 price lot is $0.001
+    fn market_fixture(bids: Pubkey, asks: Pubkey) -> Vec<u8> {
+        let mut data = vec![0; MARKET_SIZE];
+        write(&mut data, 0, &discriminator("Market"));
+        write(&mut data, BASE_DECIMALS_OFFSET, &[9]);
+        write(&mut data, QUOTE_DECIMALS_OFFSET, &[6]);
+        write(&mut data, BIDS_OFFSET, bids.as_ref());
+        write(&mut data, ASKS_OFFSET, asks.as_ref());
+        write(&mut data, QUOTE_LOT_SIZE_OFFSET, &1_i64.to_le_bytes());
+        write(
+            &mut data,
+            BASE_LOT_SIZE_OFFSET,
+            &1_000_000_i64.to_le_bytes(),
+        );
+        data
+    }
+
+    enum Node {
+        Inner([u32; 2]),
+        /// price lots, base lots, time in force
+        Leaf(i64, i64, u16),
+        Free,
+    }
+
+    fn book_side_fixture(tree_type: u8, root: u32, leaf_count: u32, nodes: &[Node]) -> Vec<u8> {
+        let mut data = vec![0; BOOK_SIDE_SIZE];
+        write(&mut data, 0, &discriminator("BookSide"));
+        write(&mut data, FIXED_ROOT_OFFSET, &root.to_le_bytes());
+        write(&mut data, FIXED_ROOT_OFFSET + 4, &leaf_count.to_le_bytes());
+        write(&mut data, ORDER_TREE_TYPE_OFFSET, &[tree_type]);
+
+        for (i, node) in nodes.iter().enumerate() {
+            let offset = NODES_OFFSET + i * NODE_SIZE;
+            match node {
+                Node::Inner(children) => {
+                    write(&mut data, offset, &[INNER_NODE_TAG]);
+                    write(
+                        &mut data,
+                        offset + CHILDREN_OFFSET,
+                        &children[0].to_le_bytes(),
+                    );
+                    write(
+                        &mut data,
+                        offset + CHILDREN_OFFSET + 4,
+                        &children[1].to_le_bytes(),
+                    );
+                }
+                Node::Leaf(price_lots, quantity, time_in_force) => {
+                    let key = ((*price_lots as u128) << 64) | i as u128;
+                    write(&mut data, offset, &[LEAF_NODE_TAG]);
+                    write(
+                        &mut data,
+                        offset + TIME_IN_FORCE_OFFSET,
+                        &time_in_force.to_le_bytes(),
+                    );
+                    write(&mut data, offset + KEY_OFFSET, &key.to_le_bytes());
+                    write(&mut data, offset + QUANTITY_OFFSET, &quantity.to_le_bytes());
+                    write(&mut data, offset + TIMESTAMP_OFFSET, &100_i64.to_le_bytes());
+                }
+                // freed leaves keep their old contents
+                Node::Free => {
+                    write(&mut data, offset, &[3]);
+                    write(&mut data, offset + QUANTITY_OFFSET, &5_i64.to_le_bytes());
+                }
+            }
+        }
+
+        data
+    }
+
+    #[test]
+    fn decodes_levels_best_first() {
+        let (bids_key, asks_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let market = market_fixture(bids_key, asks_key);
+        assert_eq!(get_book_sides(&market).unwrap(), (bids_key, asks_key));
+
+        // $19.99 x 0.5, $20.00 x 1 + 0.25 and a freed node
+        let bids = book_side_fixture(
+            BIDS_TREE_TYPE,
+            0,
+            3,
+            &[
+                Node::Inner([1, 2]),
+                Node::Leaf(19_990, 500, 0),
+                Node::Inner([3, 4]),
+                Node::Leaf(20_000, 1_000, 0),
+                Node::Leaf(20_000, 250, 0),
+                Node::Free,
+            ],
+        );
+        // $20.01 x 0.1 and an order that expired at 110
+        let asks = book_side_fixture(
+            ASKS_TREE_TYPE,
+            0,
+            2,
+            &[
+                Node::Inner([1, 2]),
+                Node::Leaf(20_010, 100, 0),
+                Node::Leaf(20_005, 100, 10),
+            ],
+        );
+
+        let mut generator = OpenbookV2L2Generator::new(&market, &bids, &asks, 120).unwrap();
+        let bids: Vec<L2Level> = generator.get_l2_bids().collect();
+        let asks: Vec<L2Level> = generator.get_l2_asks().collect();
+
+        assert_eq!(bids.len(), 2);
+        assert_eq!(bids[0].price, 20_000_000);
+        assert_eq!(bids[0].size, 1_250_000_000);
+        assert_eq!(bids[1].price, 19_990_000);
+        assert_eq!(bids[1].sources[&LiquiditySource::Openbook], 500_000_000);
+
+        assert_eq!(asks.len(), 1);
+        assert_eq!(asks[0].price, 20_010_000);
+        assert_eq!(asks[0].size, 100_000_000);
+    }
+
+    #[test]
+    fn book_side_matches_the_on_chain_layout() {
+        assert_eq!(NODE_SIZE, 88);
+        assert_eq!(BOOK_SIDE_SIZE, 90_952);
+
+        // the last node slot is readable
+        let mut nodes: Vec<Node> = (0..MAX_NODES - 1).map(|_| Node::Free).collect();
+        nodes.push(Node::Leaf(20_000, 1_000, 0));
+        let bids = book_side_fixture(BIDS_TREE_TYPE, MAX_NODES as u32 - 1, 1, &nodes);
+        let asks = book_side_fixture(ASKS_TREE_TYPE, 0, 0, &[]);
+        let market = market_fixture(Pubkey::new_unique(), Pubkey::new_unique());
+
+        let mut generator = OpenbookV2L2Generator::new(&market, &bids, &asks, 0).unwrap();
+        let bids: Vec<L2Level> = generator.get_l2_bids().collect();
+        assert_eq!(bids.len(), 1);
+        assert_eq!(bids[0].price, 20_000_000);
+        assert_eq!(bids[0].size, 1_000_000_000);
+
+        // a truncated account
+        let truncated = &asks[..BOOK_SIDE_SIZE - 1];
+        let empty_bids = book_side_fixture(BIDS_TREE_TYPE, 0, 0, &[]);
+        assert!(OpenbookV2L2Generator::new(&market, &empty_bids, truncated, 0).is_err());
+    }
+
+    #[test]
+    fn price_overflow_is_an_error() {
+        let mut market = market_fixture(Pubkey::new_unique(), Pubkey::new_unique());
+        write(&mut market, QUOTE_LOT_SIZE_OFFSET, &i64::MAX.to_le_bytes());
+        let bids = book_side_fixture(BIDS_TREE_TYPE, 0, 1, &[Node::Leaf(i64::MAX, 1, 0)]);
+        let asks = book_side_fixture(ASKS_TREE_TYPE, 0, 0, &[]);
+
+        assert!(OpenbookV2L2Generator::new(&market, &bids, &asks, 0).is_err());
+    }
+
+    #[test]
+    fn empty_and_invalid_book_sides() {
+        let market = market_fixture(Pubkey::new_unique(), Pubkey::new_unique());
+        let empty_bids = book_side_fixture(BIDS_TREE_TYPE, 0, 0, &[]);
+        let empty_asks = book_side_fixture(ASKS_TREE_TYPE, 0, 0, &[]);
+
+        let mut generator =
+            OpenbookV2L2Generator::new(&market, &empty_bids, &empty_asks, 0).unwrap();
+        assert_eq!(generator.get_l2_bids().count(), 0);
+        assert_eq!(generator.get_l2_asks().count(), 0);
+
+        // sides swapped
+        assert!(OpenbookV2L2Generator::new(&market, &empty_asks, &empty_bids, 0).is_err());
+        // not a market account
+        assert!(OpenbookV2L2Generator::new(&empty_bids, &empty_bids, &empty_asks, 0).is_err());
+        // root pointing at a free node
+        let corrupt = book_side_fixture(BIDS_TREE_TYPE, 0, 1, &[Node::Free]);
+        assert!(OpenbookV2L2Generator::new(&market, &corrupt, &empty_asks, 0).is_err());
+    }
+
+    #[test]
+    #[ignore = "needs the mainnet dumps described in sdk/tests/fixtures/README.md"]
+    fn decodes_mainnet_sol_usdc_market() {
+        let (_, market) = load_account("openbook_v2_sol_usdc_market");
+        let (bids_address, bids) = load_account("openbook_v2_sol_usdc_bids");
+        let (asks_address, asks) = load_account("openbook_v2_sol_usdc_asks");
+        let expected = load_expected_top_of_book("openbook_v2_sol_usdc");
+
+        // the dumped book sides are the ones the market points at
+        assert_eq!(
+            get_book_sides(&market).unwrap(),
+            (bids_address, asks_address)
+        );
+
+        let mut generator =
+            OpenbookV2L2Generator::new(&market, &bids, &asks, expected.unix_timestamp).unwrap();
+        let best_bid = generator.get_l2_bids().next().expect("bids");
+        let best_ask = generator.get_l2_asks().next().expect("asks");
+
+        assert_eq!(ExpectedLevel::from(&best_bid), expected.best_bid);
+        assert_eq!(ExpectedLevel::from(&best_ask), expected.best_ask);
+        assert!(best_bid.price < best_ask.price);
+    }
+}
@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     ops::{Div, Mul, Sub},
 };
 
@@ -39,6 +39,7 @@ pub enum LiquiditySource {
     Vamm,
     Dlob,
     Phoenix,
+    Openbook,
 }
 
 pub struct L2Level {
@@ -96,52 +97,57 @@ impl Iterator for L2Bids {
     type Item = L2Level;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while self.num_bids < self.num_orders && self.bid_size < 0 {
-            let mut quote_swapped = 0;
-            let mut base_swapped = 0;
-            let mut after_swap_quote_reserves = 0;
-            let mut after_swap_base_reserves = 0;
-
-            if let Some(ref top_of_book_quote_amounts) = self.top_of_book_quote_amounts {
-                if self.num_bids < top_of_book_quote_amounts.len() {
-                    let remaining_base_liquidity = self.open_bids - self.top_of_book_bid_size;
-                    quote_swapped = top_of_book_quote_amounts[self.num_bids] as u128;
+        while self.num_bids < self.num_orders && self.bid_size > 0 {
+            let mut quote_swapped: u128;
+            let mut base_swapped: i128;
+            let mut after_swap_quote_reserves: u128;
+            let mut after_swap_base_reserves: u128;
+
+            let top_of_book_quote_amount = self
+                .top_of_book_quote_amounts
+                .as_ref()
+                .and_then(|amounts| amounts.get(self.num_bids).copied());
+
+            if let Some(top_of_book_quote_amount) = top_of_book_quote_amount {
+                let remaining_base_liquidity = self.open_bids - self.top_of_book_bid_size;
+                quote_swapped = top_of_book_quote_amount as u128;
+                (after_swap_quote_reserves, after_swap_base_reserves) =
+                    calculate_amm_reserves_after_swap(
+                        &self.bid_amm,
+                        AssetType::Quote,
+                        quote_swapped as i128,
+                        SwapDirection::Remove,
+                    )
+                    .ok()?;
+                base_swapped = self
+                    .bid_amm
+                    .base_asset_reserve
+                    .abs_diff(after_swap_base_reserves) as i128;
+
+                if remaining_base_liquidity < base_swapped {
+                    base_swapped = remaining_base_liquidity;
                     (after_swap_quote_reserves, after_swap_base_reserves) =
                         calculate_amm_reserves_after_swap(
                             &self.bid_amm,
-                            AssetType::Quote,
-                            quote_swapped as i128,
-                            SwapDirection::Remove,
-                        )
-                        .ok()?;
-                    base_swapped =
-                        (self.bid_amm.base_asset_reserve - after_swap_base_reserves) as i128;
-
-                    if remaining_base_liquidity < base_swapped {
-                        base_swapped = remaining_base_liquidity;
-                        (after_swap_quote_reserves, after_swap_base_reserves) =
-                            calculate_amm_reserves_after_swap(
-                                &self.bid_amm,
-                                AssetType::Base,
-                                base_swapped,
-                                SwapDirection::Add,
-                            )
-                            .ok()?;
-                        quote_swapped = calculate_quote_asset_amount_swapped(
-                            self.bid_amm.quote_asset_reserve,
-                            after_swap_quote_reserves,
+                            AssetType::Base,
+                            base_swapped,
                             SwapDirection::Add,
-                            self.bid_amm.peg_multiplier,
                         )
                         .ok()?;
-                    }
-
-                    self.top_of_book_bid_size += base_swapped;
-                    self.bid_size = self
-                        .open_bids
-                        .sub(self.top_of_book_bid_size)
-                        .div(self.num_base_orders as i128);
+                    quote_swapped = calculate_quote_asset_amount_swapped(
+                        self.bid_amm.quote_asset_reserve,
+                        after_swap_quote_reserves,
+                        SwapDirection::Add,
+                        self.bid_amm.peg_multiplier,
+                    )
+                    .ok()?;
                 }
+
+                self.top_of_book_bid_size += base_swapped;
+                self.bid_size = self
+                    .open_bids
+                    .sub(self.top_of_book_bid_size)
+                    .div(self.num_base_orders as i128);
             } else {
                 base_swapped = self.bid_size;
                 (after_swap_quote_reserves, after_swap_base_reserves) =
@@ -193,57 +199,60 @@ impl Iterator for L2Asks {
     type Item = L2Level;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while self.num_asks < self.num_orders && self.ask_size < 0 {
-            let mut quote_swapped = 0;
-            let mut base_swapped = 0;
-            let mut after_swap_quote_reserves = 0;
-            let mut after_swap_base_reserves = 0;
-
-            if let Some(ref top_of_book_quote_amounts) = self.top_of_book_quote_amounts {
-                if self.num_asks < top_of_book_quote_amounts.len() {
-                    let remaining_base_liquidity =
-                        self.open_asks.mul(-1).sub(self.top_of_book_ask_size);
-                    quote_swapped = top_of_book_quote_amounts[self.num_asks] as u128;
+        while self.num_asks < self.num_orders && self.ask_size > 0 {
+            let mut quote_swapped: u128;
+            let mut base_swapped: i128;
+            let mut after_swap_quote_reserves: u128;
+            let mut after_swap_base_reserves: u128;
+
+            let top_of_book_quote_amount = self
+                .top_of_book_quote_amounts
+                .as_ref()
+                .and_then(|amounts| amounts.get(self.num_asks).copied());
+
+            if let Some(top_of_book_quote_amount) = top_of_book_quote_amount {
+                let remaining_base_liquidity =
+                    self.open_asks.mul(-1).sub(self.top_of_book_ask_size);
+                quote_swapped = top_of_book_quote_amount as u128;
+                (after_swap_quote_reserves, after_swap_base_reserves) =
+                    calculate_amm_reserves_after_swap(
+                        &self.ask_amm,
+                        AssetType::Quote,
+                        quote_swapped as i128,
+                        SwapDirection::Add,
+                    )
+                    .ok()?;
+                base_swapped = (self.ask_amm.base_asset_reserve - after_swap_base_reserves) as i128;
+
+                if base_swapped == 0 {
+                    return None;
+                }
+
+                if remaining_base_liquidity < base_swapped {
+                    base_swapped = remaining_base_liquidity;
                     (after_swap_quote_reserves, after_swap_base_reserves) =
                         calculate_amm_reserves_after_swap(
                             &self.ask_amm,
-                            AssetType::Quote,
-                            quote_swapped as i128,
-                            SwapDirection::Add,
-                        )
-                        .ok()?;
-                    base_swapped =
-                        (self.ask_amm.base_asset_reserve - after_swap_base_reserves) as i128;
-
-                    if base_swapped == 0 {
-                        return None;
-                    }
-
-                    if remaining_base_liquidity < base_swapped {
-                        base_swapped = remaining_base_liquidity;
-                        (after_swap_quote_reserves, after_swap_base_reserves) =
-                            calculate_amm_reserves_after_swap(
-                                &self.ask_amm,
-                                AssetType::Base,
-                                base_swapped,
-                                SwapDirection::Remove,
-                            )
-                            .ok()?;
-                        quote_swapped = calculate_quote_asset_amount_swapped(
-                            self.ask_amm.quote_asset_reserve,
-                            after_swap_quote_reserves,
+                            AssetType::Base,
+                            base_swapped,
                             SwapDirection::Remove,
-                            self.ask_amm.peg_multiplier,
                         )
                         .ok()?;
-                    }
-
-                    self.top_of_book_ask_size += base_swapped;
-                    self.ask_size = self
-                        .open_asks
-                        .sub(self.top_of_book_ask_size)
-                        .div(self.num_base_orders as i128);
+                    quote_swapped = calculate_quote_asset_amount_swapped(
+                        self.ask_amm.quote_asset_reserve,
+                        after_swap_quote_reserves,
+                        SwapDirection::Remove,
+                        self.ask_amm.peg_multiplier,
+                    )
+                    .ok()?;
                 }
+
+                self.top_of_book_ask_size += base_swapped;
+                self.ask_size = self
+                    .open_asks
+                    .abs()
+                    .sub(self.top_of_book_ask_size)
+                    .div(self.num_base_orders as i128);
             } else {
                 base_swapped = self.ask_size;
                 (after_swap_quote_reserves, after_swap_base_reserves) =
@@ -437,6 +446,7 @@ where
     }))
 }
 
+/// Merge sorted level generators into one, `compare(a, b)` is true if `a` comes before `b`
 pub(crate) fn merge_l2_level_generators<I, F>(
     mut l2_level_generators: Vec<I>,
     compare: F,
@@ -445,33 +455,68 @@ where
     I: Iterator<Item = L2Level>,
     F: Fn(&L2Level, &L2Level) -> bool,
 {
+    // the next level of each generator, only the chosen one is advanced
+    let mut heads: Vec<Option<L2Level>> = l2_level_generators
+        .iter_mut()
+        .map(|generator| generator.next())
+        .collect();
+
     std::iter::from_fn(move || {
-        let mut next = None;
-
-        for generator in &mut l2_level_generators {
-            if let Some(candidate) = generator.next() {
-                match &next {
-                    None => next = Some(candidate),
-                    Some(best) => {
-                        if compare(&candidate, best) {
-                            next = Some(candidate);
-                        }
-                    }
+        let mut best: Option<usize> = None;
+
+        for (i, head) in heads.iter().enumerate() {
+            if let Some(candidate) = head {
+                let is_better = match best.and_then(|b| heads[b].as_ref()) {
+                    Some(best_level) => compare(candidate, best_level),
+                    None => true,
+                };
+                if is_better {
+                    best = Some(i);
                 }
             }
         }
 
+        let i = best?;
+        let next = heads[i].take();
+        heads[i] = l2_level_generators[i].next();
         next
     })
 }
 
+/// Sum (price, size) `orders` into levels, best price first
+pub(crate) fn aggregate_orders(
+    orders: impl IntoIterator<Item = (u128, i128)>,
+    is_bid: bool,
+) -> Vec<(u128, i128)> {
+    let mut levels = BTreeMap::new();
+    for (price, size) in orders {
+        *levels.entry(price).or_insert(0) += size;
+    }
+
+    if is_bid {
+        levels.into_iter().rev().collect()
+    } else {
+        levels.into_iter().collect()
+    }
+}
+
+/// (price, size) levels as `L2Level`s from a single `source`
+pub(crate) fn l2_levels_from_source(
+    levels: Vec<(u128, i128)>,
+    source: LiquiditySource,
+) -> Box<dyn Iterator<Item = L2Level>> {
+    Box::new(levels.into_iter().map(move |(price, size)| {
+        L2Level::new(price, size, HashMap::from([(source.clone(), size)]))
+    }))
+}
+
 pub(crate) fn create_l2_levels(
-    mut generator: impl Iterator<Item = L2Level>,
+    generator: impl Iterator<Item = L2Level>,
     depth: usize,
 ) -> Vec<L2Level> {
     let mut levels: Vec<L2Level> = Vec::new();
 
-    if let Some(level) = generator.next() {
+    for level in generator {
         let price = level.price;
         let size = level.size;
         let len = levels.len();
@@ -496,3 +541,102 @@ pub(crate) fn create_l2_levels(
 
     levels
 }
+
+#[cfg(test)]
+mod tests {
+    use drift::{
+        math::constants::{AMM_RESERVE_PRECISION, PEG_PRECISION, PRICE_PRECISION},
+        state::oracle::HistoricalOracleData,
+    };
+
+    use super::*;
+
+    fn dlob_level(price: u128, size: i128) -> L2Level {
+        L2Level::new(price, size, HashMap::from([(LiquiditySource::Dlob, size)]))
+    }
+
+    fn vamm_level(price: u128, size: i128) -> L2Level {
+        L2Level::new(price, size, HashMap::from([(LiquiditySource::Vamm, size)]))
+    }
+
+    #[test]
+    fn vamm_asks_continue_past_top_of_book() {
+        let reserve = 1_000 * AMM_RESERVE_PRECISION;
+        let oracle_price = 100 * PRICE_PRECISION as i64;
+        let market = PerpMarket {
+            amm: AMM {
+                base_asset_reserve: reserve,
+                quote_asset_reserve: reserve,
+                sqrt_k: reserve,
+                peg_multiplier: 100 * PEG_PRECISION,
+                min_base_asset_reserve: reserve / 2,
+                max_base_asset_reserve: reserve * 2,
+                min_order_size: BASE_PRECISION as u64 / 100,
+                max_spread: 10_000,
+                funding_period: 3_600,
+                historical_oracle_data: HistoricalOracleData {
+                    last_oracle_price_twap: oracle_price,
+                    last_oracle_price_twap_5min: oracle_price,
+                    last_oracle_price_twap_ts: 100,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let oracle_price_data = OraclePriceData {
+            price: oracle_price,
+            ..Default::default()
+        };
+
+        let num_orders = 10;
+        let mut generator = VammL2Generator::new(
+            market,
+            &oracle_price_data,
+            num_orders,
+            Some(100),
+            Some(DEFAULT_TOP_OF_BOOK_QUOTE_AMOUNTS.to_vec()),
+        )
+        .unwrap();
+
+        let asks: Vec<L2Level> = generator.get_l2_asks().collect();
+        assert_eq!(asks.len(), num_orders);
+        assert!(asks.iter().all(|level| level.size > 0));
+        assert!(asks.windows(2).all(|pair| pair[0].price <= pair[1].price));
+
+        // levels past the top of book split the remaining open asks evenly
+        let base_levels = &asks[DEFAULT_TOP_OF_BOOK_QUOTE_AMOUNTS.len()..];
+        assert!(base_levels
+            .windows(2)
+            .all(|pair| pair[0].size == pair[1].size));
+
+        let total_size: i128 = asks.iter().map(|level| level.size).sum();
+        assert!(total_size <= generator.open_asks.abs());
+    }
+
+    #[test]
+    fn merged_levels_keep_every_source_level() {
+        let dlob = vec![dlob_level(101, 1), dlob_level(103, 2)].into_iter();
+        let vamm = vec![vamm_level(100, 5), vamm_level(103, 3), vamm_level(104, 4)].into_iter();
+
+        let merged = merge_l2_level_generators(vec![dlob, vamm], |a, b| a.price < b.price);
+        let prices: Vec<u128> = merged.map(|level| level.price).collect();
+
+        assert_eq!(prices, vec![100, 101, 103, 103, 104]);
+    }
+
+    #[test]
+    fn create_l2_levels_aggregates_prices_up_to_depth() {
+        let dlob = vec![dlob_level(101, 1), dlob_level(103, 2)].into_iter();
+        let vamm = vec![vamm_level(100, 5), vamm_level(103, 3), vamm_level(104, 4)].into_iter();
+        let merged = merge_l2_level_generators(vec![dlob, vamm], |a, b| a.price < b.price);
+
+        let levels = create_l2_levels(merged, 3);
+
+        assert_eq!(levels.len(), 3);
+        assert_eq!(levels[2].price, 103);
+        assert_eq!(levels[2].size, 5);
+        assert_eq!(levels[2].sources[&LiquiditySource::Dlob], 2);
+        assert_eq!(levels[2].sources[&LiquiditySource::Vamm], 3);
+    }
+}
@@ -0,0 +1,371 @@
+//! L2 levels of a Phoenix market decoded from its raw account data
+//!
+//! The market account is a `MarketHeader` followed by a `FIFOMarket` whose bids and asks are
+//! sokoban red-black trees, see
+//! https://github.com/Ellipsis-Labs/phoenix-v1/blob/master/src/state/markets/fifo.rs
+
+use solana_sdk::{keccak::hashv, pubkey::Pubkey};
+
+use crate::{
+    constants::PRICE_PRECISION,
+    types::{SdkError, SdkResult},
+    utils::read_bytes,
+};
+
+use super::order_book_levels::{
+    aggregate_orders, l2_levels_from_source, L2Level, L2OrderBookGenerator, LiquiditySource,
+};
+
+const PHOENIX_PROGRAM_ID: Pubkey =
+    solana_sdk::pubkey!("PhoeNiXZ8ByJGLkxNfZRnkUfjvmuYqLR89jjFHGqdXY");
+
+// `MarketHeader` fields
+const DISCRIMINANT_OFFSET: usize = 0;
+const BIDS_SIZE_OFFSET: usize = 16;
+const ASKS_SIZE_OFFSET: usize = 24;
+const BASE_LOT_SIZE_OFFSET: usize = 112;
+const QUOTE_DECIMALS_OFFSET: usize = 120;
+const TICK_SIZE_OFFSET: usize = 200;
+const RAW_BASE_UNITS_PER_BASE_UNIT_OFFSET: usize = 312;
+const HEADER_SIZE: usize = 576;
+
+/// `FIFOMarket` padding and scalar fields before the bids tree
+const FIFO_MARKET_FIELDS_SIZE: usize = 304;
+
+/// root, padding and node allocator fields of a tree
+const TREE_HEADER_SIZE: usize = 32;
+/// 4 u32 registers, `FIFOOrderId` and `FIFORestingOrder`
+const NODE_SIZE: usize = 64;
+const LEFT_OFFSET: usize = 0;
+const RIGHT_OFFSET: usize = 4;
+const PRICE_IN_TICKS_OFFSET: usize = 16;
+const NUM_BASE_LOTS_OFFSET: usize = 40;
+const LAST_VALID_SLOT_OFFSET: usize = 48;
+const LAST_VALID_UNIX_TIMESTAMP_OFFSET: usize = 56;
+
+fn read_u32(data: &[u8], offset: usize) -> SdkResult<u32> {
+    Ok(u32::from_le_bytes(read_bytes(data, offset)?))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> SdkResult<u64> {
+    Ok(u64::from_le_bytes(read_bytes(data, offset)?))
+}
+
+/// Phoenix account discriminant, keyed by the program id and the account's rust type name
+fn market_header_discriminant() -> u64 {
+    let hash = hashv(&[
+        PHOENIX_PROGRAM_ID.as_ref(),
+        b"phoenix::program::accounts::MarketHeader",
+    ]);
+    u64::from_le_bytes(hash.to_bytes()[..8].try_into().unwrap())
+}
+
+struct MarketParams {
+    base_lot_size: u128,
+    /// quote atoms per tick, per whole base token
+    tick_size: u128,
+    /// quote atoms per whole base token for a price of 1
+    price_divisor: u128,
+}
+
+/// Live (price in ticks, base lots) orders of the tree at `offset` with room for `max_size` nodes
+fn tree_orders(
+    data: &[u8],
+    offset: usize,
+    max_size: usize,
+    slot: u64,
+    now: i64,
+) -> SdkResult<Vec<(u64, u64)>> {
+    let nodes_offset = offset + TREE_HEADER_SIZE;
+    let mut orders = vec![];
+    let mut stack = vec![read_u32(data, offset)?];
+    let mut visited = 0;
+
+    // node indices are 1-based, 0 is the empty sentinel
+    while let Some(index) = stack.pop() {
+        if index == 0 {
+            continue;
+        }
+        if index as usize > max_size || visited == max_size {
+            return Err(SdkError::Deserializing);
+        }
+        visited += 1;
+
+        let node = nodes_offset + (index as usize - 1) * NODE_SIZE;
+        stack.push(read_u32(data, node + LEFT_OFFSET)?);
+        stack.push(read_u32(data, node + RIGHT_OFFSET)?);
+
+        let last_valid_slot = read_u64(data, node + LAST_VALID_SLOT_OFFSET)?;
+        let last_valid_unix_timestamp = read_u64(data, node + LAST_VALID_UNIX_TIMESTAMP_OFFSET)?;
+        let is_expired = (last_valid_slot != 0 && last_valid_slot < slot)
+            || (last_valid_unix_timestamp != 0 && (last_valid_unix_timestamp as i64) < now);
+        if is_expired {
+            continue;
+        }
+
+        orders.push((
+            read_u64(data, node + PRICE_IN_TICKS_OFFSET)?,
+            read_u64(data, node + NUM_BASE_LOTS_OFFSET)?,
+        ));
+    }
+
+    Ok(orders)
+}
+
+/// Resting Phoenix liquidity as fallback L2 levels, tagged `LiquiditySource::Phoenix`
+///
+/// Prices are PRICE_PRECISION and sizes are base token atoms (the drift spot market precision)
+pub struct PhoenixL2Generator {
+    bids: Vec<(u128, i128)>,
+    asks: Vec<(u128, i128)>,
+}
+
+impl PhoenixL2Generator {
+    /// Decode the book of a Phoenix market account, skipping orders expired at `slot` / `now`
+    pub fn new(market_data: &[u8], slot: u64, now: i64) -> SdkResult<Self> {
+        if read_u64(market_data, DISCRIMINANT_OFFSET)? != market_header_discriminant() {
+            return Err(SdkError::UnsupportedAccountData);
+        }
+
+        let bids_size = read_u64(market_data, BIDS_SIZE_OFFSET)? as usize;
+        let asks_size = read_u64(market_data, ASKS_SIZE_OFFSET)? as usize;
+        let quote_decimals = read_u32(market_data, QUOTE_DECIMALS_OFFSET)?;
+        let raw_base_units_per_base_unit =
+            read_u32(market_data, RAW_BASE_UNITS_PER_BASE_UNIT_OFFSET)?.max(1);
+
+        let params = MarketParams {
+            base_lot_size: read_u64(market_data, BASE_LOT_SIZE_OFFSET)? as u128,
+            tick_size: read_u64(market_data, TICK_SIZE_OFFSET)? as u128,
+            price_divisor: 10_u128
+                .checked_pow(quote_decimals)
+                .and_then(|pow| pow.checked_mul(raw_base_units_per_base_unit as u128))
+                .ok_or(SdkError::Deserializing)?,
+        };
+
+        let bids_offset = HEADER_SIZE + FIFO_MARKET_FIELDS_SIZE;
+        let asks_offset = bids_size
+            .checked_mul(NODE_SIZE)
+            .and_then(|bids_len| bids_len.checked_add(bids_offset + TREE_HEADER_SIZE))
+            .ok_or(SdkError::Deserializing)?;
+
+        let to_level = |(price_in_ticks, num_base_lots): (u64, u64)| {
+            let price = (price_in_ticks as u128)
+                .checked_mul(params.tick_size)
+                .and_then(|quote| quote.checked_mul(PRICE_PRECISION))
+                .ok_or(SdkError::Deserializing)?
+                / params.price_divisor;
+            let size = (num_base_lots as u128)
+                .checked_mul(params.base_lot_size)
+                .and_then(|size| i128::try_from(size).ok())
+                .ok_or(SdkError::Deserializing)?;
+            Ok((price, size))
+        };
+        let bids = tree_orders(market_data, bids_offset, bids_size, slot, now)?
+            .into_iter()
+            .map(to_level)
+            .collect::<SdkResult<Vec<_>>>()?;
+        let asks = tree_orders(market_data, asks_offset, asks_size, slot, now)?
+            .into_iter()
+            .map(to_level)
+            .collect::<SdkResult<Vec<_>>>()?;
+
+        Ok(Self {
+            bids: aggregate_orders(bids, true),
+            asks: aggregate_orders(asks, false),
+        })
+    }
+}
+
+impl L2OrderBookGenerator for PhoenixL2Generator {
+    fn get_l2_asks(&mut self) -> Box<dyn Iterator<Item = L2Level>> {
+        l2_levels_from_source(self.asks.clone(), LiquiditySource::Phoenix)
+    }
+
+    fn get_l2_bids(&mut self) -> Box<dyn Iterator<Item = L2Level>> {
+        l2_levels_from_source(self.bids.clone(), LiquiditySource::Phoenix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dlob::test_fixtures::{load_account, load_expected_top_of_book, ExpectedLevel};
+
+    const BIDS_SIZE: usize = 8;
+    const ASKS_SIZE: usize = 8;
+
+    struct Order {
+        left: u32,
+        right: u32,
+        price_in_ticks: u64,
+        num_base_lots: u64,
+        last_valid_slot: u64,
+    }
+
+    fn write(data: &mut [u8], offset: usize, bytes: &[u8]) {
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn write_tree(data: &mut [u8], offset: usize, root: u32, orders: &[Order]) {
+        write(data, offset, &root.to_le_bytes());
+        // allocator size and bump index
+        write(data, offset + 16, &(orders.len() as u64).to_le_bytes());
+        write(data, offset + 24, &(orders.len() as u32).to_le_bytes());
+        for (i, order) in orders.iter().enumerate() {
+            let node = offset + TREE_HEADER_SIZE + i * NODE_SIZE;
+            write(data, node + LEFT_OFFSET, &order.left.to_le_bytes());
+            write(data, node + RIGHT_OFFSET, &order.right.to_le_bytes());
+            write(
+                data,
+                node + PRICE_IN_TICKS_OFFSET,
+                &order.price_in_ticks.to_le_bytes(),
+            );
+            write(
+                data,
+                node + NUM_BASE_LOTS_OFFSET,
+                &order.num_base_lots.to_le_bytes(),
+            );
+            write(
+                data,
+                node + LAST_VALID_SLOT_OFFSET,
+                &order.last_valid_slot.to_le_bytes(),
+            );
+        }
+    }
+
+    /// SOL/USDC style market: 9 decimal base, 6 decimal quote, 0.001 SOL lots and 0.001 USDC ticks
+    fn market_fixture(bids: &[Order], bids_root: u32, asks: &[Order], asks_root: u32) -> Vec<u8> {
+        let bids_offset = HEADER_SIZE + FIFO_MARKET_FIELDS_SIZE;
+        let asks_offset = bids_offset + TREE_HEADER_SIZE + BIDS_SIZE * NODE_SIZE;
+        let mut data = vec![0; asks_offset + TREE_HEADER_SIZE + ASKS_SIZE * NODE_SIZE];
+
+        write(
+            &mut data,
+            DISCRIMINANT_OFFSET,
+            &market_header_discriminant().to_le_bytes(),
+        );
+        write(
+            &mut data,
+            BIDS_SIZE_OFFSET,
+            &(BIDS_SIZE as u64).to_le_bytes(),
+        );
+        write(
+            &mut data,
+            ASKS_SIZE_OFFSET,
+            &(ASKS_SIZE as u64).to_le_bytes(),
+        );
+        write(
+            &mut data,
+            BASE_LOT_SIZE_OFFSET,
+            &1_000_000_u64.to_le_bytes(),
+        );
+        write(&mut data, QUOTE_DECIMALS_OFFSET, &6_u32.to_le_bytes());
+        write(&mut data, TICK_SIZE_OFFSET, &1_000_u64.to_le_bytes());
+        write(
+            &mut data,
+            RAW_BASE_UNITS_PER_BASE_UNIT_OFFSET,
+            &1_u32.to_le_bytes(),
+        );
+        write_tree(&mut data, bids_offset, bids_root, bids);
+        write_tree(&mut data, asks_offset, asks_root, asks);
+
+        data
+    }
+
+    fn order(left: u32, right: u32, price_in_ticks: u64, num_base_lots: u64) -> Order {
+        Order {
+            left,
+            right,
+            price_in_ticks,
+            num_base_lots,
+            last_valid_slot: 0,
+        }
+    }
+
+    #[test]
+    fn decodes_levels_best_first() {
+        // bids tree: root 2 ($20.00) with children 1 ($19.99) and 3 ($20.00)
+        let bids = [
+            order(0, 0, 19_990, 500),
+            order(1, 3, 20_000, 1_000),
+            order(0, 0, 20_000, 250),
+        ];
+        // asks tree: root 1 ($20.01) with right child 2 ($20.05)
+        let asks = [order(0, 2, 20_010, 100), order(0, 0, 20_050, 2_000)];
+        let data = market_fixture(&bids, 2, &asks, 1);
+
+        let mut generator = PhoenixL2Generator::new(&data, 100, 0).unwrap();
+        let bids: Vec<L2Level> = generator.get_l2_bids().collect();
+        let asks: Vec<L2Level> = generator.get_l2_asks().collect();
+
+        assert_eq!(bids.len(), 2);
+        assert_eq!(bids[0].price, 20_000_000);
+        assert_eq!(bids[0].size, 1_250_000_000);
+        assert_eq!(bids[1].price, 19_990_000);
+        assert_eq!(bids[1].sources[&LiquiditySource::Phoenix], 500_000_000);
+
+        assert_eq!(asks.len(), 2);
+        assert_eq!(asks[0].price, 20_010_000);
+        assert_eq!(asks[0].size, 100_000_000);
+        assert_eq!(asks[1].price, 20_050_000);
+    }
+
+    #[test]
+    fn skips_expired_orders() {
+        let mut expired = order(0, 2, 20_010, 100);
+        expired.last_valid_slot = 99;
+        let data = market_fixture(&[], 0, &[expired, order(0, 0, 20_020, 5)], 1);
+
+        let mut generator = PhoenixL2Generator::new(&data, 100, 0).unwrap();
+        let asks: Vec<L2Level> = generator.get_l2_asks().collect();
+
+        assert_eq!(asks.len(), 1);
+        assert_eq!(asks[0].price, 20_020_000);
+        assert_eq!(generator.get_l2_bids().count(), 0);
+    }
+
+    #[test]
+    fn rejects_truncated_or_corrupt_accounts() {
+        let asks = [order(0, 0, 20_010, 100)];
+        let data = market_fixture(&[], 0, &asks, 1);
+        assert!(
+            PhoenixL2Generator::new(&data[..data.len() - NODE_SIZE * ASKS_SIZE], 0, 0).is_err()
+        );
+
+        // a node pointing at itself
+        let cycle = [order(1, 0, 20_010, 100)];
+        let data = market_fixture(&[], 0, &cycle, 1);
+        assert!(PhoenixL2Generator::new(&data, 0, 0).is_err());
+
+        // not a market header
+        let mut data = market_fixture(&[], 0, &asks, 1);
+        write(&mut data, DISCRIMINANT_OFFSET, &0_u64.to_le_bytes());
+        assert!(PhoenixL2Generator::new(&data, 0, 0).is_err());
+
+        // tree sizes that overflow the account offsets
+        let mut data = market_fixture(&[], 0, &asks, 1);
+        write(&mut data, BIDS_SIZE_OFFSET, &u64::MAX.to_le_bytes());
+        assert!(PhoenixL2Generator::new(&data, 0, 0).is_err());
+
+        // prices that overflow the price precision
+        let mut data = market_fixture(&[], 0, &[order(0, 0, u64::MAX, 1)], 1);
+        write(&mut data, TICK_SIZE_OFFSET, &u64::MAX.to_le_bytes());
+        assert!(PhoenixL2Generator::new(&data, 0, 0).is_err());
+    }
+
+    #[test]
+    #[ignore = "needs the mainnet dumps described in sdk/tests/fixtures/README.md"]
+    fn decodes_mainnet_sol_usdc_market() {
+        let (_, market) = load_account("phoenix_sol_usdc_market");
+        let expected = load_expected_top_of_book("phoenix_sol_usdc");
+
+        let mut generator =
+            PhoenixL2Generator::new(&market, expected.slot, expected.unix_timestamp).unwrap();
+        let best_bid = generator.get_l2_bids().next().expect("bids");
+        let best_ask = generator.get_l2_asks().next().expect("asks");
+
+        assert_eq!(ExpectedLevel::from(&best_bid), expected.best_bid);
+        assert_eq!(ExpectedLevel::from(&best_ask), expected.best_ask);
+        assert!(best_bid.price < best_ask.price);
+    }
+}
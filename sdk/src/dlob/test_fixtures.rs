@@ -0,0 +1,79 @@
+//! Loads mainnet account dumps from `sdk/tests/fixtures`, see the README there
+//!
+//! Each account is the output of `solana account <address> --output json --output-file <name>.json`.
+//! The book a set of dumps should decode to is kept next to them in `<name>.expected.json`.
+
+use std::{fs, path::PathBuf};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+
+use super::order_book_levels::L2Level;
+
+fn fixture_path(file_name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(file_name)
+}
+
+#[derive(Deserialize)]
+struct AccountDump {
+    pubkey: String,
+    account: DumpedAccount,
+}
+
+#[derive(Deserialize)]
+struct DumpedAccount {
+    /// (data, encoding)
+    data: (String, String),
+}
+
+/// Address and raw data of the dumped account `<name>.json`
+pub(crate) fn load_account(name: &str) -> (Pubkey, Vec<u8>) {
+    let path = fixture_path(&format!("{name}.json"));
+    let dump = fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("read account dump {}: {err}", path.display()));
+    let dump: AccountDump = serde_json::from_str(&dump).expect("solana account json");
+    assert_eq!(dump.account.data.1, "base64", "dump with --output json");
+
+    let address = dump.pubkey.parse().expect("account address");
+    let data = STANDARD
+        .decode(dump.account.data.0)
+        .expect("base64 account data");
+    (address, data)
+}
+
+/// Price (PRICE_PRECISION) and size (base token atoms) of a level
+#[derive(Debug, Deserialize, PartialEq)]
+pub(crate) struct ExpectedLevel {
+    pub price: u128,
+    pub size: i128,
+}
+
+impl From<&L2Level> for ExpectedLevel {
+    fn from(level: &L2Level) -> Self {
+        Self {
+            price: level.price,
+            size: level.size,
+        }
+    }
+}
+
+/// Top of book of the dumped accounts, taken from an independent decoder at the dump slot
+#[derive(Debug, Deserialize)]
+pub(crate) struct ExpectedTopOfBook {
+    pub slot: u64,
+    pub unix_timestamp: i64,
+    pub best_bid: ExpectedLevel,
+    pub best_ask: ExpectedLevel,
+}
+
+/// Expected book of the dumps `<name>.expected.json`
+pub(crate) fn load_expected_top_of_book(name: &str) -> ExpectedTopOfBook {
+    let path = fixture_path(&format!("{name}.expected.json"));
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("read expected book {}: {err}", path.display()));
+    serde_json::from_str(&expected).expect("expected book json")
+}
@@ -4,7 +4,11 @@ use anchor_lang::{AccountDeserialize, Discriminator};
 use drift::{
     math::constants::QUOTE_SPOT_MARKET_INDEX,
     state::{
+        fulfillment_params::{
+            openbook_v2::OpenbookV2FulfillmentConfig, phoenix::PhoenixV1FulfillmentConfig,
+        },
         oracle::{get_oracle_price, OracleSource},
+        order_params::OrderParams,
         perp_market::PerpMarket,
         spot_market::SpotMarket,
         state::State,
@@ -28,19 +32,23 @@ use solana_sdk::{
     message::VersionedMessage,
     pubkey::Pubkey,
     signature::Signature,
+    system_instruction,
 };
 use tokio::sync::RwLock;
 
 use crate::{
     blockhash_subscriber::BlockhashSubscriber,
     constants::{
-        self, derive_perp_market_account, derive_spot_market_account, market_lookup_table,
+        self, derive_openbook_v2_fulfillment_config, derive_perp_market_account,
+        derive_phoenix_fulfillment_config, derive_spot_market_account, market_lookup_table,
         state_account, MarketExt, ProgramData,
     },
     drift_client_config::ClientOpts,
     event_emitter::EventEmitter,
     marketmap::MarketMap,
     oraclemap::{Oracle, OracleMap},
+    order_builder::NewOrder,
+    tx::token_instructions,
     types::{Context, DataAndSlot, MarketId, SdkError, SdkResult, TxParams},
     user::DriftUser,
     user_config::UserSubscriptionConfig,
@@ -167,6 +175,11 @@ where
         self.backend.client()
     }
 
+    /// Return the network context the client was created for
+    pub fn context(&self) -> Context {
+        self.backend.context
+    }
+
     /// Return on-chain program metadata
     pub fn program_data(&self) -> &ProgramData {
         &self.backend.program_data
@@ -588,6 +601,24 @@ where
         self.backend.get_account(&market).await
     }
 
+    /// Get the drift fulfillment config of a Phoenix market
+    pub async fn get_phoenix_fulfillment_config(
+        &self,
+        phoenix_market: &Pubkey,
+    ) -> SdkResult<PhoenixV1FulfillmentConfig> {
+        let config = derive_phoenix_fulfillment_config(phoenix_market);
+        self.backend.get_account(&config).await
+    }
+
+    /// Get the drift fulfillment config of an OpenBook v2 market
+    pub async fn get_openbook_v2_fulfillment_config(
+        &self,
+        openbook_v2_market: &Pubkey,
+    ) -> SdkResult<OpenbookV2FulfillmentConfig> {
+        let config = derive_openbook_v2_fulfillment_config(openbook_v2_market);
+        self.backend.get_account(&config).await
+    }
+
     /// Lookup a market by symbol
     ///
     /// This operation is not free so lookups should be reused/cached by the caller
@@ -620,6 +651,27 @@ where
         self.backend.oracle_price(market).await
     }
 
+    /// Validate `order` against its market and convert it into `OrderParams`
+    pub fn build_order(&self, order: NewOrder) -> SdkResult<OrderParams> {
+        let market = order.market_id();
+        let params = match market.kind() {
+            MarketType::Perp => {
+                let perp_market = self
+                    .get_perp_market_account(market.index())
+                    .ok_or(SdkError::InvalidAccount)?;
+                order.build(&perp_market)?
+            }
+            MarketType::Spot => {
+                let spot_market = self
+                    .get_spot_market_account(market.index())
+                    .ok_or(SdkError::InvalidAccount)?;
+                order.build(&spot_market)?
+            }
+        };
+
+        Ok(params)
+    }
+
     /// Initialize a transaction given a (sub)account address
     ///
     /// ```ignore
@@ -647,6 +699,153 @@ where
         }
     }
 
+    /// Initialize the sub-account `sub_account_id` of the wallet authority, creating the stats
+    /// account first if needed
+    ///
+    /// Sub-accounts must be created in order, starting at 0
+    pub async fn initialize_user(&self, sub_account_id: u16, name: &str) -> SdkResult<Signature> {
+        let authority = *self.wallet.authority();
+        let next_sub_account_id = match self.get_user_stats(&authority).await {
+            Ok(user_stats) => Some(user_stats.number_of_sub_accounts_created),
+            Err(_) => None,
+        };
+
+        if sub_account_id != next_sub_account_id.unwrap_or(0) {
+            return Err(SdkError::Generic(format!(
+                "sub account {sub_account_id} can't be initialized, next sub account id is {}",
+                next_sub_account_id.unwrap_or(0)
+            )));
+        }
+
+        let account_data = User {
+            authority,
+            ..Default::default()
+        };
+        let mut tx = TransactionBuilder::new(
+            self.program_data(),
+            self.wallet.sub_account(sub_account_id),
+            Cow::Owned(account_data),
+            false,
+        );
+        if next_sub_account_id.is_none() {
+            tx = tx.initialize_user_stats();
+        }
+        let tx = tx.initialize_user(sub_account_id, name).build();
+
+        self.sign_and_send(tx, false).await
+    }
+
+    /// Deposit `amount` (in token precision) of the spot market's token into `sub_account_id`
+    ///
+    /// The authority's associated token account is created if missing and SOL is wrapped
+    /// for the SOL market. A wrapped SOL account created by this call is closed again.
+    pub async fn deposit(
+        &self,
+        amount: u64,
+        spot_market_index: u16,
+        sub_account_id: u16,
+        reduce_only: Option<bool>,
+    ) -> SdkResult<Signature> {
+        let (tx, token_account, is_sol, created) = self
+            .init_token_tx(spot_market_index, sub_account_id)
+            .await?;
+
+        let mut ixs = Vec::new();
+        if is_sol {
+            ixs.push(system_instruction::transfer(
+                self.wallet.authority(),
+                &token_account,
+                amount,
+            ));
+            ixs.push(token_instructions::sync_native(&token_account));
+        }
+        let mut tx =
+            tx.extend_ix(ixs)
+                .deposit(amount, spot_market_index, token_account, reduce_only);
+        if is_sol && created {
+            tx = tx.extend_ix(vec![self.close_wrapped_sol_account(&token_account)]);
+        }
+
+        self.sign_and_send(tx.build(), false).await
+    }
+
+    /// Withdraw `amount` (in token precision) of the spot market's token from `sub_account_id`
+    ///
+    /// The authority's associated token account is created if missing. For the SOL market a
+    /// wrapped SOL account created by this call is closed, unwrapping the withdrawn SOL.
+    pub async fn withdraw(
+        &self,
+        amount: u64,
+        spot_market_index: u16,
+        sub_account_id: u16,
+        reduce_only: Option<bool>,
+    ) -> SdkResult<Signature> {
+        let (tx, token_account, is_sol, created) = self
+            .init_token_tx(spot_market_index, sub_account_id)
+            .await?;
+
+        let mut tx = tx.withdraw(amount, spot_market_index, token_account, reduce_only);
+        if is_sol && created {
+            tx = tx.extend_ix(vec![self.close_wrapped_sol_account(&token_account)]);
+        }
+
+        self.sign_and_send(tx.build(), false).await
+    }
+
+    /// Start a deposit/withdraw tx for `sub_account_id`, creating the authority's token account
+    ///
+    /// Returns the tx, the token account, whether the market's token is SOL and whether the token
+    /// account is created by the tx. Only a wrapped SOL account's existence is checked, other token
+    /// accounts are never closed.
+    async fn init_token_tx(
+        &self,
+        spot_market_index: u16,
+        sub_account_id: u16,
+    ) -> SdkResult<(TransactionBuilder, Pubkey, bool, bool)> {
+        let spot_market = self
+            .get_spot_market_account(spot_market_index)
+            .ok_or_else(|| {
+                SdkError::Generic(format!("spot market {spot_market_index} not found"))
+            })?;
+        let sub_account = self.wallet.sub_account(sub_account_id);
+        let account_data = self.get_user_account(&sub_account).await?;
+
+        let authority = self.wallet.authority();
+        let token_account =
+            constants::derive_associated_token_account(authority, &spot_market.mint);
+        let is_sol = spot_market.mint == constants::WRAPPED_SOL_MINT;
+        // a pre-existing wrapped SOL account may hold a balance the authority wants to keep
+        let created = is_sol
+            && self
+                .backend
+                .rpc_client
+                .get_account_with_commitment(&token_account, self.backend.rpc_client.commitment())
+                .await?
+                .value
+                .is_none();
+        let tx = TransactionBuilder::new(
+            self.program_data(),
+            sub_account,
+            Cow::Owned(account_data),
+            false,
+        )
+        .extend_ix(vec![
+            token_instructions::create_associated_token_account_idempotent(
+                authority,
+                authority,
+                &spot_market.mint,
+            ),
+        ]);
+
+        Ok((tx, token_account, is_sol, created))
+    }
+
+    /// Close the wrapped SOL account, returning its lamports to the authority
+    fn close_wrapped_sol_account(&self, token_account: &Pubkey) -> Instruction {
+        let authority = self.wallet.authority();
+        token_instructions::close_account(token_account, authority, authority)
+    }
+
     pub async fn get_recent_priority_fees(
         &self,
         writable_markets: &[MarketId],
@@ -856,6 +1055,7 @@ where
 /// Provides the heavy-lifting and network facing features of the SDK
 /// It is intended to be a singleton
 pub struct DriftClientBackend<T: AccountProvider> {
+    pub context: Context,
     pub rpc_client: Arc<RpcClient>,
     pub account_provider: T,
     pub program_data: ProgramData,
@@ -914,6 +1114,7 @@ impl<T: AccountProvider> DriftClientBackend<T> {
         )));
 
         Ok(Self {
+            context,
             rpc_client: Arc::new(rpc_client),
             account_provider,
             program_data: ProgramData::new(
@@ -66,6 +66,7 @@ pub mod marketmap;
 pub mod math;
 pub mod memcmp;
 pub mod oraclemap;
+pub mod order_builder;
 pub mod priority_fee;
 pub mod slot_subscriber;
 pub mod tx;
@@ -300,7 +301,7 @@ impl AccountProvider for WsAccountProvider {
 /// Prefer `DriftClient::init_tx`
 ///
 /// ```ignore
-/// use drift_sdk::{types::Context, TransactionBuilder, Wallet};
+/// use drift_sdk::{order_builder::NewOrder, types::{Context, MarketId}, TransactionBuilder, Wallet};
 ///
 /// let wallet = Wallet::from_seed_bs58(Context::Dev, "seed");
 /// let client = DriftClient::new("api.example.com").await.unwrap();
@@ -308,9 +309,9 @@ impl AccountProvider for WsAccountProvider {
 ///
 /// let tx = TransactionBuilder::new(client.program_data, wallet.default_sub_account(), account_data.into())
 ///     .cancel_all_orders()
-///     .place_orders(&[
-///         NewOrder::default().build(),
-///         NewOrder::default().build(),
+///     .place_orders(vec![
+///         client.build_order(NewOrder::limit(MarketId::perp(0), PositionDirection::Long, 1.0, 140.0))?,
+///         client.build_order(NewOrder::market(MarketId::perp(0), PositionDirection::Short, 0.5))?,
 ///     ])
 ///     .legacy()
 ///     .build();
@@ -399,6 +400,70 @@ impl<'a> TransactionBuilder<'a> {
         self
     }
 
+    /// Initialize the stats account of the authority, required before the first sub-account
+    pub fn initialize_user_stats(mut self) -> Self {
+        let accounts = build_accounts(
+            self.program_data,
+            drift::accounts::InitializeUserStats {
+                user_stats: Wallet::derive_stats_account(&self.authority, &constants::PROGRAM_ID),
+                state: *state_account(),
+                authority: self.authority,
+                payer: self.authority,
+                rent: solana_sdk::sysvar::rent::ID,
+                system_program: solana_sdk::system_program::ID,
+            },
+            &[],
+            &[],
+            &[],
+        );
+
+        let ix = Instruction {
+            program_id: constants::PROGRAM_ID,
+            accounts,
+            data: InstructionData::data(&drift::instruction::InitializeUserStats {}),
+        };
+        self.ixs.push(ix);
+
+        self
+    }
+
+    /// Initialize the sub-account `sub_account_id` of the authority
+    ///
+    /// `name` is truncated to 32 bytes
+    pub fn initialize_user(mut self, sub_account_id: u16, name: &str) -> Self {
+        let accounts = build_accounts(
+            self.program_data,
+            drift::accounts::InitializeUser {
+                user: Wallet::derive_user_account(
+                    &self.authority,
+                    sub_account_id,
+                    &constants::PROGRAM_ID,
+                ),
+                user_stats: Wallet::derive_stats_account(&self.authority, &constants::PROGRAM_ID),
+                state: *state_account(),
+                authority: self.authority,
+                payer: self.authority,
+                rent: solana_sdk::sysvar::rent::ID,
+                system_program: solana_sdk::system_program::ID,
+            },
+            &[],
+            &[],
+            &[],
+        );
+
+        let ix = Instruction {
+            program_id: constants::PROGRAM_ID,
+            accounts,
+            data: InstructionData::data(&drift::instruction::InitializeUser {
+                sub_account_id,
+                name: utils::encode_name(name),
+            }),
+        };
+        self.ixs.push(ix);
+
+        self
+    }
+
     /// Deposit collateral into account
     pub fn deposit(
         mut self,
@@ -866,6 +931,10 @@ impl<'a> TransactionBuilder<'a> {
         remaining_accounts: Vec<AccountMeta>,
     ) -> Self {
         let filler = filler.unwrap_or(&self.authority);
+        let markets_writable = match order.market_type {
+            MarketType::Perp => vec![MarketId::perp(order.market_index)],
+            MarketType::Spot => vec![MarketId::spot(order.market_index), MarketId::QUOTE_SPOT],
+        };
         let mut accounts = build_accounts(
             self.program_data,
             drift::accounts::TriggerOrder {
@@ -876,7 +945,7 @@ impl<'a> TransactionBuilder<'a> {
             },
             &[user_account],
             &[],
-            &[MarketId::perp(order.market_index)],
+            &markets_writable,
         );
 
         // accounts.extend(remaining_accounts);
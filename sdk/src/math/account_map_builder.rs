@@ -9,133 +9,330 @@ use drift::{
         perp_market_map::{MarketSet, PerpMarketMap},
         spot_market::SpotMarket,
         spot_market_map::SpotMarketMap,
+        state::OracleGuardRails,
         user::User,
     },
 };
-use fnv::FnvHashSet;
+use fnv::FnvHashMap;
 use solana_sdk::{account::Account, pubkey::Pubkey};
 
 use crate::{
-    constants, drift_client::DriftClient, utils::zero_account_to_bytes, AccountProvider, MarketId,
-    SdkError, SdkResult,
+    constants,
+    drift_client::DriftClient,
+    marketmap::MarketMap,
+    oraclemap::{Oracle, OracleMap as SdkOracleMap},
+    utils::write_zero_account,
+    AccountProvider, MarketId, SdkError, SdkResult,
 };
 
-/// Builds an AccountMap of relevant spot, perp, and oracle accounts from rpc
+/// Market and oracle accounts needed to build `AccountMaps`
+pub trait AccountMapSource {
+    /// Write the spot market account into `data`, returns its (pubkey, oracle)
+    fn spot_market(&self, market_index: u16, data: &mut Vec<u8>) -> SdkResult<(Pubkey, Pubkey)>;
+    /// Write the perp market account into `data`, returns its (pubkey, oracle)
+    fn perp_market(&self, market_index: u16, data: &mut Vec<u8>) -> SdkResult<(Pubkey, Pubkey)>;
+    /// Write the oracle account into `data`, returns its owner program
+    fn oracle(&self, oracle: &Pubkey, data: &mut Vec<u8>) -> SdkResult<Pubkey>;
+    /// Slot the oracle prices are valid at
+    fn slot(&self) -> u64;
+    fn oracle_guard_rails(&self) -> SdkResult<OracleGuardRails>;
+}
+
+impl<T: AccountProvider> AccountMapSource for DriftClient<T> {
+    fn spot_market(&self, market_index: u16, data: &mut Vec<u8>) -> SdkResult<(Pubkey, Pubkey)> {
+        let market = self
+            .get_spot_market_account(market_index)
+            .ok_or(SdkError::MarketNotFound(MarketId::spot(market_index)))?;
+        write_zero_account(&market, data);
+        Ok((market.pubkey, market.oracle))
+    }
+
+    fn perp_market(&self, market_index: u16, data: &mut Vec<u8>) -> SdkResult<(Pubkey, Pubkey)> {
+        let market = self
+            .get_perp_market_account(market_index)
+            .ok_or(SdkError::MarketNotFound(MarketId::perp(market_index)))?;
+        write_zero_account(&market, data);
+        Ok((market.pubkey, market.amm.oracle))
+    }
+
+    fn oracle(&self, oracle: &Pubkey, data: &mut Vec<u8>) -> SdkResult<Pubkey> {
+        let entry = self
+            .backend
+            .oracle_map
+            .oraclemap
+            .get(oracle)
+            .ok_or(SdkError::OracleNotFound(*oracle))?;
+        data.clear();
+        data.extend_from_slice(&entry.raw);
+        Ok(oracle_owner(entry.source))
+    }
+
+    fn slot(&self) -> u64 {
+        let perp_slot = self.backend.perp_market_map.get_latest_slot();
+        let spot_slot = self.backend.spot_market_map.get_latest_slot();
+        let oracle_slot = self.backend.oracle_map.get_latest_slot();
+        oracle_slot.max(perp_slot).max(spot_slot)
+    }
+
+    fn oracle_guard_rails(&self) -> SdkResult<OracleGuardRails> {
+        self.backend
+            .state_account
+            .read()
+            .map(|state| state.oracle_guard_rails)
+            .map_err(|_| SdkError::Generic("state account lock poisoned".to_string()))
+    }
+}
+
+struct SerializedMarket {
+    pubkey: Pubkey,
+    oracle: Pubkey,
+    data: Vec<u8>,
+}
+
+/// Point in time copy of serialized markets and oracles
+///
+/// Serializes every account once so margin can be computed for many users without
+/// touching the live maps, e.g. when scanning the whole `UserMap`
+#[derive(Default)]
+pub struct AccountSnapshot {
+    spot_markets: FnvHashMap<u16, SerializedMarket>,
+    perp_markets: FnvHashMap<u16, SerializedMarket>,
+    /// oracle -> (owner, raw account data)
+    oracles: FnvHashMap<Pubkey, (Pubkey, Vec<u8>)>,
+    slot: u64,
+    oracle_guard_rails: OracleGuardRails,
+}
+
+impl AccountSnapshot {
+    pub fn new(
+        perp_markets: &[PerpMarket],
+        spot_markets: &[SpotMarket],
+        oracles: &[Oracle],
+        slot: u64,
+        oracle_guard_rails: OracleGuardRails,
+    ) -> Self {
+        let mut snapshot = Self {
+            slot,
+            oracle_guard_rails,
+            ..Default::default()
+        };
+        for market in perp_markets {
+            snapshot.insert_perp_market(market);
+        }
+        for market in spot_markets {
+            snapshot.insert_spot_market(market);
+        }
+        for oracle in oracles {
+            snapshot.insert_oracle(oracle);
+        }
+
+        snapshot
+    }
+
+    /// Snapshot the given market and oracle maps
+    pub fn from_maps(
+        perp_market_map: &MarketMap<PerpMarket>,
+        spot_market_map: &MarketMap<SpotMarket>,
+        oracle_map: &SdkOracleMap,
+        oracle_guard_rails: OracleGuardRails,
+    ) -> Self {
+        let slot = oracle_map
+            .get_latest_slot()
+            .max(perp_market_map.get_latest_slot())
+            .max(spot_market_map.get_latest_slot());
+
+        Self::new(
+            &perp_market_map.values(),
+            &spot_market_map.values(),
+            &oracle_map.values(),
+            slot,
+            oracle_guard_rails,
+        )
+    }
+
+    /// Snapshot the markets and oracles `client` is subscribed to
+    pub fn from_client<T: AccountProvider>(client: &DriftClient<T>) -> SdkResult<Self> {
+        Ok(Self::from_maps(
+            &client.backend.perp_market_map,
+            &client.backend.spot_market_map,
+            &client.backend.oracle_map,
+            client.oracle_guard_rails()?,
+        ))
+    }
+
+    pub fn insert_perp_market(&mut self, market: &PerpMarket) {
+        let entry = self
+            .perp_markets
+            .entry(market.market_index)
+            .or_insert_with(|| SerializedMarket {
+                pubkey: market.pubkey,
+                oracle: market.amm.oracle,
+                data: Vec::new(),
+            });
+        entry.pubkey = market.pubkey;
+        entry.oracle = market.amm.oracle;
+        write_zero_account(market, &mut entry.data);
+    }
+
+    pub fn insert_spot_market(&mut self, market: &SpotMarket) {
+        let entry = self
+            .spot_markets
+            .entry(market.market_index)
+            .or_insert_with(|| SerializedMarket {
+                pubkey: market.pubkey,
+                oracle: market.oracle,
+                data: Vec::new(),
+            });
+        entry.pubkey = market.pubkey;
+        entry.oracle = market.oracle;
+        write_zero_account(market, &mut entry.data);
+    }
+
+    pub fn insert_oracle(&mut self, oracle: &Oracle) {
+        self.slot = self.slot.max(oracle.slot);
+        self.oracles.insert(
+            oracle.pubkey,
+            (oracle_owner(oracle.source), oracle.raw.clone()),
+        );
+    }
+
+    pub fn set_slot(&mut self, slot: u64) {
+        self.slot = slot;
+    }
+}
+
+impl AccountMapSource for AccountSnapshot {
+    fn spot_market(&self, market_index: u16, data: &mut Vec<u8>) -> SdkResult<(Pubkey, Pubkey)> {
+        let market = self
+            .spot_markets
+            .get(&market_index)
+            .ok_or(SdkError::MarketNotFound(MarketId::spot(market_index)))?;
+        data.clear();
+        data.extend_from_slice(&market.data);
+        Ok((market.pubkey, market.oracle))
+    }
+
+    fn perp_market(&self, market_index: u16, data: &mut Vec<u8>) -> SdkResult<(Pubkey, Pubkey)> {
+        let market = self
+            .perp_markets
+            .get(&market_index)
+            .ok_or(SdkError::MarketNotFound(MarketId::perp(market_index)))?;
+        data.clear();
+        data.extend_from_slice(&market.data);
+        Ok((market.pubkey, market.oracle))
+    }
+
+    fn oracle(&self, oracle: &Pubkey, data: &mut Vec<u8>) -> SdkResult<Pubkey> {
+        let (owner, raw) = self
+            .oracles
+            .get(oracle)
+            .ok_or(SdkError::OracleNotFound(*oracle))?;
+        data.clear();
+        data.extend_from_slice(raw);
+        Ok(*owner)
+    }
+
+    fn slot(&self) -> u64 {
+        self.slot
+    }
+
+    fn oracle_guard_rails(&self) -> SdkResult<OracleGuardRails> {
+        Ok(self.oracle_guard_rails)
+    }
+}
+
+/// Builds an AccountMap of the spot, perp, and oracle accounts relevant to a user
+///
+/// Account buffers are kept between builds, reuse one builder when computing margin for
+/// many users
 #[derive(Default)]
-pub(crate) struct AccountMapBuilder {
+pub struct AccountMapBuilder {
     /// placeholder account values populated with real market & oracle account data
     accounts: Vec<(Pubkey, Account)>,
+    oracles: Vec<Pubkey>,
 }
 
 impl AccountMapBuilder {
-    /// Constructs the account map + drift state account
+    /// Constructs the account map from `client`'s subscribed markets and oracles
     pub fn build<T: AccountProvider>(
         &mut self,
         client: &DriftClient<T>,
         user: &User,
     ) -> SdkResult<AccountMaps> {
-        let mut oracles = FnvHashSet::<Pubkey>::default();
-        let mut spot_markets = Vec::<SpotMarket>::with_capacity(user.spot_positions.len() + 1); // +1 incase missing USDC position
-        let mut perp_markets = Vec::<PerpMarket>::with_capacity(user.perp_positions.len());
-
-        for p in user.spot_positions.iter().filter(|p| !p.is_available()) {
-            let market = client
-                .get_spot_market_account(p.market_index)
-                .expect("spot market");
-            oracles.insert(market.oracle);
-            self.accounts.push((market.pubkey, Account::default()));
-            spot_markets.push(market);
-        }
-
-        let quote_market = client
-            .get_spot_market_account(MarketId::QUOTE_SPOT.index)
-            .expect("spot market");
-        if oracles.insert(quote_market.oracle) {
-            // ensure always include the spot USDC market
-            self.accounts
-                .push((quote_market.pubkey, Account::default()));
-            spot_markets.push(quote_market);
-        }
+        self.build_from(client, user)
+    }
 
-        for p in user.perp_positions.iter().filter(|p| !p.is_available()) {
-            let market = client
-                .get_perp_market_account(p.market_index)
-                .expect("perp market");
-            oracles.insert(market.amm.oracle);
-            self.accounts.push((market.pubkey, Account::default()));
-            perp_markets.push(market);
-        }
+    /// Constructs the account map from any `AccountMapSource`
+    pub fn build_from<S: AccountMapSource + ?Sized>(
+        &mut self,
+        source: &S,
+        user: &User,
+    ) -> SdkResult<AccountMaps> {
+        self.oracles.clear();
+        let mut len = 0;
 
-        self.accounts
-            .extend(oracles.iter().map(|x| (*x, Default::default())));
-        let mut accounts_iter = self.accounts.iter_mut();
-
-        let mut spot_accounts = Vec::<AccountInfo>::with_capacity(spot_markets.len());
-        for market in spot_markets.iter() {
-            let (pubkey, account) = accounts_iter.next().unwrap();
-            account.data = zero_account_to_bytes(*market);
-            spot_accounts.push(AccountInfo::new(
-                pubkey,
-                false,
-                false,
-                &mut account.lamports,
-                &mut account.data[..],
-                &constants::PROGRAM_ID,
-                false,
-                0,
-            ));
+        // always include the spot USDC market
+        let quote_index = MarketId::QUOTE_SPOT.index();
+        let has_quote_position = user
+            .spot_positions
+            .iter()
+            .any(|p| !p.is_available() && p.market_index == quote_index);
+        let spot_indexes = user
+            .spot_positions
+            .iter()
+            .filter(|p| !p.is_available())
+            .map(|p| p.market_index)
+            .chain((!has_quote_position).then_some(quote_index));
+        for market_index in spot_indexes {
+            let (pubkey, account) = self.account(len);
+            let (market, oracle) = source.spot_market(market_index, &mut account.data)?;
+            *pubkey = market;
+            self.add_oracle(oracle);
+            len += 1;
         }
+        let spot_len = len;
 
-        let mut perp_accounts = Vec::<AccountInfo>::with_capacity(perp_markets.len());
-        for market in perp_markets.iter() {
-            let (pubkey, account) = accounts_iter.next().unwrap();
-            account.data = zero_account_to_bytes(*market);
-            perp_accounts.push(AccountInfo::new(
-                pubkey,
-                false,
-                false,
-                &mut account.lamports,
-                &mut account.data[..],
-                &constants::PROGRAM_ID,
-                false,
-                0,
-            ));
+        for market_index in user
+            .perp_positions
+            .iter()
+            .filter(|p| !p.is_available())
+            .map(|p| p.market_index)
+        {
+            let (pubkey, account) = self.account(len);
+            let (market, oracle) = source.perp_market(market_index, &mut account.data)?;
+            *pubkey = market;
+            self.add_oracle(oracle);
+            len += 1;
         }
+        let perp_len = len - spot_len;
 
-        let mut oracle_accounts = Vec::<AccountInfo>::with_capacity(oracles.len());
-        for oracle_key in oracles.iter() {
-            let oracle = client
-                .backend
-                .oracle_map
-                .get(oracle_key)
-                .expect("oracle exists");
-            let owner = match oracle.source {
-                OracleSource::Pyth
-                | OracleSource::Pyth1K
-                | OracleSource::Pyth1M
-                | OracleSource::PythStableCoin => &pyth_program::ID,
-                OracleSource::Switchboard => &switchboard_program::ID,
-                OracleSource::QuoteAsset => &constants::DEFAULT_PUBKEY,
-                OracleSource::Prelaunch => &drift::ID,
-            };
-            let (pubkey, account) = accounts_iter.next().unwrap();
-            account.data.clone_from(&oracle.raw);
-            oracle_accounts.push(AccountInfo::new(
-                pubkey,
-                false,
-                false,
-                &mut account.lamports,
-                &mut account.data[..],
-                owner,
-                false,
-                0,
-            ));
+        for i in 0..self.oracles.len() {
+            let oracle = self.oracles[i];
+            let (pubkey, account) = self.account(len);
+            account.owner = source.oracle(&oracle, &mut account.data)?;
+            *pubkey = oracle;
+            len += 1;
         }
 
-        let perp_slot = client.backend.perp_market_map.get_latest_slot();
-        let spot_slot = client.backend.spot_market_map.get_latest_slot();
-        let oracle_slot = client.backend.oracle_map.get_latest_slot();
-        let slot = std::cmp::max(oracle_slot, std::cmp::max(perp_slot, spot_slot));
+        let (spot, rest) = self.accounts[..len].split_at_mut(spot_len);
+        let (perp, oracles) = rest.split_at_mut(perp_len);
+        let spot_accounts: Vec<AccountInfo> = spot.iter_mut().map(market_account_info).collect();
+        let perp_accounts: Vec<AccountInfo> = perp.iter_mut().map(market_account_info).collect();
+        let oracle_accounts: Vec<AccountInfo> = oracles
+            .iter_mut()
+            .map(|(pubkey, account)| {
+                AccountInfo::new(
+                    pubkey,
+                    false,
+                    false,
+                    &mut account.lamports,
+                    &mut account.data[..],
+                    &account.owner,
+                    false,
+                    0,
+                )
+            })
+            .collect();
 
         let perp_market_map =
             PerpMarketMap::load(&MarketSet::default(), &mut perp_accounts.iter().peekable())
@@ -145,11 +342,10 @@ impl AccountMapBuilder {
             SpotMarketMap::load(&MarketSet::default(), &mut spot_accounts.iter().peekable())
                 .map_err(|err| SdkError::Anchor(Box::new(err.into())))?;
 
-        let state_account = client.backend.state_account.read().unwrap();
         let oracle_map = OracleMap::load(
             &mut oracle_accounts.iter().peekable(),
-            slot,
-            Some(state_account.oracle_guard_rails),
+            source.slot(),
+            Some(source.oracle_guard_rails()?),
         )
         .map_err(|err| SdkError::Anchor(Box::new(err.into())))?;
 
@@ -159,4 +355,112 @@ impl AccountMapBuilder {
             oracle_map,
         })
     }
+
+    /// The `index`th reusable account buffer
+    fn account(&mut self, index: usize) -> &mut (Pubkey, Account) {
+        if index == self.accounts.len() {
+            self.accounts.push(Default::default());
+        }
+        &mut self.accounts[index]
+    }
+
+    fn add_oracle(&mut self, oracle: Pubkey) {
+        if !self.oracles.contains(&oracle) {
+            self.oracles.push(oracle);
+        }
+    }
+}
+
+fn market_account_info((pubkey, account): &mut (Pubkey, Account)) -> AccountInfo<'_> {
+    AccountInfo::new(
+        pubkey,
+        false,
+        false,
+        &mut account.lamports,
+        &mut account.data[..],
+        &constants::PROGRAM_ID,
+        false,
+        0,
+    )
+}
+
+fn oracle_owner(source: OracleSource) -> Pubkey {
+    match source {
+        OracleSource::Pyth
+        | OracleSource::Pyth1K
+        | OracleSource::Pyth1M
+        | OracleSource::PythStableCoin => pyth_program::ID,
+        OracleSource::Switchboard => switchboard_program::ID,
+        OracleSource::QuoteAsset => constants::DEFAULT_PUBKEY,
+        OracleSource::Prelaunch => drift::ID,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use drift::math::constants::SPOT_CUMULATIVE_INTEREST_PRECISION;
+
+    use super::*;
+
+    fn usdc() -> SpotMarket {
+        SpotMarket {
+            market_index: 0,
+            pubkey: Pubkey::new_unique(),
+            oracle_source: OracleSource::QuoteAsset,
+            decimals: 6,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            ..Default::default()
+        }
+    }
+
+    fn quote_oracle() -> Oracle {
+        Oracle {
+            pubkey: Pubkey::default(),
+            data: Default::default(),
+            source: OracleSource::QuoteAsset,
+            slot: 1,
+            raw: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_missing_market_is_an_error() {
+        let snapshot =
+            AccountSnapshot::new(&[], &[usdc()], &[quote_oracle()], 1, Default::default());
+        let mut user = User::default();
+        user.perp_positions[0].market_index = 3;
+        user.perp_positions[0].base_asset_amount = 1;
+
+        let mut builder = AccountMapBuilder::default();
+        assert!(matches!(
+            builder.build_from(&snapshot, &user),
+            Err(SdkError::MarketNotFound(market)) if market == MarketId::perp(3)
+        ));
+
+        let empty = AccountSnapshot::default();
+        assert!(matches!(
+            builder.build_from(&empty, &User::default()),
+            Err(SdkError::MarketNotFound(market)) if market == MarketId::QUOTE_SPOT
+        ));
+    }
+
+    #[test]
+    fn test_builds_reuse_account_buffers() {
+        let snapshot =
+            AccountSnapshot::new(&[], &[usdc()], &[quote_oracle()], 1, Default::default());
+        let mut user = User::default();
+        user.spot_positions[0].market_index = 0;
+        user.spot_positions[0].scaled_balance = 1;
+
+        let mut builder = AccountMapBuilder::default();
+        for _ in 0..3 {
+            let maps = builder.build_from(&snapshot, &user).unwrap();
+            assert!(maps.spot_market_map.get_ref(&0).is_ok());
+        }
+
+        // the quote market is not added twice and its oracle only once
+        assert_eq!(builder.accounts.len(), 2);
+        assert_eq!(builder.oracles, vec![Pubkey::default()]);
+    }
 }
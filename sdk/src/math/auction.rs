@@ -5,7 +5,11 @@ use drift::{
     state::user::{Order, OrderType},
 };
 
-use crate::is_one_of_variant;
+use crate::{
+    dlob::order_book_levels::L2Level,
+    is_one_of_variant,
+    types::{SdkError, SdkResult},
+};
 
 pub fn is_auction_complete(order: &Order, slot: u64) -> bool {
     if order.auction_duration == 0 {
@@ -103,3 +107,290 @@ fn get_auction_price_for_oracle_offset_auction(
 
     oracle_price as i128 + price_offset
 }
+
+/// How `derive_market_order_auction` prices a new taker auction
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AuctionConfig {
+    /// max slippage accepted beyond the worst book price, e.g. `0.005` for 0.5%
+    pub slippage_tolerance: f64,
+    /// offset of the start price from the oracle in the taker's favour, e.g. `0.0005`
+    pub start_price_offset: f64,
+    pub min_duration: u8,
+    pub max_duration: u8,
+}
+
+impl Default for AuctionConfig {
+    fn default() -> Self {
+        Self {
+            slippage_tolerance: 0.005,
+            start_price_offset: 0.0005,
+            min_duration: 10,
+            max_duration: 60,
+        }
+    }
+}
+
+/// Auction of a new taker order in `PRICE_PRECISION`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DerivedAuction {
+    pub duration: u8,
+    pub start_price: i64,
+    pub end_price: i64,
+    /// worst price the order may fill at, equals `end_price`
+    pub limit_price: i64,
+}
+
+impl DerivedAuction {
+    /// Prices as offsets from `oracle_price`, for `Oracle` orders
+    pub fn to_oracle_offsets(self, oracle_price: i64) -> Self {
+        Self {
+            duration: self.duration,
+            start_price: self.start_price - oracle_price,
+            end_price: self.end_price - oracle_price,
+            limit_price: self.limit_price,
+        }
+    }
+}
+
+/// Derive auction params for a market order of `base_asset_amount` the way drift's UI does
+///
+/// `levels` is the side of the book the order takes from, best level first (asks for
+/// longs, bids for shorts). The auction starts at the oracle price offset in the taker's
+/// favour (or the best level if that is better), ends at the worst level needed to fill
+/// the order widened by the slippage tolerance, and lasts longer the more the order moves
+/// the book relative to that tolerance. If the book can't fill the whole order the worse
+/// of the deepest level and the oracle price is taken as the worst level.
+pub fn derive_market_order_auction(
+    levels: &[L2Level],
+    oracle_price: i64,
+    direction: PositionDirection,
+    base_asset_amount: u64,
+    config: &AuctionConfig,
+) -> SdkResult<DerivedAuction> {
+    if oracle_price <= 0 {
+        return Err(SdkError::Generic(format!(
+            "invalid oracle price {oracle_price}"
+        )));
+    }
+    if base_asset_amount == 0 {
+        return Err(SdkError::Generic("order size must be positive".to_string()));
+    }
+    if config.min_duration == 0 || config.min_duration > config.max_duration {
+        return Err(SdkError::Generic(format!(
+            "invalid auction duration range {}..={}",
+            config.min_duration, config.max_duration
+        )));
+    }
+
+    let is_long = direction == PositionDirection::Long;
+    // move `price` by `fraction` against the taker, negative is in the taker's favour
+    let worsen = |price: f64, fraction: f64| {
+        if is_long {
+            price * (1.0 + fraction)
+        } else {
+            price * (1.0 - fraction)
+        }
+    };
+
+    let mut remaining = base_asset_amount as i128;
+    let mut best_price = None;
+    let mut worst_price = None;
+    for level in levels.iter().filter(|l| l.size != 0) {
+        best_price.get_or_insert(level.price);
+        worst_price = Some(level.price);
+        remaining -= level.size.abs();
+        if remaining <= 0 {
+            break;
+        }
+    }
+
+    let oracle = oracle_price as f64;
+    let worst = match worst_price {
+        Some(price) if remaining <= 0 => price as f64,
+        Some(price) if is_long => oracle.max(price as f64),
+        Some(price) => oracle.min(price as f64),
+        None => oracle,
+    };
+    let end_price = worsen(worst, config.slippage_tolerance);
+
+    let mut start_price = worsen(oracle, -config.start_price_offset);
+    if let Some(best) = best_price {
+        let best = best as f64;
+        start_price = if is_long {
+            start_price.min(best)
+        } else {
+            start_price.max(best)
+        };
+    }
+
+    let reference = best_price.map(|p| p as f64).unwrap_or(oracle);
+    let impact = (worst - reference).abs() / reference;
+    let fraction = if config.slippage_tolerance > 0.0 {
+        (impact / config.slippage_tolerance).min(1.0)
+    } else {
+        1.0
+    };
+    let duration =
+        config.min_duration as f64 + (config.max_duration - config.min_duration) as f64 * fraction;
+
+    let (start_price, end_price) = (start_price.round() as i64, end_price.round() as i64);
+    if end_price <= 0 {
+        return Err(SdkError::Generic(format!(
+            "slippage tolerance {} leaves no valid end price",
+            config.slippage_tolerance
+        )));
+    }
+
+    Ok(DerivedAuction {
+        duration: duration.round() as u8,
+        start_price,
+        end_price,
+        limit_price: end_price,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    const PRICE: i64 = 1_000_000;
+    const BASE: i128 = 1_000_000_000;
+
+    fn level(price: i64, size: i128) -> L2Level {
+        L2Level::new(price as u128, size, HashMap::new())
+    }
+
+    fn asks() -> Vec<L2Level> {
+        vec![
+            level(100 * PRICE + 10_000, BASE),
+            level(100 * PRICE + 50_000, 2 * BASE),
+            level(100 * PRICE + 200_000, 5 * BASE),
+        ]
+    }
+
+    #[test]
+    fn test_small_long_starts_below_best_ask() {
+        let auction = derive_market_order_auction(
+            &asks(),
+            100 * PRICE,
+            PositionDirection::Long,
+            BASE as u64 / 2,
+            &AuctionConfig::default(),
+        )
+        .unwrap();
+
+        // oracle - 0.05%
+        assert_eq!(auction.start_price, 99_950_000);
+        // best ask + 0.5%
+        assert_eq!(auction.end_price, 100_510_050);
+        assert_eq!(auction.limit_price, auction.end_price);
+        assert_eq!(auction.duration, 10);
+    }
+
+    #[test]
+    fn test_large_long_walks_the_book() {
+        let small = derive_market_order_auction(
+            &asks(),
+            100 * PRICE,
+            PositionDirection::Long,
+            2 * BASE as u64,
+            &AuctionConfig::default(),
+        )
+        .unwrap();
+        let large = derive_market_order_auction(
+            &asks(),
+            100 * PRICE,
+            PositionDirection::Long,
+            6 * BASE as u64,
+            &AuctionConfig::default(),
+        )
+        .unwrap();
+
+        assert_eq!(small.end_price, 100_550_250);
+        assert_eq!(large.end_price, 100_701_000);
+        assert!(small.duration > 10 && small.duration < large.duration);
+        assert!(large.duration <= 60);
+    }
+
+    #[test]
+    fn test_short_mirrors_long() {
+        let bids = vec![level(99 * PRICE, BASE), level(98 * PRICE, BASE)];
+        let auction = derive_market_order_auction(
+            &bids,
+            100 * PRICE,
+            PositionDirection::Short,
+            2 * BASE as u64,
+            &AuctionConfig::default(),
+        )
+        .unwrap();
+
+        // oracle + 0.05% is above the best bid
+        assert_eq!(auction.start_price, 100_050_000);
+        assert_eq!(auction.end_price, 97_510_000);
+        assert_eq!(auction.duration, 60);
+
+        let offsets = auction.to_oracle_offsets(100 * PRICE);
+        assert_eq!(offsets.start_price, 50_000);
+        assert_eq!(offsets.end_price, -2_490_000);
+        assert_eq!(offsets.limit_price, auction.limit_price);
+    }
+
+    #[test]
+    fn test_thin_book_falls_back_to_oracle_slippage() {
+        let empty = derive_market_order_auction(
+            &[],
+            100 * PRICE,
+            PositionDirection::Long,
+            BASE as u64,
+            &AuctionConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(empty.end_price, 100_500_000);
+
+        let thin = derive_market_order_auction(
+            &asks(),
+            100 * PRICE,
+            PositionDirection::Long,
+            100 * BASE as u64,
+            &AuctionConfig::default(),
+        )
+        .unwrap();
+        // the deepest ask is above the oracle
+        assert_eq!(thin.end_price, 100_701_000);
+    }
+
+    #[test]
+    fn test_invalid_inputs() {
+        let config = AuctionConfig {
+            min_duration: 20,
+            max_duration: 10,
+            ..Default::default()
+        };
+        assert!(derive_market_order_auction(
+            &asks(),
+            100 * PRICE,
+            PositionDirection::Long,
+            1,
+            &config
+        )
+        .is_err());
+        assert!(derive_market_order_auction(
+            &asks(),
+            0,
+            PositionDirection::Long,
+            1,
+            &AuctionConfig::default()
+        )
+        .is_err());
+        assert!(derive_market_order_auction(
+            &asks(),
+            PRICE,
+            PositionDirection::Long,
+            0,
+            &AuctionConfig::default()
+        )
+        .is_err());
+    }
+}
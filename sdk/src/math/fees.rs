@@ -0,0 +1,385 @@
+//! fee tiers, maker rebates and filler rewards
+//!
+
+use drift::{
+    math::constants::QUOTE_PRECISION_U64,
+    state::{
+        state::{FeeStructure, FeeTier, State},
+        user::MarketType,
+    },
+};
+
+use crate::types::UserStatsAccount;
+
+/// 30 day volume (taker + maker) needed for perp fee tiers 1..=5
+const PERP_VOLUME_THRESHOLDS: [u64; 5] = [
+    2_000_000 * QUOTE_PRECISION_U64,
+    10_000_000 * QUOTE_PRECISION_U64,
+    20_000_000 * QUOTE_PRECISION_U64,
+    80_000_000 * QUOTE_PRECISION_U64,
+    200_000_000 * QUOTE_PRECISION_U64,
+];
+
+/// Insurance fund stake needed for each perp stake benefit
+const STAKE_THRESHOLDS: [u64; 5] = [
+    (1_000 - 1) * QUOTE_PRECISION_U64,
+    (10_000 - 1) * QUOTE_PRECISION_U64,
+    (50_000 - 1) * QUOTE_PRECISION_U64,
+    (100_000 - 1) * QUOTE_PRECISION_U64,
+    (250_000 - 5) * QUOTE_PRECISION_U64,
+];
+
+/// Percent taker fee discount and maker rebate boost per stake threshold reached
+const STAKE_BENEFIT_PERCENT: [u32; 6] = [0, 5, 10, 20, 30, 40];
+
+/// Fees of one fill (QUOTE_PRECISION)
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct FillFees {
+    /// paid by the taker, after the referee discount
+    pub taker_fee: u64,
+    /// paid to the maker, 0 when filling against the AMM
+    pub maker_rebate: u64,
+    /// paid to the filler out of the taker fee
+    pub filler_reward: u64,
+    /// paid to the taker's referrer out of the taker fee
+    pub referrer_reward: u64,
+    /// taker fee discount for referred takers
+    pub referee_discount: u64,
+}
+
+/// Fee structure of `market_type`
+pub fn get_fee_structure(state: &State, market_type: MarketType) -> &FeeStructure {
+    match market_type {
+        MarketType::Perp => &state.perp_fee_structure,
+        MarketType::Spot => &state.spot_fee_structure,
+    }
+}
+
+/// Fee tier of a user, tier 0 for users without stats
+///
+/// Perp tiers are picked by 30 day volume and boosted by insurance fund stake, spot markets
+/// use tier 0 for everyone
+pub fn determine_user_fee_tier(
+    user_stats: Option<&UserStatsAccount>,
+    fee_structure: &FeeStructure,
+    market_type: MarketType,
+) -> FeeTier {
+    let user_stats = match (user_stats, market_type) {
+        (Some(user_stats), MarketType::Perp) => user_stats,
+        _ => return fee_structure.fee_tiers[0],
+    };
+
+    let total_30d_volume = user_stats
+        .taker_volume_30d
+        .saturating_add(user_stats.maker_volume_30d);
+    let tier_index = PERP_VOLUME_THRESHOLDS
+        .iter()
+        .position(|threshold| total_30d_volume < *threshold)
+        .unwrap_or(PERP_VOLUME_THRESHOLDS.len());
+    let stake_index = STAKE_THRESHOLDS
+        .iter()
+        .position(|threshold| user_stats.if_staked_quote_asset_amount < *threshold)
+        .unwrap_or(STAKE_THRESHOLDS.len());
+
+    let mut tier = fee_structure.fee_tiers[tier_index];
+    let stake_benefit = STAKE_BENEFIT_PERCENT[stake_index];
+    if stake_benefit > 0 {
+        tier.fee_numerator = tier.fee_numerator * (100 - stake_benefit) / 100;
+        tier.maker_rebate_numerator = tier.maker_rebate_numerator * (100 + stake_benefit) / 100;
+    }
+
+    tier
+}
+
+/// Maker rebate (numerator, denominator) of the base fee tier, scaled by a perp market's
+/// `fee_adjustment` percent
+pub fn get_maker_rebate(state: &State, market_type: MarketType, fee_adjustment: i16) -> (u32, u32) {
+    let tier = &get_fee_structure(state, market_type).fee_tiers[0];
+    (
+        adjust_fee(tier.maker_rebate_numerator as u64, fee_adjustment) as u32,
+        tier.maker_rebate_denominator,
+    )
+}
+
+/// Taker fee of `quote_asset_amount` before referral discounts, rounded up
+pub fn calculate_taker_fee(quote_asset_amount: u64, tier: &FeeTier, fee_adjustment: i16) -> u64 {
+    adjust_fee(
+        mul_div_ceil(quote_asset_amount, tier.fee_numerator, tier.fee_denominator),
+        fee_adjustment,
+    )
+}
+
+/// Maker rebate of `quote_asset_amount`, rounded down
+pub fn calculate_maker_rebate(quote_asset_amount: u64, tier: &FeeTier, fee_adjustment: i16) -> u64 {
+    adjust_fee(
+        mul_div(
+            quote_asset_amount,
+            tier.maker_rebate_numerator,
+            tier.maker_rebate_denominator,
+        ),
+        fee_adjustment,
+    )
+}
+
+/// Filler reward for a taker fee, the lower of a share of the fee and a reward growing with
+/// the order's age
+pub fn calculate_filler_reward(
+    taker_fee: u64,
+    seconds_since_order: i64,
+    fee_structure: &FeeStructure,
+) -> u64 {
+    let reward_structure = &fee_structure.filler_reward_structure;
+    let size_reward = mul_div(
+        taker_fee,
+        reward_structure.reward_numerator,
+        reward_structure.reward_denominator,
+    );
+
+    // (age * 10^8)^(1/4) is 100 for a 1 second old order
+    let age = seconds_since_order.max(1) as u128;
+    let time_reward =
+        (fourth_root(age * 100_000_000) * reward_structure.time_based_reward_lower_bound / 100)
+            .min(u64::MAX as u128) as u64;
+
+    size_reward.min(time_reward)
+}
+
+fn fourth_root(n: u128) -> u128 {
+    let mut root = (n as f64).powf(0.25) as u128;
+    while root.pow(4) > n {
+        root -= 1;
+    }
+    while (root + 1).pow(4) <= n {
+        root += 1;
+    }
+    root
+}
+
+/// Fees of filling `quote_asset_amount` of a taker order
+///
+/// `maker_tier` is `None` when the taker fills against the AMM
+pub fn calculate_fill_fees(
+    quote_asset_amount: u64,
+    fee_structure: &FeeStructure,
+    taker_tier: &FeeTier,
+    maker_tier: Option<&FeeTier>,
+    fee_adjustment: i16,
+    has_referrer: bool,
+    seconds_since_order: i64,
+) -> FillFees {
+    let mut taker_fee = calculate_taker_fee(quote_asset_amount, taker_tier, fee_adjustment);
+    let (referee_discount, referrer_reward) = if has_referrer {
+        (
+            mul_div(
+                taker_fee,
+                taker_tier.referee_fee_numerator,
+                taker_tier.referee_fee_denominator,
+            ),
+            mul_div(
+                taker_fee,
+                taker_tier.referrer_reward_numerator,
+                taker_tier.referrer_reward_denominator,
+            ),
+        )
+    } else {
+        (0, 0)
+    };
+    taker_fee -= referee_discount;
+
+    let maker_rebate = match maker_tier {
+        Some(tier) => calculate_maker_rebate(quote_asset_amount, tier, fee_adjustment),
+        None => 0,
+    };
+    let filler_reward = calculate_filler_reward(taker_fee, seconds_since_order, fee_structure);
+
+    FillFees {
+        taker_fee,
+        maker_rebate,
+        filler_reward,
+        referrer_reward,
+        referee_discount,
+    }
+}
+
+fn adjust_fee(fee: u64, fee_adjustment: i16) -> u64 {
+    if fee_adjustment == 0 {
+        return fee;
+    }
+    (fee as i128 * (100 + fee_adjustment as i128) / 100).max(0) as u64
+}
+
+fn mul_div(amount: u64, numerator: u32, denominator: u32) -> u64 {
+    if denominator == 0 {
+        return 0;
+    }
+    (amount as u128 * numerator as u128 / denominator as u128) as u64
+}
+
+fn mul_div_ceil(amount: u64, numerator: u32, denominator: u32) -> u64 {
+    if denominator == 0 {
+        return 0;
+    }
+    (amount as u128 * numerator as u128).div_ceil(denominator as u128) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use drift::state::state::OrderFillerRewardStructure;
+
+    use super::*;
+
+    fn tier(fee_bps_x10: u32, rebate_bps_x10: u32) -> FeeTier {
+        FeeTier {
+            fee_numerator: fee_bps_x10,
+            fee_denominator: 100_000,
+            maker_rebate_numerator: rebate_bps_x10,
+            maker_rebate_denominator: 100_000,
+            referrer_reward_numerator: 10,
+            referrer_reward_denominator: 100,
+            referee_fee_numerator: 5,
+            referee_fee_denominator: 100,
+        }
+    }
+
+    /// drift mainnet perp fee tiers: 10 bps taker / 2 bps maker down to 5 / 2
+    fn perp_fee_structure() -> FeeStructure {
+        let mut fee_structure = FeeStructure::default();
+        fee_structure.fee_tiers[0] = tier(100, 20);
+        fee_structure.fee_tiers[1] = tier(80, 20);
+        fee_structure.fee_tiers[2] = tier(75, 20);
+        fee_structure.fee_tiers[3] = tier(70, 20);
+        fee_structure.fee_tiers[4] = tier(60, 20);
+        fee_structure.fee_tiers[5] = tier(50, 20);
+        fee_structure.filler_reward_structure = OrderFillerRewardStructure {
+            reward_numerator: 10,
+            reward_denominator: 100,
+            time_based_reward_lower_bound: 10_000, // $0.01
+        };
+        fee_structure
+    }
+
+    fn user_stats(volume: u64, stake: u64) -> UserStatsAccount {
+        UserStatsAccount {
+            taker_volume_30d: volume / 2,
+            maker_volume_30d: volume - volume / 2,
+            if_staked_quote_asset_amount: stake,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_fee_tier_by_volume_and_stake() {
+        let fee_structure = perp_fee_structure();
+        let tier_of = |stats: Option<&UserStatsAccount>, market_type| {
+            determine_user_fee_tier(stats, &fee_structure, market_type)
+        };
+
+        assert_eq!(tier_of(None, MarketType::Perp), fee_structure.fee_tiers[0]);
+        assert_eq!(
+            tier_of(Some(&user_stats(0, 0)), MarketType::Perp),
+            fee_structure.fee_tiers[0]
+        );
+        assert_eq!(
+            tier_of(
+                Some(&user_stats(2_000_000 * QUOTE_PRECISION_U64, 0)),
+                MarketType::Perp
+            ),
+            fee_structure.fee_tiers[1]
+        );
+        assert_eq!(
+            tier_of(
+                Some(&user_stats(500_000_000 * QUOTE_PRECISION_U64, 0)),
+                MarketType::Perp
+            ),
+            fee_structure.fee_tiers[5]
+        );
+
+        // $10k staked: 10% off the taker fee, 10% more maker rebate
+        let staked = tier_of(
+            Some(&user_stats(0, 10_000 * QUOTE_PRECISION_U64)),
+            MarketType::Perp,
+        );
+        assert_eq!(staked.fee_numerator, 90);
+        assert_eq!(staked.maker_rebate_numerator, 22);
+
+        // spot ignores volume and stake
+        assert_eq!(
+            tier_of(
+                Some(&user_stats(500_000_000 * QUOTE_PRECISION_U64, 0)),
+                MarketType::Spot
+            ),
+            fee_structure.fee_tiers[0]
+        );
+    }
+
+    #[test]
+    fn test_fill_fees_against_maker() {
+        let fee_structure = perp_fee_structure();
+        let taker_tier = fee_structure.fee_tiers[0];
+        let maker_tier = fee_structure.fee_tiers[0];
+        // $10,000 fill, order 16 seconds old
+        let fees = calculate_fill_fees(
+            10_000 * QUOTE_PRECISION_U64,
+            &fee_structure,
+            &taker_tier,
+            Some(&maker_tier),
+            0,
+            false,
+            16,
+        );
+
+        assert_eq!(
+            fees,
+            FillFees {
+                taker_fee: 10 * QUOTE_PRECISION_U64,
+                maker_rebate: 2 * QUOTE_PRECISION_U64,
+                // 10% of the fee is $1, the time based reward is 2 * $0.01
+                filler_reward: 20_000,
+                referrer_reward: 0,
+                referee_discount: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_fill_fees_with_referrer_and_fee_adjustment() {
+        let fee_structure = perp_fee_structure();
+        let taker_tier = fee_structure.fee_tiers[0];
+        // $100 fill against the amm in a market with fees cut by 50%, 10^8 seconds old
+        let fees = calculate_fill_fees(
+            100 * QUOTE_PRECISION_U64,
+            &fee_structure,
+            &taker_tier,
+            None,
+            -50,
+            true,
+            100_000_000,
+        );
+
+        // 10 bps of $100 halved is $0.05, 5% referee discount
+        assert_eq!(fees.referee_discount, 2_500);
+        assert_eq!(fees.taker_fee, 47_500);
+        assert_eq!(fees.referrer_reward, 5_000);
+        assert_eq!(fees.maker_rebate, 0);
+        // size based reward, 10% of the discounted fee
+        assert_eq!(fees.filler_reward, 4_750);
+    }
+
+    #[test]
+    fn test_taker_fee_rounds_up() {
+        let tier = tier(100, 20);
+        assert_eq!(calculate_taker_fee(1, &tier, 0), 1);
+        assert_eq!(calculate_maker_rebate(1, &tier, 0), 0);
+    }
+
+    #[test]
+    fn test_maker_rebate_with_fee_adjustment() {
+        let mut state = State::default();
+        state.perp_fee_structure = perp_fee_structure();
+        assert_eq!(get_maker_rebate(&state, MarketType::Perp, 0), (20, 100_000));
+        assert_eq!(
+            get_maker_rebate(&state, MarketType::Perp, -50),
+            (10, 100_000)
+        );
+    }
+}
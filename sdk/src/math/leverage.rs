@@ -10,18 +10,20 @@ use drift::state::user::User;
 
 pub fn get_leverage<T: AccountProvider>(client: &DriftClient<T>, user: &User) -> SdkResult<u128> {
     let mut accounts_builder = AccountMapBuilder::default();
-    let mut account_maps = accounts_builder.build(client, user)?;
+    get_leverage_inner(user, &mut accounts_builder.build(client, user)?)
+}
 
+pub(crate) fn get_leverage_inner(user: &User, account_maps: &mut AccountMaps) -> SdkResult<u128> {
     let AccountMaps {
-        perp_market_map,
-        spot_market_map,
+        ref perp_market_map,
+        ref spot_market_map,
         ref mut oracle_map,
     } = account_maps;
 
     let margin_calculation = calculate_margin_requirement_and_total_collateral_and_liability_info(
         user,
-        &perp_market_map,
-        &spot_market_map,
+        perp_market_map,
+        spot_market_map,
         oracle_map,
         MarginContext::standard(MarginRequirementType::Maintenance),
     )
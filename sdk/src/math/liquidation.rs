@@ -280,7 +280,7 @@ pub fn calculate_margin_requirements<T: AccountProvider>(
 }
 
 /// Calculate the margin requirements of `user` (internal)
-fn calculate_margin_requirements_inner(
+pub(crate) fn calculate_margin_requirements_inner(
     user: &User,
     account_maps: &mut AccountMaps,
 ) -> SdkResult<MarginRequirementInfo> {
@@ -350,7 +350,7 @@ pub fn calculate_collateral<T: AccountProvider>(
     )
 }
 
-fn calculate_collateral_inner(
+pub(crate) fn calculate_collateral_inner(
     user: &User,
     account_maps: &mut AccountMaps,
     margin_category: MarginCategory,
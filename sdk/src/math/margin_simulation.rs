@@ -0,0 +1,597 @@
+//! what-if margin simulation of hypothetical fills, orders and transfers
+//!
+
+use drift::{
+    controller::{position::PositionDirection, spot_balance::update_spot_balances},
+    error::ErrorCode,
+    math::constants::BASE_PRECISION_I128,
+    state::{
+        order_params::OrderParams,
+        spot_market::{SpotBalanceType, SpotMarket},
+        user::{MarketType, PerpPosition, User},
+    },
+};
+
+use crate::{
+    drift_client::DriftClient,
+    math::{
+        account_map_builder::{AccountMapBuilder, AccountMapSource},
+        leverage::get_leverage_inner,
+        liquidation::{
+            calculate_collateral_inner, calculate_liquidation_price_inner,
+            calculate_margin_requirements_inner, CollateralInfo, MarginCategory,
+            MarginRequirementInfo,
+        },
+    },
+    types::MarketId,
+    utils::deserialize_account,
+    AccountProvider, SdkError, SdkResult,
+};
+
+/// A hypothetical change to a user account
+///
+/// Signed base amounts are positive for longs/buys and negative for shorts/sells
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SimulatedChange {
+    /// perp fill of `base_asset_amount` (BASE_PRECISION) at `price` (PRICE_PRECISION)
+    PerpFill {
+        market_index: u16,
+        base_asset_amount: i64,
+        price: u64,
+    },
+    /// spot fill of `base_asset_amount` (token precision) at `price`, settled against USDC
+    ///
+    /// On the USDC market itself both legs are in USDC and only their difference is applied
+    SpotFill {
+        market_index: u16,
+        base_asset_amount: i64,
+        price: u64,
+    },
+    /// open order, counted in the worst case margin requirement
+    Order {
+        market: MarketId,
+        base_asset_amount: i64,
+    },
+    /// deposit of `amount` (token precision)
+    Deposit { market_index: u16, amount: u64 },
+    /// withdraw of `amount` (token precision), borrowing if it exceeds the deposit
+    Withdraw { market_index: u16, amount: u64 },
+}
+
+impl SimulatedChange {
+    /// Open order for `params`
+    pub fn order(params: &OrderParams) -> Self {
+        let base_asset_amount = params.base_asset_amount as i64;
+        Self::Order {
+            market: match params.market_type {
+                MarketType::Perp => MarketId::perp(params.market_index),
+                MarketType::Spot => MarketId::spot(params.market_index),
+            },
+            base_asset_amount: if params.direction == PositionDirection::Long {
+                base_asset_amount
+            } else {
+                -base_asset_amount
+            },
+        }
+    }
+}
+
+/// Margin state of a user account
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarginSnapshot {
+    pub margin_requirement: MarginRequirementInfo,
+    pub initial_collateral: CollateralInfo,
+    pub maintenance_collateral: CollateralInfo,
+    /// PRICE_PRECISION
+    pub leverage: u128,
+    /// (perp market index, liquidation price) of open perp positions, -1 if not liquidatable
+    pub liquidation_prices: Vec<(u16, i64)>,
+}
+
+impl MarginSnapshot {
+    /// True if total collateral covers the initial margin requirement
+    pub fn meets_initial_margin(&self) -> bool {
+        self.initial_collateral.total >= self.margin_requirement.initial as i128
+    }
+
+    pub fn liquidation_price(&self, market_index: u16) -> Option<i64> {
+        self.liquidation_prices
+            .iter()
+            .find(|(index, _)| *index == market_index)
+            .map(|(_, price)| *price)
+    }
+}
+
+/// Margin state of a user before and after a set of `SimulatedChange`s
+#[derive(Debug, Clone)]
+pub struct MarginSimulation {
+    pub before: MarginSnapshot,
+    pub after: MarginSnapshot,
+    /// the user account with the changes applied
+    pub user: User,
+}
+
+impl MarginSimulation {
+    /// Change in initial free collateral (QUOTE_PRECISION)
+    pub fn free_collateral_delta(&self) -> i128 {
+        self.after.initial_collateral.free - self.before.initial_collateral.free
+    }
+
+    /// True if the account would still meet its initial margin requirement, i.e. the
+    /// changes pass a pre-trade risk check
+    pub fn is_allowed(&self) -> bool {
+        self.after.meets_initial_margin()
+    }
+}
+
+/// Simulate the margin state of `user` after `changes`
+///
+/// Changes are applied in order to a copy of `user`, markets and oracles are taken from
+/// `client`'s current state. Fees and funding are not simulated.
+pub fn simulate_margin<T: AccountProvider>(
+    client: &DriftClient<T>,
+    user: &User,
+    changes: &[SimulatedChange],
+) -> SdkResult<MarginSimulation> {
+    simulate_margin_from(client, user, changes)
+}
+
+/// Simulate the margin state of `user` after `changes`, with markets and oracles from any
+/// `AccountMapSource`
+pub fn simulate_margin_from<S: AccountMapSource + ?Sized>(
+    source: &S,
+    user: &User,
+    changes: &[SimulatedChange],
+) -> SdkResult<MarginSimulation> {
+    let mut simulated = *user;
+    for change in changes {
+        apply_change(source, &mut simulated, change)?;
+    }
+
+    Ok(MarginSimulation {
+        before: margin_snapshot_from(source, user)?,
+        after: margin_snapshot_from(source, &simulated)?,
+        user: simulated,
+    })
+}
+
+/// Current margin state of `user`
+pub fn margin_snapshot<T: AccountProvider>(
+    client: &DriftClient<T>,
+    user: &User,
+) -> SdkResult<MarginSnapshot> {
+    margin_snapshot_from(client, user)
+}
+
+/// Current margin state of `user`, with markets and oracles from any `AccountMapSource`
+pub fn margin_snapshot_from<S: AccountMapSource + ?Sized>(
+    source: &S,
+    user: &User,
+) -> SdkResult<MarginSnapshot> {
+    let mut accounts_builder = AccountMapBuilder::default();
+    let mut account_maps = accounts_builder.build_from(source, user)?;
+
+    let mut liquidation_prices = Vec::new();
+    for position in user.perp_positions.iter().filter(|p| p.is_open_position()) {
+        let liquidation_price =
+            calculate_liquidation_price_inner(user, position.market_index, &mut account_maps)?;
+        liquidation_prices.push((position.market_index, liquidation_price));
+    }
+
+    Ok(MarginSnapshot {
+        margin_requirement: calculate_margin_requirements_inner(user, &mut account_maps)?,
+        initial_collateral: calculate_collateral_inner(
+            user,
+            &mut account_maps,
+            MarginCategory::Initial,
+        )?,
+        maintenance_collateral: calculate_collateral_inner(
+            user,
+            &mut account_maps,
+            MarginCategory::Maintenance,
+        )?,
+        leverage: get_leverage_inner(user, &mut account_maps)?,
+        liquidation_prices,
+    })
+}
+
+fn apply_change<S: AccountMapSource + ?Sized>(
+    source: &S,
+    user: &mut User,
+    change: &SimulatedChange,
+) -> SdkResult<()> {
+    match *change {
+        SimulatedChange::PerpFill {
+            market_index,
+            base_asset_amount,
+            price,
+        } => {
+            let position = user
+                .force_get_perp_position_mut(market_index)
+                .map_err(|err| SdkError::Anchor(Box::new(err.into())))?;
+            apply_perp_fill(position, base_asset_amount, price);
+        }
+        SimulatedChange::SpotFill {
+            market_index,
+            base_asset_amount,
+            price,
+        } => {
+            let mut spot_market = get_spot_market(source, market_index)?;
+            let quote_amount = (base_asset_amount.unsigned_abs() as u128 * price as u128)
+                / 10_u128.pow(spot_market.decimals);
+
+            if market_index == MarketId::QUOTE_SPOT.index() {
+                // both legs move the same balance, apply them as one transfer
+                let base_amount = base_asset_amount.unsigned_abs() as u128;
+                let (net_amount, is_deposit) = if base_amount >= quote_amount {
+                    (base_amount - quote_amount, base_asset_amount > 0)
+                } else {
+                    (quote_amount - base_amount, base_asset_amount < 0)
+                };
+                if net_amount > 0 {
+                    let direction = if is_deposit {
+                        SpotBalanceType::Deposit
+                    } else {
+                        SpotBalanceType::Borrow
+                    };
+                    apply_spot_transfer(user, &mut spot_market, net_amount, direction)?;
+                }
+                return Ok(());
+            }
+
+            let (base_direction, quote_direction) = if base_asset_amount > 0 {
+                (SpotBalanceType::Deposit, SpotBalanceType::Borrow)
+            } else {
+                (SpotBalanceType::Borrow, SpotBalanceType::Deposit)
+            };
+            apply_spot_transfer(
+                user,
+                &mut spot_market,
+                base_asset_amount.unsigned_abs() as u128,
+                base_direction,
+            )?;
+            let mut quote_market = get_spot_market(source, MarketId::QUOTE_SPOT.index())?;
+            apply_spot_transfer(user, &mut quote_market, quote_amount, quote_direction)?;
+        }
+        SimulatedChange::Order {
+            market,
+            base_asset_amount,
+        } => apply_order(user, market, base_asset_amount)?,
+        SimulatedChange::Deposit {
+            market_index,
+            amount,
+        } => {
+            let mut spot_market = get_spot_market(source, market_index)?;
+            apply_spot_transfer(
+                user,
+                &mut spot_market,
+                amount as u128,
+                SpotBalanceType::Deposit,
+            )?;
+        }
+        SimulatedChange::Withdraw {
+            market_index,
+            amount,
+        } => {
+            let mut spot_market = get_spot_market(source, market_index)?;
+            apply_spot_transfer(
+                user,
+                &mut spot_market,
+                amount as u128,
+                SpotBalanceType::Borrow,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Adds an open order to the position of `market`, failing like the program once every order
+/// slot of the user is taken
+fn apply_order(user: &mut User, market: MarketId, base_asset_amount: i64) -> SdkResult<()> {
+    let open_orders: usize = user
+        .perp_positions
+        .iter()
+        .map(|p| p.open_orders as usize)
+        .chain(user.spot_positions.iter().map(|p| p.open_orders as usize))
+        .sum();
+    if open_orders >= user.orders.len() {
+        return Err(SdkError::DriftProgramError(ErrorCode::MaxNumberOfOrders));
+    }
+
+    let (open_bids, open_asks, open_orders) = if market.kind() == MarketType::Perp {
+        let position = user
+            .force_get_perp_position_mut(market.index())
+            .map_err(|err| SdkError::Anchor(Box::new(err.into())))?;
+        (
+            &mut position.open_bids,
+            &mut position.open_asks,
+            &mut position.open_orders,
+        )
+    } else {
+        let position = user
+            .force_get_spot_position_mut(market.index())
+            .map_err(|err| SdkError::Anchor(Box::new(err.into())))?;
+        (
+            &mut position.open_bids,
+            &mut position.open_asks,
+            &mut position.open_orders,
+        )
+    };
+    if base_asset_amount > 0 {
+        *open_bids += base_asset_amount;
+    } else {
+        *open_asks += base_asset_amount;
+    }
+    *open_orders = open_orders
+        .checked_add(1)
+        .ok_or(SdkError::DriftProgramError(ErrorCode::MaxNumberOfOrders))?;
+
+    Ok(())
+}
+
+fn get_spot_market<S: AccountMapSource + ?Sized>(
+    source: &S,
+    market_index: u16,
+) -> SdkResult<SpotMarket> {
+    let mut data = Vec::new();
+    source.spot_market(market_index, &mut data)?;
+    deserialize_account(&mut data.as_slice()).ok_or(SdkError::InvalidAccount)
+}
+
+/// Move `token_amount` in (`Deposit`) or out (`Borrow`) of the user's spot position
+fn apply_spot_transfer(
+    user: &mut User,
+    spot_market: &mut SpotMarket,
+    token_amount: u128,
+    direction: SpotBalanceType,
+) -> SdkResult<()> {
+    let position = user
+        .force_get_spot_position_mut(spot_market.market_index)
+        .map_err(|err| SdkError::Anchor(Box::new(err.into())))?;
+    update_spot_balances(token_amount, &direction, spot_market, position, false)
+        .map_err(|err| SdkError::Anchor(Box::new(err.into())))
+}
+
+/// Fill `base_asset_amount` of `position` at `price`, realized pnl stays in the quote amount
+fn apply_perp_fill(position: &mut PerpPosition, base_asset_amount: i64, price: u64) {
+    let quote_delta = (-(base_asset_amount as i128) * price as i128 / BASE_PRECISION_I128) as i64;
+    let current = position.base_asset_amount;
+
+    if current == 0 || current.signum() == base_asset_amount.signum() {
+        position.quote_entry_amount += quote_delta;
+    } else if base_asset_amount.abs() <= current.abs() {
+        // reduce, keep the entry price of what is left
+        let remaining = (current.abs() - base_asset_amount.abs()) as i128;
+        position.quote_entry_amount =
+            (position.quote_entry_amount as i128 * remaining / current.abs() as i128) as i64;
+    } else {
+        // flip, the new position is entered at `price`
+        let flipped = (base_asset_amount.abs() - current.abs()) as i128;
+        position.quote_entry_amount =
+            (quote_delta as i128 * flipped / base_asset_amount.abs() as i128) as i64;
+    }
+
+    position.quote_break_even_amount = position.quote_entry_amount;
+    position.quote_asset_amount += quote_delta;
+    position.base_asset_amount += base_asset_amount;
+}
+
+#[cfg(test)]
+mod tests {
+    use drift::{
+        math::constants::{
+            BASE_PRECISION_I64, PRICE_PRECISION_U64, QUOTE_PRECISION_I128, QUOTE_PRECISION_I64,
+            SPOT_CUMULATIVE_INTEREST_PRECISION, SPOT_WEIGHT_PRECISION,
+        },
+        state::oracle::OracleSource,
+    };
+    use solana_sdk::pubkey::Pubkey;
+
+    use super::*;
+    use crate::{math::account_map_builder::AccountSnapshot, oraclemap::Oracle};
+
+    fn usdc() -> SpotMarket {
+        SpotMarket {
+            market_index: 0,
+            pubkey: Pubkey::new_unique(),
+            oracle_source: OracleSource::QuoteAsset,
+            decimals: 6,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            initial_asset_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_asset_weight: SPOT_WEIGHT_PRECISION,
+            initial_liability_weight: SPOT_WEIGHT_PRECISION,
+            maintenance_liability_weight: SPOT_WEIGHT_PRECISION,
+            ..Default::default()
+        }
+    }
+
+    fn usdc_snapshot() -> AccountSnapshot {
+        let quote_oracle = Oracle {
+            pubkey: Pubkey::default(),
+            data: Default::default(),
+            source: OracleSource::QuoteAsset,
+            slot: 1,
+            raw: Vec::new(),
+        };
+        AccountSnapshot::new(&[], &[usdc()], &[quote_oracle], 1, Default::default())
+    }
+
+    #[test]
+    fn test_perp_fill_open_reduce_and_flip() {
+        let mut position = PerpPosition::default();
+
+        apply_perp_fill(
+            &mut position,
+            2 * BASE_PRECISION_I64,
+            100 * PRICE_PRECISION_U64,
+        );
+        assert_eq!(position.base_asset_amount, 2 * BASE_PRECISION_I64);
+        assert_eq!(position.quote_entry_amount, -200 * QUOTE_PRECISION_I64);
+        assert_eq!(position.quote_asset_amount, -200 * QUOTE_PRECISION_I64);
+
+        // sell half at 110, 10 of realized pnl stays in the quote amount
+        apply_perp_fill(
+            &mut position,
+            -BASE_PRECISION_I64,
+            110 * PRICE_PRECISION_U64,
+        );
+        assert_eq!(position.base_asset_amount, BASE_PRECISION_I64);
+        assert_eq!(position.quote_entry_amount, -100 * QUOTE_PRECISION_I64);
+        assert_eq!(position.quote_asset_amount, -90 * QUOTE_PRECISION_I64);
+
+        // sell 3 at 120, flipping into a 2 short entered at 120
+        apply_perp_fill(
+            &mut position,
+            -3 * BASE_PRECISION_I64,
+            120 * PRICE_PRECISION_U64,
+        );
+        assert_eq!(position.base_asset_amount, -2 * BASE_PRECISION_I64);
+        assert_eq!(position.quote_entry_amount, 240 * QUOTE_PRECISION_I64);
+        assert_eq!(position.quote_break_even_amount, 240 * QUOTE_PRECISION_I64);
+        assert_eq!(position.quote_asset_amount, 270 * QUOTE_PRECISION_I64);
+    }
+
+    #[test]
+    fn test_spot_withdraw_beyond_deposit_borrows() {
+        let mut user = User::default();
+        let mut spot_market = SpotMarket {
+            market_index: 1,
+            decimals: 9,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            ..Default::default()
+        };
+
+        apply_spot_transfer(
+            &mut user,
+            &mut spot_market,
+            1_000_000_000,
+            SpotBalanceType::Deposit,
+        )
+        .unwrap();
+        apply_spot_transfer(
+            &mut user,
+            &mut spot_market,
+            1_500_000_000,
+            SpotBalanceType::Borrow,
+        )
+        .unwrap();
+
+        let position = user.get_spot_position(1).unwrap();
+        assert_eq!(position.balance_type, SpotBalanceType::Borrow);
+        assert_eq!(
+            position.get_signed_token_amount(&spot_market).unwrap(),
+            -500_000_000
+        );
+    }
+
+    #[test]
+    fn test_quote_market_spot_fill() {
+        let snapshot = usdc_snapshot();
+        let mut user = User::default();
+        apply_spot_transfer(
+            &mut user,
+            &mut usdc(),
+            100 * QUOTE_PRECISION_I64 as u128,
+            SpotBalanceType::Deposit,
+        )
+        .unwrap();
+
+        // buying 10 USDC for 10 USDC leaves the balance as is
+        let simulation = simulate_margin_from(
+            &snapshot,
+            &user,
+            &[SimulatedChange::SpotFill {
+                market_index: 0,
+                base_asset_amount: 10 * QUOTE_PRECISION_I64,
+                price: PRICE_PRECISION_U64,
+            }],
+        )
+        .unwrap();
+        assert_eq!(
+            simulation.before.initial_collateral.total,
+            100 * QUOTE_PRECISION_I128
+        );
+        assert_eq!(simulation.after, simulation.before);
+        assert_eq!(simulation.free_collateral_delta(), 0);
+        assert_eq!(
+            simulation
+                .user
+                .get_spot_position(0)
+                .unwrap()
+                .get_signed_token_amount(&usdc())
+                .unwrap(),
+            100 * QUOTE_PRECISION_I128
+        );
+
+        // selling 10 USDC for 11 USDC nets 1 USDC
+        let simulation = simulate_margin_from(
+            &snapshot,
+            &user,
+            &[SimulatedChange::SpotFill {
+                market_index: 0,
+                base_asset_amount: -10 * QUOTE_PRECISION_I64,
+                price: PRICE_PRECISION_U64 * 11 / 10,
+            }],
+        )
+        .unwrap();
+        assert_eq!(
+            simulation.after.initial_collateral.total,
+            101 * QUOTE_PRECISION_I128
+        );
+        assert_eq!(simulation.free_collateral_delta(), QUOTE_PRECISION_I128);
+    }
+
+    #[test]
+    fn test_orders_past_the_order_slots_fail() {
+        let mut user = User::default();
+        let max_orders = user.orders.len();
+
+        for i in 0..max_orders {
+            let market = if i % 2 == 0 {
+                MarketId::perp(0)
+            } else {
+                MarketId::spot(1)
+            };
+            apply_order(&mut user, market, BASE_PRECISION_I64).unwrap();
+        }
+        assert_eq!(
+            user.get_perp_position(0).unwrap().open_orders as usize,
+            max_orders / 2
+        );
+        assert_eq!(
+            user.get_perp_position(0).unwrap().open_bids,
+            (max_orders / 2) as i64 * BASE_PRECISION_I64
+        );
+
+        assert!(matches!(
+            apply_order(&mut user, MarketId::perp(0), -BASE_PRECISION_I64),
+            Err(SdkError::DriftProgramError(ErrorCode::MaxNumberOfOrders))
+        ));
+        assert_eq!(
+            user.get_perp_position(0).unwrap().open_orders as usize,
+            max_orders / 2
+        );
+    }
+
+    #[test]
+    fn test_order_change_from_params() {
+        let params = OrderParams {
+            market_type: MarketType::Spot,
+            market_index: 1,
+            direction: PositionDirection::Short,
+            base_asset_amount: 5,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            SimulatedChange::order(&params),
+            SimulatedChange::Order {
+                market: MarketId::spot(1),
+                base_asset_amount: -5,
+            }
+        );
+    }
+}
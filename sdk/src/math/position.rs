@@ -1 +1,255 @@
-use drift::state::user::PerpPosition;
+//! perp position analytics
+//!
+
+use drift::{
+    math::constants::{BASE_PRECISION_I128, FUNDING_RATE_BUFFER_I128, MARGIN_PRECISION_U128},
+    state::{perp_market::PerpMarket, user::PerpPosition},
+};
+
+use crate::{math::liquidation::MarginCategory, SdkError, SdkResult};
+
+/// PnL breakdown of a perp position (QUOTE_PRECISION)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PositionPnl {
+    /// pnl of the open base amount against its entry price
+    pub unrealized: i128,
+    /// pnl of closed base amount not yet settled into the quote spot balance
+    pub realized: i128,
+    /// funding owed to (positive) or by (negative) the position since its last settlement
+    pub unsettled_funding: i128,
+    /// `unrealized + realized + unsettled_funding`, what settling at `oracle_price` would pay
+    pub unsettled: i128,
+    /// pnl already settled into the quote spot balance
+    pub settled: i128,
+}
+
+/// Average entry price (PRICE_PRECISION), 0 without a position
+pub fn calculate_entry_price(position: &PerpPosition) -> i64 {
+    price_from_quote(position.quote_entry_amount, position.base_asset_amount)
+}
+
+/// Price (PRICE_PRECISION) at which closing the position breaks even after fees and
+/// funding paid, 0 without a position
+pub fn calculate_break_even_price(position: &PerpPosition) -> i64 {
+    price_from_quote(position.quote_break_even_amount, position.base_asset_amount)
+}
+
+fn price_from_quote(quote_amount: i64, base_asset_amount: i64) -> i64 {
+    if base_asset_amount == 0 {
+        return 0;
+    }
+
+    (-(quote_amount as i128) * BASE_PRECISION_I128 / base_asset_amount as i128).unsigned_abs()
+        as i64
+}
+
+/// Absolute value of the position at `oracle_price` (QUOTE_PRECISION)
+pub fn calculate_position_notional(position: &PerpPosition, oracle_price: i64) -> u128 {
+    position.base_asset_amount.unsigned_abs() as u128 * oracle_price.unsigned_abs() as u128
+        / BASE_PRECISION_I128 as u128
+}
+
+/// Funding pnl accrued since the position last settled funding (QUOTE_PRECISION)
+///
+/// Longs pay when the cumulative long funding rate rose, shorts receive when the cumulative
+/// short rate rose. Payments are rounded against the user like the program does.
+pub fn calculate_unsettled_funding_pnl(position: &PerpPosition, market: &PerpMarket) -> i128 {
+    if position.base_asset_amount == 0 {
+        return 0;
+    }
+
+    let cumulative_funding_rate = if position.base_asset_amount > 0 {
+        market.amm.cumulative_funding_rate_long
+    } else {
+        market.amm.cumulative_funding_rate_short
+    };
+    let funding_rate_delta =
+        cumulative_funding_rate - position.last_cumulative_funding_rate as i128;
+
+    // base (BASE_PRECISION) * rate (PRICE_PRECISION * FUNDING_RATE_BUFFER) -> QUOTE_PRECISION
+    (-funding_rate_delta * position.base_asset_amount as i128)
+        .div_euclid(BASE_PRECISION_I128 * FUNDING_RATE_BUFFER_I128)
+}
+
+/// PnL of `position` at `oracle_price` (PRICE_PRECISION), split into its parts
+pub fn calculate_position_pnl(
+    position: &PerpPosition,
+    market: &PerpMarket,
+    oracle_price: i64,
+) -> PositionPnl {
+    let base_asset_value =
+        position.base_asset_amount as i128 * oracle_price as i128 / BASE_PRECISION_I128;
+    let unrealized = base_asset_value + position.quote_entry_amount as i128;
+    let realized = position.quote_asset_amount as i128 - position.quote_entry_amount as i128;
+    let unsettled_funding = calculate_unsettled_funding_pnl(position, market);
+
+    PositionPnl {
+        unrealized,
+        realized,
+        unsettled_funding,
+        unsettled: unrealized + realized + unsettled_funding,
+        settled: position.settled_pnl as i128,
+    }
+}
+
+/// Margin `position` contributes to the user's requirement at `oracle_price`, including
+/// its open orders (QUOTE_PRECISION)
+pub fn calculate_position_margin_requirement(
+    position: &PerpPosition,
+    market: &PerpMarket,
+    oracle_price: i64,
+    margin_category: MarginCategory,
+) -> SdkResult<u128> {
+    let worst_case_base_asset_amount = position
+        .worst_case_base_asset_amount()
+        .map_err(|err| SdkError::Anchor(Box::new(err.into())))?
+        .unsigned_abs();
+    let margin_ratio = market
+        .get_margin_ratio(worst_case_base_asset_amount, margin_category.into())
+        .map_err(|err| SdkError::Anchor(Box::new(err.into())))?;
+
+    let worst_case_notional = worst_case_base_asset_amount * oracle_price.unsigned_abs() as u128
+        / BASE_PRECISION_I128 as u128;
+
+    Ok(worst_case_notional * margin_ratio as u128 / MARGIN_PRECISION_U128)
+}
+
+#[cfg(test)]
+mod tests {
+    use drift::math::constants::{
+        BASE_PRECISION_I64, FUNDING_RATE_PRECISION_I128, PRICE_PRECISION_I64, QUOTE_PRECISION_I64,
+    };
+
+    use super::*;
+
+    /// long 2 @ $100 after selling 1 @ $110 from a 3 @ $100 position, $1 of fees paid
+    fn long_position() -> PerpPosition {
+        PerpPosition {
+            market_index: 0,
+            base_asset_amount: 2 * BASE_PRECISION_I64,
+            quote_entry_amount: -200 * QUOTE_PRECISION_I64,
+            quote_break_even_amount: -201 * QUOTE_PRECISION_I64,
+            quote_asset_amount: -191 * QUOTE_PRECISION_I64,
+            settled_pnl: 5 * QUOTE_PRECISION_I64,
+            ..Default::default()
+        }
+    }
+
+    fn market() -> PerpMarket {
+        let mut market = PerpMarket {
+            margin_ratio_initial: 1000,    // 10x
+            margin_ratio_maintenance: 500, // 20x
+            ..Default::default()
+        };
+        market.amm.cumulative_funding_rate_long = 2 * FUNDING_RATE_PRECISION_I128;
+        market.amm.cumulative_funding_rate_short = 2 * FUNDING_RATE_PRECISION_I128;
+        market
+    }
+
+    #[test]
+    fn test_entry_and_break_even_price() {
+        let long = long_position();
+        assert_eq!(calculate_entry_price(&long), 100 * PRICE_PRECISION_I64);
+        assert_eq!(calculate_break_even_price(&long), 100_500_000);
+
+        let short = PerpPosition {
+            base_asset_amount: -BASE_PRECISION_I64 / 2,
+            quote_entry_amount: 60 * QUOTE_PRECISION_I64,
+            quote_break_even_amount: 59 * QUOTE_PRECISION_I64,
+            ..Default::default()
+        };
+        assert_eq!(calculate_entry_price(&short), 120 * PRICE_PRECISION_I64);
+        assert_eq!(
+            calculate_break_even_price(&short),
+            118 * PRICE_PRECISION_I64
+        );
+
+        assert_eq!(calculate_entry_price(&PerpPosition::default()), 0);
+    }
+
+    #[test]
+    fn test_notional() {
+        assert_eq!(
+            calculate_position_notional(&long_position(), 105 * PRICE_PRECISION_I64),
+            210 * QUOTE_PRECISION_I64 as u128
+        );
+    }
+
+    #[test]
+    fn test_unsettled_funding() {
+        let mut long = long_position();
+        // rate rose $1.50 per base since the last settlement, the long pays $3
+        long.last_cumulative_funding_rate = (FUNDING_RATE_PRECISION_I128 / 2) as i64;
+        assert_eq!(
+            calculate_unsettled_funding_pnl(&long, &market()),
+            -3 * QUOTE_PRECISION_I64 as i128
+        );
+
+        // a short receives the same move
+        let short = PerpPosition {
+            base_asset_amount: -BASE_PRECISION_I64,
+            last_cumulative_funding_rate: FUNDING_RATE_PRECISION_I128 as i64,
+            ..Default::default()
+        };
+        assert_eq!(
+            calculate_unsettled_funding_pnl(&short, &market()),
+            QUOTE_PRECISION_I64 as i128
+        );
+
+        // a payment of a fraction of a quote unit rounds against the user
+        let dust = PerpPosition {
+            base_asset_amount: 1,
+            last_cumulative_funding_rate: 0,
+            ..Default::default()
+        };
+        assert_eq!(calculate_unsettled_funding_pnl(&dust, &market()), -1);
+    }
+
+    #[test]
+    fn test_position_pnl() {
+        let mut long = long_position();
+        long.last_cumulative_funding_rate = (2 * FUNDING_RATE_PRECISION_I128) as i64;
+
+        let pnl = calculate_position_pnl(&long, &market(), 105 * PRICE_PRECISION_I64);
+        assert_eq!(
+            pnl,
+            PositionPnl {
+                unrealized: 10 * QUOTE_PRECISION_I64 as i128,
+                realized: 9 * QUOTE_PRECISION_I64 as i128,
+                unsettled_funding: 0,
+                unsettled: 19 * QUOTE_PRECISION_I64 as i128,
+                settled: 5 * QUOTE_PRECISION_I64 as i128,
+            }
+        );
+    }
+
+    #[test]
+    fn test_margin_requirement_includes_open_orders() {
+        let mut long = long_position();
+        let price = 100 * PRICE_PRECISION_I64;
+
+        assert_eq!(
+            calculate_position_margin_requirement(&long, &market(), price, MarginCategory::Initial)
+                .unwrap(),
+            20 * QUOTE_PRECISION_I64 as u128
+        );
+        assert_eq!(
+            calculate_position_margin_requirement(
+                &long,
+                &market(),
+                price,
+                MarginCategory::Maintenance
+            )
+            .unwrap(),
+            10 * QUOTE_PRECISION_I64 as u128
+        );
+
+        // a resting bid for 1 more counts towards the worst case size
+        long.open_bids = BASE_PRECISION_I64;
+        assert_eq!(
+            calculate_position_margin_requirement(&long, &market(), price, MarginCategory::Initial)
+                .unwrap(),
+            30 * QUOTE_PRECISION_I64 as u128
+        );
+    }
+}
@@ -1,5 +1,545 @@
-use drift::state::user::SpotPosition;
+//! spot balance, interest rate and withdraw limit math
+//!
 
-// pub fn  is_spot_position_available(position: &SpotPosition) -> bool {
-//     position.scaled_balance == 0 && position.open_orders == 0
-// }
+use drift::{
+    math::{
+        constants::{
+            ONE_YEAR, PERCENTAGE_PRECISION, SPOT_RATE_PRECISION, SPOT_UTILIZATION_PRECISION,
+            SPOT_WEIGHT_PRECISION_U128,
+        },
+        margin::MarginRequirementType,
+    },
+    state::{
+        spot_market::{SpotBalanceType, SpotMarket},
+        user::SpotPosition,
+    },
+};
+
+use crate::{SdkError, SdkResult};
+
+/// 10^19, scaled balances have `19 - decimals` more decimals than tokens
+const SCALED_BALANCE_DECIMALS: u32 = 19;
+
+/// Cumulative interest of a spot market (SPOT_CUMULATIVE_INTEREST_PRECISION)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CumulativeInterest {
+    pub deposit: u128,
+    pub borrow: u128,
+}
+
+impl CumulativeInterest {
+    /// Interest as last stored in `market`
+    pub fn of(market: &SpotMarket) -> Self {
+        Self {
+            deposit: market.cumulative_deposit_interest,
+            borrow: market.cumulative_borrow_interest,
+        }
+    }
+}
+
+/// Annualized rates of a spot market (SPOT_RATE_PRECISION)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SpotRates {
+    /// borrowed / deposited (SPOT_UTILIZATION_PRECISION)
+    pub utilization: u128,
+    pub borrow_rate: u128,
+    pub deposit_rate: u128,
+}
+
+/// Token amount of a scaled balance given `cumulative_interest`, borrows round up
+pub fn get_token_amount_with_interest(
+    scaled_balance: u128,
+    decimals: u32,
+    cumulative_interest: u128,
+    balance_type: SpotBalanceType,
+) -> u128 {
+    let precision_decrease = 10_u128.pow(SCALED_BALANCE_DECIMALS - decimals);
+    let token_amount = scaled_balance * cumulative_interest;
+    match balance_type {
+        SpotBalanceType::Deposit => token_amount / precision_decrease,
+        SpotBalanceType::Borrow => token_amount.div_ceil(precision_decrease),
+    }
+}
+
+/// Token amount of a scaled balance at the market's stored interest
+pub fn get_token_amount(
+    scaled_balance: u128,
+    market: &SpotMarket,
+    balance_type: SpotBalanceType,
+) -> u128 {
+    let interest = CumulativeInterest::of(market);
+    let cumulative_interest = match balance_type {
+        SpotBalanceType::Deposit => interest.deposit,
+        SpotBalanceType::Borrow => interest.borrow,
+    };
+    get_token_amount_with_interest(
+        scaled_balance,
+        market.decimals,
+        cumulative_interest,
+        balance_type,
+    )
+}
+
+/// Signed token amount of `position` including interest accrued until `now`, negative for
+/// borrows
+pub fn get_signed_token_amount_at(position: &SpotPosition, market: &SpotMarket, now: i64) -> i128 {
+    let interest = calculate_accumulated_interest(market, now);
+    let (cumulative_interest, sign) = match position.balance_type {
+        SpotBalanceType::Deposit => (interest.deposit, 1),
+        SpotBalanceType::Borrow => (interest.borrow, -1),
+    };
+
+    sign * get_token_amount_with_interest(
+        position.scaled_balance as u128,
+        market.decimals,
+        cumulative_interest,
+        position.balance_type,
+    ) as i128
+}
+
+/// Total deposited and borrowed tokens of `market`
+pub fn get_market_token_amounts(market: &SpotMarket) -> (u128, u128) {
+    (
+        get_token_amount(market.deposit_balance, market, SpotBalanceType::Deposit),
+        get_token_amount(market.borrow_balance, market, SpotBalanceType::Borrow),
+    )
+}
+
+/// Utilization after depositing (positive) or borrowing (negative) `delta` tokens
+/// (SPOT_UTILIZATION_PRECISION)
+pub fn calculate_utilization(market: &SpotMarket, delta: i128) -> u128 {
+    let (mut deposits, mut borrows) = get_market_token_amounts(market);
+    if delta > 0 {
+        deposits += delta as u128;
+    } else {
+        borrows += delta.unsigned_abs();
+    }
+
+    if borrows == 0 {
+        0
+    } else if deposits == 0 {
+        u128::MAX
+    } else {
+        borrows * SPOT_UTILIZATION_PRECISION / deposits
+    }
+}
+
+/// Borrow rate at `utilization`, linear up to the optimal utilization and steeper above it
+/// (SPOT_RATE_PRECISION)
+pub fn calculate_borrow_rate(market: &SpotMarket, utilization: u128) -> u128 {
+    let utilization = utilization.min(SPOT_UTILIZATION_PRECISION);
+    let optimal_utilization = market.optimal_utilization as u128;
+    let optimal_borrow_rate = market.optimal_borrow_rate as u128;
+    let max_borrow_rate = market.max_borrow_rate as u128;
+
+    if utilization > optimal_utilization {
+        let surplus_utilization = utilization - optimal_utilization;
+        let slope = max_borrow_rate.saturating_sub(optimal_borrow_rate)
+            * SPOT_UTILIZATION_PRECISION
+            / (SPOT_UTILIZATION_PRECISION - optimal_utilization);
+        optimal_borrow_rate + surplus_utilization * slope / SPOT_UTILIZATION_PRECISION
+    } else if optimal_utilization == 0 {
+        0
+    } else {
+        let slope = optimal_borrow_rate * SPOT_UTILIZATION_PRECISION / optimal_utilization;
+        utilization * slope / SPOT_UTILIZATION_PRECISION
+    }
+}
+
+/// Current rates of `market`
+pub fn calculate_spot_rates(market: &SpotMarket) -> SpotRates {
+    calculate_projected_spot_rates(market, 0)
+}
+
+/// Rates after depositing (positive) or borrowing (negative) `delta` tokens
+pub fn calculate_projected_spot_rates(market: &SpotMarket, delta: i128) -> SpotRates {
+    let utilization = calculate_utilization(market, delta);
+    let borrow_rate = calculate_borrow_rate(market, utilization);
+    // depositors earn the borrow interest minus the insurance fund's cut
+    let deposit_rate = borrow_rate * utilization.min(SPOT_UTILIZATION_PRECISION)
+        / SPOT_UTILIZATION_PRECISION
+        * (PERCENTAGE_PRECISION - market.insurance_fund.total_factor as u128)
+        / PERCENTAGE_PRECISION;
+
+    SpotRates {
+        utilization,
+        borrow_rate,
+        deposit_rate,
+    }
+}
+
+/// Cumulative interest of `market` with interest accrued from its last update until `now`
+pub fn calculate_accumulated_interest(market: &SpotMarket, now: i64) -> CumulativeInterest {
+    let stored = CumulativeInterest::of(market);
+    let elapsed = now.saturating_sub(market.last_interest_ts as i64);
+    if elapsed <= 0 {
+        return stored;
+    }
+
+    let rates = calculate_spot_rates(market);
+    if rates.borrow_rate == 0 {
+        return stored;
+    }
+    let elapsed = elapsed as u128;
+
+    CumulativeInterest {
+        deposit: stored.deposit
+            + stored.deposit * rates.deposit_rate * elapsed / ONE_YEAR / SPOT_RATE_PRECISION,
+        borrow: stored.borrow
+            + stored.borrow * rates.borrow_rate * elapsed / ONE_YEAR / SPOT_RATE_PRECISION
+            + 1,
+    }
+}
+
+/// Tokens that can leave the market without touching other users' deposits
+pub fn get_available_liquidity(market: &SpotMarket) -> u128 {
+    let (deposits, borrows) = get_market_token_amounts(market);
+    deposits.saturating_sub(borrows)
+}
+
+/// Max tokens of `position` that can be withdrawn without borrowing, given the user's
+/// initial `free_collateral` (QUOTE_PRECISION)
+pub fn calculate_max_withdrawable(
+    position: &SpotPosition,
+    market: &SpotMarket,
+    oracle_price: i64,
+    free_collateral: u128,
+) -> SdkResult<u128> {
+    if oracle_price <= 0 {
+        return Err(SdkError::InvalidOracle);
+    }
+    if position.balance_type != SpotBalanceType::Deposit {
+        return Ok(0);
+    }
+    let deposit = get_token_amount(
+        position.scaled_balance as u128,
+        market,
+        position.balance_type,
+    );
+    let asset_weight = market
+        .get_asset_weight(deposit, oracle_price, &MarginRequirementType::Initial)
+        .map_err(|err| SdkError::Anchor(Box::new(err.into())))?;
+
+    let max_by_collateral =
+        tokens_for_value(free_collateral, market, oracle_price, asset_weight as u128);
+
+    Ok(deposit
+        .min(max_by_collateral)
+        .min(get_available_liquidity(market)))
+}
+
+/// Max tokens that can be withdrawn from `position` including a new borrow, given the
+/// user's initial `free_collateral` (QUOTE_PRECISION)
+pub fn calculate_max_borrowable(
+    position: &SpotPosition,
+    market: &SpotMarket,
+    oracle_price: i64,
+    free_collateral: u128,
+) -> SdkResult<u128> {
+    if oracle_price <= 0 {
+        return Err(SdkError::InvalidOracle);
+    }
+    let mut withdrawable = 0;
+    let mut remaining_collateral = free_collateral;
+
+    if position.balance_type == SpotBalanceType::Deposit {
+        let deposit = get_token_amount(
+            position.scaled_balance as u128,
+            market,
+            position.balance_type,
+        );
+        withdrawable = calculate_max_withdrawable(position, market, oracle_price, free_collateral)?;
+        if withdrawable < deposit {
+            return Ok(withdrawable);
+        }
+
+        let asset_weight = market
+            .get_asset_weight(deposit, oracle_price, &MarginRequirementType::Initial)
+            .map_err(|err| SdkError::Anchor(Box::new(err.into())))?;
+        remaining_collateral = remaining_collateral.saturating_sub(token_value(
+            deposit,
+            market,
+            oracle_price,
+            asset_weight as u128,
+        ));
+    }
+
+    // the liability weight grows with the borrow size: estimate the size at the base weight, then
+    // size it again at the weight of that estimate, which can only be smaller
+    let mut borrowable = 0;
+    for _ in 0..2 {
+        let liability_weight = market
+            .get_liability_weight(borrowable, &MarginRequirementType::Initial)
+            .map_err(|err| SdkError::Anchor(Box::new(err.into())))?;
+        borrowable = tokens_for_value(
+            remaining_collateral,
+            market,
+            oracle_price,
+            liability_weight as u128,
+        );
+    }
+
+    Ok(withdrawable
+        .saturating_add(borrowable)
+        .min(get_available_liquidity(market)))
+}
+
+/// Oracle price (PRICE_PRECISION) of a borrowed asset at which its liability alone uses up
+/// the user's maintenance `free_collateral`, `None` for deposits
+pub fn calculate_borrow_liquidation_price(
+    position: &SpotPosition,
+    market: &SpotMarket,
+    oracle_price: i64,
+    free_collateral: u128,
+) -> SdkResult<Option<i64>> {
+    if position.balance_type != SpotBalanceType::Borrow || position.scaled_balance == 0 {
+        return Ok(None);
+    }
+    let borrow = get_token_amount(
+        position.scaled_balance as u128,
+        market,
+        position.balance_type,
+    );
+    let liability_weight = market
+        .get_liability_weight(borrow, &MarginRequirementType::Maintenance)
+        .map_err(|err| SdkError::Anchor(Box::new(err.into())))?;
+
+    // free collateral lost per unit of price increase
+    let weighted_borrow = borrow * liability_weight as u128 / SPOT_WEIGHT_PRECISION_U128;
+    if weighted_borrow == 0 {
+        return Ok(None);
+    }
+    let price_delta = free_collateral * 10_u128.pow(market.decimals) / weighted_borrow;
+
+    Ok(Some(oracle_price.saturating_add(
+        i64::try_from(price_delta).unwrap_or(i64::MAX),
+    )))
+}
+
+/// Weighted value of `token_amount` (QUOTE_PRECISION)
+fn token_value(token_amount: u128, market: &SpotMarket, oracle_price: i64, weight: u128) -> u128 {
+    token_amount * oracle_price.unsigned_abs() as u128 * weight
+        / SPOT_WEIGHT_PRECISION_U128
+        / 10_u128.pow(market.decimals)
+}
+
+/// Tokens whose weighted value is `value` (QUOTE_PRECISION), unbounded for a zero weight
+fn tokens_for_value(value: u128, market: &SpotMarket, oracle_price: i64, weight: u128) -> u128 {
+    let weighted_price = oracle_price.unsigned_abs() as u128 * weight;
+    if weighted_price == 0 {
+        return u128::MAX;
+    }
+    value * 10_u128.pow(market.decimals) * SPOT_WEIGHT_PRECISION_U128 / weighted_price
+}
+
+#[cfg(test)]
+mod tests {
+    use drift::math::constants::{
+        PRICE_PRECISION_I64, QUOTE_PRECISION, SPOT_BALANCE_PRECISION,
+        SPOT_CUMULATIVE_INTEREST_PRECISION,
+    };
+
+    use super::*;
+
+    const SOL: u128 = 1_000_000_000;
+
+    /// 1000 SOL deposited, 500 borrowed, 80% optimal utilization at 10%, 100% max rate
+    fn sol_market() -> SpotMarket {
+        let mut market = SpotMarket {
+            market_index: 1,
+            decimals: 9,
+            cumulative_deposit_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            cumulative_borrow_interest: SPOT_CUMULATIVE_INTEREST_PRECISION,
+            deposit_balance: 1000 * SPOT_BALANCE_PRECISION,
+            borrow_balance: 500 * SPOT_BALANCE_PRECISION,
+            optimal_utilization: 800_000,
+            optimal_borrow_rate: 100_000,
+            max_borrow_rate: 1_000_000,
+            initial_asset_weight: 8_000,
+            maintenance_asset_weight: 9_000,
+            initial_liability_weight: 12_000,
+            maintenance_liability_weight: 11_000,
+            last_interest_ts: 1_000,
+            ..Default::default()
+        };
+        market.insurance_fund.total_factor = 100_000; // 10%
+        market
+    }
+
+    fn deposit(tokens: u128) -> SpotPosition {
+        SpotPosition {
+            market_index: 1,
+            scaled_balance: (tokens * SPOT_BALANCE_PRECISION / SOL) as u64,
+            balance_type: SpotBalanceType::Deposit,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_token_amounts_follow_cumulative_interest() {
+        let mut market = sol_market();
+        market.cumulative_deposit_interest = 11 * SPOT_CUMULATIVE_INTEREST_PRECISION / 10;
+
+        assert_eq!(
+            get_token_amount(SPOT_BALANCE_PRECISION, &market, SpotBalanceType::Deposit),
+            1_100_000_000
+        );
+        assert_eq!(get_market_token_amounts(&market), (1100 * SOL, 500 * SOL));
+        // borrows round up
+        assert_eq!(
+            get_token_amount_with_interest(
+                1,
+                9,
+                SPOT_CUMULATIVE_INTEREST_PRECISION + 1,
+                SpotBalanceType::Borrow
+            ),
+            2
+        );
+    }
+
+    #[test]
+    fn test_rates_below_and_above_optimal_utilization() {
+        let market = sol_market();
+        let rates = calculate_spot_rates(&market);
+        // 50% utilization on a 10% / 80% slope
+        assert_eq!(rates.utilization, 500_000);
+        assert_eq!(rates.borrow_rate, 62_500);
+        // 6.25% * 50% * (1 - 10%)
+        assert_eq!(rates.deposit_rate, 28_125);
+
+        // borrowing 400 more lifts utilization to 90%, halfway up the steep slope
+        let projected = calculate_projected_spot_rates(&market, -400 * SOL as i128);
+        assert_eq!(projected.utilization, 900_000);
+        assert_eq!(projected.borrow_rate, 550_000);
+
+        let deposit_more = calculate_projected_spot_rates(&market, 1000 * SOL as i128);
+        assert_eq!(deposit_more.utilization, 250_000);
+    }
+
+    #[test]
+    fn test_interest_accrues_for_elapsed_time() {
+        let market = sol_market();
+        assert_eq!(
+            calculate_accumulated_interest(&market, 1_000),
+            CumulativeInterest::of(&market)
+        );
+
+        let year_later = calculate_accumulated_interest(&market, 1_000 + ONE_YEAR as i64);
+        assert_eq!(year_later.borrow, 10_625_000_001);
+        assert_eq!(year_later.deposit, 10_281_250_000);
+
+        let position = deposit(100 * SOL);
+        assert_eq!(
+            get_signed_token_amount_at(&position, &market, 1_000 + ONE_YEAR as i64),
+            102_812_500_000
+        );
+    }
+
+    #[test]
+    fn test_max_withdrawable_and_borrowable() {
+        let market = sol_market();
+        let price = 100 * PRICE_PRECISION_I64;
+        let position = deposit(10 * SOL);
+
+        // $400 of free collateral covers 5 SOL at an 80% asset weight
+        let free_collateral = 400 * QUOTE_PRECISION;
+        assert_eq!(
+            calculate_max_withdrawable(&position, &market, price, free_collateral).unwrap(),
+            5 * SOL
+        );
+        assert_eq!(
+            calculate_max_borrowable(&position, &market, price, free_collateral).unwrap(),
+            5 * SOL
+        );
+
+        // $1400 covers the whole $800 weighted deposit, the other $600 borrows 5 SOL at 120%
+        let free_collateral = 1400 * QUOTE_PRECISION;
+        assert_eq!(
+            calculate_max_withdrawable(&position, &market, price, free_collateral).unwrap(),
+            10 * SOL
+        );
+        assert_eq!(
+            calculate_max_borrowable(&position, &market, price, free_collateral).unwrap(),
+            15 * SOL
+        );
+
+        // capped by the 500 SOL the market has available
+        assert_eq!(
+            calculate_max_borrowable(&SpotPosition::default(), &market, price, u64::MAX as u128)
+                .unwrap(),
+            500 * SOL
+        );
+    }
+
+    #[test]
+    fn test_borrow_size_premium_reduces_max_borrowable() {
+        let mut market = sol_market();
+        market.deposit_balance = 100_000 * SPOT_BALANCE_PRECISION;
+        let price = 100 * PRICE_PRECISION_I64;
+        let free_collateral = 120_000 * QUOTE_PRECISION;
+        let no_position = SpotPosition::default();
+
+        // 1000 SOL at the base 120% weight
+        assert_eq!(
+            calculate_max_borrowable(&no_position, &market, price, free_collateral).unwrap(),
+            1000 * SOL
+        );
+
+        market.imf_factor = 1_000;
+        let borrowable =
+            calculate_max_borrowable(&no_position, &market, price, free_collateral).unwrap();
+        assert!(borrowable < 1000 * SOL);
+        let weight = market
+            .get_liability_weight(borrowable, &MarginRequirementType::Initial)
+            .unwrap();
+        assert!(token_value(borrowable, &market, price, weight as u128) <= free_collateral);
+    }
+
+    #[test]
+    fn test_invalid_inputs_do_not_panic() {
+        let market = sol_market();
+        let position = deposit(10 * SOL);
+
+        assert!(calculate_max_withdrawable(&position, &market, 0, QUOTE_PRECISION).is_err());
+        assert!(calculate_max_borrowable(&position, &market, -1, QUOTE_PRECISION).is_err());
+
+        // a max rate below the optimal rate flattens the steep slope instead of underflowing
+        let mut market = sol_market();
+        market.max_borrow_rate = 50_000;
+        assert_eq!(calculate_borrow_rate(&market, 900_000), 100_000);
+
+        let borrow = SpotPosition {
+            market_index: 1,
+            scaled_balance: 1,
+            balance_type: SpotBalanceType::Borrow,
+            ..Default::default()
+        };
+        assert_eq!(
+            calculate_borrow_liquidation_price(&borrow, &market, 1, u64::MAX as u128).unwrap(),
+            Some(i64::MAX)
+        );
+    }
+
+    #[test]
+    fn test_borrow_liquidation_price() {
+        let market = sol_market();
+        let price = 100 * PRICE_PRECISION_I64;
+        let borrow = SpotPosition {
+            market_index: 1,
+            scaled_balance: 10 * SPOT_BALANCE_PRECISION as u64,
+            balance_type: SpotBalanceType::Borrow,
+            ..Default::default()
+        };
+
+        // 10 SOL at a 110% weight lose $11 of collateral per $1, $110 lasts $10
+        assert_eq!(
+            calculate_borrow_liquidation_price(&borrow, &market, price, 110 * QUOTE_PRECISION)
+                .unwrap(),
+            Some(110 * PRICE_PRECISION_I64)
+        );
+        assert_eq!(
+            calculate_borrow_liquidation_price(&deposit(SOL), &market, price, 0).unwrap(),
+            None
+        );
+    }
+}
@@ -1,6 +1,9 @@
 use crate::utils::get_ws_url;
 use crate::websocket_account_subscriber::{AccountUpdate, WebsocketAccountSubscriber};
-use crate::{event_emitter::EventEmitter, SdkResult};
+use crate::{
+    event_emitter::{Event, EventEmitter},
+    SdkResult,
+};
 use dashmap::DashMap;
 use drift::state::oracle::{get_oracle_price, OraclePriceData, OracleSource};
 use solana_account_decoder::{UiAccountData, UiAccountEncoding};
@@ -22,6 +25,16 @@ pub struct Oracle {
     pub raw: Vec<u8>,
 }
 
+impl Event for Oracle {
+    fn box_clone(&self) -> Box<dyn Event> {
+        Box::new((*self).clone())
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
 pub struct OracleMap {
     subscribed: AtomicBool,
     pub(crate) oraclemap: Arc<DashMap<Pubkey, Oracle>>,
@@ -38,6 +51,7 @@ pub struct OracleMap {
 
 impl OracleMap {
     pub const SUBSCRIPTION_ID: &'static str = "oraclemap";
+    pub const UPDATE_EVENT_ID: &'static str = "oraclemap_update";
 
     pub fn new(
         commitment: CommitmentConfig,
@@ -113,6 +127,7 @@ impl OracleMap {
 
             let oracle_source_by_oracle_key = self.oracle_infos.clone();
             let oracle_map = self.oraclemap.clone();
+            let event_emitter = self.event_emitter;
 
             self.event_emitter
                 .subscribe(OracleMap::SUBSCRIPTION_ID, move |event| {
@@ -143,16 +158,16 @@ impl OracleMap {
                                     update.slot,
                                 ) {
                                     Ok(price_data) => {
-                                        oracle_map.insert(
-                                            oracle_pubkey,
-                                            Oracle {
-                                                pubkey: oracle_pubkey,
-                                                data: price_data,
-                                                source: *oracle_source.value(),
-                                                slot: update.slot,
-                                                raw: data,
-                                            },
-                                        );
+                                        let oracle = Oracle {
+                                            pubkey: oracle_pubkey,
+                                            data: price_data,
+                                            source: *oracle_source.value(),
+                                            slot: update.slot,
+                                            raw: data,
+                                        };
+                                        oracle_map.insert(oracle_pubkey, oracle.clone());
+                                        event_emitter
+                                            .emit(OracleMap::UPDATE_EVENT_ID, Box::new(oracle));
                                     }
                                     Err(err) => {
                                         log::error!("Failed to get oracle price: {:?}", err)
@@ -277,6 +292,16 @@ impl OracleMap {
         Ok(())
     }
 
+    /// Call `handler` with every oracle update received by the subscription, after it was stored
+    pub fn on_update<F: 'static + Send + Fn(&Oracle)>(&self, handler: F) {
+        self.event_emitter
+            .subscribe(OracleMap::UPDATE_EVENT_ID, move |event| {
+                if let Some(oracle) = event.as_any().downcast_ref::<Oracle>() {
+                    handler(oracle);
+                }
+            });
+    }
+
     #[allow(dead_code)]
     pub fn size(&self) -> usize {
         self.oraclemap.len()
@@ -0,0 +1,830 @@
+//! Validated builder for drift `OrderParams` taking human unit prices and sizes
+//!
+
+use drift::{
+    controller::position::PositionDirection,
+    state::{
+        order_params::{ModifyOrderParams, OrderParams, PostOnlyParam},
+        perp_market::PerpMarket,
+        spot_market::SpotMarket,
+        user::{Order, OrderTriggerCondition, OrderType},
+    },
+};
+use thiserror::Error;
+
+use crate::{
+    constants::{BASE_PRECISION, PRICE_PRECISION},
+    types::MarketId,
+};
+
+/// Reasons a `NewOrder` can't be turned into `OrderParams`
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum OrderError {
+    #[error("order is for {order:?} but market is {market:?}")]
+    MarketMismatch { order: MarketId, market: MarketId },
+    #[error("invalid order size: {0}")]
+    InvalidSize(f64),
+    #[error("order size {size} is below the market's min order size {min_order_size}")]
+    BelowMinOrderSize { size: u64, min_order_size: u64 },
+    #[error("{0:?} orders need a price")]
+    MissingPrice(OrderType),
+    #[error("invalid price: {0}")]
+    InvalidPrice(f64),
+    #[error("{0:?} orders can't be post-only")]
+    PostOnlyNotAllowed(OrderType),
+    #[error("post-only orders can't be immediate-or-cancel")]
+    PostOnlyImmediateOrCancel,
+    #[error("post-only orders can't have an auction")]
+    PostOnlyAuction,
+    #[error("invalid auction: {0}")]
+    InvalidAuction(&'static str),
+}
+
+/// Tick, step and size limits of a market that orders are rounded to
+pub trait OrderMarket {
+    fn market_id(&self) -> MarketId;
+    /// price increment (PRICE_PRECISION)
+    fn order_tick_size(&self) -> u64;
+    /// size increment (base precision)
+    fn order_step_size(&self) -> u64;
+    /// min size of non reduce-only orders (base precision)
+    fn min_order_size(&self) -> u64;
+    /// precision of order sizes
+    fn base_precision(&self) -> u64;
+}
+
+impl OrderMarket for PerpMarket {
+    fn market_id(&self) -> MarketId {
+        MarketId::perp(self.market_index)
+    }
+    fn order_tick_size(&self) -> u64 {
+        self.amm.order_tick_size
+    }
+    fn order_step_size(&self) -> u64 {
+        self.amm.order_step_size
+    }
+    fn min_order_size(&self) -> u64 {
+        self.amm.min_order_size
+    }
+    fn base_precision(&self) -> u64 {
+        BASE_PRECISION
+    }
+}
+
+impl OrderMarket for SpotMarket {
+    fn market_id(&self) -> MarketId {
+        MarketId::spot(self.market_index)
+    }
+    fn order_tick_size(&self) -> u64 {
+        self.order_tick_size
+    }
+    fn order_step_size(&self) -> u64 {
+        self.order_step_size
+    }
+    fn min_order_size(&self) -> u64 {
+        self.min_order_size
+    }
+    fn base_precision(&self) -> u64 {
+        10_u64.pow(self.decimals)
+    }
+}
+
+/// Auction of a taker order, prices are absolute or oracle offsets for `Oracle` orders
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AuctionParams {
+    /// duration in slots
+    pub duration: u8,
+    pub start_price: f64,
+    pub end_price: f64,
+}
+
+/// Order with human unit prices and sizes
+///
+/// ```ignore
+/// let market = client.get_perp_market_account(0).unwrap();
+/// let params = NewOrder::limit(MarketId::perp(0), PositionDirection::Long, 1.5, 140.25)
+///     .post_only(PostOnlyParam::MustPostOnly)
+///     .build(&market)?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewOrder {
+    order_type: OrderType,
+    market: MarketId,
+    direction: PositionDirection,
+    size: f64,
+    price: Option<f64>,
+    oracle_price_offset: Option<f64>,
+    trigger_price: Option<f64>,
+    trigger_condition: OrderTriggerCondition,
+    reduce_only: bool,
+    post_only: PostOnlyParam,
+    immediate_or_cancel: bool,
+    user_order_id: u8,
+    max_ts: Option<i64>,
+    auction: Option<AuctionParams>,
+}
+
+impl NewOrder {
+    fn new(
+        order_type: OrderType,
+        market: MarketId,
+        direction: PositionDirection,
+        size: f64,
+    ) -> Self {
+        Self {
+            order_type,
+            market,
+            direction,
+            size,
+            price: None,
+            oracle_price_offset: None,
+            trigger_price: None,
+            trigger_condition: OrderTriggerCondition::Above,
+            reduce_only: false,
+            post_only: PostOnlyParam::None,
+            immediate_or_cancel: false,
+            user_order_id: 0,
+            max_ts: None,
+            auction: None,
+        }
+    }
+
+    /// Market order, optionally bounded by a worst `price`
+    pub fn market(market: MarketId, direction: PositionDirection, size: f64) -> Self {
+        Self::new(OrderType::Market, market, direction, size)
+    }
+
+    pub fn limit(market: MarketId, direction: PositionDirection, size: f64, price: f64) -> Self {
+        Self::new(OrderType::Limit, market, direction, size).price(price)
+    }
+
+    /// Limit order floating at `offset` from the oracle price
+    pub fn oracle_limit(
+        market: MarketId,
+        direction: PositionDirection,
+        size: f64,
+        offset: f64,
+    ) -> Self {
+        Self::new(OrderType::Limit, market, direction, size).oracle_price_offset(offset)
+    }
+
+    /// Market order whose auction is priced relative to the oracle, requires `auction`
+    pub fn oracle(market: MarketId, direction: PositionDirection, size: f64) -> Self {
+        Self::new(OrderType::Oracle, market, direction, size)
+    }
+
+    /// Set the limit as an `offset` from the oracle price, e.g. the worst price of an `oracle` order
+    pub fn oracle_price_offset(mut self, offset: f64) -> Self {
+        self.oracle_price_offset = Some(offset);
+        self
+    }
+
+    pub fn trigger_market(
+        market: MarketId,
+        direction: PositionDirection,
+        size: f64,
+        trigger_price: f64,
+        condition: OrderTriggerCondition,
+    ) -> Self {
+        let mut order = Self::new(OrderType::TriggerMarket, market, direction, size);
+        order.trigger_price = Some(trigger_price);
+        order.trigger_condition = condition;
+        order
+    }
+
+    pub fn trigger_limit(
+        market: MarketId,
+        direction: PositionDirection,
+        size: f64,
+        trigger_price: f64,
+        condition: OrderTriggerCondition,
+        price: f64,
+    ) -> Self {
+        let mut order = Self::new(OrderType::TriggerLimit, market, direction, size);
+        order.trigger_price = Some(trigger_price);
+        order.trigger_condition = condition;
+        order.price(price)
+    }
+
+    /// Set the limit (or worst fill) price
+    pub fn price(mut self, price: f64) -> Self {
+        self.price = Some(price);
+        self
+    }
+
+    pub fn reduce_only(mut self, reduce_only: bool) -> Self {
+        self.reduce_only = reduce_only;
+        self
+    }
+
+    pub fn post_only(mut self, post_only: PostOnlyParam) -> Self {
+        self.post_only = post_only;
+        self
+    }
+
+    pub fn immediate_or_cancel(mut self, immediate_or_cancel: bool) -> Self {
+        self.immediate_or_cancel = immediate_or_cancel;
+        self
+    }
+
+    pub fn user_order_id(mut self, user_order_id: u8) -> Self {
+        self.user_order_id = user_order_id;
+        self
+    }
+
+    /// Expire the order at unix timestamp `max_ts`
+    pub fn max_ts(mut self, max_ts: i64) -> Self {
+        self.max_ts = Some(max_ts);
+        self
+    }
+
+    pub fn auction(mut self, auction: AuctionParams) -> Self {
+        self.auction = Some(auction);
+        self
+    }
+
+    pub fn order_type(&self) -> OrderType {
+        self.order_type
+    }
+
+    pub fn market_id(&self) -> MarketId {
+        self.market
+    }
+
+    pub fn direction(&self) -> PositionDirection {
+        self.direction
+    }
+
+    pub fn size(&self) -> f64 {
+        self.size
+    }
+
+    /// Validate the order against `market` and convert it into program units
+    ///
+    /// Sizes are rounded down to the step size, prices are rounded to the tick size away
+    /// from the spread (down for longs, up for shorts)
+    pub fn build(self, market: &impl OrderMarket) -> Result<OrderParams, OrderError> {
+        if market.market_id() != self.market {
+            return Err(OrderError::MarketMismatch {
+                order: self.market,
+                market: market.market_id(),
+            });
+        }
+
+        let base_asset_amount = self.base_asset_amount(market)?;
+        let is_long = self.direction == PositionDirection::Long;
+        let tick_size = market.order_tick_size().max(1);
+
+        let needs_price = matches!(self.order_type, OrderType::Limit | OrderType::TriggerLimit)
+            && self.oracle_price_offset.is_none();
+        let price = match self.price {
+            Some(price) => to_ticks(price, tick_size, is_long)?,
+            None if needs_price => return Err(OrderError::MissingPrice(self.order_type)),
+            None => 0,
+        };
+        if needs_price && price == 0 {
+            return Err(OrderError::InvalidPrice(self.price.unwrap_or_default()));
+        }
+
+        let trigger_price = match (self.order_type, self.trigger_price) {
+            (OrderType::TriggerMarket | OrderType::TriggerLimit, Some(trigger_price)) => {
+                match to_ticks(trigger_price, tick_size, is_long)? {
+                    0 => return Err(OrderError::InvalidPrice(trigger_price)),
+                    trigger_price => Some(trigger_price),
+                }
+            }
+            (OrderType::TriggerMarket | OrderType::TriggerLimit, None) => {
+                return Err(OrderError::MissingPrice(self.order_type))
+            }
+            _ => None,
+        };
+
+        let oracle_price_offset = match self.oracle_price_offset {
+            Some(offset) => Some(to_offset_ticks(offset, tick_size, is_long)?),
+            None => None,
+        };
+
+        if self.post_only != PostOnlyParam::None {
+            if self.order_type != OrderType::Limit {
+                return Err(OrderError::PostOnlyNotAllowed(self.order_type));
+            }
+            if self.immediate_or_cancel {
+                return Err(OrderError::PostOnlyImmediateOrCancel);
+            }
+            if self.auction.is_some() {
+                return Err(OrderError::PostOnlyAuction);
+            }
+        }
+
+        let (auction_duration, auction_start_price, auction_end_price) =
+            self.auction_params(tick_size, price, oracle_price_offset)?;
+
+        Ok(OrderParams {
+            order_type: self.order_type,
+            market_type: self.market.kind(),
+            direction: self.direction,
+            user_order_id: self.user_order_id,
+            base_asset_amount,
+            price,
+            market_index: self.market.index(),
+            reduce_only: self.reduce_only,
+            post_only: self.post_only,
+            immediate_or_cancel: self.immediate_or_cancel,
+            max_ts: self.max_ts,
+            trigger_price,
+            trigger_condition: self.trigger_condition,
+            oracle_price_offset,
+            auction_duration,
+            auction_start_price,
+            auction_end_price,
+        })
+    }
+
+    fn base_asset_amount(&self, market: &impl OrderMarket) -> Result<u64, OrderError> {
+        to_base_asset_amount(self.size, self.reduce_only, market)
+    }
+
+    fn auction_params(
+        &self,
+        tick_size: u64,
+        price: u64,
+        oracle_price_offset: Option<i32>,
+    ) -> Result<(Option<u8>, Option<i64>, Option<i64>), OrderError> {
+        let auction = match self.auction {
+            Some(auction) => auction,
+            None if self.order_type == OrderType::Oracle => {
+                return Err(OrderError::InvalidAuction("oracle orders need an auction"))
+            }
+            None => return Ok((None, None, None)),
+        };
+
+        if matches!(
+            self.order_type,
+            OrderType::TriggerMarket | OrderType::TriggerLimit
+        ) {
+            return Err(OrderError::InvalidAuction(
+                "trigger orders get their auction when triggered",
+            ));
+        }
+        if auction.duration == 0 {
+            return Err(OrderError::InvalidAuction("duration must be positive"));
+        }
+
+        let is_long = self.direction == PositionDirection::Long;
+        let (start_price, end_price) = if self.order_type == OrderType::Oracle {
+            (
+                to_offset_ticks(auction.start_price, tick_size, is_long)? as i64,
+                to_offset_ticks(auction.end_price, tick_size, is_long)? as i64,
+            )
+        } else {
+            (
+                to_ticks(auction.start_price, tick_size, is_long)? as i64,
+                to_ticks(auction.end_price, tick_size, is_long)? as i64,
+            )
+        };
+
+        if self.order_type != OrderType::Oracle && (start_price <= 0 || end_price <= 0) {
+            return Err(OrderError::InvalidAuction("prices must be positive"));
+        }
+        // the auction moves against the taker, from the best to the worst price
+        if (is_long && start_price > end_price) || (!is_long && start_price < end_price) {
+            return Err(OrderError::InvalidAuction(
+                "start price must be better than end price",
+            ));
+        }
+        if price > 0 && self.order_type != OrderType::Oracle {
+            let price = price as i64;
+            if (is_long && end_price > price) || (!is_long && end_price < price) {
+                return Err(OrderError::InvalidAuction(
+                    "end price is worse than the limit price",
+                ));
+            }
+        }
+        if let (OrderType::Oracle, Some(limit_offset)) = (self.order_type, oracle_price_offset) {
+            let limit_offset = limit_offset as i64;
+            if (is_long && end_price > limit_offset) || (!is_long && end_price < limit_offset) {
+                return Err(OrderError::InvalidAuction(
+                    "end price is worse than the oracle price offset limit",
+                ));
+            }
+        }
+
+        Ok((Some(auction.duration), Some(start_price), Some(end_price)))
+    }
+}
+
+/// Changes to an open order with human unit prices and sizes, rounded like `NewOrder`
+///
+/// ```ignore
+/// let market = client.get_perp_market_account(order.market_index).unwrap();
+/// let params = ModifyOrder::default().price(141.5).build(&order, &market)?;
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModifyOrder {
+    size: Option<f64>,
+    price: Option<f64>,
+    trigger_price: Option<f64>,
+    oracle_price_offset: Option<f64>,
+    reduce_only: Option<bool>,
+    post_only: Option<PostOnlyParam>,
+}
+
+impl ModifyOrder {
+    pub fn size(mut self, size: f64) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn price(mut self, price: f64) -> Self {
+        self.price = Some(price);
+        self
+    }
+
+    pub fn trigger_price(mut self, trigger_price: f64) -> Self {
+        self.trigger_price = Some(trigger_price);
+        self
+    }
+
+    pub fn oracle_price_offset(mut self, offset: f64) -> Self {
+        self.oracle_price_offset = Some(offset);
+        self
+    }
+
+    pub fn reduce_only(mut self, reduce_only: bool) -> Self {
+        self.reduce_only = Some(reduce_only);
+        self
+    }
+
+    pub fn post_only(mut self, post_only: PostOnlyParam) -> Self {
+        self.post_only = Some(post_only);
+        self
+    }
+
+    /// Convert the changes to `order` into program units, rounded to `market` as in
+    /// `NewOrder::build` with the order's direction
+    pub fn build(
+        self,
+        order: &Order,
+        market: &impl OrderMarket,
+    ) -> Result<ModifyOrderParams, OrderError> {
+        let order_market = MarketId::from((order.market_index, order.market_type));
+        if market.market_id() != order_market {
+            return Err(OrderError::MarketMismatch {
+                order: order_market,
+                market: market.market_id(),
+            });
+        }
+
+        let is_long = order.direction == PositionDirection::Long;
+        let tick_size = market.order_tick_size().max(1);
+        let reduce_only = self.reduce_only.unwrap_or(order.reduce_only);
+        let to_price = |price: f64| match to_ticks(price, tick_size, is_long)? {
+            0 => Err(OrderError::InvalidPrice(price)),
+            price => Ok(price),
+        };
+
+        Ok(ModifyOrderParams {
+            base_asset_amount: self
+                .size
+                .map(|size| to_base_asset_amount(size, reduce_only, market))
+                .transpose()?,
+            price: self.price.map(to_price).transpose()?,
+            trigger_price: self.trigger_price.map(to_price).transpose()?,
+            oracle_price_offset: self
+                .oracle_price_offset
+                .map(|offset| to_offset_ticks(offset, tick_size, is_long))
+                .transpose()?,
+            reduce_only: self.reduce_only,
+            post_only: self.post_only,
+            ..Default::default()
+        })
+    }
+}
+
+/// Convert a size into `market`'s base precision, rounded down to its step size
+fn to_base_asset_amount(
+    size: f64,
+    reduce_only: bool,
+    market: &impl OrderMarket,
+) -> Result<u64, OrderError> {
+    if !size.is_finite() || size <= 0.0 {
+        return Err(OrderError::InvalidSize(size));
+    }
+
+    let step_size = market.order_step_size().max(1);
+    let raw = size * market.base_precision() as f64;
+    if raw >= u64::MAX as f64 {
+        return Err(OrderError::InvalidSize(size));
+    }
+    // tolerate float noise, e.g. 0.3 * 1e9 = 299999999.99999994
+    let base_asset_amount = ((raw + 0.5) as u64 / step_size) * step_size;
+
+    if base_asset_amount == 0 || (!reduce_only && base_asset_amount < market.min_order_size()) {
+        return Err(OrderError::BelowMinOrderSize {
+            size: base_asset_amount,
+            min_order_size: market.min_order_size(),
+        });
+    }
+
+    Ok(base_asset_amount)
+}
+
+/// Convert a price into PRICE_PRECISION, rounded to `tick_size` (down if `round_down`)
+fn to_ticks(price: f64, tick_size: u64, round_down: bool) -> Result<u64, OrderError> {
+    let raw = price * PRICE_PRECISION as f64;
+    if !price.is_finite() || price < 0.0 || raw >= u64::MAX as f64 {
+        return Err(OrderError::InvalidPrice(price));
+    }
+
+    let ticks = raw / tick_size as f64;
+    // ignore float noise below a millionth of a tick
+    let ticks = if round_down {
+        (ticks + 1e-6).floor()
+    } else {
+        (ticks - 1e-6).ceil()
+    };
+
+    Ok(ticks as u64 * tick_size)
+}
+
+/// Convert an oracle offset into PRICE_PRECISION, rounded to `tick_size` (down if `round_down`)
+fn to_offset_ticks(offset: f64, tick_size: u64, round_down: bool) -> Result<i32, OrderError> {
+    if !offset.is_finite() {
+        return Err(OrderError::InvalidPrice(offset));
+    }
+
+    let ticks = offset * PRICE_PRECISION as f64 / tick_size as f64;
+    let ticks = if round_down {
+        (ticks + 1e-6).floor()
+    } else {
+        (ticks - 1e-6).ceil()
+    };
+    let offset_ticks = ticks * tick_size as f64;
+    if offset_ticks.abs() > i32::MAX as f64 {
+        return Err(OrderError::InvalidPrice(offset));
+    }
+
+    Ok(offset_ticks as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use drift::state::user::MarketType;
+
+    use super::*;
+
+    fn sol_perp() -> PerpMarket {
+        let mut market = PerpMarket {
+            market_index: 0,
+            ..Default::default()
+        };
+        market.amm.order_tick_size = 100; // $0.0001
+        market.amm.order_step_size = 10_000_000; // 0.01
+        market.amm.min_order_size = 10_000_000;
+        market
+    }
+
+    fn sol_spot() -> SpotMarket {
+        SpotMarket {
+            market_index: 1,
+            decimals: 9,
+            order_tick_size: 100,
+            order_step_size: 1_000_000,
+            min_order_size: 10_000_000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_limit_order_rounds_to_market() {
+        let long = NewOrder::limit(MarketId::perp(0), PositionDirection::Long, 1.234, 140.12345)
+            .build(&sol_perp())
+            .unwrap();
+        assert_eq!(long.base_asset_amount, 1_230_000_000);
+        assert_eq!(long.price, 140_123_400);
+        assert_eq!(long.market_type, MarketType::Perp);
+
+        let short = NewOrder::limit(MarketId::perp(0), PositionDirection::Short, 0.3, 140.12345)
+            .build(&sol_perp())
+            .unwrap();
+        assert_eq!(short.base_asset_amount, 300_000_000);
+        assert_eq!(short.price, 140_123_500);
+    }
+
+    #[test]
+    fn test_spot_order_uses_token_decimals() {
+        let order = NewOrder::limit(MarketId::spot(1), PositionDirection::Long, 0.5, 140.0)
+            .build(&sol_spot())
+            .unwrap();
+        assert_eq!(order.base_asset_amount, 500_000_000);
+        assert_eq!(order.market_type, MarketType::Spot);
+
+        assert_eq!(
+            NewOrder::limit(MarketId::spot(2), PositionDirection::Long, 0.5, 140.0)
+                .build(&sol_spot()),
+            Err(OrderError::MarketMismatch {
+                order: MarketId::spot(2),
+                market: MarketId::spot(1),
+            })
+        );
+    }
+
+    #[test]
+    fn test_min_order_size() {
+        let order = NewOrder::market(MarketId::perp(0), PositionDirection::Short, 0.001);
+        assert_eq!(
+            order.clone().build(&sol_perp()),
+            Err(OrderError::BelowMinOrderSize {
+                size: 0,
+                min_order_size: 10_000_000
+            })
+        );
+
+        let mut market = sol_perp();
+        market.amm.min_order_size = 100_000_000;
+        let small = NewOrder::market(MarketId::perp(0), PositionDirection::Short, 0.05);
+        assert!(matches!(
+            small.clone().build(&market),
+            Err(OrderError::BelowMinOrderSize { .. })
+        ));
+        // reduce-only orders may close out dust positions
+        assert!(small.reduce_only(true).build(&market).is_ok());
+    }
+
+    #[test]
+    fn test_post_only_combinations() {
+        let limit = NewOrder::limit(MarketId::perp(0), PositionDirection::Long, 1.0, 100.0)
+            .post_only(PostOnlyParam::MustPostOnly);
+        assert!(limit.clone().reduce_only(true).build(&sol_perp()).is_ok());
+        assert_eq!(
+            limit.immediate_or_cancel(true).build(&sol_perp()),
+            Err(OrderError::PostOnlyImmediateOrCancel)
+        );
+
+        assert_eq!(
+            NewOrder::market(MarketId::perp(0), PositionDirection::Long, 1.0)
+                .post_only(PostOnlyParam::TryPostOnly)
+                .build(&sol_perp()),
+            Err(OrderError::PostOnlyNotAllowed(OrderType::Market))
+        );
+    }
+
+    #[test]
+    fn test_trigger_orders() {
+        let order = NewOrder::trigger_limit(
+            MarketId::perp(0),
+            PositionDirection::Short,
+            1.0,
+            120.0,
+            OrderTriggerCondition::Below,
+            119.5,
+        )
+        .build(&sol_perp())
+        .unwrap();
+        assert_eq!(order.trigger_price, Some(120_000_000));
+        assert_eq!(order.trigger_condition, OrderTriggerCondition::Below);
+        assert_eq!(order.price, 119_500_000);
+
+        let order = NewOrder::trigger_market(
+            MarketId::perp(0),
+            PositionDirection::Short,
+            1.0,
+            120.0,
+            OrderTriggerCondition::Below,
+        )
+        .auction(AuctionParams {
+            duration: 10,
+            start_price: 120.0,
+            end_price: 119.0,
+        });
+        assert!(matches!(
+            order.build(&sol_perp()),
+            Err(OrderError::InvalidAuction(_))
+        ));
+    }
+
+    #[test]
+    fn test_oracle_orders() {
+        let oracle_limit =
+            NewOrder::oracle_limit(MarketId::perp(0), PositionDirection::Long, 1.0, -0.05)
+                .build(&sol_perp())
+                .unwrap();
+        assert_eq!(oracle_limit.price, 0);
+        assert_eq!(oracle_limit.oracle_price_offset, Some(-50_000));
+
+        let order = NewOrder::oracle(MarketId::perp(0), PositionDirection::Short, 1.0);
+        assert!(order.clone().build(&sol_perp()).is_err());
+
+        let params = order
+            .auction(AuctionParams {
+                duration: 20,
+                start_price: 0.1,
+                end_price: -0.2,
+            })
+            .build(&sol_perp())
+            .unwrap();
+        assert_eq!(params.auction_duration, Some(20));
+        assert_eq!(params.auction_start_price, Some(100_000));
+        assert_eq!(params.auction_end_price, Some(-200_000));
+        assert_eq!(params.oracle_price_offset, None);
+    }
+
+    #[test]
+    fn test_oracle_order_price_offset_limit() {
+        let order = NewOrder::oracle(MarketId::perp(0), PositionDirection::Short, 1.0).auction(
+            AuctionParams {
+                duration: 20,
+                start_price: 0.1,
+                end_price: -0.2,
+            },
+        );
+
+        // shorts round the limit up
+        let params = order
+            .clone()
+            .oracle_price_offset(-0.25005)
+            .build(&sol_perp())
+            .unwrap();
+        assert_eq!(params.order_type, OrderType::Oracle);
+        assert_eq!(params.price, 0);
+        assert_eq!(params.oracle_price_offset, Some(-250_000));
+
+        assert_eq!(
+            order.oracle_price_offset(-0.1).build(&sol_perp()),
+            Err(OrderError::InvalidAuction(
+                "end price is worse than the oracle price offset limit"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_auction_validation() {
+        let order = NewOrder::limit(MarketId::perp(0), PositionDirection::Long, 1.0, 101.0);
+        let auction = |start_price, end_price| AuctionParams {
+            duration: 10,
+            start_price,
+            end_price,
+        };
+
+        assert!(order
+            .clone()
+            .auction(auction(100.0, 101.0))
+            .build(&sol_perp())
+            .is_ok());
+        assert!(matches!(
+            order
+                .clone()
+                .auction(auction(101.0, 100.0))
+                .build(&sol_perp()),
+            Err(OrderError::InvalidAuction(_))
+        ));
+        assert!(matches!(
+            order.auction(auction(100.0, 102.0)).build(&sol_perp()),
+            Err(OrderError::InvalidAuction(_))
+        ));
+    }
+
+    #[test]
+    fn test_modify_order_rounds_like_new_orders() {
+        let order = Order {
+            market_index: 0,
+            market_type: MarketType::Perp,
+            direction: PositionDirection::Short,
+            ..Default::default()
+        };
+
+        let params = ModifyOrder::default()
+            .size(1.234)
+            .price(140.12345)
+            .oracle_price_offset(-0.12345)
+            .build(&order, &sol_perp())
+            .unwrap();
+        assert_eq!(params.base_asset_amount, Some(1_230_000_000));
+        assert_eq!(params.price, Some(140_123_500));
+        assert_eq!(params.oracle_price_offset, Some(-123_400));
+        assert_eq!(params.trigger_price, None);
+        assert_eq!(params.reduce_only, None);
+
+        assert!(matches!(
+            ModifyOrder::default()
+                .size(0.001)
+                .build(&order, &sol_perp()),
+            Err(OrderError::BelowMinOrderSize { .. })
+        ));
+        // a long price rounding down to 0
+        let long = Order {
+            direction: PositionDirection::Long,
+            ..order
+        };
+        assert!(matches!(
+            ModifyOrder::default()
+                .price(0.00001)
+                .build(&long, &sol_perp()),
+            Err(OrderError::InvalidPrice(_))
+        ));
+        assert!(matches!(
+            ModifyOrder::default().price(1.0).build(&order, &sol_spot()),
+            Err(OrderError::MarketMismatch { .. })
+        ));
+    }
+}
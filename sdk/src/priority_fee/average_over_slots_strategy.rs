@@ -1,20 +1,16 @@
-use super::types::{PriorityFeeResponse, PriorityFeeStrategy};
+use super::types::{PriorityFeeSample, PriorityFeeStrategy};
 
 #[derive(Debug, Clone)]
-pub(crate) struct AverageOverSlotsStrategy;
+pub struct AverageOverSlotsStrategy;
 
 impl PriorityFeeStrategy for AverageOverSlotsStrategy {
-    fn calculate(&self, samples: PriorityFeeResponse) -> u64 {
-        if let PriorityFeeResponse::Solana(res) = samples {
-            if res.is_empty() {
-                return 0;
-            }
-
-            let running_sum_fees: u64 = res.iter().map(|x| x.prioritization_fee).sum();
-
-            return running_sum_fees / res.len() as u64;
+    fn calculate(&self, samples: &[PriorityFeeSample]) -> u64 {
+        if samples.is_empty() {
+            return 0;
         }
 
-        0
+        let running_sum_fees: u64 = samples.iter().map(|x| x.fee).sum();
+
+        running_sum_fees / samples.len() as u64
     }
 }
@@ -0,0 +1,62 @@
+use super::types::{PriorityFeeSample, PriorityFeeStrategy};
+
+/// Exponentially weighted moving average of the sampled fees, oldest slot first,
+/// so the most recent slots carry the most weight.
+/// Samples without a slot are weighted in the order they were received.
+#[derive(Debug, Clone)]
+pub struct EwmaStrategy {
+    alpha: f64,
+}
+
+impl EwmaStrategy {
+    /// `alpha` is the weight of each new sample and is clamped to [0, 1]
+    pub fn new(alpha: f64) -> Self {
+        Self {
+            alpha: alpha.clamp(0.0, 1.0),
+        }
+    }
+}
+
+impl PriorityFeeStrategy for EwmaStrategy {
+    fn calculate(&self, samples: &[PriorityFeeSample]) -> u64 {
+        let mut samples = samples.to_vec();
+        samples.sort_by_key(|x| x.slot);
+
+        let mut iter = samples.iter();
+        let mut ewma = match iter.next() {
+            Some(first) => first.fee as f64,
+            None => return 0,
+        };
+
+        for sample in iter {
+            ewma = self.alpha * sample.fee as f64 + (1.0 - self.alpha) * ewma;
+        }
+
+        ewma.round() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ewma_strategy_weights_recent_slots() {
+        // received newest first
+        let samples = vec![
+            PriorityFeeSample::new(Some(3), 400),
+            PriorityFeeSample::new(Some(2), 200),
+            PriorityFeeSample::new(Some(1), 100),
+        ];
+
+        // 100 -> 0.5 * 200 + 0.5 * 100 = 150 -> 0.5 * 400 + 0.5 * 150 = 275
+        assert_eq!(EwmaStrategy::new(0.5).calculate(&samples), 275);
+        assert_eq!(EwmaStrategy::new(1.0).calculate(&samples), 400);
+        assert_eq!(EwmaStrategy::new(0.0).calculate(&samples), 100);
+    }
+
+    #[test]
+    fn test_ewma_strategy_empty() {
+        assert_eq!(EwmaStrategy::new(0.5).calculate(&[]), 0);
+    }
+}
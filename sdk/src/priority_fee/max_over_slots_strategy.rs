@@ -1,17 +1,10 @@
-use super::types::{PriorityFeeResponse, PriorityFeeStrategy};
+use super::types::{PriorityFeeSample, PriorityFeeStrategy};
 
-pub(crate) struct MaxOverSlotsStrategy;
+#[derive(Debug, Clone)]
+pub struct MaxOverSlotsStrategy;
 
 impl PriorityFeeStrategy for MaxOverSlotsStrategy {
-    fn calculate(&self, samples: PriorityFeeResponse) -> u64 {
-        if let PriorityFeeResponse::Solana(res) = samples {
-            if res.is_empty() {
-                return 0;
-            }
-
-            return res.iter().map(|x| x.prioritization_fee).max().unwrap();
-        }
-
-        0
+    fn calculate(&self, samples: &[PriorityFeeSample]) -> u64 {
+        samples.iter().map(|x| x.fee).max().unwrap_or(0)
     }
 }
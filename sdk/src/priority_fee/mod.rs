@@ -1,8 +1,11 @@
-mod average_over_slots_strategy;
+pub mod average_over_slots_strategy;
 pub mod drift_priority_fee_method;
+pub mod ewma_strategy;
 pub mod helius_priority_fee_method;
-mod max_over_slots_strategy;
+pub mod max_over_slots_strategy;
+pub mod percentile_strategy;
 pub mod priority_fee_subscriber;
 pub mod priority_fee_subscriber_map;
 mod solana_priority_fee_method;
+pub mod trimmed_mean_strategy;
 pub mod types;
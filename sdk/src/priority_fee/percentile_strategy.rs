@@ -0,0 +1,60 @@
+use super::types::{PriorityFeeSample, PriorityFeeStrategy};
+
+/// Returns the nearest-rank percentile of the sampled fees.
+#[derive(Debug, Clone)]
+pub struct PercentileStrategy {
+    percentile: u8,
+}
+
+impl PercentileStrategy {
+    /// `percentile` is clamped to 100
+    pub fn new(percentile: u8) -> Self {
+        Self {
+            percentile: percentile.min(100),
+        }
+    }
+}
+
+impl PriorityFeeStrategy for PercentileStrategy {
+    fn calculate(&self, samples: &[PriorityFeeSample]) -> u64 {
+        if samples.is_empty() {
+            return 0;
+        }
+
+        let mut fees: Vec<u64> = samples.iter().map(|x| x.fee).collect();
+        fees.sort_unstable();
+
+        let rank = (self.percentile as usize * fees.len() + 99) / 100;
+
+        fees[rank.saturating_sub(1)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(fees: &[u64]) -> Vec<PriorityFeeSample> {
+        fees.iter()
+            .enumerate()
+            .map(|(slot, fee)| PriorityFeeSample::new(Some(slot as u64), *fee))
+            .collect()
+    }
+
+    #[test]
+    fn test_percentile_strategy() {
+        let samples = samples(&[50, 10, 40, 20, 30]);
+
+        assert_eq!(PercentileStrategy::new(0).calculate(&samples), 10);
+        assert_eq!(PercentileStrategy::new(20).calculate(&samples), 10);
+        assert_eq!(PercentileStrategy::new(50).calculate(&samples), 30);
+        assert_eq!(PercentileStrategy::new(75).calculate(&samples), 40);
+        assert_eq!(PercentileStrategy::new(100).calculate(&samples), 50);
+        assert_eq!(PercentileStrategy::new(200).calculate(&samples), 50);
+    }
+
+    #[test]
+    fn test_percentile_strategy_empty() {
+        assert_eq!(PercentileStrategy::new(50).calculate(&[]), 0);
+    }
+}
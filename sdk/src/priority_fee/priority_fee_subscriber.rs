@@ -148,16 +148,12 @@ impl<T: AccountProvider> PriorityFeeSubscriber<T> {
                     self.latest_priority_fee = first.prioritization_fee;
                     self.last_slot_seen = first.slot;
 
-                    self.last_avg_strategy_result = self
-                        .average_strategy
-                        .calculate(PriorityFeeResponse::Solana(&samples));
-                    self.last_max_strategy_result = self
-                        .max_strategy
-                        .calculate(PriorityFeeResponse::Solana(&samples));
+                    let fee_samples = PriorityFeeResponse::Solana(&samples).to_samples().concat();
+                    self.last_avg_strategy_result = self.average_strategy.calculate(&fee_samples);
+                    self.last_max_strategy_result = self.max_strategy.calculate(&fee_samples);
 
                     if let Some(custom_strategy) = &self.custom_strategy {
-                        self.last_custom_strategy_result =
-                            custom_strategy.calculate(PriorityFeeResponse::Solana(&samples));
+                        self.last_custom_strategy_result = custom_strategy.calculate(&fee_samples);
                     }
                 }
 
@@ -192,8 +188,8 @@ impl<T: AccountProvider> PriorityFeeSubscriber<T> {
                         }
 
                         if let Some(custom_strategy) = &self.custom_strategy {
-                            self.last_custom_strategy_result =
-                                custom_strategy.calculate(PriorityFeeResponse::Helius(res));
+                            self.last_custom_strategy_result = custom_strategy
+                                .calculate(&PriorityFeeResponse::Helius(res).to_samples().concat());
                         }
                     }
                     Err(_e) => {
@@ -234,8 +230,13 @@ impl<T: AccountProvider> PriorityFeeSubscriber<T> {
                         }
 
                         if let Some(custom_strategy) = &self.custom_strategy {
-                            self.last_custom_strategy_result =
-                                custom_strategy.calculate(PriorityFeeResponse::Drift(sample));
+                            // the tx write locks every market, so it has to outbid the busiest one
+                            self.last_custom_strategy_result = PriorityFeeResponse::Drift(sample)
+                                .to_samples()
+                                .iter()
+                                .map(|samples| custom_strategy.calculate(samples))
+                                .max()
+                                .unwrap_or_default();
                         }
                     }
                 }
@@ -1,4 +1,7 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
 
 use tokio::{
     sync::mpsc,
@@ -11,16 +14,23 @@ use super::{
     drift_priority_fee_method::{
         fetch_drift_priority_fee, DriftMarketInfo, DriftPriorityFeeLevels, DriftPriorityFeeResponse,
     },
-    types::{PriorityFeeSubscriberMapConfig, DEFAULT_PRIORITY_FEE_MAP_FREQUENCY_MS},
+    helius_priority_fee_method::HeliusPriorityLevel,
+    types::{
+        PriorityFeeSample, PriorityFeeStrategy, PriorityFeeSubscriberMapConfig,
+        DEFAULT_PRIORITY_FEE_MAP_FREQUENCY_MS,
+    },
 };
 
 #[derive(Debug, Clone)]
 pub struct PriorityFeeSubscriberMap {
     frequency_ms: u64,
     // interval_id: Option<Interval>,
-    drift_markets: Option<Vec<DriftMarketInfo>>,
+    /// shared with the subscription loop, so market updates apply to a running subscription
+    drift_markets: Arc<RwLock<Option<Vec<DriftMarketInfo>>>>,
     drift_priority_fee_endpoint: Option<String>,
-    fees_map: HashMap<String, HashMap<u64, DriftPriorityFeeLevels>>,
+    fees_map: Arc<RwLock<HashMap<String, HashMap<u64, DriftPriorityFeeLevels>>>>,
+    default_strategy: Option<Arc<dyn PriorityFeeStrategy>>,
+    market_strategies: HashMap<String, HashMap<u64, Arc<dyn PriorityFeeStrategy>>>,
     stop_tx: Option<mpsc::Sender<()>>,
 }
 
@@ -36,27 +46,33 @@ impl PriorityFeeSubscriberMap {
         Self {
             frequency_ms,
             // interval_id: None,
-            drift_markets: config.drift_markets,
+            drift_markets: Arc::new(RwLock::new(config.drift_markets)),
             drift_priority_fee_endpoint: Some(config.drift_priority_fee_endpoint),
-            fees_map,
+            fees_map: Arc::new(RwLock::new(fees_map)),
+            default_strategy: config.default_strategy,
+            market_strategies: HashMap::new(),
             stop_tx: None,
         }
     }
 
     pub fn update_fees_map(&mut self, drift_priority_fee_res: DriftPriorityFeeResponse) {
+        let mut fees_map = self.fees_map.write().unwrap();
         drift_priority_fee_res.0.iter().for_each(|fee| {
-            if let Some(fee_level) = self.fees_map.get_mut(&fee.market_type) {
+            if let Some(fee_level) = fees_map.get_mut(&fee.market_type) {
                 fee_level.insert(fee.market_index, fee.clone());
             }
         });
     }
 
+    /// Loads the fees and keeps refreshing them every `frequency_ms`.
+    ///
+    /// Returns the error of the first load, the subscription keeps refreshing regardless
     pub async fn subscribe(&mut self) -> SdkResult<()> {
         if self.stop_tx.is_some() {
             return Ok(());
         }
 
-        self.load().await?;
+        let first_load = self.load().await;
 
         let (tx, mut rx) = mpsc::channel(1);
         self.stop_tx = Some(tx);
@@ -101,7 +117,7 @@ impl PriorityFeeSubscriberMap {
         //     }
         // });
 
-        Ok(())
+        first_load
     }
 
     pub async fn unsubscribe(&mut self) -> SdkResult<()> {
@@ -113,7 +129,8 @@ impl PriorityFeeSubscriberMap {
     }
 
     async fn load(&mut self) -> SdkResult<()> {
-        if let Some(drift_markets) = &self.drift_markets {
+        let drift_markets = self.drift_markets.read().unwrap().clone();
+        if let Some(drift_markets) = drift_markets {
             let market_types: Vec<&str> = drift_markets
                 .iter()
                 .map(|m| m.market_type.as_str())
@@ -159,18 +176,148 @@ impl PriorityFeeSubscriberMap {
     }
 
     pub fn update_market_type_and_index(&mut self, drift_markets: Vec<DriftMarketInfo>) {
-        self.drift_markets = Some(drift_markets);
+        *self.drift_markets.write().unwrap() = Some(drift_markets);
+    }
+
+    pub fn get_drift_markets(&self) -> Option<Vec<DriftMarketInfo>> {
+        self.drift_markets.read().unwrap().clone()
     }
 
     pub fn get_priority_fees(
         &self,
         market_type: &str,
         market_index: u64,
-    ) -> Option<&DriftPriorityFeeLevels> {
-        if let Some(level) = self.fees_map.get(market_type) {
-            level.get(&market_index)
+    ) -> Option<DriftPriorityFeeLevels> {
+        if let Some(level) = self.fees_map.read().unwrap().get(market_type) {
+            level.get(&market_index).cloned()
         } else {
             None
         }
     }
+
+    /// Set the strategy used for a single market, `None` falls back to the default strategy
+    pub fn update_market_strategy(
+        &mut self,
+        market_type: &str,
+        market_index: u64,
+        strategy: Option<Arc<dyn PriorityFeeStrategy>>,
+    ) {
+        match strategy {
+            Some(strategy) => {
+                self.market_strategies
+                    .entry(market_type.to_string())
+                    .or_default()
+                    .insert(market_index, strategy);
+            }
+            None => {
+                if let Some(strategies) = self.market_strategies.get_mut(market_type) {
+                    strategies.remove(&market_index);
+                }
+            }
+        }
+    }
+
+    pub fn update_default_strategy(&mut self, strategy: Option<Arc<dyn PriorityFeeStrategy>>) {
+        self.default_strategy = strategy;
+    }
+
+    /// Priority fee for a market, calculated by the market strategy, or the default strategy.
+    /// Without any strategy the medium priority level is returned.
+    pub fn get_priority_fee(&self, market_type: &str, market_index: u64) -> Option<u64> {
+        let levels = self.get_priority_fees(market_type, market_index)?;
+
+        let strategy = self
+            .market_strategies
+            .get(market_type)
+            .and_then(|strategies| strategies.get(&market_index))
+            .or(self.default_strategy.as_ref());
+
+        match strategy {
+            Some(strategy) => Some(
+                strategy.calculate(&PriorityFeeSample::from_levels(&levels.priority_fee_level)),
+            ),
+            None => levels
+                .priority_fee_level
+                .get(&HeliusPriorityLevel::Medium)
+                .copied(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::priority_fee::{
+        max_over_slots_strategy::MaxOverSlotsStrategy, percentile_strategy::PercentileStrategy,
+    };
+
+    fn fee_levels(market_type: &str, market_index: u64, base: u64) -> DriftPriorityFeeLevels {
+        DriftPriorityFeeLevels {
+            priority_fee_level: HashMap::from([
+                (HeliusPriorityLevel::Min, base),
+                (HeliusPriorityLevel::Low, base * 2),
+                (HeliusPriorityLevel::Medium, base * 3),
+                (HeliusPriorityLevel::High, base * 4),
+                (HeliusPriorityLevel::VeryHigh, base * 5),
+                (HeliusPriorityLevel::UnsafeMax, base * 6),
+            ]),
+            market_type: market_type.to_string(),
+            market_index,
+        }
+    }
+
+    fn subscriber_map() -> PriorityFeeSubscriberMap {
+        let mut map = PriorityFeeSubscriberMap::new(PriorityFeeSubscriberMapConfig {
+            frequency_ms: None,
+            drift_markets: None,
+            drift_priority_fee_endpoint: String::new(),
+            default_strategy: None,
+        });
+        map.update_fees_map(DriftPriorityFeeResponse(vec![
+            fee_levels("perp", 0, 100),
+            fee_levels("perp", 1, 1_000),
+            fee_levels("spot", 0, 10),
+        ]));
+        map
+    }
+
+    #[test]
+    fn test_priority_fee_without_strategy() {
+        let map = subscriber_map();
+
+        assert_eq!(map.get_priority_fee("perp", 0), Some(300));
+        assert_eq!(map.get_priority_fee("spot", 0), Some(30));
+        assert_eq!(map.get_priority_fee("perp", 2), None);
+    }
+
+    #[test]
+    fn test_priority_fee_per_market_strategy() {
+        let mut map = subscriber_map();
+        map.update_default_strategy(Some(Arc::new(PercentileStrategy::new(25))));
+        map.update_market_strategy("perp", 1, Some(Arc::new(MaxOverSlotsStrategy)));
+
+        assert_eq!(map.get_priority_fee("perp", 0), Some(200));
+        assert_eq!(map.get_priority_fee("perp", 1), Some(6_000));
+        assert_eq!(map.get_priority_fee("spot", 0), Some(20));
+
+        map.update_market_strategy("perp", 1, None);
+        assert_eq!(map.get_priority_fee("perp", 1), Some(2_000));
+    }
+
+    #[test]
+    fn test_clones_share_markets_and_fees() {
+        let mut map = subscriber_map();
+        let mut subscription = map.clone();
+
+        map.update_market_type_and_index(vec![DriftMarketInfo {
+            market_type: "perp".to_string(),
+            market_index: 3,
+        }]);
+        subscription.update_fees_map(DriftPriorityFeeResponse(vec![fee_levels("perp", 3, 7)]));
+
+        let markets = subscription.get_drift_markets().unwrap();
+        assert_eq!(markets.len(), 1);
+        assert_eq!(markets[0].market_index, 3);
+        assert_eq!(map.get_priority_fee("perp", 3), Some(21));
+    }
 }
@@ -0,0 +1,51 @@
+use super::types::{PriorityFeeSample, PriorityFeeStrategy};
+
+/// Mean of the sampled fees after dropping `trim_fraction` of the samples from each end,
+/// which removes outliers that would skew a plain average.
+#[derive(Debug, Clone)]
+pub struct TrimmedMeanStrategy {
+    trim_fraction: f64,
+}
+
+impl TrimmedMeanStrategy {
+    /// `trim_fraction` is clamped to [0, 0.5)
+    pub fn new(trim_fraction: f64) -> Self {
+        Self {
+            trim_fraction: trim_fraction.clamp(0.0, 0.49),
+        }
+    }
+}
+
+impl PriorityFeeStrategy for TrimmedMeanStrategy {
+    fn calculate(&self, samples: &[PriorityFeeSample]) -> u64 {
+        if samples.is_empty() {
+            return 0;
+        }
+
+        let mut fees: Vec<u64> = samples.iter().map(|x| x.fee).collect();
+        fees.sort_unstable();
+
+        let trim = (fees.len() as f64 * self.trim_fraction).floor() as usize;
+        let trimmed = &fees[trim..fees.len() - trim];
+
+        trimmed.iter().sum::<u64>() / trimmed.len() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trimmed_mean_strategy() {
+        let samples: Vec<PriorityFeeSample> = [1, 100, 110, 120, 1_000_000]
+            .iter()
+            .map(|fee| PriorityFeeSample::new(None, *fee))
+            .collect();
+
+        assert_eq!(TrimmedMeanStrategy::new(0.2).calculate(&samples), 110);
+        assert_eq!(TrimmedMeanStrategy::new(0.0).calculate(&samples), 200_066);
+        assert_eq!(TrimmedMeanStrategy::new(0.9).calculate(&samples), 110);
+        assert_eq!(TrimmedMeanStrategy::new(0.2).calculate(&[]), 0);
+    }
+}
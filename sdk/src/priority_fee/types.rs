@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{collections::HashMap, fmt::Debug, sync::Arc};
 
 use solana_sdk::pubkey::Pubkey;
 
@@ -6,7 +6,7 @@ use crate::{drift_client::DriftClient, AccountProvider};
 
 use super::{
     drift_priority_fee_method::{DriftMarketInfo, DriftPriorityFeeResponse},
-    helius_priority_fee_method::HeliusPriorityFeeResponse,
+    helius_priority_fee_method::{HeliusPriorityFeeResponse, HeliusPriorityLevel},
     solana_priority_fee_method::SolanaPriorityFeeResponse,
 };
 
@@ -16,10 +16,58 @@ pub(crate) enum PriorityFeeResponse<'a> {
     Drift(DriftPriorityFeeResponse),
 }
 
+impl<'a> PriorityFeeResponse<'a> {
+    /// Normalize the source specific response into fee samples, one set per drift market.
+    /// Solana and helius samples aren't market specific and come back as a single set.
+    pub(crate) fn to_samples(&self) -> Vec<Vec<PriorityFeeSample>> {
+        match self {
+            PriorityFeeResponse::Solana(res) => vec![res
+                .iter()
+                .map(|x| PriorityFeeSample::new(Some(x.slot), x.prioritization_fee))
+                .collect()],
+            PriorityFeeResponse::Helius(res) => vec![match &res.result.priority_fee_levels {
+                Some(levels) => PriorityFeeSample::from_levels(&levels.0),
+                None => res
+                    .result
+                    .priority_fee_estimate
+                    .map(|fee| vec![PriorityFeeSample::new(None, fee)])
+                    .unwrap_or_default(),
+            }],
+            PriorityFeeResponse::Drift(res) => res
+                .0
+                .iter()
+                .map(|levels| PriorityFeeSample::from_levels(&levels.priority_fee_level))
+                .collect(),
+        }
+    }
+}
+
+/// A single priority fee observation in micro lamports per CU, independent of the source it came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriorityFeeSample {
+    /// slot the fee was observed in, `None` for sources that only report fee levels (helius, drift)
+    pub slot: Option<u64>,
+    pub fee: u64,
+}
+
+impl PriorityFeeSample {
+    pub fn new(slot: Option<u64>, fee: u64) -> Self {
+        Self { slot, fee }
+    }
+
+    /// One sample per priority level, sorted by fee ascending.
+    pub fn from_levels(levels: &HashMap<HeliusPriorityLevel, u64>) -> Vec<Self> {
+        let mut samples: Vec<Self> = levels.values().map(|fee| Self::new(None, *fee)).collect();
+        samples.sort_by_key(|sample| sample.fee);
+        samples
+    }
+}
+
 pub const DEFAULT_PRIORITY_FEE_MAP_FREQUENCY_MS: u64 = 10_000;
 
-pub trait PriorityFeeStrategy {
-    fn calculate(&self, samples: PriorityFeeResponse) -> u64;
+/// Calculates a priority fee (micro lamports per CU) from the samples of the configured `PriorityFeeMethod`.
+pub trait PriorityFeeStrategy: Debug + Send + Sync {
+    fn calculate(&self, samples: &[PriorityFeeSample]) -> u64;
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -104,4 +152,41 @@ pub struct PriorityFeeSubscriberMapConfig {
 
     /// url for drift cached priority fee endpoint
     pub drift_priority_fee_endpoint: String,
+
+    /// strategy applied to markets without their own strategy, defaults to the medium priority level
+    pub default_strategy: Option<Arc<dyn PriorityFeeStrategy>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::priority_fee::drift_priority_fee_method::DriftPriorityFeeLevels;
+
+    fn fee_levels(market_index: u64, base: u64) -> DriftPriorityFeeLevels {
+        DriftPriorityFeeLevels {
+            priority_fee_level: HashMap::from([
+                (HeliusPriorityLevel::Low, base),
+                (HeliusPriorityLevel::Medium, base * 2),
+                (HeliusPriorityLevel::High, base * 3),
+            ]),
+            market_type: "perp".to_string(),
+            market_index,
+        }
+    }
+
+    #[test]
+    fn test_drift_samples_per_market() {
+        let res = DriftPriorityFeeResponse(vec![fee_levels(0, 10), fee_levels(1, 1_000)]);
+        let samples = PriorityFeeResponse::Drift(res).to_samples();
+
+        assert_eq!(samples.len(), 2);
+        assert_eq!(
+            samples[0].iter().map(|s| s.fee).collect::<Vec<_>>(),
+            vec![10, 20, 30]
+        );
+        assert_eq!(
+            samples[1].iter().map(|s| s.fee).collect::<Vec<_>>(),
+            vec![1_000, 2_000, 3_000]
+        );
+    }
 }
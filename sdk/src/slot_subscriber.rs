@@ -112,6 +112,16 @@ impl SlotSubscriber {
         Ok(())
     }
 
+    /// Call `handler` with every new slot received by the subscription
+    pub fn on_slot<F: 'static + Send + Fn(u64)>(&self, handler: F) {
+        self.event_emitter
+            .subscribe(SlotSubscriber::SUBSCRIPTION_ID, move |event| {
+                if let Some(update) = event.as_any().downcast_ref::<SlotUpdate>() {
+                    handler(update.latest_slot);
+                }
+            });
+    }
+
     pub fn get_slot(&self) -> u64 {
         self.current_slot()
     }
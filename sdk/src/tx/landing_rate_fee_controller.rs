@@ -0,0 +1,499 @@
+use std::collections::{HashMap, VecDeque};
+
+use solana_sdk::pubkey::Pubkey;
+
+pub const DEFAULT_TARGET_LANDING_RATE: f64 = 0.8;
+pub const DEFAULT_LANDING_RATE_WINDOW: usize = 50;
+pub const DEFAULT_MIN_SAMPLES: usize = 5;
+pub const DEFAULT_MULTIPLIER_STEP: f64 = 0.1;
+pub const DEFAULT_MIN_MULTIPLIER: f64 = 0.5;
+pub const DEFAULT_MAX_MULTIPLIER: f64 = 10.0;
+
+/// landing rate deviation from the target that does not trigger an adjustment
+const LANDING_RATE_TOLERANCE: f64 = 0.05;
+const MICRO_LAMPORTS_PER_LAMPORT: u64 = 1_000_000;
+
+#[derive(Debug, Clone, Default)]
+pub struct LandingRateFeeControllerConfig {
+    /// fraction of sent txs that should land, default: 0.8
+    pub target_landing_rate: Option<f64>,
+    /// number of most recent tx outcomes considered per market
+    pub window_size: Option<usize>,
+    /// number of outcomes required before the multiplier is adjusted
+    pub min_samples: Option<usize>,
+    /// relative change applied to the multiplier on each adjustment
+    pub multiplier_step: Option<f64>,
+    pub min_multiplier: Option<f64>,
+    pub max_multiplier: Option<f64>,
+    /// upper bound on the resulting compute unit price
+    pub max_priority_fee_micro_lamports: Option<u64>,
+}
+
+/// Outcome of a single tx, fee and expected reward are this market's share and 0 for dropped txs
+#[derive(Debug, Clone, Copy)]
+struct TxOutcome {
+    landed: bool,
+    fee_lamports: u64,
+    expected_reward_lamports: u64,
+}
+
+/// Sent/landed bookkeeping for a single writable market account.
+#[derive(Debug, Clone)]
+pub struct MarketLandingStats {
+    outcomes: VecDeque<TxOutcome>,
+    sent: u64,
+    landed: u64,
+    in_flight: u64,
+    multiplier: f64,
+}
+
+impl MarketLandingStats {
+    fn new() -> Self {
+        Self {
+            outcomes: VecDeque::new(),
+            sent: 0,
+            landed: 0,
+            in_flight: 0,
+            multiplier: 1.0,
+        }
+    }
+
+    /// Landing rate over the current window, `None` until an outcome was recorded.
+    pub fn landing_rate(&self) -> Option<f64> {
+        if self.outcomes.is_empty() {
+            return None;
+        }
+
+        let landed = self
+            .outcomes
+            .iter()
+            .filter(|outcome| outcome.landed)
+            .count();
+        Some(landed as f64 / self.outcomes.len() as f64)
+    }
+
+    pub fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    pub fn landed(&self) -> u64 {
+        self.landed
+    }
+
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight
+    }
+
+    pub fn multiplier(&self) -> f64 {
+        self.multiplier
+    }
+
+    /// Priority fees paid on the landed txs in the current window
+    pub fn fees_spent_lamports(&self) -> u64 {
+        self.outcomes
+            .iter()
+            .map(|outcome| outcome.fee_lamports)
+            .sum()
+    }
+
+    /// Rewards the landed txs in the current window were expected to earn
+    pub fn expected_rewards_lamports(&self) -> u64 {
+        self.outcomes
+            .iter()
+            .map(|outcome| outcome.expected_reward_lamports)
+            .sum()
+    }
+
+    /// Returns true if priority fees paid on landed txs in the current window exceed the rewards
+    /// those txs were expected to earn.
+    pub fn is_spend_above_rewards(&self) -> bool {
+        self.fees_spent_lamports() > self.expected_rewards_lamports()
+    }
+}
+
+/// Closed loop controller that scales the priority fee per writable market so that
+/// the share of our own txs that land approaches `target_landing_rate`.
+///
+/// The multiplier is raised while txs are being dropped and lowered while more txs land than needed.
+/// Raising is suspended and the multiplier backs off once fees spent exceed the expected fill rewards.
+#[derive(Debug, Clone)]
+pub struct LandingRateFeeController {
+    markets: HashMap<Pubkey, MarketLandingStats>,
+    target_landing_rate: f64,
+    window_size: usize,
+    min_samples: usize,
+    multiplier_step: f64,
+    min_multiplier: f64,
+    max_multiplier: f64,
+    max_priority_fee_micro_lamports: Option<u64>,
+}
+
+impl LandingRateFeeController {
+    pub fn new(config: LandingRateFeeControllerConfig) -> Self {
+        let window_size = config
+            .window_size
+            .unwrap_or(DEFAULT_LANDING_RATE_WINDOW)
+            .max(1);
+
+        Self {
+            markets: HashMap::new(),
+            target_landing_rate: config
+                .target_landing_rate
+                .unwrap_or(DEFAULT_TARGET_LANDING_RATE)
+                .clamp(0.0, 1.0),
+            window_size,
+            min_samples: config
+                .min_samples
+                .unwrap_or(DEFAULT_MIN_SAMPLES)
+                .clamp(1, window_size),
+            multiplier_step: config.multiplier_step.unwrap_or(DEFAULT_MULTIPLIER_STEP),
+            min_multiplier: config.min_multiplier.unwrap_or(DEFAULT_MIN_MULTIPLIER),
+            max_multiplier: config.max_multiplier.unwrap_or(DEFAULT_MAX_MULTIPLIER),
+            max_priority_fee_micro_lamports: config.max_priority_fee_micro_lamports,
+        }
+    }
+
+    pub fn get_market_stats(&self, market: &Pubkey) -> Option<&MarketLandingStats> {
+        self.markets.get(market)
+    }
+
+    /// Current multiplier for a market, 1.0 if nothing was recorded for it yet.
+    pub fn get_multiplier(&self, market: &Pubkey) -> f64 {
+        self.markets
+            .get(market)
+            .map(|stats| stats.multiplier)
+            .unwrap_or(1.0)
+    }
+
+    /// Scales `base_compute_unit_price` by the highest multiplier among the writable markets of a tx,
+    /// bounded by `max_priority_fee_micro_lamports`.
+    pub fn get_compute_unit_price(
+        &self,
+        writable_markets: &[Pubkey],
+        base_compute_unit_price: u64,
+    ) -> u64 {
+        let multiplier = writable_markets
+            .iter()
+            .map(|market| self.get_multiplier(market))
+            .fold(None, |max: Option<f64>, m| {
+                Some(max.map_or(m, |max| max.max(m)))
+            })
+            .unwrap_or(1.0);
+
+        let price = (base_compute_unit_price as f64 * multiplier).round() as u64;
+
+        match self.max_priority_fee_micro_lamports {
+            Some(max_fee) => price.min(max_fee),
+            None => price,
+        }
+    }
+
+    pub fn record_sent(&mut self, writable_markets: &[Pubkey]) {
+        for market in writable_markets {
+            let stats = self
+                .markets
+                .entry(*market)
+                .or_insert_with(MarketLandingStats::new);
+            stats.sent += 1;
+            stats.in_flight += 1;
+        }
+    }
+
+    /// Records a landed tx. Fee and expected reward are split evenly across the writable markets.
+    pub fn record_landed(
+        &mut self,
+        writable_markets: &[Pubkey],
+        priority_fee_lamports: u64,
+        expected_reward_lamports: u64,
+    ) {
+        if writable_markets.is_empty() {
+            return;
+        }
+
+        let num_markets = writable_markets.len() as u64;
+        self.record_outcome(
+            writable_markets,
+            TxOutcome {
+                landed: true,
+                fee_lamports: priority_fee_lamports / num_markets,
+                expected_reward_lamports: expected_reward_lamports / num_markets,
+            },
+        );
+    }
+
+    /// Records a tx that timed out without landing.
+    pub fn record_dropped(&mut self, writable_markets: &[Pubkey]) {
+        self.record_outcome(
+            writable_markets,
+            TxOutcome {
+                landed: false,
+                fee_lamports: 0,
+                expected_reward_lamports: 0,
+            },
+        );
+    }
+
+    /// Records a tx that is no longer tracked before its outcome was known, it only leaves the
+    /// in flight count and does not affect the landing rate.
+    pub fn record_untracked(&mut self, writable_markets: &[Pubkey]) {
+        for market in writable_markets {
+            if let Some(stats) = self.markets.get_mut(market) {
+                stats.in_flight = stats.in_flight.saturating_sub(1);
+            }
+        }
+    }
+
+    fn record_outcome(&mut self, writable_markets: &[Pubkey], outcome: TxOutcome) {
+        for market in writable_markets {
+            let stats = self
+                .markets
+                .entry(*market)
+                .or_insert_with(MarketLandingStats::new);
+            if outcome.landed {
+                stats.landed += 1;
+            }
+            stats.in_flight = stats.in_flight.saturating_sub(1);
+            stats.outcomes.push_back(outcome);
+            while stats.outcomes.len() > self.window_size {
+                stats.outcomes.pop_front();
+            }
+        }
+
+        for market in writable_markets {
+            self.adjust_multiplier(market);
+        }
+    }
+
+    fn adjust_multiplier(&mut self, market: &Pubkey) {
+        let (target, min_samples, step) = (
+            self.target_landing_rate,
+            self.min_samples,
+            self.multiplier_step,
+        );
+        let (min_multiplier, max_multiplier) = (self.min_multiplier, self.max_multiplier);
+
+        let stats = match self.markets.get_mut(market) {
+            Some(stats) => stats,
+            None => return,
+        };
+        if stats.outcomes.len() < min_samples {
+            return;
+        }
+        let landing_rate = match stats.landing_rate() {
+            Some(landing_rate) => landing_rate,
+            None => return,
+        };
+
+        if stats.is_spend_above_rewards() {
+            stats.multiplier *= 1.0 - step;
+        } else if landing_rate < target - LANDING_RATE_TOLERANCE {
+            stats.multiplier *= 1.0 + step;
+        } else if landing_rate > target + LANDING_RATE_TOLERANCE {
+            stats.multiplier *= 1.0 - step;
+        }
+
+        stats.multiplier = stats.multiplier.clamp(min_multiplier, max_multiplier);
+    }
+}
+
+/// Converts a compute unit price (micro lamports per CU) and limit into the priority fee paid in lamports.
+pub fn priority_fee_lamports(compute_unit_price: u64, compute_unit_limit: u32) -> u64 {
+    compute_unit_price.saturating_mul(compute_unit_limit as u64) / MICRO_LAMPORTS_PER_LAMPORT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controller() -> LandingRateFeeController {
+        LandingRateFeeController::new(LandingRateFeeControllerConfig {
+            target_landing_rate: Some(0.8),
+            window_size: Some(10),
+            min_samples: Some(5),
+            multiplier_step: Some(0.1),
+            min_multiplier: Some(0.5),
+            max_multiplier: Some(3.0),
+            max_priority_fee_micro_lamports: Some(50_000),
+        })
+    }
+
+    fn land_txs(controller: &mut LandingRateFeeController, market: &Pubkey, n: usize) {
+        for _ in 0..n {
+            controller.record_sent(&[*market]);
+            controller.record_landed(&[*market], 0, 1_000);
+        }
+    }
+
+    fn drop_txs(controller: &mut LandingRateFeeController, market: &Pubkey, n: usize) {
+        for _ in 0..n {
+            controller.record_sent(&[*market]);
+            controller.record_dropped(&[*market]);
+        }
+    }
+
+    #[test]
+    fn test_no_adjustment_before_min_samples() {
+        let mut controller = controller();
+        let market = Pubkey::new_unique();
+
+        drop_txs(&mut controller, &market, 4);
+
+        assert_eq!(controller.get_multiplier(&market), 1.0);
+        let stats = controller.get_market_stats(&market).unwrap();
+        assert_eq!(stats.sent(), 4);
+        assert_eq!(stats.landed(), 0);
+        assert_eq!(stats.in_flight(), 0);
+        assert_eq!(stats.landing_rate(), Some(0.0));
+    }
+
+    #[test]
+    fn test_multiplier_increases_when_dropping() {
+        let mut controller = controller();
+        let market = Pubkey::new_unique();
+
+        drop_txs(&mut controller, &market, 5);
+        assert!((controller.get_multiplier(&market) - 1.1).abs() < 1e-9);
+
+        drop_txs(&mut controller, &market, 100);
+        assert_eq!(controller.get_multiplier(&market), 3.0);
+    }
+
+    #[test]
+    fn test_multiplier_decreases_when_all_land() {
+        let mut controller = controller();
+        let market = Pubkey::new_unique();
+
+        land_txs(&mut controller, &market, 5);
+        assert!((controller.get_multiplier(&market) - 0.9).abs() < 1e-9);
+
+        land_txs(&mut controller, &market, 100);
+        assert_eq!(controller.get_multiplier(&market), 0.5);
+    }
+
+    #[test]
+    fn test_multiplier_holds_at_target() {
+        let mut controller = controller();
+        let market = Pubkey::new_unique();
+
+        // 8 of 10 land -> exactly on target
+        land_txs(&mut controller, &market, 4);
+        drop_txs(&mut controller, &market, 1);
+        land_txs(&mut controller, &market, 4);
+        let before = controller.get_multiplier(&market);
+        drop_txs(&mut controller, &market, 1);
+
+        let stats = controller.get_market_stats(&market).unwrap();
+        assert_eq!(stats.landing_rate(), Some(0.8));
+        assert_eq!(controller.get_multiplier(&market), before);
+    }
+
+    #[test]
+    fn test_backs_off_when_spend_exceeds_rewards() {
+        let mut controller = controller();
+        let market = Pubkey::new_unique();
+
+        drop_txs(&mut controller, &market, 10);
+        let raised = controller.get_multiplier(&market);
+        assert!(raised > 1.0);
+
+        controller.record_sent(&[market]);
+        controller.record_landed(&[market], 5_000, 1_000);
+
+        let stats = controller.get_market_stats(&market).unwrap();
+        assert!(stats.is_spend_above_rewards());
+        assert!(controller.get_multiplier(&market) < raised);
+    }
+
+    #[test]
+    fn test_spend_only_counts_txs_in_window() {
+        let mut controller = controller();
+        let market = Pubkey::new_unique();
+
+        controller.record_sent(&[market]);
+        controller.record_landed(&[market], 5_000, 1_000);
+        assert!(controller
+            .get_market_stats(&market)
+            .unwrap()
+            .is_spend_above_rewards());
+
+        // backs off while the expensive tx is in the window, window size is 10
+        drop_txs(&mut controller, &market, 9);
+        let backed_off = controller.get_multiplier(&market);
+        assert!(backed_off < 1.0);
+
+        // the expensive tx leaves the window, the drops raise the multiplier again
+        drop_txs(&mut controller, &market, 1);
+
+        let stats = controller.get_market_stats(&market).unwrap();
+        assert_eq!(stats.fees_spent_lamports(), 0);
+        assert_eq!(stats.expected_rewards_lamports(), 0);
+        assert!(!stats.is_spend_above_rewards());
+        assert_eq!(stats.landed(), 1);
+        assert!(controller.get_multiplier(&market) > backed_off);
+    }
+
+    #[test]
+    fn test_markets_are_tracked_independently() {
+        let mut controller = controller();
+        let dropping = Pubkey::new_unique();
+        let landing = Pubkey::new_unique();
+
+        drop_txs(&mut controller, &dropping, 5);
+        land_txs(&mut controller, &landing, 5);
+
+        assert!(controller.get_multiplier(&dropping) > 1.0);
+        assert!(controller.get_multiplier(&landing) < 1.0);
+        assert_eq!(
+            controller.get_compute_unit_price(&[dropping, landing], 1_000),
+            1_100
+        );
+    }
+
+    #[test]
+    fn test_compute_unit_price_bounded_by_max_fee() {
+        let mut controller = controller();
+        let market = Pubkey::new_unique();
+
+        drop_txs(&mut controller, &market, 100);
+
+        assert_eq!(controller.get_compute_unit_price(&[market], 10_000), 30_000);
+        assert_eq!(controller.get_compute_unit_price(&[market], 20_000), 50_000);
+        assert_eq!(controller.get_compute_unit_price(&[], 1_000), 1_000);
+    }
+
+    #[test]
+    fn test_fee_and_reward_split_across_markets() {
+        let mut controller = controller();
+        let market_a = Pubkey::new_unique();
+        let market_b = Pubkey::new_unique();
+
+        controller.record_sent(&[market_a, market_b]);
+        controller.record_landed(&[market_a, market_b], 1_000, 4_000);
+
+        let stats = controller.get_market_stats(&market_a).unwrap();
+        assert_eq!(stats.fees_spent_lamports(), 500);
+        assert_eq!(stats.expected_rewards_lamports(), 2_000);
+    }
+
+    #[test]
+    fn test_untracked_tx_leaves_in_flight() {
+        let mut controller = controller();
+        let market = Pubkey::new_unique();
+
+        controller.record_sent(&[market]);
+        controller.record_sent(&[market]);
+        controller.record_untracked(&[market]);
+
+        let stats = controller.get_market_stats(&market).unwrap();
+        assert_eq!(stats.in_flight(), 1);
+        assert_eq!(stats.sent(), 2);
+        assert_eq!(stats.landing_rate(), None);
+    }
+
+    #[test]
+    fn test_priority_fee_lamports() {
+        assert_eq!(priority_fee_lamports(1_000, 1_400_000), 1_400);
+        assert_eq!(priority_fee_lamports(0, 1_400_000), 0);
+        assert_eq!(priority_fee_lamports(1, 200_000), 0);
+    }
+}
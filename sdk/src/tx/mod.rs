@@ -1 +1,5 @@
+pub mod landing_rate_fee_controller;
 pub mod priority_fee_calculator;
+pub mod token_instructions;
+pub mod tx_confirmer;
+pub mod tx_packer;
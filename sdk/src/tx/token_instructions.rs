@@ -0,0 +1,93 @@
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_program,
+};
+
+use crate::constants::{
+    derive_associated_token_account, ASSOCIATED_TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID,
+};
+
+/// `AssociatedTokenAccountInstruction::CreateIdempotent`
+const CREATE_IDEMPOTENT_TAG: u8 = 1;
+/// `TokenInstruction::CloseAccount`
+const CLOSE_ACCOUNT_TAG: u8 = 9;
+/// `TokenInstruction::SyncNative`
+const SYNC_NATIVE_TAG: u8 = 17;
+
+/// Create the associated token account of `owner` for `mint`, no-op if it already exists
+pub fn create_associated_token_account_idempotent(
+    payer: &Pubkey,
+    owner: &Pubkey,
+    mint: &Pubkey,
+) -> Instruction {
+    let associated_token_account = derive_associated_token_account(owner, mint);
+
+    Instruction {
+        program_id: ASSOCIATED_TOKEN_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(associated_token_account, false),
+            AccountMeta::new_readonly(*owner, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(system_program::ID, false),
+            AccountMeta::new_readonly(TOKEN_PROGRAM_ID, false),
+        ],
+        data: vec![CREATE_IDEMPOTENT_TAG],
+    }
+}
+
+/// Sync the token balance of a wrapped SOL account with its lamports
+pub fn sync_native(token_account: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: TOKEN_PROGRAM_ID,
+        accounts: vec![AccountMeta::new(*token_account, false)],
+        data: vec![SYNC_NATIVE_TAG],
+    }
+}
+
+/// Close `token_account`, sending its lamports to `destination`
+pub fn close_account(token_account: &Pubkey, destination: &Pubkey, owner: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: TOKEN_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(*token_account, false),
+            AccountMeta::new(*destination, false),
+            AccountMeta::new_readonly(*owner, true),
+        ],
+        data: vec![CLOSE_ACCOUNT_TAG],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::WRAPPED_SOL_MINT;
+
+    #[test]
+    fn test_create_associated_token_account_idempotent() {
+        let payer = Pubkey::new_unique();
+        let ix = create_associated_token_account_idempotent(&payer, &payer, &WRAPPED_SOL_MINT);
+
+        assert_eq!(ix.program_id, ASSOCIATED_TOKEN_PROGRAM_ID);
+        assert_eq!(ix.data, vec![1]);
+        assert_eq!(
+            ix.accounts[1].pubkey,
+            derive_associated_token_account(&payer, &WRAPPED_SOL_MINT)
+        );
+        assert!(ix.accounts[0].is_signer && ix.accounts[0].is_writable);
+        assert!(ix.accounts[1].is_writable);
+    }
+
+    #[test]
+    fn test_close_account_requires_owner_signature() {
+        let account = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let ix = close_account(&account, &owner, &owner);
+
+        assert_eq!(ix.data, vec![9]);
+        assert!(ix.accounts[2].is_signer);
+        assert!(!ix.accounts[2].is_writable);
+        assert_eq!(sync_native(&account).data, vec![17]);
+    }
+}
@@ -0,0 +1,630 @@
+//! Confirms sent txs through `signatureSubscribe`, with batched `getSignatureStatuses` polling as fallback
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures_util::StreamExt;
+use log::{debug, warn};
+use solana_client::{
+    nonblocking::{pubsub_client::PubsubClient, rpc_client::RpcClient},
+    rpc_config::{RpcSignatureSubscribeConfig, RpcTransactionConfig},
+    rpc_response::RpcSignatureResult,
+};
+use solana_sdk::{
+    commitment_config::CommitmentConfig, signature::Signature, transaction::TransactionError,
+};
+use solana_transaction_status::{
+    option_serializer::OptionSerializer, TransactionStatus, UiTransactionEncoding,
+};
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+};
+
+use crate::{blockhash_subscriber::BlockhashSubscriber, SdkResult};
+
+/// max signatures accepted by a single `getSignatureStatuses` request
+pub const MAX_SIGNATURE_STATUSES_PER_REQUEST: usize = 256;
+pub const DEFAULT_STATUS_POLL_INTERVAL_MS: u64 = 2_000;
+pub const DEFAULT_RATE_LIMIT_BACKOFF_MS: u64 = 5_000;
+pub const DEFAULT_SIGNATURE_SUBSCRIPTION_TIMEOUT_MS: u64 = 90_000;
+pub const DEFAULT_MAX_PENDING_TXS: usize = 10_000;
+
+#[derive(Debug, Clone, Default)]
+pub struct TxConfirmerConfig {
+    /// commitment a tx must reach to be reported landed or failed, default: confirmed
+    pub commitment: Option<CommitmentConfig>,
+    /// how often (ms) pending txs are checked with `getSignatureStatuses`, default: 2000
+    pub status_poll_interval_ms: Option<u64>,
+    /// pause (ms) status polling after the rpc rate limited us, default: 5000
+    pub rate_limit_backoff_ms: Option<u64>,
+    /// give up (ms) on a tx's signature subscription and leave it to polling, default: 90000
+    pub signature_subscription_timeout_ms: Option<u64>,
+    /// fetch the logs of landed and failed txs, default: true
+    pub fetch_logs: Option<bool>,
+    /// txs tracked at once, the oldest is evicted beyond this, default: 10000
+    pub max_pending_txs: Option<usize>,
+}
+
+/// Final state of a tracked tx
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxOutcome {
+    /// executed successfully in `slot`
+    Landed {
+        slot: u64,
+        logs: Option<Vec<String>>,
+    },
+    /// included in `slot`, but execution failed
+    Failed {
+        slot: u64,
+        error: TransactionError,
+        logs: Option<Vec<String>>,
+    },
+    /// not seen before its blockhash expired, it can no longer land
+    Dropped,
+    /// no longer tracked before its outcome was known, either evicted by newer txs or replaced by
+    /// another `track` of the same signature
+    Evicted,
+}
+
+impl TxOutcome {
+    fn with_logs(self, tx_logs: Option<Vec<String>>) -> Self {
+        match self {
+            TxOutcome::Landed { slot, .. } => TxOutcome::Landed {
+                slot,
+                logs: tx_logs,
+            },
+            TxOutcome::Failed { slot, error, .. } => TxOutcome::Failed {
+                slot,
+                error,
+                logs: tx_logs,
+            },
+            TxOutcome::Dropped => TxOutcome::Dropped,
+            TxOutcome::Evicted => TxOutcome::Evicted,
+        }
+    }
+}
+
+/// Outcome of a tracked tx, with the metadata it was tracked with
+#[derive(Debug, Clone)]
+pub struct ConfirmedTx<M> {
+    pub signature: Signature,
+    pub metadata: M,
+    pub outcome: TxOutcome,
+    /// time between `track` and the outcome
+    pub elapsed: Duration,
+}
+
+struct PendingTx<M> {
+    metadata: M,
+    last_valid_block_height: u64,
+    tracked_at: Instant,
+}
+
+/// Txs waiting for an outcome, each one is claimed by whichever source resolves it first
+struct PendingTxs<M> {
+    txs: HashMap<Signature, PendingTx<M>>,
+    max_len: usize,
+}
+
+impl<M> PendingTxs<M> {
+    fn new(max_len: usize) -> Self {
+        Self {
+            txs: HashMap::new(),
+            max_len,
+        }
+    }
+
+    /// Returns the txs that are no longer tracked: a replaced tx with the same signature and the
+    /// oldest txs beyond `max_len`
+    fn insert(
+        &mut self,
+        signature: Signature,
+        last_valid_block_height: u64,
+        metadata: M,
+    ) -> Vec<(Signature, PendingTx<M>)> {
+        let mut evicted = Vec::new();
+        let replaced = self.txs.insert(
+            signature,
+            PendingTx {
+                metadata,
+                last_valid_block_height,
+                tracked_at: Instant::now(),
+            },
+        );
+        if let Some(replaced) = replaced {
+            evicted.push((signature, replaced));
+        }
+
+        while self.txs.len() > self.max_len {
+            let oldest = self
+                .txs
+                .iter()
+                .filter(|(pending_signature, _)| **pending_signature != signature)
+                .min_by_key(|(_, tx)| tx.tracked_at)
+                .map(|(pending_signature, _)| *pending_signature);
+            match oldest.and_then(|oldest| self.txs.remove_entry(&oldest)) {
+                Some(oldest) => evicted.push(oldest),
+                None => break,
+            }
+        }
+
+        evicted
+    }
+
+    /// Removes `signature`, `None` if it was already resolved
+    fn claim(&mut self, signature: &Signature) -> Option<PendingTx<M>> {
+        self.txs.remove(signature)
+    }
+
+    /// Whether the blockhash of `signature` can no longer be used at `current_block_height`
+    fn is_expired(&self, signature: &Signature, current_block_height: u64) -> bool {
+        self.txs
+            .get(signature)
+            .map(|tx| current_block_height > tx.last_valid_block_height)
+            .unwrap_or(false)
+    }
+
+    fn signatures(&self) -> Vec<Signature> {
+        self.txs.keys().copied().collect()
+    }
+
+    fn len(&self) -> usize {
+        self.txs.len()
+    }
+}
+
+/// Landed or failed outcome of a status that reached the required commitment
+fn outcome_from_status(status: &TransactionStatus) -> TxOutcome {
+    match &status.err {
+        Some(error) => TxOutcome::Failed {
+            slot: status.slot,
+            error: error.clone(),
+            logs: None,
+        },
+        None => TxOutcome::Landed {
+            slot: status.slot,
+            logs: None,
+        },
+    }
+}
+
+struct TxConfirmerState<M> {
+    pending: Mutex<PendingTxs<M>>,
+    rpc_client: Arc<RpcClient>,
+    blockhash_subscriber: BlockhashSubscriber,
+    outcome_tx: UnboundedSender<ConfirmedTx<M>>,
+    commitment: CommitmentConfig,
+    fetch_logs: bool,
+}
+
+impl<M> TxConfirmerState<M> {
+    /// Delivers `outcome` for `signature` unless it was already resolved
+    async fn resolve(&self, signature: &Signature, outcome: TxOutcome) {
+        let pending_tx = match self.pending.lock().unwrap().claim(signature) {
+            Some(pending_tx) => pending_tx,
+            None => return,
+        };
+
+        let outcome = if self.fetch_logs && !matches!(outcome, TxOutcome::Dropped) {
+            outcome.with_logs(self.get_logs(signature).await)
+        } else {
+            outcome
+        };
+
+        let _ = self.outcome_tx.send(ConfirmedTx {
+            signature: *signature,
+            metadata: pending_tx.metadata,
+            outcome,
+            elapsed: pending_tx.tracked_at.elapsed(),
+        });
+    }
+
+    async fn get_logs(&self, signature: &Signature) -> Option<Vec<String>> {
+        // `getTransaction` does not support processed commitment
+        let commitment = if self.commitment.is_at_least_confirmed() {
+            self.commitment
+        } else {
+            CommitmentConfig::confirmed()
+        };
+        let config = RpcTransactionConfig {
+            encoding: Some(UiTransactionEncoding::Json),
+            commitment: Some(commitment),
+            max_supported_transaction_version: Some(0),
+        };
+
+        match self
+            .rpc_client
+            .get_transaction_with_config(signature, config)
+            .await
+        {
+            Ok(tx) => match tx.transaction.meta.map(|meta| meta.log_messages) {
+                Some(OptionSerializer::Some(logs)) => Some(logs),
+                _ => None,
+            },
+            Err(e) => {
+                warn!("failed to fetch logs of tx {signature}: {e}");
+                None
+            }
+        }
+    }
+
+    /// Resolves pending txs whose status reached the commitment, and those not found after their
+    /// blockhash expired
+    ///
+    /// A failed batch is retried on the next poll, only a rate limit error stops the poll.
+    async fn poll_statuses(&self) -> SdkResult<()> {
+        let signatures = self.pending.lock().unwrap().signatures();
+        if signatures.is_empty() {
+            return Ok(());
+        }
+
+        // read before the statuses, so a tx missing from them can not land after this height anymore
+        let current_block_height = self.blockhash_subscriber.get_latest_block_height().await;
+
+        for batch in signatures.chunks(MAX_SIGNATURE_STATUSES_PER_REQUEST) {
+            let statuses = match self.rpc_client.get_signature_statuses(batch).await {
+                Ok(response) => response.value,
+                // stop polling so the caller backs off
+                Err(e) if e.to_string().contains("429") => return Err(e.into()),
+                Err(e) => {
+                    warn!(
+                        "failed to poll statuses of {} signatures, polling the remaining batches: {e}",
+                        batch.len()
+                    );
+                    continue;
+                }
+            };
+            for (signature, status) in batch.iter().zip(statuses) {
+                match status {
+                    Some(status) if status.satisfies_commitment(self.commitment) => {
+                        self.resolve(signature, outcome_from_status(&status)).await;
+                    }
+                    // seen, but not at the required commitment yet
+                    Some(_) => {}
+                    None => {
+                        let is_expired = self
+                            .pending
+                            .lock()
+                            .unwrap()
+                            .is_expired(signature, current_block_height);
+                        if is_expired {
+                            self.resolve(signature, TxOutcome::Dropped).await;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Waits for the outcome of `signature` on a `signatureSubscribe` subscription
+async fn watch_signature<M>(
+    pubsub_client: &PubsubClient,
+    state: &TxConfirmerState<M>,
+    signature: Signature,
+    timeout: Duration,
+) -> SdkResult<()> {
+    let config = RpcSignatureSubscribeConfig {
+        commitment: Some(state.commitment),
+        enable_received_notification: Some(false),
+    };
+    let (mut notifications, unsubscriber) = pubsub_client
+        .signature_subscribe(&signature, Some(config))
+        .await?;
+
+    let notification = tokio::time::timeout(timeout, notifications.next()).await;
+    unsubscriber().await;
+
+    if let Ok(Some(response)) = notification {
+        if let RpcSignatureResult::ProcessedSignature(result) = response.value {
+            let slot = response.context.slot;
+            let outcome = match result.err {
+                Some(error) => TxOutcome::Failed {
+                    slot,
+                    error,
+                    logs: None,
+                },
+                None => TxOutcome::Landed { slot, logs: None },
+            };
+            state.resolve(&signature, outcome).await;
+        }
+    }
+
+    Ok(())
+}
+
+/// Tracks sent txs until they land, fail or expire, and delivers each outcome once on a channel.
+///
+/// Every tracked tx gets its own `signatureSubscribe` subscription when a pubsub client is given.
+/// Pending txs are also polled with batched `getSignatureStatuses`, which catches txs whose
+/// subscription failed or was missed, and expires those not found once the block height from
+/// `blockhash_subscriber` passed their last valid block height. The blockhash subscriber must be
+/// subscribed for txs to expire.
+pub struct TxConfirmer<M> {
+    state: Arc<TxConfirmerState<M>>,
+    pubsub_client: Option<Arc<PubsubClient>>,
+    status_poll_interval: Duration,
+    rate_limit_backoff: Duration,
+    signature_subscription_timeout: Duration,
+    poll_task: Option<JoinHandle<()>>,
+}
+
+impl<M> TxConfirmer<M>
+where
+    M: Send + 'static,
+{
+    /// Returns the confirmer and the receiver of tx outcomes
+    pub fn new(
+        rpc_client: Arc<RpcClient>,
+        pubsub_client: Option<Arc<PubsubClient>>,
+        blockhash_subscriber: BlockhashSubscriber,
+        config: TxConfirmerConfig,
+    ) -> (Self, UnboundedReceiver<ConfirmedTx<M>>) {
+        let (outcome_tx, outcome_rx) = mpsc::unbounded_channel();
+
+        let confirmer = Self {
+            state: Arc::new(TxConfirmerState {
+                pending: Mutex::new(PendingTxs::new(
+                    config.max_pending_txs.unwrap_or(DEFAULT_MAX_PENDING_TXS),
+                )),
+                rpc_client,
+                blockhash_subscriber,
+                outcome_tx,
+                commitment: config.commitment.unwrap_or(CommitmentConfig::confirmed()),
+                fetch_logs: config.fetch_logs.unwrap_or(true),
+            }),
+            pubsub_client,
+            status_poll_interval: Duration::from_millis(
+                config
+                    .status_poll_interval_ms
+                    .unwrap_or(DEFAULT_STATUS_POLL_INTERVAL_MS),
+            ),
+            rate_limit_backoff: Duration::from_millis(
+                config
+                    .rate_limit_backoff_ms
+                    .unwrap_or(DEFAULT_RATE_LIMIT_BACKOFF_MS),
+            ),
+            signature_subscription_timeout: Duration::from_millis(
+                config
+                    .signature_subscription_timeout_ms
+                    .unwrap_or(DEFAULT_SIGNATURE_SUBSCRIPTION_TIMEOUT_MS),
+            ),
+            poll_task: None,
+        };
+
+        (confirmer, outcome_rx)
+    }
+
+    /// Starts polling the statuses of pending txs
+    pub fn subscribe(&mut self) {
+        if self.poll_task.is_some() {
+            return;
+        }
+
+        let state = self.state.clone();
+        let status_poll_interval = self.status_poll_interval;
+        let rate_limit_backoff = self.rate_limit_backoff;
+        self.poll_task = Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(status_poll_interval);
+            loop {
+                interval.tick().await;
+                if let Err(e) = state.poll_statuses().await {
+                    if e.to_string().contains("429") {
+                        warn!(
+                            "tx confirmer rate limited, pausing for {} ms",
+                            rate_limit_backoff.as_millis()
+                        );
+                        tokio::time::sleep(rate_limit_backoff).await;
+                    } else {
+                        warn!("failed to poll signature statuses: {e}");
+                    }
+                }
+            }
+        }));
+    }
+
+    pub fn unsubscribe(&mut self) {
+        if let Some(poll_task) = self.poll_task.take() {
+            poll_task.abort();
+        }
+    }
+
+    /// Tracks a sent tx until its outcome is known, `metadata` is returned with the outcome
+    ///
+    /// `last_valid_block_height` is the last block height the tx's blockhash is valid for. Txs
+    /// that are no longer tracked because of this one are delivered as `TxOutcome::Evicted`.
+    pub fn track(&self, signature: Signature, last_valid_block_height: u64, metadata: M) {
+        let evicted =
+            self.state
+                .pending
+                .lock()
+                .unwrap()
+                .insert(signature, last_valid_block_height, metadata);
+        for (evicted_signature, evicted_tx) in evicted {
+            warn!("tx {evicted_signature} evicted before its outcome was known");
+            let _ = self.state.outcome_tx.send(ConfirmedTx {
+                signature: evicted_signature,
+                metadata: evicted_tx.metadata,
+                outcome: TxOutcome::Evicted,
+                elapsed: evicted_tx.tracked_at.elapsed(),
+            });
+        }
+
+        if let Some(pubsub_client) = &self.pubsub_client {
+            let pubsub_client = pubsub_client.clone();
+            let state = self.state.clone();
+            let timeout = self.signature_subscription_timeout;
+            tokio::spawn(async move {
+                if let Err(e) = watch_signature(&pubsub_client, &state, signature, timeout).await {
+                    debug!("signature subscription for {signature} failed, polling instead: {e}");
+                }
+            });
+        }
+    }
+
+    /// Number of tracked txs without an outcome yet
+    pub fn pending_count(&self) -> usize {
+        self.state.pending.lock().unwrap().len()
+    }
+}
+
+impl<M> Drop for TxConfirmer<M> {
+    fn drop(&mut self) {
+        if let Some(poll_task) = self.poll_task.take() {
+            poll_task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::instruction::InstructionError;
+    use solana_transaction_status::TransactionConfirmationStatus;
+
+    use super::*;
+
+    fn status(err: Option<TransactionError>) -> TransactionStatus {
+        TransactionStatus {
+            slot: 42,
+            confirmations: None,
+            status: match &err {
+                Some(err) => Err(err.clone()),
+                None => Ok(()),
+            },
+            err,
+            confirmation_status: Some(TransactionConfirmationStatus::Confirmed),
+        }
+    }
+
+    #[test]
+    fn outcome_from_status_splits_landed_and_failed() {
+        assert_eq!(
+            outcome_from_status(&status(None)),
+            TxOutcome::Landed {
+                slot: 42,
+                logs: None
+            }
+        );
+
+        let error = TransactionError::InstructionError(1, InstructionError::Custom(6000));
+        assert_eq!(
+            outcome_from_status(&status(Some(error.clone()))),
+            TxOutcome::Failed {
+                slot: 42,
+                error,
+                logs: None
+            }
+        );
+    }
+
+    #[test]
+    fn pending_txs_expire_after_last_valid_block_height() {
+        let signature = Signature::new_unique();
+        let mut pending = PendingTxs::new(DEFAULT_MAX_PENDING_TXS);
+        pending.insert(signature, 100, ());
+
+        assert!(!pending.is_expired(&signature, 99));
+        assert!(!pending.is_expired(&signature, 100));
+        assert!(pending.is_expired(&signature, 101));
+        assert!(!pending.is_expired(&Signature::new_unique(), 101));
+    }
+
+    #[test]
+    fn pending_tx_is_claimed_once() {
+        let signature = Signature::new_unique();
+        let mut pending = PendingTxs::new(DEFAULT_MAX_PENDING_TXS);
+        pending.insert(signature, 100, 7_u16);
+
+        assert_eq!(pending.claim(&signature).map(|tx| tx.metadata), Some(7));
+        assert!(pending.claim(&signature).is_none());
+        assert_eq!(pending.len(), 0);
+    }
+
+    #[test]
+    fn pending_txs_evict_replaced_and_oldest() {
+        let mut pending = PendingTxs::new(2);
+        let (first, second, third) = (
+            Signature::new_unique(),
+            Signature::new_unique(),
+            Signature::new_unique(),
+        );
+        assert!(pending.insert(first, 100, 1_u16).is_empty());
+        assert!(pending.insert(second, 100, 2).is_empty());
+
+        let replaced = pending.insert(second, 100, 3);
+        assert_eq!(replaced.len(), 1);
+        assert_eq!((replaced[0].0, replaced[0].1.metadata), (second, 2));
+
+        let evicted = pending.insert(third, 100, 4);
+        assert_eq!(evicted.len(), 1);
+        assert_eq!((evicted[0].0, evicted[0].1.metadata), (first, 1));
+        assert_eq!(pending.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn evicted_tx_is_delivered() {
+        let endpoint = "http://localhost:8899".to_string();
+        let (confirmer, mut outcomes) = TxConfirmer::new(
+            Arc::new(RpcClient::new(endpoint.clone())),
+            None,
+            BlockhashSubscriber::new(1_000, endpoint),
+            TxConfirmerConfig {
+                fetch_logs: Some(false),
+                max_pending_txs: Some(1),
+                ..Default::default()
+            },
+        );
+
+        let (first, second) = (Signature::new_unique(), Signature::new_unique());
+        confirmer.track(first, 100, "first");
+        confirmer.track(second, 100, "second");
+        assert_eq!(confirmer.pending_count(), 1);
+
+        let evicted = outcomes.recv().await.expect("outcome");
+        assert_eq!(evicted.signature, first);
+        assert_eq!(evicted.metadata, "first");
+        assert_eq!(evicted.outcome, TxOutcome::Evicted);
+        assert!(outcomes.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn outcome_is_delivered_once() {
+        let endpoint = "http://localhost:8899".to_string();
+        let (confirmer, mut outcomes) = TxConfirmer::new(
+            Arc::new(RpcClient::new(endpoint.clone())),
+            None,
+            BlockhashSubscriber::new(1_000, endpoint),
+            TxConfirmerConfig {
+                fetch_logs: Some(false),
+                ..Default::default()
+            },
+        );
+
+        let signature = Signature::new_unique();
+        confirmer.track(signature, 100, "fill");
+        assert_eq!(confirmer.pending_count(), 1);
+
+        // e.g. the subscription and the status poll both see the tx
+        let landed = TxOutcome::Landed {
+            slot: 42,
+            logs: None,
+        };
+        confirmer.state.resolve(&signature, landed.clone()).await;
+        confirmer
+            .state
+            .resolve(&signature, TxOutcome::Dropped)
+            .await;
+
+        let confirmed = outcomes.recv().await.expect("outcome");
+        assert_eq!(confirmed.signature, signature);
+        assert_eq!(confirmed.metadata, "fill");
+        assert_eq!(confirmed.outcome, landed);
+        assert!(outcomes.try_recv().is_err());
+        assert_eq!(confirmer.pending_count(), 0);
+    }
+}
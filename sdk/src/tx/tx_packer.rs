@@ -0,0 +1,656 @@
+//! Packs instruction groups into v0 transactions under size, account and compute budgets
+//!
+//! Sizes are computed exactly as `v0::Message::try_compile` would lay the message out, including
+//! address lookup table compression, so packed txs never have to be serialized to be measured.
+
+use std::collections::HashMap;
+
+use solana_sdk::{
+    address_lookup_table_account::AddressLookupTableAccount,
+    hash::Hash,
+    instruction::Instruction,
+    message::{v0, VersionedMessage},
+    packet::PACKET_DATA_SIZE,
+    pubkey::Pubkey,
+};
+
+use crate::{SdkError, SdkResult};
+
+/// max accounts a tx can lock
+pub const DEFAULT_MAX_ACCOUNTS_PER_TX: usize = 64;
+pub const DEFAULT_MAX_COMPUTE_UNITS_PER_TX: u32 = 1_400_000;
+/// groups searched exhaustively by `PackStrategy::Optimal`, the rest are added greedily
+const MAX_OPTIMAL_GROUPS: usize = 16;
+
+const SIGNATURE_SIZE: usize = 64;
+const PUBKEY_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Default)]
+pub struct TxPackerConfig {
+    /// serialized tx size limit, default: `PACKET_DATA_SIZE` (1232)
+    pub max_tx_size: Option<usize>,
+    /// static plus lookup table accounts, default: 64
+    pub max_accounts: Option<usize>,
+    /// default: 1_400_000
+    pub max_compute_units: Option<u32>,
+}
+
+/// Instructions that must land in the same tx, e.g. a fill and its setup
+#[derive(Debug, Clone)]
+pub struct InstructionGroup {
+    pub instructions: Vec<Instruction>,
+    pub compute_units: u32,
+}
+
+impl InstructionGroup {
+    pub fn new(instructions: Vec<Instruction>, compute_units: u32) -> Self {
+        Self {
+            instructions,
+            compute_units,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackStrategy {
+    /// add groups in order, skipping those that no longer fit
+    Greedy,
+    /// fit as many groups as possible, preferring earlier groups on ties
+    Optimal,
+}
+
+/// A tx worth of instruction groups
+#[derive(Debug, Clone)]
+pub struct PackedTx {
+    /// indexes of the packed groups in the input, in input order
+    pub group_indexes: Vec<usize>,
+    /// base instructions followed by the instructions of each packed group
+    pub instructions: Vec<Instruction>,
+    /// serialized tx size (bytes)
+    pub tx_size: usize,
+    pub num_accounts: usize,
+    pub compute_units: u32,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct KeyMeta {
+    is_signer: bool,
+    is_writable: bool,
+    is_invoked: bool,
+}
+
+/// Running size of a v0 tx as instructions are added
+#[derive(Debug, Clone)]
+pub struct TxSizer<'a> {
+    /// first lookup table containing each address
+    table_index: &'a HashMap<Pubkey, usize>,
+    num_tables: usize,
+    keys: HashMap<Pubkey, KeyMeta>,
+    num_instructions: usize,
+    instructions_size: usize,
+}
+
+impl<'a> TxSizer<'a> {
+    fn new(payer: &Pubkey, table_index: &'a HashMap<Pubkey, usize>, num_tables: usize) -> Self {
+        let mut keys = HashMap::new();
+        keys.insert(
+            *payer,
+            KeyMeta {
+                is_signer: true,
+                is_writable: true,
+                is_invoked: false,
+            },
+        );
+
+        Self {
+            table_index,
+            num_tables,
+            keys,
+            num_instructions: 0,
+            instructions_size: 0,
+        }
+    }
+
+    pub fn add_instructions(&mut self, instructions: &[Instruction]) {
+        for ix in instructions {
+            self.keys.entry(ix.program_id).or_default().is_invoked = true;
+            for account in &ix.accounts {
+                let meta = self.keys.entry(account.pubkey).or_default();
+                meta.is_signer |= account.is_signer;
+                meta.is_writable |= account.is_writable;
+            }
+
+            // program id index, account indexes, data
+            self.instructions_size += 1
+                + compact_u16_size(ix.accounts.len())
+                + ix.accounts.len()
+                + compact_u16_size(ix.data.len())
+                + ix.data.len();
+            self.num_instructions += 1;
+        }
+    }
+
+    /// Serialized size of the signed tx
+    pub fn transaction_size(&self) -> usize {
+        let layout = self.layout();
+        let lookups_size: usize = layout
+            .table_lookups
+            .iter()
+            .filter(|(writable, readonly)| *writable + *readonly > 0)
+            .map(|(writable, readonly)| {
+                PUBKEY_SIZE
+                    + compact_u16_size(*writable)
+                    + writable
+                    + compact_u16_size(*readonly)
+                    + readonly
+            })
+            .sum();
+
+        let message_size = 1 // version prefix
+            + 3 // header
+            + compact_u16_size(layout.num_static_keys)
+            + layout.num_static_keys * PUBKEY_SIZE
+            + PUBKEY_SIZE // recent blockhash
+            + compact_u16_size(self.num_instructions)
+            + self.instructions_size
+            + compact_u16_size(layout.num_used_tables())
+            + lookups_size;
+
+        compact_u16_size(layout.num_signers) + layout.num_signers * SIGNATURE_SIZE + message_size
+    }
+
+    /// Accounts the tx references, static and loaded from lookup tables
+    pub fn num_accounts(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn num_signers(&self) -> usize {
+        self.layout().num_signers
+    }
+
+    fn layout(&self) -> MessageLayout {
+        let mut layout = MessageLayout {
+            num_signers: 0,
+            num_static_keys: 0,
+            table_lookups: vec![(0, 0); self.num_tables],
+        };
+
+        for (key, meta) in &self.keys {
+            if meta.is_signer {
+                layout.num_signers += 1;
+            } else if !meta.is_invoked {
+                // only non signer accounts that are not invoked programs can be loaded
+                if let Some(table) = self.table_index.get(key) {
+                    let (writable, readonly) = &mut layout.table_lookups[*table];
+                    if meta.is_writable {
+                        *writable += 1;
+                    } else {
+                        *readonly += 1;
+                    }
+                    continue;
+                }
+            }
+            layout.num_static_keys += 1;
+        }
+
+        layout
+    }
+}
+
+struct MessageLayout {
+    num_signers: usize,
+    num_static_keys: usize,
+    /// (writable, readonly) accounts loaded from each lookup table
+    table_lookups: Vec<(usize, usize)>,
+}
+
+impl MessageLayout {
+    fn num_used_tables(&self) -> usize {
+        self.table_lookups
+            .iter()
+            .filter(|(writable, readonly)| writable + readonly > 0)
+            .count()
+    }
+}
+
+/// Packs instruction groups into as few txs as fit under the configured budgets
+#[derive(Debug, Clone)]
+pub struct TxPacker {
+    payer: Pubkey,
+    lookup_tables: Vec<AddressLookupTableAccount>,
+    table_index: HashMap<Pubkey, usize>,
+    base_instructions: Vec<Instruction>,
+    base_compute_units: u32,
+    max_tx_size: usize,
+    max_accounts: usize,
+    max_compute_units: u32,
+}
+
+impl TxPacker {
+    pub fn new(
+        payer: Pubkey,
+        lookup_tables: Vec<AddressLookupTableAccount>,
+        config: TxPackerConfig,
+    ) -> Self {
+        let mut table_index = HashMap::new();
+        for (index, table) in lookup_tables.iter().enumerate() {
+            for address in &table.addresses {
+                table_index.entry(*address).or_insert(index);
+            }
+        }
+
+        Self {
+            payer,
+            lookup_tables,
+            table_index,
+            base_instructions: vec![],
+            base_compute_units: 0,
+            max_tx_size: config.max_tx_size.unwrap_or(PACKET_DATA_SIZE),
+            max_accounts: config.max_accounts.unwrap_or(DEFAULT_MAX_ACCOUNTS_PER_TX),
+            max_compute_units: config
+                .max_compute_units
+                .unwrap_or(DEFAULT_MAX_COMPUTE_UNITS_PER_TX),
+        }
+    }
+
+    /// Instructions prepended to every tx, e.g. compute budget instructions
+    pub fn base_instructions(mut self, instructions: Vec<Instruction>, compute_units: u32) -> Self {
+        self.base_instructions = instructions;
+        self.base_compute_units = compute_units;
+        self
+    }
+
+    /// Sizer of a tx holding only the base instructions
+    pub fn sizer(&self) -> TxSizer<'_> {
+        let mut sizer = TxSizer::new(&self.payer, &self.table_index, self.lookup_tables.len());
+        sizer.add_instructions(&self.base_instructions);
+        sizer
+    }
+
+    /// Whether a tx measured by `sizer` using `compute_units` (including the base instructions) is
+    /// within budget
+    pub fn fits(&self, sizer: &TxSizer, compute_units: u32) -> bool {
+        sizer.transaction_size() <= self.max_tx_size
+            && sizer.num_accounts() <= self.max_accounts
+            && compute_units <= self.max_compute_units
+    }
+
+    /// Packs a single tx out of `groups`, `None` if no group fits on its own
+    pub fn pack_tx(&self, groups: &[InstructionGroup], strategy: PackStrategy) -> Option<PackedTx> {
+        let candidates: Vec<usize> = (0..groups.len()).collect();
+        self.pack_candidates(groups, &candidates, strategy)
+    }
+
+    /// Packs all `groups` into txs
+    ///
+    /// Returns the txs and the indexes of groups too large to fit in any tx
+    pub fn pack(
+        &self,
+        groups: &[InstructionGroup],
+        strategy: PackStrategy,
+    ) -> (Vec<PackedTx>, Vec<usize>) {
+        let base = self.sizer();
+        let (mut remaining, unpackable): (Vec<usize>, Vec<usize>) =
+            (0..groups.len()).partition(|index| {
+                let group = &groups[*index];
+                let mut sizer = base.clone();
+                sizer.add_instructions(&group.instructions);
+                self.fits(
+                    &sizer,
+                    self.base_compute_units.saturating_add(group.compute_units),
+                )
+            });
+
+        let mut txs = vec![];
+        while !remaining.is_empty() {
+            let tx = match self.pack_candidates(groups, &remaining, strategy) {
+                Some(tx) => tx,
+                None => break,
+            };
+            remaining.retain(|index| !tx.group_indexes.contains(index));
+            txs.push(tx);
+        }
+
+        (txs, unpackable)
+    }
+
+    /// Compiles the v0 message of a packed tx
+    pub fn compile(&self, tx: &PackedTx, recent_blockhash: Hash) -> SdkResult<VersionedMessage> {
+        v0::Message::try_compile(
+            &self.payer,
+            &tx.instructions,
+            &self.lookup_tables,
+            recent_blockhash,
+        )
+        .map(VersionedMessage::V0)
+        .map_err(|err| SdkError::Generic(format!("failed to compile: {err}")))
+    }
+
+    fn pack_candidates(
+        &self,
+        groups: &[InstructionGroup],
+        candidates: &[usize],
+        strategy: PackStrategy,
+    ) -> Option<PackedTx> {
+        let base = self.sizer();
+        let (exhaustive, rest) = match strategy {
+            PackStrategy::Greedy => (&candidates[..0], candidates),
+            PackStrategy::Optimal => candidates.split_at(candidates.len().min(MAX_OPTIMAL_GROUPS)),
+        };
+
+        let mut chosen = vec![];
+        let mut best = vec![];
+        self.search(
+            groups,
+            exhaustive,
+            &base,
+            self.base_compute_units,
+            &mut chosen,
+            &mut best,
+        );
+
+        let mut sizer = base;
+        let mut compute_units = self.base_compute_units;
+        for index in &best {
+            sizer.add_instructions(&groups[*index].instructions);
+            compute_units = compute_units.saturating_add(groups[*index].compute_units);
+        }
+        for index in rest {
+            let group = &groups[*index];
+            let mut next = sizer.clone();
+            next.add_instructions(&group.instructions);
+            let next_compute_units = compute_units.saturating_add(group.compute_units);
+            if self.fits(&next, next_compute_units) {
+                sizer = next;
+                compute_units = next_compute_units;
+                best.push(*index);
+            }
+        }
+
+        if best.is_empty() {
+            return None;
+        }
+
+        let mut instructions = self.base_instructions.clone();
+        for index in &best {
+            instructions.extend(groups[*index].instructions.iter().cloned());
+        }
+
+        Some(PackedTx {
+            group_indexes: best,
+            instructions,
+            tx_size: sizer.transaction_size(),
+            num_accounts: sizer.num_accounts(),
+            compute_units,
+        })
+    }
+
+    /// Depth first search for the largest set of `candidates` fitting in one tx. Including a group
+    /// is tried before excluding it and only strictly larger sets replace `best`, so ties resolve to
+    /// the earliest groups.
+    fn search(
+        &self,
+        groups: &[InstructionGroup],
+        candidates: &[usize],
+        sizer: &TxSizer,
+        compute_units: u32,
+        chosen: &mut Vec<usize>,
+        best: &mut Vec<usize>,
+    ) {
+        if chosen.len() + candidates.len() <= best.len() {
+            return;
+        }
+        let (index, rest) = match candidates.split_first() {
+            Some(split) => split,
+            None => {
+                *best = chosen.clone();
+                return;
+            }
+        };
+
+        let group = &groups[*index];
+        let mut next = sizer.clone();
+        next.add_instructions(&group.instructions);
+        let next_compute_units = compute_units.saturating_add(group.compute_units);
+        if self.fits(&next, next_compute_units) {
+            chosen.push(*index);
+            self.search(groups, rest, &next, next_compute_units, chosen, best);
+            chosen.pop();
+        }
+
+        self.search(groups, rest, sizer, compute_units, chosen, best);
+    }
+}
+
+/// Bytes used to encode `len` as a compact-u16
+fn compact_u16_size(len: usize) -> usize {
+    match len {
+        0..=0x7f => 1,
+        0x80..=0x3fff => 2,
+        _ => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use solana_sdk::{
+        compute_budget::ComputeBudgetInstruction, instruction::AccountMeta, signature::Signature,
+        transaction::VersionedTransaction,
+    };
+
+    use super::*;
+
+    const NUM_KEYS: u8 = 24;
+
+    fn key(index: u8) -> Pubkey {
+        Pubkey::new_from_array([index + 1; 32])
+    }
+
+    fn payer() -> Pubkey {
+        key(0)
+    }
+
+    /// keys 4..16 in the first table, 12..20 in the second (12..16 are in both)
+    fn lookup_tables() -> Vec<AddressLookupTableAccount> {
+        vec![
+            AddressLookupTableAccount {
+                key: Pubkey::new_unique(),
+                addresses: (4..16).map(key).collect(),
+            },
+            AddressLookupTableAccount {
+                key: Pubkey::new_unique(),
+                addresses: (12..20).map(key).collect(),
+            },
+        ]
+    }
+
+    /// Serialized size of the tx `try_compile` builds, with a placeholder for each signature
+    fn compiled_size(message: VersionedMessage) -> usize {
+        let num_signers = message.header().num_required_signatures as usize;
+        let tx = VersionedTransaction {
+            signatures: vec![Signature::default(); num_signers],
+            message,
+        };
+        bincode::serialize(&tx).unwrap().len()
+    }
+
+    fn compiled_num_accounts(message: &VersionedMessage) -> usize {
+        match message {
+            VersionedMessage::V0(message) => {
+                message.account_keys.len()
+                    + message
+                        .address_table_lookups
+                        .iter()
+                        .map(|lookup| lookup.writable_indexes.len() + lookup.readonly_indexes.len())
+                        .sum::<usize>()
+            }
+            VersionedMessage::Legacy(message) => message.account_keys.len(),
+        }
+    }
+
+    fn instruction_strategy() -> impl Strategy<Value = Instruction> {
+        (
+            0..NUM_KEYS,
+            prop::collection::vec(
+                (0..NUM_KEYS, prop::bool::weighted(0.1), any::<bool>()),
+                0..8,
+            ),
+            0..300usize,
+        )
+            .prop_map(|(program_id, accounts, data_len)| Instruction {
+                program_id: key(program_id),
+                accounts: accounts
+                    .into_iter()
+                    .map(|(index, is_signer, is_writable)| AccountMeta {
+                        pubkey: key(index),
+                        is_signer,
+                        is_writable,
+                    })
+                    .collect(),
+                data: vec![7; data_len],
+            })
+    }
+
+    fn group_strategy() -> impl Strategy<Value = InstructionGroup> {
+        (
+            prop::collection::vec(instruction_strategy(), 1..3),
+            0..400_000u32,
+        )
+            .prop_map(|(instructions, compute_units)| {
+                InstructionGroup::new(instructions, compute_units)
+            })
+    }
+
+    fn packer() -> TxPacker {
+        TxPacker::new(payer(), lookup_tables(), TxPackerConfig::default()).base_instructions(
+            vec![ComputeBudgetInstruction::set_compute_unit_limit(1_400_000)],
+            0,
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn test_size_matches_serialization(
+            instructions in prop::collection::vec(instruction_strategy(), 0..12),
+        ) {
+            let packer = packer();
+            let mut sizer = packer.sizer();
+            sizer.add_instructions(&instructions);
+
+            let mut all_instructions = packer.base_instructions.clone();
+            all_instructions.extend(instructions);
+            let message = VersionedMessage::V0(
+                v0::Message::try_compile(
+                    &payer(),
+                    &all_instructions,
+                    &lookup_tables(),
+                    Hash::default(),
+                )
+                .unwrap(),
+            );
+
+            prop_assert_eq!(sizer.num_signers(), message.header().num_required_signatures as usize);
+            prop_assert_eq!(sizer.num_accounts(), compiled_num_accounts(&message));
+            prop_assert_eq!(sizer.transaction_size(), compiled_size(message));
+        }
+
+        #[test]
+        fn test_packed_txs_are_within_budget(
+            groups in prop::collection::vec(group_strategy(), 0..12),
+            optimal in any::<bool>(),
+        ) {
+            let packer = packer();
+            let strategy = if optimal { PackStrategy::Optimal } else { PackStrategy::Greedy };
+            let (txs, unpackable) = packer.pack(&groups, strategy);
+
+            let mut packed: Vec<usize> = unpackable.clone();
+            for tx in &txs {
+                let message = packer.compile(tx, Hash::default()).unwrap();
+                prop_assert_eq!(tx.num_accounts, compiled_num_accounts(&message));
+                prop_assert_eq!(tx.tx_size, compiled_size(message));
+                prop_assert!(tx.tx_size <= PACKET_DATA_SIZE);
+                prop_assert!(tx.num_accounts <= DEFAULT_MAX_ACCOUNTS_PER_TX);
+                prop_assert!(tx.compute_units <= DEFAULT_MAX_COMPUTE_UNITS_PER_TX);
+                packed.extend(&tx.group_indexes);
+            }
+
+            // every group ends up in exactly one tx or is reported as unpackable
+            packed.sort();
+            prop_assert_eq!(packed, (0..groups.len()).collect::<Vec<_>>());
+        }
+
+        #[test]
+        fn test_optimal_packs_at_least_as_many_as_greedy(
+            groups in prop::collection::vec(group_strategy(), 0..10),
+        ) {
+            let packer = packer();
+            let greedy = packer.pack_tx(&groups, PackStrategy::Greedy);
+            let optimal = packer.pack_tx(&groups, PackStrategy::Optimal);
+
+            let count = |tx: Option<PackedTx>| tx.map(|tx| tx.group_indexes.len()).unwrap_or(0);
+            prop_assert!(count(optimal) >= count(greedy));
+        }
+    }
+
+    #[test]
+    fn test_lookup_table_accounts_are_compressed() {
+        let packer = packer();
+        let ix = |index: u8| Instruction {
+            program_id: key(1),
+            accounts: vec![AccountMeta::new(key(index), false)],
+            data: vec![],
+        };
+
+        let mut static_sizer = packer.sizer();
+        static_sizer.add_instructions(&[ix(2)]);
+        let mut lookup_sizer = packer.sizer();
+        lookup_sizer.add_instructions(&[ix(5)]);
+
+        // 32 byte key vs 1 byte index plus a 34 byte table lookup
+        assert_eq!(
+            lookup_sizer.transaction_size(),
+            static_sizer.transaction_size() + 3
+        );
+        // another account from the same table costs its 4 byte instruction and 1 byte index
+        let mut sizer = lookup_sizer.clone();
+        sizer.add_instructions(&[ix(6)]);
+        assert_eq!(
+            sizer.transaction_size(),
+            lookup_sizer.transaction_size() + 5
+        );
+    }
+
+    #[test]
+    fn test_optimal_beats_greedy_when_a_large_group_comes_first() {
+        let packer = packer();
+        let group = |data_len: usize| {
+            InstructionGroup::new(
+                vec![Instruction {
+                    program_id: key(1),
+                    accounts: vec![],
+                    data: vec![0; data_len],
+                }],
+                0,
+            )
+        };
+        // the large group leaves no room for a small one, skipping it fits three
+        let groups = vec![group(800), group(300), group(300), group(300)];
+
+        let greedy = packer.pack_tx(&groups, PackStrategy::Greedy).unwrap();
+        assert_eq!(greedy.group_indexes, vec![0]);
+        let optimal = packer.pack_tx(&groups, PackStrategy::Optimal).unwrap();
+        assert_eq!(optimal.group_indexes, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_compute_unit_budget() {
+        let packer = packer();
+        let groups = vec![InstructionGroup::new(vec![], 600_000); 5];
+
+        let (txs, unpackable) = packer.pack(&groups, PackStrategy::Greedy);
+        assert!(unpackable.is_empty());
+        let sizes: Vec<usize> = txs.iter().map(|tx| tx.group_indexes.len()).collect();
+        assert_eq!(sizes, vec![2, 2, 1]);
+    }
+}
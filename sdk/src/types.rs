@@ -74,6 +74,15 @@ impl MarketId {
         index: 0,
         kind: MarketType::Spot,
     };
+
+    /// Index of the market
+    pub const fn index(&self) -> u16 {
+        self.index
+    }
+    /// Type of the market (spot or perp)
+    pub const fn kind(&self) -> MarketType {
+        self.kind
+    }
 }
 
 impl From<(u16, MarketType)> for MarketId {
@@ -174,6 +183,12 @@ pub enum SdkError {
     JitOrderNotFound,
     #[error("Drift Program occured. Error Code: {0}")]
     DriftProgramError(drift::error::ErrorCode),
+    #[error("invalid order: {0}")]
+    InvalidOrder(#[from] crate::order_builder::OrderError),
+    #[error("market not found: {0:?}")]
+    MarketNotFound(MarketId),
+    #[error("oracle not found: {0}")]
+    OracleNotFound(Pubkey),
 }
 
 impl SdkError {
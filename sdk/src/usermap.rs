@@ -24,6 +24,7 @@ use solana_client::rpc_response::{OptionalContext, RpcKeyedAccount};
 use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::pubkey::Pubkey;
 
+pub mod health_index;
 pub mod user_stats_map;
 
 #[derive(Clone)]
@@ -112,6 +113,20 @@ impl UserMap {
         Ok(())
     }
 
+    /// Call `handler` with every user account update received by the subscription
+    pub fn on_update<F: 'static + Send + Fn(Pubkey, &User)>(&self, handler: F) {
+        self.subscription
+            .event_emitter
+            .subscribe(UserMap::SUBSCRIPTION_ID, move |event| {
+                if let Some(update) = event.as_any().downcast_ref::<ProgramAccountUpdate<User>>() {
+                    match Pubkey::from_str(&update.pubkey) {
+                        Ok(pubkey) => handler(pubkey, &update.data_and_slot.data),
+                        Err(err) => log::warn!("invalid user pubkey {}: {err}", update.pubkey),
+                    }
+                }
+            });
+    }
+
     pub async fn add_pubkey(&mut self, user_account_pubkey: &Pubkey) -> SdkResult<()> {
         let user_data = self.rpc.get_account_data(user_account_pubkey).await?;
         let user = User::try_deserialize(&mut user_data.as_slice()).unwrap();
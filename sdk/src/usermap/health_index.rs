@@ -0,0 +1,707 @@
+//! Margin health of every user in a `UserMap`, ordered from least to most healthy
+//!
+
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+};
+
+use drift::{
+    math::constants::MARGIN_PRECISION,
+    state::{perp_market::PerpMarket, spot_market::SpotMarket, user::User},
+};
+use fnv::{FnvHashMap, FnvHashSet};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::{
+    drift_client::DriftClient,
+    math::{
+        account_map_builder::{AccountMapBuilder, AccountSnapshot},
+        liquidation::{
+            calculate_collateral_inner, calculate_liquidation_price_inner,
+            calculate_margin_requirements_inner, MarginCategory,
+        },
+    },
+    oraclemap::Oracle,
+    AccountProvider, SdkError, SdkResult,
+};
+
+use super::UserMap;
+
+/// Recompute users when their oracle moved more than this since the last computation
+const DEFAULT_ORACLE_MOVE_THRESHOLD: f64 = 0.005;
+
+/// A perp position and the oracle price it gets liquidated at
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PerpPositionRisk {
+    pub market_index: u16,
+    pub base_asset_amount: i64,
+    /// PRICE_PRECISION, -1 if price moves in this market alone can't liquidate the user
+    pub liquidation_price: i64,
+}
+
+impl PerpPositionRisk {
+    /// True if the position is liquidated at `oracle_price`
+    pub fn is_liquidated_at(&self, oracle_price: i64) -> bool {
+        if self.liquidation_price < 0 {
+            return false;
+        }
+        if self.base_asset_amount > 0 {
+            oracle_price <= self.liquidation_price
+        } else {
+            oracle_price >= self.liquidation_price
+        }
+    }
+}
+
+/// Maintenance margin state of one user
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserHealth {
+    pub pubkey: Pubkey,
+    /// QUOTE_PRECISION
+    pub total_collateral: i128,
+    /// QUOTE_PRECISION
+    pub free_collateral: i128,
+    /// QUOTE_PRECISION
+    pub margin_requirement: u128,
+    /// total collateral / margin requirement (MARGIN_PRECISION), below 1.0 is liquidatable
+    pub margin_ratio: i128,
+    /// 0 (liquidatable) to 100 (no margin requirement)
+    pub health: u8,
+    pub perp_positions: Vec<PerpPositionRisk>,
+    /// perp markets the user has a position, open orders or LP shares in
+    pub perp_market_indexes: Vec<u16>,
+    /// spot markets the user has a balance or open orders in
+    pub spot_market_indexes: Vec<u16>,
+}
+
+impl UserHealth {
+    fn new(
+        pubkey: Pubkey,
+        total_collateral: i128,
+        free_collateral: i128,
+        margin_requirement: u128,
+    ) -> Self {
+        let (margin_ratio, health) = if margin_requirement == 0 {
+            (i128::MAX, 100)
+        } else {
+            let margin_ratio =
+                total_collateral * MARGIN_PRECISION as i128 / margin_requirement as i128;
+            let health = if total_collateral <= 0 {
+                0
+            } else {
+                100 - (margin_requirement * 100 / total_collateral as u128).min(100) as u8
+            };
+            (margin_ratio, health)
+        };
+
+        Self {
+            pubkey,
+            total_collateral,
+            free_collateral,
+            margin_requirement,
+            margin_ratio,
+            health,
+            perp_positions: Vec::new(),
+            perp_market_indexes: Vec::new(),
+            spot_market_indexes: Vec::new(),
+        }
+    }
+
+    pub fn is_liquidatable(&self) -> bool {
+        self.margin_ratio < MARGIN_PRECISION as i128
+    }
+
+    pub fn perp_position(&self, market_index: u16) -> Option<&PerpPositionRisk> {
+        self.perp_positions
+            .iter()
+            .find(|p| p.market_index == market_index)
+    }
+}
+
+/// Compute the health of `user` against the markets and oracles in `snapshot`
+pub fn calculate_user_health(
+    builder: &mut AccountMapBuilder,
+    snapshot: &AccountSnapshot,
+    pubkey: Pubkey,
+    user: &User,
+) -> SdkResult<UserHealth> {
+    let mut account_maps = builder.build_from(snapshot, user)?;
+    let collateral =
+        calculate_collateral_inner(user, &mut account_maps, MarginCategory::Maintenance)?;
+    let margin_requirement = calculate_margin_requirements_inner(user, &mut account_maps)?;
+
+    let mut health = UserHealth::new(
+        pubkey,
+        collateral.total,
+        collateral.free,
+        margin_requirement.maintenance,
+    );
+    for position in user.perp_positions.iter().filter(|p| p.is_open_position()) {
+        health.perp_positions.push(PerpPositionRisk {
+            market_index: position.market_index,
+            base_asset_amount: position.base_asset_amount,
+            liquidation_price: calculate_liquidation_price_inner(
+                user,
+                position.market_index,
+                &mut account_maps,
+            )?,
+        });
+    }
+    health.perp_market_indexes = perp_market_indexes(user);
+    health.spot_market_indexes = user
+        .spot_positions
+        .iter()
+        .filter(|p| !p.is_available())
+        .map(|p| p.market_index)
+        .collect();
+
+    Ok(health)
+}
+
+/// Perp markets whose price affects `user`'s margin, through a position, open orders or LP shares
+fn perp_market_indexes(user: &User) -> Vec<u16> {
+    user.perp_positions
+        .iter()
+        .filter(|p| !p.is_available())
+        .map(|p| p.market_index)
+        .collect()
+}
+
+/// Users ordered by health with reverse indexes by market
+#[derive(Default)]
+struct HealthBook {
+    users: FnvHashMap<Pubkey, UserHealth>,
+    by_health: BTreeSet<(i128, Pubkey)>,
+    perp_users: FnvHashMap<u16, FnvHashSet<Pubkey>>,
+    spot_users: FnvHashMap<u16, FnvHashSet<Pubkey>>,
+}
+
+impl HealthBook {
+    fn insert(&mut self, health: UserHealth) {
+        self.remove(&health.pubkey);
+
+        let pubkey = health.pubkey;
+        self.by_health.insert((health.margin_ratio, pubkey));
+        for market_index in &health.perp_market_indexes {
+            self.perp_users
+                .entry(*market_index)
+                .or_default()
+                .insert(pubkey);
+        }
+        for market_index in &health.spot_market_indexes {
+            self.spot_users
+                .entry(*market_index)
+                .or_default()
+                .insert(pubkey);
+        }
+        self.users.insert(pubkey, health);
+    }
+
+    fn remove(&mut self, pubkey: &Pubkey) -> Option<UserHealth> {
+        let health = self.users.remove(pubkey)?;
+        self.by_health.remove(&(health.margin_ratio, *pubkey));
+        for market_index in &health.perp_market_indexes {
+            if let Some(users) = self.perp_users.get_mut(market_index) {
+                users.remove(pubkey);
+            }
+        }
+        for market_index in &health.spot_market_indexes {
+            if let Some(users) = self.spot_users.get_mut(market_index) {
+                users.remove(pubkey);
+            }
+        }
+
+        Some(health)
+    }
+
+    fn least_healthy(&self, n: usize) -> Vec<UserHealth> {
+        self.by_health
+            .iter()
+            .take(n)
+            .filter_map(|(_, pubkey)| self.users.get(pubkey).cloned())
+            .collect()
+    }
+
+    /// Users not liquidatable now whose position in `market_index` is liquidated at `price`
+    fn liquidatable_at(&self, market_index: u16, price: i64) -> Vec<UserHealth> {
+        let users = match self.perp_users.get(&market_index) {
+            Some(users) => users,
+            None => return Vec::new(),
+        };
+
+        let mut liquidatable: Vec<UserHealth> = users
+            .iter()
+            .filter_map(|pubkey| self.users.get(pubkey))
+            .filter(|health| {
+                !health.is_liquidatable()
+                    && health
+                        .perp_position(market_index)
+                        .is_some_and(|p| p.is_liquidated_at(price))
+            })
+            .cloned()
+            .collect();
+        liquidatable.sort_by_key(|health| (health.margin_ratio, health.pubkey));
+
+        liquidatable
+    }
+}
+
+struct HealthIndexState {
+    book: HealthBook,
+    snapshot: AccountSnapshot,
+    builder: AccountMapBuilder,
+    /// oracle price each oracle had when its users were last computed
+    reference_prices: FnvHashMap<Pubkey, i64>,
+}
+
+impl HealthIndexState {
+    /// Oracles in `prices` that moved by `threshold` or more from the price their users were
+    /// last computed at, or that have no such price yet
+    fn moved_oracles(
+        &self,
+        prices: &FnvHashMap<Pubkey, i64>,
+        threshold: f64,
+    ) -> FnvHashSet<Pubkey> {
+        prices
+            .iter()
+            .filter(|(oracle, price)| match self.reference_prices.get(*oracle) {
+                Some(&reference) if reference != 0 => {
+                    ((**price - reference) as f64 / reference as f64).abs() >= threshold
+                }
+                _ => true,
+            })
+            .map(|(oracle, _)| *oracle)
+            .collect()
+    }
+
+    /// Update the snapshot with the moved `oracles` and the markets priced by them, returns the
+    /// users of those markets
+    fn apply_oracle_moves(
+        &mut self,
+        oracles: &[Oracle],
+        perp_markets: &[PerpMarket],
+        spot_markets: &[SpotMarket],
+    ) -> FnvHashSet<Pubkey> {
+        let moved: FnvHashSet<Pubkey> = oracles.iter().map(|oracle| oracle.pubkey).collect();
+        let mut users = FnvHashSet::default();
+
+        for oracle in oracles {
+            self.snapshot.insert_oracle(oracle);
+            self.reference_prices
+                .insert(oracle.pubkey, oracle.data.price);
+        }
+        for market in perp_markets
+            .iter()
+            .filter(|market| moved.contains(&market.amm.oracle))
+        {
+            self.snapshot.insert_perp_market(market);
+            if let Some(market_users) = self.book.perp_users.get(&market.market_index) {
+                users.extend(market_users.iter().copied());
+            }
+        }
+        for market in spot_markets
+            .iter()
+            .filter(|market| moved.contains(&market.oracle))
+        {
+            self.snapshot.insert_spot_market(market);
+            if let Some(market_users) = self.book.spot_users.get(&market.market_index) {
+                users.extend(market_users.iter().copied());
+            }
+        }
+
+        users
+    }
+
+    fn update_user(&mut self, pubkey: Pubkey, user: &User) {
+        match calculate_user_health(&mut self.builder, &self.snapshot, pubkey, user) {
+            Ok(health) => self.book.insert(health),
+            Err(err) => {
+                log::warn!("failed to compute health of {pubkey}: {err}");
+                self.book.remove(&pubkey);
+            }
+        }
+    }
+}
+
+/// Margin ratio, free collateral and liquidation prices of every user in a `UserMap`
+///
+/// Users are recomputed when their account changes (see `subscribe`) and when an oracle
+/// they depend on moves past a threshold (see `subscribe` and `refresh`)
+pub struct UserHealthIndex<T: AccountProvider> {
+    drift_client: DriftClient<T>,
+    usermap: UserMap,
+    oracle_move_threshold: f64,
+    state: Arc<Mutex<HealthIndexState>>,
+}
+
+impl<T: AccountProvider> UserHealthIndex<T> {
+    pub fn new(drift_client: DriftClient<T>, usermap: UserMap) -> Self {
+        Self {
+            drift_client,
+            usermap,
+            oracle_move_threshold: DEFAULT_ORACLE_MOVE_THRESHOLD,
+            state: Arc::new(Mutex::new(HealthIndexState {
+                book: HealthBook::default(),
+                snapshot: AccountSnapshot::default(),
+                builder: AccountMapBuilder::default(),
+                reference_prices: FnvHashMap::default(),
+            })),
+        }
+    }
+
+    /// Recompute users of an oracle once it moved by this fraction, e.g. `0.005` for 0.5%
+    pub fn with_oracle_move_threshold(mut self, threshold: f64) -> Self {
+        self.oracle_move_threshold = threshold;
+        self
+    }
+
+    /// Compute the health of every user in the `UserMap`
+    pub fn rebuild(&self) -> SdkResult<()> {
+        let snapshot = AccountSnapshot::from_client(&self.drift_client)?;
+        let reference_prices = self.oracle_prices();
+
+        let mut state = self.lock()?;
+        state.snapshot = snapshot;
+        state.reference_prices = reference_prices;
+        state.book = HealthBook::default();
+        for entry in self.usermap.usermap.iter() {
+            match entry.key().parse::<Pubkey>() {
+                Ok(pubkey) => state.update_user(pubkey, entry.value()),
+                Err(err) => log::warn!("invalid user pubkey {}: {err}", entry.key()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Recompute users whenever the `UserMap` subscription receives an account update, and the
+    /// users of an oracle whenever the `OracleMap` subscription moves it past the threshold
+    pub fn subscribe(&self)
+    where
+        T: Clone,
+    {
+        let state = Arc::clone(&self.state);
+        self.usermap
+            .on_update(move |pubkey, user| match state.lock() {
+                Ok(mut state) => state.update_user(pubkey, user),
+                Err(_) => log::error!("health index lock poisoned"),
+            });
+
+        let drift_client = self.drift_client.clone();
+        let usermap = self.usermap.clone();
+        let oracle_move_threshold = self.oracle_move_threshold;
+        let state = Arc::clone(&self.state);
+        self.drift_client
+            .backend
+            .oracle_map
+            .on_update(move |oracle| {
+                let prices = FnvHashMap::from_iter([(oracle.pubkey, oracle.data.price)]);
+                if let Err(err) = recompute_moved_oracles(
+                    &drift_client,
+                    &usermap,
+                    &state,
+                    &prices,
+                    oracle_move_threshold,
+                ) {
+                    log::warn!(
+                        "failed to recompute users of oracle {}: {err}",
+                        oracle.pubkey
+                    );
+                }
+            });
+    }
+
+    /// Refresh markets and oracles, recomputing the users of every oracle that moved past
+    /// the threshold since they were last computed
+    ///
+    /// Returns the number of recomputed users
+    pub fn refresh(&self) -> SdkResult<usize> {
+        recompute_moved_oracles(
+            &self.drift_client,
+            &self.usermap,
+            &self.state,
+            &self.oracle_prices(),
+            self.oracle_move_threshold,
+        )
+    }
+
+    /// Stop tracking a user, e.g. after its account was deleted
+    pub fn remove_user(&self, pubkey: &Pubkey) -> SdkResult<Option<UserHealth>> {
+        Ok(self.lock()?.book.remove(pubkey))
+    }
+
+    pub fn get(&self, pubkey: &Pubkey) -> SdkResult<Option<UserHealth>> {
+        Ok(self.lock()?.book.users.get(pubkey).cloned())
+    }
+
+    pub fn len(&self) -> SdkResult<usize> {
+        Ok(self.lock()?.book.users.len())
+    }
+
+    pub fn is_empty(&self) -> SdkResult<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// The `n` users with the lowest margin ratio
+    pub fn least_healthy(&self, n: usize) -> SdkResult<Vec<UserHealth>> {
+        Ok(self.lock()?.book.least_healthy(n))
+    }
+
+    /// Users that are not liquidatable now but would be if the oracle price of perp
+    /// `market_index` moved by `price_change`, e.g. `-0.1` for a 10% drop
+    pub fn liquidatable_after_move(
+        &self,
+        market_index: u16,
+        price_change: f64,
+    ) -> SdkResult<Vec<UserHealth>> {
+        let oracle = self
+            .drift_client
+            .get_oracle_price_data_and_slot_for_perp_market(market_index)
+            .ok_or(SdkError::InvalidOracle)?;
+        let price = (oracle.data.price as f64 * (1.0 + price_change)).round() as i64;
+
+        Ok(self.lock()?.book.liquidatable_at(market_index, price))
+    }
+
+    fn oracle_prices(&self) -> FnvHashMap<Pubkey, i64> {
+        self.drift_client
+            .backend
+            .oracle_map
+            .values()
+            .into_iter()
+            .map(|oracle| (oracle.pubkey, oracle.data.price))
+            .collect()
+    }
+
+    fn lock(&self) -> SdkResult<std::sync::MutexGuard<'_, HealthIndexState>> {
+        lock_state(&self.state)
+    }
+}
+
+fn lock_state(
+    state: &Mutex<HealthIndexState>,
+) -> SdkResult<std::sync::MutexGuard<'_, HealthIndexState>> {
+    state
+        .lock()
+        .map_err(|_| SdkError::Generic("health index lock poisoned".to_string()))
+}
+
+/// Recompute the users of every oracle in `prices` that moved past `threshold`, after updating
+/// the cached snapshot with those oracles and the markets they price
+///
+/// Returns the number of recomputed users
+fn recompute_moved_oracles<T: AccountProvider>(
+    drift_client: &DriftClient<T>,
+    usermap: &UserMap,
+    state: &Mutex<HealthIndexState>,
+    prices: &FnvHashMap<Pubkey, i64>,
+    threshold: f64,
+) -> SdkResult<usize> {
+    let moved = lock_state(state)?.moved_oracles(prices, threshold);
+    if moved.is_empty() {
+        return Ok(0);
+    }
+    let oracles: Vec<Oracle> = moved
+        .iter()
+        .filter_map(|oracle| drift_client.backend.oracle_map.get(oracle))
+        .collect();
+
+    let mut state = lock_state(state)?;
+    let users = state.apply_oracle_moves(
+        &oracles,
+        &drift_client.get_perp_market_accounts(),
+        &drift_client.get_spot_market_accounts(),
+    );
+    for pubkey in &users {
+        match usermap.usermap.get(&pubkey.to_string()) {
+            Some(user) => state.update_user(*pubkey, user.value()),
+            None => {
+                state.book.remove(pubkey);
+            }
+        }
+    }
+
+    Ok(users.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use drift::math::constants::{BASE_PRECISION_I64, PRICE_PRECISION_I64, QUOTE_PRECISION_I128};
+
+    use super::*;
+
+    fn health(collateral: i128, requirement: u128, position: Option<(i64, i64)>) -> UserHealth {
+        let mut health = UserHealth::new(
+            Pubkey::new_unique(),
+            collateral * QUOTE_PRECISION_I128,
+            (collateral - requirement as i128).max(0) * QUOTE_PRECISION_I128,
+            requirement * QUOTE_PRECISION_I128 as u128,
+        );
+        if let Some((base_asset_amount, liquidation_price)) = position {
+            health.perp_positions.push(PerpPositionRisk {
+                market_index: 0,
+                base_asset_amount: base_asset_amount * BASE_PRECISION_I64,
+                liquidation_price: liquidation_price * PRICE_PRECISION_I64,
+            });
+            health.perp_market_indexes.push(0);
+        }
+        health
+    }
+
+    #[test]
+    fn test_health_and_margin_ratio() {
+        let idle = health(100, 0, None);
+        assert_eq!(idle.health, 100);
+        assert!(!idle.is_liquidatable());
+
+        let levered = health(100, 25, None);
+        assert_eq!(levered.health, 75);
+        assert_eq!(levered.margin_ratio, 4 * MARGIN_PRECISION as i128);
+
+        let underwater = health(20, 25, None);
+        assert_eq!(underwater.health, 0);
+        assert!(underwater.is_liquidatable());
+    }
+
+    #[test]
+    fn test_book_orders_by_margin_ratio_and_updates_in_place() {
+        let mut book = HealthBook::default();
+        let safe = health(100, 10, Some((1, 50)));
+        let risky = health(100, 80, Some((-1, 110)));
+        let idle = health(100, 0, None);
+        book.insert(safe.clone());
+        book.insert(risky.clone());
+        book.insert(idle.clone());
+
+        let order: Vec<Pubkey> = book.least_healthy(3).iter().map(|h| h.pubkey).collect();
+        assert_eq!(order, vec![risky.pubkey, safe.pubkey, idle.pubkey]);
+
+        // the risky user closes its position
+        let mut closed = health(100, 0, None);
+        closed.pubkey = risky.pubkey;
+        book.insert(closed);
+        assert_eq!(book.least_healthy(1)[0].pubkey, safe.pubkey);
+        assert_eq!(book.by_health.len(), 3);
+        assert!(!book.perp_users[&0].contains(&risky.pubkey));
+
+        assert!(book.remove(&safe.pubkey).is_some());
+        assert!(book.remove(&safe.pubkey).is_none());
+        assert_eq!(book.least_healthy(10).len(), 2);
+    }
+
+    #[test]
+    fn test_moved_oracles() {
+        let mut state = HealthIndexState {
+            book: HealthBook::default(),
+            snapshot: AccountSnapshot::default(),
+            builder: AccountMapBuilder::default(),
+            reference_prices: FnvHashMap::default(),
+        };
+        let (steady, moved, new) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        state.reference_prices.insert(steady, 1_000);
+        state.reference_prices.insert(moved, 1_000);
+
+        let prices = FnvHashMap::from_iter([(steady, 1_004), (moved, 995), (new, 1_000)]);
+        let mut expected = FnvHashSet::default();
+        expected.insert(moved);
+        expected.insert(new);
+        assert_eq!(state.moved_oracles(&prices, 0.005), expected);
+    }
+
+    #[test]
+    fn test_indexes_users_by_open_order_and_lp_markets() {
+        let mut user = User::default();
+        user.perp_positions[0].market_index = 1;
+        user.perp_positions[0].base_asset_amount = BASE_PRECISION_I64;
+        user.perp_positions[1].market_index = 2;
+        user.perp_positions[1].open_orders = 1;
+        user.perp_positions[2].market_index = 3;
+        user.perp_positions[2].lp_shares = BASE_PRECISION_I64 as u64;
+        assert_eq!(perp_market_indexes(&user), vec![1, 2, 3]);
+
+        let mut orders_only = health(100, 10, None);
+        orders_only.perp_market_indexes.push(2);
+        let mut book = HealthBook::default();
+        book.insert(orders_only.clone());
+        assert!(book.perp_users[&2].contains(&orders_only.pubkey));
+        // no position to be liquidated at a price
+        assert!(book.liquidatable_at(2, 0).is_empty());
+
+        book.remove(&orders_only.pubkey);
+        assert!(book.perp_users[&2].is_empty());
+    }
+
+    #[test]
+    fn test_apply_oracle_moves() {
+        let mut state = HealthIndexState {
+            book: HealthBook::default(),
+            snapshot: AccountSnapshot::default(),
+            builder: AccountMapBuilder::default(),
+            reference_prices: FnvHashMap::default(),
+        };
+        let (moved, steady) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut perp_markets = [PerpMarket::default(), PerpMarket::default()];
+        perp_markets[0].amm.oracle = moved;
+        perp_markets[1].market_index = 1;
+        perp_markets[1].amm.oracle = steady;
+        let mut spot_market = SpotMarket::default();
+        spot_market.market_index = 1;
+        spot_market.oracle = moved;
+
+        let perp_user = health(100, 10, Some((1, 50)));
+        let mut spot_user = health(100, 10, None);
+        spot_user.spot_market_indexes.push(1);
+        let mut steady_user = health(100, 10, None);
+        steady_user.perp_market_indexes.push(1);
+        for user in [&perp_user, &spot_user, &steady_user] {
+            state.book.insert(user.clone());
+        }
+
+        let mut oracle = Oracle {
+            pubkey: moved,
+            data: Default::default(),
+            source: Default::default(),
+            slot: 7,
+            raw: Vec::new(),
+        };
+        oracle.data.price = 1_010;
+        let users = state.apply_oracle_moves(&[oracle], &perp_markets, &[spot_market]);
+
+        let mut expected = FnvHashSet::default();
+        expected.insert(perp_user.pubkey);
+        expected.insert(spot_user.pubkey);
+        assert_eq!(users, expected);
+        assert_eq!(state.reference_prices.get(&moved), Some(&1_010));
+        assert!(!state.reference_prices.contains_key(&steady));
+    }
+
+    #[test]
+    fn test_liquidatable_at_price() {
+        let mut book = HealthBook::default();
+        let long = health(100, 50, Some((1, 90)));
+        let short = health(100, 50, Some((-1, 110)));
+        let already_liquidatable = health(40, 50, Some((1, 99)));
+        book.insert(long.clone());
+        book.insert(short.clone());
+        book.insert(already_liquidatable);
+
+        let pubkeys = |users: Vec<UserHealth>| users.iter().map(|h| h.pubkey).collect::<Vec<_>>();
+        assert_eq!(
+            pubkeys(book.liquidatable_at(0, 89 * PRICE_PRECISION_I64)),
+            vec![long.pubkey]
+        );
+        assert_eq!(
+            pubkeys(book.liquidatable_at(0, 110 * PRICE_PRECISION_I64)),
+            vec![short.pubkey]
+        );
+        assert!(book
+            .liquidatable_at(0, 100 * PRICE_PRECISION_I64)
+            .is_empty());
+        assert!(book.liquidatable_at(1, 0).is_empty());
+    }
+}
@@ -60,19 +60,30 @@ where
         Ok(())
     }
 
+    /// Unsubscribed UserStats of `authority`, polled by this map's account loader
+    ///
+    /// Lets callers sharing the map subscribe it without holding the map, then `insert` it
+    pub fn new_user_stat(&self, authority: Pubkey) -> SdkResult<UserStats<T>> {
+        UserStats::new(UserStatsConfig {
+            account_subscription: Some(UserStatsSubscriptionConfig::Polling {
+                account_loader: self.bulk_account_loader.clone(),
+            }),
+            drift_client: self.drift_client.clone(),
+            user_stats_account_public_key: get_user_stats_account_pubkey(&PROGRAM_ID, authority),
+        })
+    }
+
+    pub fn insert(&mut self, authority: Pubkey, user_stat: UserStats<T>) {
+        self.user_stats_map.insert(authority, user_stat);
+    }
+
     pub async fn add_user_stat(
         &mut self,
         authority: Pubkey,
         user_stats_account: Option<UserStatsAccount>,
         skip_fetch: Option<bool>,
     ) -> SdkResult<()> {
-        let mut user_stat = UserStats::new(UserStatsConfig {
-            account_subscription: Some(UserStatsSubscriptionConfig::Polling {
-                account_loader: self.bulk_account_loader.clone(),
-            }),
-            drift_client: self.drift_client.clone(),
-            user_stats_account_public_key: get_user_stats_account_pubkey(&PROGRAM_ID, authority),
-        })?;
+        let mut user_stat = self.new_user_stat(authority)?;
 
         if let Some(true) = skip_fetch {
             if let UserStatsAccountSubscriber::Polling(ref mut polling) =
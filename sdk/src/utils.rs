@@ -151,6 +151,32 @@ pub(crate) fn zero_account_to_bytes<T: bytemuck::Pod + anchor_lang::Discriminato
     account_data
 }
 
+/// Like `zero_account_to_bytes` but writes into `data`, reusing its allocation
+pub(crate) fn write_zero_account<T: bytemuck::Pod + anchor_lang::Discriminator>(
+    account: &T,
+    data: &mut Vec<u8>,
+) {
+    data.clear();
+    data.extend_from_slice(bytemuck::bytes_of(&T::DISCRIMINATOR));
+    data.extend_from_slice(bytemuck::bytes_of(account));
+}
+
+/// Fixed size field at `offset` of raw account `data`, for accounts without a typed layout
+pub(crate) fn read_bytes<const N: usize>(data: &[u8], offset: usize) -> SdkResult<[u8; N]> {
+    data.get(offset..offset + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(SdkError::Deserializing)
+}
+
+/// Encode an account name into the fixed size, space padded on-chain format
+pub fn encode_name(name: &str) -> [u8; 32] {
+    let mut encoded = [b' '; 32];
+    let bytes = name.as_bytes();
+    let len = bytes.len().min(encoded.len());
+    encoded[..len].copy_from_slice(&bytes[..len]);
+    encoded
+}
+
 #[cfg(any(test, test_utils))]
 pub mod envs {
     //! test env vars
@@ -212,4 +238,14 @@ mod tests {
         let http_url = "http://dlob.drift.trade";
         assert!(http_to_ws(http_url).unwrap() == "ws://dlob.drift.trade/ws")
     }
+
+    #[test]
+    fn test_encode_name() {
+        let encoded = encode_name("Main Account");
+        assert_eq!(&encoded[..12], b"Main Account");
+        assert!(encoded[12..].iter().all(|b| *b == b' '));
+
+        let long = "a".repeat(40);
+        assert_eq!(encode_name(&long), [b'a'; 32]);
+    }
 }